@@ -1,5 +1,4 @@
-use crate::{writer, state, memory};
-use core::sync::atomic::Ordering;
+use crate::{writer, memory};
 
 #[repr(C, packed)]
 #[derive(Clone, Copy)]
@@ -75,12 +74,62 @@ pub struct Fadt {
 
 pub static mut FADT: Option<Fadt> = None;
 
+#[repr(C, packed)]
+#[derive(Clone, Copy)]
+pub struct MadtHeader {
+    pub header: AcpiHeader,
+    pub local_apic_addr: u32,
+    pub flags: u32,
+}
+
+/// What we keep from the MADT: where the interrupt controllers live and how ISA IRQs are wired
+#[derive(Clone, Copy)]
+pub struct MadtInfo {
+    pub local_apic_addr: u64,
+    pub ioapic_addr: Option<u64>,
+    pub isa_overrides: [(u32, u16); 16], // ISA IRQ -> (GSI, MPS INTI flags)
+    pub lapic_ids: [u8; crate::smp::MAX_CPUS], // Usable CPUs' APIC IDs, in MADT order
+    pub cpu_count: usize,
+}
+
+pub static mut MADT: Option<MadtInfo> = None;
+
+/// Event timer block ID, then the register block as a Generic Address Structure
+#[repr(C, packed)]
+#[derive(Clone, Copy)]
+pub struct HpetTable {
+    pub header: AcpiHeader,
+    pub event_timer_block_id: u32,
+    pub address_space_id: u8, // 0 = system memory
+    pub register_bit_width: u8,
+    pub register_bit_offset: u8,
+    pub reserved: u8,
+    pub address: u64,
+    pub hpet_number: u8,
+    pub minimum_tick: u16,
+    pub page_protection: u8,
+}
+
+pub static mut HPET_BASE: Option<u64> = None;
+
+/// FADT RESET_REG (ACPI 2.0+): where to write `value` to reset the machine
+#[derive(Clone, Copy)]
+pub struct ResetReg {
+    pub address_space_id: u8, // 0 = system memory, 1 = I/O port
+    pub address: u64,
+    pub value: u8,
+}
+
+pub static mut RESET_REG: Option<ResetReg> = None;
+
+// Past the end of `Fadt`: RESET_REG's Generic Address Structure, then RESET_VALUE
+const FADT_RESET_REG_OFFSET: u64 = 116;
+const FADT_RESET_VALUE_OFFSET: u64 = 128;
+const FADT_RESET_REG_SUP: u32 = 1 << 10;
+
 pub fn init(rsdp_ptr: u64) {
-    let hhdm = state::HHDM_OFFSET.load(Ordering::Relaxed);
-    
     // 1. Map and parse RSDP
-    map_region(rsdp_ptr, 1024); // Map at least a page
-    let rsdp = unsafe { &*((rsdp_ptr + hhdm) as *const Rsdp) };
+    let rsdp = unsafe { &*(map_region(rsdp_ptr, core::mem::size_of::<Rsdp>() as u64) as *const Rsdp) };
 
     if &rsdp.signature != b"RSD PTR " {
         writer::print("[ACPI] Error: Invalid RSDP Signature\n");
@@ -96,8 +145,10 @@ pub fn init(rsdp_ptr: u64) {
     };
 
     // 2. Map and parse XSDT/RSDT
-    map_region(xsdt_addr, 4096);
-    let xsdt = unsafe { &*((xsdt_addr + hhdm) as *const AcpiHeader) };
+    let xsdt_virt = map_region(xsdt_addr, core::mem::size_of::<AcpiHeader>() as u64);
+    let xsdt_len = unsafe { (*(xsdt_virt as *const AcpiHeader)).length } as u64;
+    let xsdt_virt = map_region(xsdt_addr, xsdt_len);
+    let xsdt = unsafe { &*(xsdt_virt as *const AcpiHeader) };
     if &xsdt.signature != b"XSDT" && &xsdt.signature != b"RSDT" {
         writer::print("[ACPI] Error: Invalid XSDT/RSDT Signature\n");
         return;
@@ -107,7 +158,7 @@ pub fn init(rsdp_ptr: u64) {
     writer::print(&alloc::format!("[ACPI] Found {} tables\n", entries));
 
     for i in 0..entries {
-        let table_ptr_addr = xsdt_addr + hhdm + core::mem::size_of::<AcpiHeader>() as u64 + (i * if rsdp.revision >= 2 { 8 } else { 4 }) as u64;
+        let table_ptr_addr = xsdt_virt + core::mem::size_of::<AcpiHeader>() as u64 + (i * if rsdp.revision >= 2 { 8 } else { 4 }) as u64;
         let table_phys = if rsdp.revision >= 2 {
             unsafe { *(table_ptr_addr as *const u64) }
         } else {
@@ -115,39 +166,138 @@ pub fn init(rsdp_ptr: u64) {
         };
 
         // 3. Map and parse Table Header
-        map_region(table_phys, 4096);
-        let header = unsafe { &*((table_phys + hhdm) as *const AcpiHeader) };
+        let header_virt = map_region(table_phys, core::mem::size_of::<AcpiHeader>() as u64);
+        let header = unsafe { &*(header_virt as *const AcpiHeader) };
         let sig = core::str::from_utf8(&header.signature).unwrap_or("????");
         writer::print(&alloc::format!("[ACPI] Table: {}\n", sig));
 
         if sig == "FACP" {
-            map_region(table_phys, header.length as u64);
-            let fadt = unsafe { *((table_phys + hhdm) as *const Fadt) };
+            let base = map_region(table_phys, header.length as u64);
+            let fadt = unsafe { *(base as *const Fadt) };
             unsafe { FADT = Some(fadt) };
+            if header.length as u64 > FADT_RESET_VALUE_OFFSET && fadt.flags & FADT_RESET_REG_SUP != 0 {
+                let reset = unsafe {
+                    ResetReg {
+                        address_space_id: *((base + FADT_RESET_REG_OFFSET) as *const u8),
+                        address: core::ptr::read_unaligned((base + FADT_RESET_REG_OFFSET + 4) as *const u64),
+                        value: *((base + FADT_RESET_VALUE_OFFSET) as *const u8),
+                    }
+                };
+                unsafe { RESET_REG = Some(reset) };
+            }
+        } else if sig == "APIC" {
+            let madt = parse_madt(map_region(table_phys, header.length as u64));
+            unsafe { MADT = Some(madt) };
+        } else if sig == "HPET" {
+            let hpet = unsafe { *(map_region(table_phys, header.length as u64) as *const HpetTable) };
+            if hpet.address_space_id == 0 {
+                let base = hpet.address;
+                writer::print(&alloc::format!("[ACPI] HPET at {:#x}\n", base));
+                unsafe { HPET_BASE = Some(base) };
+            }
         }
     }
 }
 
-/// Helper to map a physical region in the HHDM, ensuring page alignment
-fn map_region(phys: u64, size: u64) {
-    let hhdm = state::HHDM_OFFSET.load(Ordering::Relaxed);
-    let start_page = phys & !0xFFF;
-    let end_page = (phys + size + 0xFFF) & !0xFFF;
-    
-    for page in (start_page..end_page).step_by(4096) {
-        unsafe { memory::map_kernel_page(page + hhdm, page); }
+/// Walks the MADT's variable-length entries (type, length, body...)
+fn parse_madt(virt: u64) -> MadtInfo {
+    let madt = unsafe { &*(virt as *const MadtHeader) };
+    let mut info = MadtInfo {
+        local_apic_addr: madt.local_apic_addr as u64,
+        ioapic_addr: None,
+        isa_overrides: [(0, 0); 16],
+        lapic_ids: [0; crate::smp::MAX_CPUS],
+        cpu_count: 0,
+    };
+    // Identity mapping unless an override says otherwise
+    for (irq, entry) in info.isa_overrides.iter_mut().enumerate() {
+        *entry = (irq as u32, 0);
+    }
+
+    let end = virt + madt.header.length as u64;
+    let mut ptr = virt + core::mem::size_of::<MadtHeader>() as u64;
+    while ptr + 2 <= end {
+        let (kind, len) = unsafe { (*(ptr as *const u8), *((ptr + 1) as *const u8)) };
+        if len < 2 { break; }
+        unsafe {
+            match kind {
+                // Processor Local APIC: ACPI processor ID, APIC ID, flags u32
+                // (bit 0 = enabled, bit 1 = online capable)
+                0 => {
+                    let apic_id = *((ptr + 3) as *const u8);
+                    let flags = core::ptr::read_unaligned((ptr + 4) as *const u32);
+                    if flags & 0x3 != 0 && info.cpu_count < crate::smp::MAX_CPUS {
+                        info.lapic_ids[info.cpu_count] = apic_id;
+                        info.cpu_count += 1;
+                    }
+                }
+                // I/O APIC: id, reserved, address u32, GSI base u32
+                1 if info.ioapic_addr.is_none() => {
+                    info.ioapic_addr = Some(core::ptr::read_unaligned((ptr + 4) as *const u32) as u64);
+                }
+                // Interrupt Source Override: bus, source IRQ, GSI u32, flags u16
+                2 => {
+                    let source = *((ptr + 3) as *const u8) as usize;
+                    let gsi = core::ptr::read_unaligned((ptr + 4) as *const u32);
+                    let flags = core::ptr::read_unaligned((ptr + 8) as *const u16);
+                    if source < 16 { info.isa_overrides[source] = (gsi, flags); }
+                }
+                // Local APIC Address Override (64-bit)
+                5 => {
+                    info.local_apic_addr = core::ptr::read_unaligned((ptr + 4) as *const u64);
+                }
+                _ => {}
+            }
+        }
+        ptr += len as u64;
+    }
+
+    if let Some(addr) = info.ioapic_addr {
+        writer::print(&alloc::format!("[ACPI] IOAPIC at {:#x}\n", addr));
+    }
+    writer::print(&alloc::format!("[ACPI] {} CPU(s) in MADT\n", info.cpu_count));
+    info
+}
+
+/// IOAPIC base address from the MADT, if the firmware reported one
+pub fn ioapic_base() -> Option<u64> {
+    unsafe { MADT.and_then(|m| m.ioapic_addr) }
+}
+
+/// APIC IDs of the usable CPUs (BSP included); empty without a MADT
+pub fn cpu_apic_ids() -> alloc::vec::Vec<u8> {
+    unsafe {
+        match MADT {
+            Some(m) => m.lapic_ids[..m.cpu_count].to_vec(),
+            None => alloc::vec::Vec::new(),
+        }
+    }
+}
+
+/// HPET register block from the HPET table, if the firmware has one
+pub fn hpet_base() -> Option<u64> {
+    unsafe { HPET_BASE }
+}
+
+/// GSI and polarity/trigger flags for an ISA IRQ
+pub fn isa_irq_to_gsi(irq: u8) -> (u32, u16) {
+    unsafe {
+        match MADT {
+            Some(m) if (irq as usize) < 16 => m.isa_overrides[irq as usize],
+            _ => (irq as u32, 0),
+        }
     }
 }
 
+/// Helper to map a physical region in the HHDM, ensuring page alignment
+/// Maps a firmware region and returns its virtual address
+fn map_region(phys: u64, size: u64) -> u64 {
+    memory::map_kernel_range(phys, size)
+}
+
+/// Emulator-only power off: QEMU/Bochs and VirtualBox expose fixed ACPI ports.
+/// Harmless on real hardware, where nothing listens there.
 pub fn shutdown() {
-    writer::print("[ACPI] Shutdown initiated...\n");
-    // For a real shutdown, we need to:
-    // 1. Find the \_S5 object in the DSDT/SSDTs (requires an AML parser)
-    // 2. Write the SLP_TYPa and SLP_TYPb values to PM1a_CNT and PM1b_CNT
-    
-    // AML parsing is very complex. For now, we'll try the QEMU debug port
-    // or wait for a future phase with full AML support.
-    
     unsafe {
         use x86_64::instructions::port::Port;
         // QEMU/Bochs specific shutdown
@@ -156,3 +306,111 @@ pub fn shutdown() {
         Port::<u16>::new(0x4004).write(0x3400);
     }
 }
+
+/// Resets the machine: pulses the CPU reset line through the keyboard controller,
+/// then tries the FADT reset register. Never returns.
+pub fn reboot() -> ! {
+    x86_64::instructions::interrupts::disable();
+    unsafe {
+        use x86_64::instructions::port::Port;
+        // 1. 8042: wait for the input buffer to drain, then command 0xFE (pulse reset)
+        let mut status = Port::<u8>::new(0x64);
+        for _ in 0..100_000 {
+            if status.read() & 0x02 == 0 { break; }
+            core::hint::spin_loop();
+        }
+        status.write(0xFE);
+        for _ in 0..1_000_000 { core::hint::spin_loop(); }
+
+        // 2. ACPI reset register, if the firmware provides one
+        if let Some(reset) = RESET_REG {
+            match reset.address_space_id {
+                1 => Port::<u8>::new(reset.address as u16).write(reset.value),
+                0 => {
+                    core::ptr::write_volatile(map_region(reset.address, 1) as *mut u8, reset.value);
+                }
+                _ => {}
+            }
+        }
+    }
+    // 3. Execution should not get here
+    loop { x86_64::instructions::hlt(); }
+}
+
+const SLP_EN: u16 = 1 << 13;
+const SCI_EN: u16 = 1;
+
+/// Enters S5 (soft off). Tries the emulator ports first, then writes SLP_TYPx | SLP_EN
+/// to the FADT's PM1 control blocks using the \_S5 values from the DSDT.
+pub fn shutdown_via_fadt() {
+    writer::print("[ACPI] Shutdown initiated...\n");
+    shutdown();
+
+    let fadt = match unsafe { FADT } {
+        Some(f) => f,
+        None => {
+            writer::print("[ACPI] Error: No FADT, cannot power off.\n");
+            return;
+        }
+    };
+    let pm1a = fadt.pm1a_control_block;
+    let pm1b = fadt.pm1b_control_block;
+    if pm1a == 0 {
+        writer::print("[ACPI] Error: FADT has no PM1a control block.\n");
+        return;
+    }
+
+    // 1. SLP_TYP values from \_S5, or the common ATX value if the DSDT doesn't say
+    let (slp_typ_a, slp_typ_b) = find_s5(fadt.dsdt as u64).unwrap_or((5, 5));
+
+    unsafe {
+        use x86_64::instructions::port::Port;
+        // 2. Switch the chipset into ACPI mode if the firmware left it in legacy mode
+        let mut pm1a_port = Port::<u16>::new(pm1a as u16);
+        if pm1a_port.read() & SCI_EN == 0 && fadt.smi_command_port != 0 && fadt.acpi_enable != 0 {
+            Port::<u8>::new(fadt.smi_command_port as u16).write(fadt.acpi_enable);
+            for _ in 0..1000 {
+                if pm1a_port.read() & SCI_EN != 0 { break; }
+                for _ in 0..10_000 { core::hint::spin_loop(); }
+            }
+        }
+
+        // 3. Sleep
+        x86_64::instructions::interrupts::disable();
+        pm1a_port.write(((slp_typ_a as u16) << 10) | SLP_EN);
+        if pm1b != 0 {
+            Port::<u16>::new(pm1b as u16).write(((slp_typ_b as u16) << 10) | SLP_EN);
+        }
+        x86_64::instructions::interrupts::enable();
+    }
+    writer::print("[ACPI] Error: Machine did not power off.\n");
+}
+
+/// Minimal AML scan for `Name(_S5_, Package(){ SLP_TYPa, SLP_TYPb, ... })` in the DSDT
+fn find_s5(dsdt_phys: u64) -> Option<(u8, u8)> {
+    if dsdt_phys == 0 { return None; }
+    let header = map_region(dsdt_phys, core::mem::size_of::<AcpiHeader>() as u64);
+    let len = unsafe { (*(header as *const AcpiHeader)).length } as usize;
+    let aml = unsafe { core::slice::from_raw_parts(map_region(dsdt_phys, len as u64) as *const u8, len) };
+
+    let hdr = core::mem::size_of::<AcpiHeader>();
+    let pos = aml[hdr..].windows(4).position(|w| w == b"_S5_")? + hdr;
+    // Must be preceded by NameOp (optionally with a root prefix '\') and followed by PackageOp
+    let named = aml.get(pos - 1) == Some(&0x08) || (aml.get(pos - 2) == Some(&0x08) && aml.get(pos - 1) == Some(&b'\\'));
+    if !named || aml.get(pos + 4) != Some(&0x12) { return None; }
+
+    // Skip PackageOp, PkgLength (top two bits of the lead byte = extra bytes), NumElements
+    let mut i = pos + 5;
+    i += ((*aml.get(i)? >> 6) & 0x3) as usize + 1;
+    i += 1;
+
+    let read_int = |i: &mut usize| -> Option<u8> {
+        if *aml.get(*i)? == 0x0A { *i += 1; } // BytePrefix
+        let v = *aml.get(*i)?;
+        *i += 1;
+        Some(v)
+    };
+    let a = read_int(&mut i)?;
+    let b = read_int(&mut i)?;
+    Some((a, b))
+}
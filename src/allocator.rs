@@ -1,39 +1,246 @@
-use linked_list_allocator::LockedHeap;
-use x86_64::structures::paging::mapper::MapToError;
-use x86_64::VirtAddr;
+use core::alloc::{GlobalAlloc, Layout};
+use core::ptr;
+use spin::Mutex;
 
 // 1. DEFINE THE HEAP
-// We create a wrapper around the allocator that is thread-safe (LockedHeap).
+// A buddy allocator: the heap is carved into power-of-two blocks, freed blocks are merged
+// with their buddy immediately, so long-running window/string churn doesn't fragment it.
 #[global_allocator]
-static ALLOCATOR: LockedHeap = LockedHeap::empty();
+static ALLOCATOR: BuddyAllocator = BuddyAllocator::empty();
 
 // 2. DEFINE THE MEMORY REGION
-// Instead of scanning RAM, we reserve a big chunk of memory 
+// Instead of scanning RAM, we reserve a big chunk of memory
 // inside our own kernel binary to act as the heap.
 // 32 MiB size.
-pub const HEAP_SIZE: usize = 32 * 1024 * 1024; 
+pub const HEAP_SIZE: usize = 32 * 1024 * 1024;
+
+// Page-aligned so every block is aligned to its own size (up to 4 KiB)
+#[repr(C, align(4096))]
+struct HeapMem([u8; HEAP_SIZE]);
 
 // We use 'static mut' to allocate space in the BSS section.
 // This is effectively a big array of zero bytes.
-static mut HEAP_MEM: [u8; HEAP_SIZE] = [0; HEAP_SIZE];
+static mut HEAP_MEM: HeapMem = HeapMem([0; HEAP_SIZE]);
+
+// --- BUDDY ALLOCATOR ---
+const MIN_BLOCK_SHIFT: usize = 5; // 32-byte blocks: room for the free-list links
+const MIN_BLOCK: usize = 1 << MIN_BLOCK_SHIFT;
+const MAX_ORDERS: usize = 48;
+const MAX_ALIGN: usize = 4096;
+
+// Lives inside each free block
+struct FreeBlock {
+    next: *mut FreeBlock,
+    prev: *mut FreeBlock,
+}
+
+struct BuddyInner {
+    base: usize,       // Address of offset 0 in the arena
+    max_order: usize,  // Arena size is MIN_BLOCK << max_order
+    free_lists: [*mut FreeBlock; MAX_ORDERS],
+    bitmap: *mut u64,  // One "is free" bit per block, per order
+    level_base: [usize; MAX_ORDERS], // First bit of each order in `bitmap`
+    used: usize,
+    total: usize,
+}
+
+// The raw pointers only ever point into the heap region, guarded by the Mutex
+unsafe impl Send for BuddyInner {}
+
+pub struct BuddyAllocator {
+    inner: Mutex<BuddyInner>,
+}
+
+impl BuddyAllocator {
+    pub const fn empty() -> Self {
+        BuddyAllocator {
+            inner: Mutex::new(BuddyInner {
+                base: 0,
+                max_order: 0,
+                free_lists: [ptr::null_mut(); MAX_ORDERS],
+                bitmap: ptr::null_mut(),
+                level_base: [0; MAX_ORDERS],
+                used: 0,
+                total: 0,
+            }),
+        }
+    }
+
+    /// # Safety
+    /// `start..start + size` must be unused, writable memory that lives forever; must be
+    /// called once, before the first allocation.
+    pub unsafe fn init(&self, start: *mut u8, size: usize) {
+        self.inner.lock().init(start as usize, size);
+    }
+
+    pub fn used(&self) -> usize {
+        x86_64::instructions::interrupts::without_interrupts(|| self.inner.lock().used)
+    }
+
+    pub fn size(&self) -> usize {
+        x86_64::instructions::interrupts::without_interrupts(|| self.inner.lock().total)
+    }
+}
+
+impl BuddyInner {
+    unsafe fn init(&mut self, start: usize, size: usize) {
+        // 1. Align the arena and size it to the next power of two
+        let base = (start + MAX_ALIGN - 1) & !(MAX_ALIGN - 1);
+        let end = (start + size) & !(MIN_BLOCK - 1);
+        let usable = end - base;
+        let arena = usable.next_power_of_two();
+        self.base = base;
+        self.max_order = arena.trailing_zeros() as usize - MIN_BLOCK_SHIFT;
+
+        // 2. Free-bit bitmap sits at the start of the arena and is never handed out
+        let mut bits = 0;
+        for order in 0..=self.max_order {
+            self.level_base[order] = bits;
+            bits += arena >> (order + MIN_BLOCK_SHIFT);
+        }
+        let bitmap_bytes = bits.div_ceil(64) * 8;
+        self.bitmap = base as *mut u64;
+        ptr::write_bytes(self.bitmap, 0, bitmap_bytes / 8);
+
+        // 3. Cover the rest with the largest aligned blocks that fit
+        let mut off = (bitmap_bytes + MIN_BLOCK - 1) & !(MIN_BLOCK - 1);
+        let region_end = usable;
+        self.total = region_end - off;
+        while off < region_end {
+            let mut order = self.max_order;
+            while (off & ((MIN_BLOCK << order) - 1)) != 0 || off + (MIN_BLOCK << order) > region_end {
+                order -= 1;
+            }
+            self.push_free(order, off);
+            off += MIN_BLOCK << order;
+        }
+    }
+
+    fn order_for(layout: &Layout) -> usize {
+        let size = layout.size().max(layout.align()).max(MIN_BLOCK).next_power_of_two();
+        size.trailing_zeros() as usize - MIN_BLOCK_SHIFT
+    }
+
+    fn bit_index(&self, order: usize, off: usize) -> usize {
+        self.level_base[order] + (off >> (order + MIN_BLOCK_SHIFT))
+    }
+
+    unsafe fn is_free(&self, order: usize, off: usize) -> bool {
+        let bit = self.bit_index(order, off);
+        (*self.bitmap.add(bit / 64) & (1 << (bit % 64))) != 0
+    }
+
+    unsafe fn set_free_bit(&mut self, order: usize, off: usize, free: bool) {
+        let bit = self.bit_index(order, off);
+        let word = self.bitmap.add(bit / 64);
+        if free { *word |= 1 << (bit % 64); } else { *word &= !(1 << (bit % 64)); }
+    }
+
+    unsafe fn push_free(&mut self, order: usize, off: usize) {
+        let block = (self.base + off) as *mut FreeBlock;
+        let head = self.free_lists[order];
+        (*block).next = head;
+        (*block).prev = ptr::null_mut();
+        if !head.is_null() { (*head).prev = block; }
+        self.free_lists[order] = block;
+        self.set_free_bit(order, off, true);
+    }
+
+    unsafe fn remove_free(&mut self, order: usize, off: usize) {
+        let block = (self.base + off) as *mut FreeBlock;
+        let (next, prev) = ((*block).next, (*block).prev);
+        if prev.is_null() { self.free_lists[order] = next; } else { (*prev).next = next; }
+        if !next.is_null() { (*next).prev = prev; }
+        self.set_free_bit(order, off, false);
+    }
+
+    unsafe fn alloc(&mut self, layout: Layout) -> *mut u8 {
+        let order = Self::order_for(&layout);
+        if layout.align() > MAX_ALIGN || self.max_order == 0 || order > self.max_order {
+            return ptr::null_mut();
+        }
+
+        // 1. Smallest non-empty list that can satisfy the request
+        let mut found = order;
+        while found <= self.max_order && self.free_lists[found].is_null() { found += 1; }
+        if found > self.max_order { return ptr::null_mut(); }
+
+        let off = self.free_lists[found] as usize - self.base;
+        self.remove_free(found, off);
+
+        // 2. Split, returning the upper halves to the free lists
+        while found > order {
+            found -= 1;
+            self.push_free(found, off + (MIN_BLOCK << found));
+        }
+
+        self.used += MIN_BLOCK << order;
+        (self.base + off) as *mut u8
+    }
+
+    unsafe fn dealloc(&mut self, ptr: *mut u8, layout: Layout) {
+        let mut order = Self::order_for(&layout);
+        let mut off = ptr as usize - self.base;
+        self.used -= MIN_BLOCK << order;
+
+        // Merge with the buddy for as long as it is free too
+        while order < self.max_order {
+            let buddy = off ^ (MIN_BLOCK << order);
+            if !self.is_free(order, buddy) { break; }
+            self.remove_free(order, buddy);
+            off &= !(MIN_BLOCK << order);
+            order += 1;
+        }
+        self.push_free(order, off);
+    }
+}
+
+unsafe impl GlobalAlloc for BuddyAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        // No preemption while holding the lock, or the next task to allocate would spin forever
+        let ptr = x86_64::instructions::interrupts::without_interrupts(|| self.inner.lock().alloc(layout));
+        if !ptr.is_null() { return ptr; }
+
+        // Out of heap: kill one victim (its stack goes back to the heap as it drops) and retry once
+        match crate::scheduler::kill_heaviest_task() {
+            Some(name) => {
+                crate::serial_println!("[OOM] Killed task '{}' to free memory", name);
+                drop(name);
+                x86_64::instructions::interrupts::without_interrupts(|| self.inner.lock().alloc(layout))
+            }
+            None => ptr,
+        }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        x86_64::instructions::interrupts::without_interrupts(|| self.inner.lock().dealloc(ptr, layout))
+    }
+}
 
 // 3. INITIALIZE
 pub fn init_heap() {
     unsafe {
         // Tell the allocator to use our static array as the heap source.
-        let heap_start = HEAP_MEM.as_ptr() as usize;
-        ALLOCATOR.lock().init(heap_start as *mut u8, HEAP_SIZE);
+        let heap_start = ptr::addr_of_mut!(HEAP_MEM) as *mut u8;
+        ALLOCATOR.init(heap_start, HEAP_SIZE);
     }
 }
 
 pub fn get_heap_usage() -> (usize, usize) {
-    let heap = ALLOCATOR.lock();
-    (heap.used(), heap.size())
+    (ALLOCATOR.used(), ALLOCATOR.size())
 }
 
 // 4. ERROR HANDLING
-// If we run out of memory, this function is called.
+// If we run out of memory, this function is called. `alloc` has already killed a victim
+// and retried, so the memory really isn't there: if the caller is a task it exits, only the
+// kernel itself panics.
 #[alloc_error_handler]
 fn alloc_error_handler(layout: core::alloc::Layout) -> ! {
+    let in_task = crate::scheduler::SCHEDULER.try_lock().is_some_and(|s| s.current().is_some());
+    if in_task {
+        crate::serial_println!("[OOM] Allocation of {} bytes failed; exiting the current task", layout.size());
+        crate::scheduler::task_exit();
+    }
+
     panic!("allocation error: {:?}", layout)
-}
\ No newline at end of file
+}
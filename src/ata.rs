@@ -1,9 +1,12 @@
 use x86_64::instructions::port::Port;
 use alloc::vec::Vec;
+use spin::Mutex;
+use crate::sync::Semaphore;
 
 // PRIMARY BUS PORTS
 const DATA_PORT: u16 = 0x1F0;
 const ERROR_PORT: u16 = 0x1F1;
+const FEATURES_PORT: u16 = 0x1F1; // Same port as ERROR, on write
 const SECTOR_COUNT_PORT: u16 = 0x1F2;
 const LBA_LOW_PORT: u16 = 0x1F3;
 const LBA_MID_PORT: u16 = 0x1F4;
@@ -16,19 +19,238 @@ const STATUS_PORT: u16 = 0x1F7;
 const CMD_READ_SECTORS: u8 = 0x20;
 const CMD_WRITE_SECTORS: u8 = 0x30;
 const CMD_IDENTIFY: u8 = 0xEC;
+const CMD_SMART: u8 = 0xB0;
+
+// SMART READ DATA: the subcommand goes in FEATURES, the signature in LBA mid/high
+const SMART_READ_DATA: u8 = 0xD0;
+const SMART_LBA_MID: u8 = 0x4F;
+const SMART_LBA_HIGH: u8 = 0xC2;
+
+// --- WRITE-BEHIND CACHE ---
+// `write_sectors` only lands here; the "DiskFlush" task (or `flush_cache`) writes dirty
+// sectors out later. Clean sectors stay cached until their slot is needed.
+const CACHE_SECTORS: usize = 64;
+
+#[derive(Clone, Copy)]
+struct CacheEntry {
+    master: bool,
+    lba: u32,
+    data: [u8; 512],
+    valid: bool,
+    dirty: bool,
+}
+
+pub struct DiskCache {
+    entries: [CacheEntry; CACHE_SECTORS],
+}
+
+const EMPTY_ENTRY: CacheEntry = CacheEntry { master: true, lba: 0, data: [0; 512], valid: false, dirty: false };
+
+impl DiskCache {
+    const fn new() -> Self {
+        DiskCache { entries: [EMPTY_ENTRY; CACHE_SECTORS] }
+    }
+
+    fn find(&self, master: bool, lba: u32) -> Option<usize> {
+        self.entries.iter().position(|e| e.valid && e.master == master && e.lba == lba)
+    }
+
+    fn get(&self, master: bool, lba: u32) -> Option<&[u8; 512]> {
+        self.find(master, lba).map(|i| &self.entries[i].data)
+    }
+
+    /// Stores a dirty sector. False if every slot holds unwritten data.
+    fn insert(&mut self, master: bool, lba: u32, data: &[u8; 512]) -> bool {
+        let slot = self.find(master, lba)
+            .or_else(|| self.entries.iter().position(|e| !e.valid))
+            .or_else(|| self.entries.iter().position(|e| !e.dirty));
+        match slot {
+            Some(i) => {
+                self.entries[i] = CacheEntry { master, lba, data: *data, valid: true, dirty: true };
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn dirty_count(&self) -> usize {
+        self.entries.iter().filter(|e| e.valid && e.dirty).count()
+    }
+}
+
+static CACHE: Mutex<DiskCache> = Mutex::new(DiskCache::new());
+// Signalled when the cache goes from clean to dirty, so "DiskFlush" only wakes when there
+// is something to write
+static CACHE_DIRTIED: Semaphore = Semaphore::new(0);
+// Held for a whole flush so two flushes can't land old data after new
+static FLUSH_LOCK: Mutex<()> = Mutex::new(());
+// One complete PIO command (select, command, data transfer) at a time on the primary bus:
+// the DiskFlush task, SMART and the shell would otherwise interleave register writes
+static BUS_LOCK: Mutex<()> = Mutex::new(());
+
+/// Runs `f` owning the bus, with interrupts off so a task switch can't leave it held
+fn with_bus<R>(f: impl FnOnce() -> R) -> R {
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        let _bus = BUS_LOCK.lock();
+        f()
+    })
+}
+
+/// Dirty sectors waiting to be written, across both drives
+pub fn dirty_sectors() -> usize {
+    x86_64::instructions::interrupts::without_interrupts(|| CACHE.lock().dirty_count())
+}
+
+/// Writes every cached sector of both drives to disk (before power-off, say)
+pub fn flush_all_caches() {
+    AtaDrive::new(true).flush_cache();
+    AtaDrive::new(false).flush_cache();
+}
+
+/// Scheduler task: flushes the cache 5 seconds after it was first dirtied, letting the
+/// writes in between pile up. Blocked while the cache is clean.
+pub extern "C" fn disk_flush_task(_arg: u64) {
+    loop {
+        if dirty_sectors() == 0 {
+            CACHE_DIRTIED.wait();
+        }
+        crate::scheduler::sleep(5 * 100);
+        flush_all_caches();
+    }
+}
 
 pub struct AtaDrive {
     master: bool,
 }
 
+// --- SMART ---
+// The data block holds 30 attribute slots of 12 bytes from offset 2:
+//   [0] id, [1..3] flags, [3] value, [4] worst, [5..11] raw (LE), [11] reserved
+
+const SMART_ATTR_OFFSET: usize = 2;
+const SMART_ATTR_SIZE: usize = 12;
+const SMART_ATTR_COUNT: usize = 30;
+/// Offset of the health/offline-collection status byte
+pub const SMART_STATUS_OFFSET: usize = 362;
+
+pub struct SmartAttr {
+    pub id: u8,
+    pub name: &'static str,
+    pub value: u8,
+    pub worst: u8,
+    pub raw: u64,
+}
+
+fn smart_attr_name(id: u8) -> &'static str {
+    match id {
+        1 => "Raw Read Error Rate",
+        5 => "Reallocated Sectors",
+        9 => "Power-On Hours",
+        12 => "Power Cycle Count",
+        177 => "Wear Leveling Count",
+        190 => "Temperature",
+        194 => "Temperature Celsius",
+        197 => "Current Pending Sectors",
+        198 => "Offline Uncorrectable",
+        199 => "UDMA CRC Error Count",
+        _ => "Unknown",
+    }
+}
+
 impl AtaDrive {
     pub fn new(master: bool) -> Self {
         AtaDrive { master }
     }
 
-    /// Reads a 256-word (512 byte) sector from LBA address
+    /// Reads `sectors` sectors from LBA address, cached (unflushed) data included
     pub fn read_sectors(&self, lba: u32, sectors: u8) -> Vec<u8> {
-        unsafe {
+        let cached: Vec<Option<[u8; 512]>> = x86_64::instructions::interrupts::without_interrupts(|| {
+            let cache = CACHE.lock();
+            (0..sectors as u32).map(|i| cache.get(self.master, lba + i).copied()).collect()
+        });
+        if cached.iter().all(|c| c.is_some()) {
+            return cached.iter().flatten().flatten().copied().collect();
+        }
+
+        let mut data = self.read_from_disk(lba, sectors);
+        if data.len() == sectors as usize * 512 {
+            for (i, sector) in cached.iter().enumerate() {
+                if let Some(sector) = sector {
+                    data[i * 512..(i + 1) * 512].copy_from_slice(sector);
+                }
+            }
+        }
+        data
+    }
+
+    /// Queues `data` for writing at `lba`; a trailing partial sector is zero-padded.
+    /// Goes straight to disk (after a flush) when the cache is full of unwritten sectors.
+    pub fn write_sectors(&self, lba: u32, data: &[u8]) {
+        for (i, chunk) in data.chunks(512).enumerate() {
+            let mut sector = [0u8; 512];
+            sector[..chunk.len()].copy_from_slice(chunk);
+            let lba = lba + i as u32;
+            let (cached, was_clean) = x86_64::instructions::interrupts::without_interrupts(|| {
+                let mut cache = CACHE.lock();
+                let was_clean = cache.dirty_count() == 0;
+                (cache.insert(self.master, lba, &sector), was_clean)
+            });
+            if cached && was_clean {
+                CACHE_DIRTIED.signal();
+            }
+            if !cached {
+                self.flush_cache();
+                let cached = x86_64::instructions::interrupts::without_interrupts(|| {
+                    CACHE.lock().insert(self.master, lba, &sector)
+                });
+                if !cached && !self.write_to_disk(lba, &sector) {
+                    crate::klog_error!("[ATA] Write to LBA {} failed.\n", lba);
+                }
+            }
+        }
+    }
+
+    /// Writes this drive's dirty sectors in LBA order, consecutive ones in one command.
+    /// A sector is only marked clean once its write succeeded; failed ones stay dirty.
+    pub fn flush_cache(&self) {
+        let _flush = FLUSH_LOCK.lock();
+        // Copy out under the lock; the slow disk writes happen without it
+        let mut dirty: Vec<(u32, [u8; 512])> = x86_64::instructions::interrupts::without_interrupts(|| {
+            let cache = CACHE.lock();
+            cache.entries.iter()
+                .filter(|e| e.valid && e.dirty && e.master == self.master)
+                .map(|e| (e.lba, e.data))
+                .collect()
+        });
+        dirty.sort_unstable_by_key(|(lba, _)| *lba);
+
+        let mut i = 0;
+        while i < dirty.len() {
+            let first = i;
+            let start = dirty[i].0;
+            let mut run = Vec::new();
+            while i < dirty.len() && dirty[i].0 == start + (run.len() / 512) as u32 && run.len() < 255 * 512 {
+                run.extend_from_slice(&dirty[i].1);
+                i += 1;
+            }
+            if !self.write_to_disk(start, &run) {
+                crate::klog_error!("[ATA] Flush of LBA {}..{} failed, keeping it cached.\n", start, start + (i - first) as u32);
+                continue;
+            }
+            // Sectors rewritten while we were writing carry newer data and stay dirty
+            x86_64::instructions::interrupts::without_interrupts(|| {
+                let mut cache = CACHE.lock();
+                for (lba, data) in &dirty[first..i] {
+                    if let Some(idx) = cache.find(self.master, *lba) {
+                        if cache.entries[idx].data == *data { cache.entries[idx].dirty = false; }
+                    }
+                }
+            });
+        }
+    }
+
+    fn read_from_disk(&self, lba: u32, sectors: u8) -> Vec<u8> {
+        with_bus(|| unsafe {
             // 1. Wait for drive to be ready
             self.wait_busy();
 
@@ -67,12 +289,13 @@ impl AtaDrive {
                 }
             }
             data
-        }
+        })
     }
 
     /// Writes data to sector. Data must be multiple of 512 bytes.
-    pub fn write_sectors(&self, lba: u32, data: &[u8]) {
-        unsafe {
+    /// False if the drive reports an error (ERR or DF).
+    fn write_to_disk(&self, lba: u32, data: &[u8]) -> bool {
+        with_bus(|| unsafe {
             self.wait_busy();
             let sectors = (data.len() / 512) as u8;
 
@@ -89,6 +312,7 @@ impl AtaDrive {
             // Write Data
             for chunk in data.chunks(512) {
                 self.wait_busy();
+                if Port::<u8>::new(STATUS_PORT).read() & 0x21 != 0 { return false; }
                 self.wait_drq();
 
                 for i in (0..512).step_by(2) {
@@ -99,7 +323,11 @@ impl AtaDrive {
                 // Flush cache logic is usually needed here for real hardware
                 // Port::<u8>::new(COMMAND_PORT).write(0xE7); // Cache Flush
             }
-        }
+
+            // The last sector's status only shows once the drive has taken it
+            self.wait_busy();
+            Port::<u8>::new(STATUS_PORT).read() & 0x21 == 0
+        })
     }
 
     // Helper: Wait until BSY (Busy) bit is 0
@@ -116,29 +344,88 @@ impl AtaDrive {
         while (port.read() & 0x08) == 0 { core::hint::spin_loop(); }
     }
     
+    /// Issues SMART READ DATA and returns the 512-byte attribute block.
+    /// None if the drive aborts (SMART disabled or unsupported) or goes quiet.
+    pub fn smart_read_data(&self) -> Option<[u8; 512]> {
+        with_bus(|| unsafe {
+            self.wait_busy();
+            Port::<u8>::new(DRIVE_PORT).write(if self.master { 0xA0 } else { 0xB0 });
+            self.wait_busy();
+
+            Port::<u8>::new(FEATURES_PORT).write(SMART_READ_DATA);
+            Port::<u8>::new(SECTOR_COUNT_PORT).write(1);
+            Port::<u8>::new(LBA_LOW_PORT).write(0);
+            Port::<u8>::new(LBA_MID_PORT).write(SMART_LBA_MID);
+            Port::<u8>::new(LBA_HIGH_PORT).write(SMART_LBA_HIGH);
+            Port::<u8>::new(COMMAND_PORT).write(CMD_SMART);
+
+            let mut port = Port::<u8>::new(STATUS_PORT);
+            if port.read() == 0 { return None; } // No drive
+            self.wait_busy();
+
+            // ERR with ABRT means SMART isn't available
+            let status = port.read();
+            if status & 0x01 != 0 {
+                let _ = Port::<u8>::new(ERROR_PORT).read();
+                return None;
+            }
+            if status & 0x08 == 0 { return None; }
+
+            let mut data = [0u8; 512];
+            for i in 0..256 {
+                let word = Port::<u16>::new(DATA_PORT).read();
+                data[i * 2] = word as u8;
+                data[i * 2 + 1] = (word >> 8) as u8;
+            }
+            Some(data)
+        })
+    }
+
+    /// Decodes the attribute table from a SMART READ DATA block, skipping empty slots
+    pub fn parse_smart(data: &[u8; 512]) -> Vec<SmartAttr> {
+        let mut attrs = Vec::new();
+        for slot in 0..SMART_ATTR_COUNT {
+            let e = &data[SMART_ATTR_OFFSET + slot * SMART_ATTR_SIZE..][..SMART_ATTR_SIZE];
+            if e[0] == 0 { continue; }
+            let raw = e[5..11].iter().rev().fold(0u64, |acc, &b| (acc << 8) | b as u64);
+            attrs.push(SmartAttr { id: e[0], name: smart_attr_name(e[0]), value: e[3], worst: e[4], raw });
+        }
+        attrs
+    }
+
     // Check if drive exists via Identify
     pub fn identify(&self) -> bool {
-        unsafe {
+        self.identify_data().is_some()
+    }
+
+    /// The 256-word IDENTIFY DEVICE block, or None if no drive answers
+    pub fn identify_data(&self) -> Option<[u16; 256]> {
+        with_bus(|| unsafe {
             self.wait_busy();
             Port::<u8>::new(DRIVE_PORT).write(if self.master { 0xA0 } else { 0xB0 });
             self.wait_busy();
             Port::<u8>::new(COMMAND_PORT).write(CMD_IDENTIFY);
             
-            if Port::<u8>::new(STATUS_PORT).read() == 0 { return false; }
+            if Port::<u8>::new(STATUS_PORT).read() == 0 { return None; }
             
             // Poll until BSY clears
             let mut port = Port::<u8>::new(STATUS_PORT);
             while (port.read() & 0x80) != 0 { 
-                if (port.read() & 0x01) != 0 { return false; } // Error
+                if (port.read() & 0x01) != 0 { return None; } // Error
             }
             
             // Check Data Ready
             if (port.read() & 0x08) != 0 {
-                // Read 256 words to clear buffer
-                for _ in 0..256 { Port::<u16>::new(DATA_PORT).read(); }
-                return true;
+                let mut words = [0u16; 256];
+                for w in words.iter_mut() { *w = Port::<u16>::new(DATA_PORT).read(); }
+                return Some(words);
             }
-            false
-        }
+            None
+        })
+    }
+
+    /// Addressable sectors in LBA28 mode (IDENTIFY words 60-61); 0 without a drive
+    pub fn sector_count(&self) -> u64 {
+        self.identify_data().map_or(0, |id| (id[60] as u64) | ((id[61] as u64) << 16))
     }
 }
\ No newline at end of file
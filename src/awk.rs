@@ -0,0 +1,686 @@
+use alloc::boxed::Box;
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+
+// --- AWK ---
+// A small awk: pattern-action rules run over every line, split into whitespace fields.
+//   program   := (BEGIN action | END action | pattern [action] | action)*
+//   action    := '{' [statement ((';' | newline) statement)*] '}'
+//   statement := print [expr (',' expr)*] | name ('=' | '+=' | '-=') expr | name ('++' | '--')
+//   expr      := ||, &&, ~ !~, == != < <= > >=, concatenation, + -, * / %, unary ! -, primary
+//   primary   := number | "string" | /regex/ | $primary | NR | NF | name | '(' expr ')'
+// A bare /regex/ matches against $0. Numbers are integers. No user functions or loops.
+
+#[derive(Clone, PartialEq)]
+enum Token {
+    Num(i64),
+    Str(String),
+    Regex(String),
+    Ident(String),
+    Dollar,
+    LBrace,
+    RBrace,
+    LParen,
+    RParen,
+    Comma,
+    Semi, // ';' or a newline
+    Op(&'static str),
+}
+
+// Longest first, so "==" wins over "="
+const OPS: &[&str] = &[
+    "&&", "||", "==", "!=", "<=", ">=", "+=", "-=", "++", "--", "!~",
+    "<", ">", "+", "-", "*", "/", "%", "!", "~", "=",
+];
+
+#[derive(Clone, Copy)]
+enum BinOp { Add, Sub, Mul, Div, Mod, Concat, Eq, Ne, Lt, Le, Gt, Ge, And, Or, Match, NotMatch }
+
+enum Expr {
+    Num(i64),
+    Str(String),
+    Regex(String), // Matches $0
+    Field(Box<Expr>),
+    Var(String),
+    Neg(Box<Expr>),
+    Not(Box<Expr>),
+    Binary(Box<Expr>, BinOp, Box<Expr>),
+}
+
+enum Stmt {
+    Print(Vec<Expr>),
+    Assign(String, Option<BinOp>, Expr), // `x += e` is Assign(x, Some(Add), e)
+}
+
+struct Rule {
+    pattern: Option<Expr>,
+    action: Option<Vec<Stmt>>, // None prints the line
+}
+
+pub struct AwkProgram {
+    begin: Vec<Stmt>,
+    rules: Vec<Rule>,
+    end: Vec<Stmt>,
+}
+
+// --- LEXER ---
+
+/// Whether `/` after this token is division rather than the start of a regex
+fn ends_operand(t: &Token) -> bool {
+    matches!(t, Token::Num(_) | Token::Str(_) | Token::Ident(_) | Token::RParen)
+}
+
+fn tokenize(src: &str) -> Result<Vec<Token>, String> {
+    let chars: Vec<char> = src.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+
+        // 1. Whitespace and comments; a newline ends a statement like ';'
+        if c == '\n' {
+            tokens.push(Token::Semi);
+            i += 1;
+            continue;
+        }
+        if c.is_whitespace() {
+            i += 1;
+            continue;
+        }
+        if c == '#' {
+            while i < chars.len() && chars[i] != '\n' { i += 1; }
+            continue;
+        }
+
+        // 2. Numbers and names
+        if c.is_ascii_digit() {
+            let start = i;
+            while i < chars.len() && chars[i].is_ascii_digit() { i += 1; }
+            let text: String = chars[start..i].iter().collect();
+            tokens.push(Token::Num(text.parse().map_err(|_| format!("number {} is too large", text))?));
+            continue;
+        }
+        if c.is_ascii_alphabetic() || c == '_' {
+            let start = i;
+            while i < chars.len() && (chars[i].is_ascii_alphanumeric() || chars[i] == '_') { i += 1; }
+            tokens.push(Token::Ident(chars[start..i].iter().collect()));
+            continue;
+        }
+
+        // 3. "strings" (with \n \t \\ \" escapes) and /regexes/ (only \/ is unescaped)
+        if c == '"' || (c == '/' && !tokens.last().is_some_and(ends_operand)) {
+            let mut text = String::new();
+            i += 1;
+            loop {
+                match chars.get(i) {
+                    None => return Err(format!("unterminated {}", if c == '"' { "string" } else { "regex" })),
+                    Some(&q) if q == c => break,
+                    Some('\\') if i + 1 < chars.len() => {
+                        i += 1;
+                        match (c, chars[i]) {
+                            ('"', 'n') => text.push('\n'),
+                            ('"', 't') => text.push('\t'),
+                            ('"', e) | ('/', e @ '/') => text.push(e),
+                            (_, e) => { text.push('\\'); text.push(e); }
+                        }
+                    }
+                    Some(&ch) => text.push(ch),
+                }
+                i += 1;
+            }
+            i += 1;
+            tokens.push(if c == '"' { Token::Str(text) } else { Token::Regex(text) });
+            continue;
+        }
+
+        // 4. Punctuation and operators
+        let punct = match c {
+            '$' => Some(Token::Dollar),
+            '{' => Some(Token::LBrace),
+            '}' => Some(Token::RBrace),
+            '(' => Some(Token::LParen),
+            ')' => Some(Token::RParen),
+            ',' => Some(Token::Comma),
+            ';' => Some(Token::Semi),
+            _ => None,
+        };
+        if let Some(t) = punct {
+            tokens.push(t);
+            i += 1;
+            continue;
+        }
+        let rest: String = chars[i..(i + 2).min(chars.len())].iter().collect();
+        match OPS.iter().find(|op| rest.starts_with(**op)) {
+            Some(op) => {
+                tokens.push(Token::Op(op));
+                i += op.len();
+            }
+            None => return Err(format!("unexpected '{}'", c)),
+        }
+    }
+    Ok(tokens)
+}
+
+// --- PARSER ---
+
+// Unary operators, `$` and '(' recurse (each level passes `unary` and `primary`); past this
+// many the task stack is at risk
+const MAX_DEPTH: usize = 64;
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+    depth: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    /// Consumes the next token if it is `t`
+    fn eat(&mut self, t: &Token) -> bool {
+        if self.peek() == Some(t) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn eat_op(&mut self, op: &'static str) -> bool {
+        self.eat(&Token::Op(op))
+    }
+
+    fn skip_separators(&mut self) {
+        while self.eat(&Token::Semi) {}
+    }
+
+    fn unexpected(&self) -> String {
+        let text = match self.peek() {
+            None => return "unexpected end of program".to_string(),
+            Some(Token::Num(n)) => n.to_string(),
+            Some(Token::Str(s)) => format!("\"{}\"", s),
+            Some(Token::Regex(r)) => format!("/{}/", r),
+            Some(Token::Ident(name)) => name.clone(),
+            Some(Token::Dollar) => "$".to_string(),
+            Some(Token::LBrace) => "{".to_string(),
+            Some(Token::RBrace) => "}".to_string(),
+            Some(Token::LParen) => "(".to_string(),
+            Some(Token::RParen) => ")".to_string(),
+            Some(Token::Comma) => ",".to_string(),
+            Some(Token::Semi) => ";".to_string(),
+            Some(Token::Op(op)) => op.to_string(),
+        };
+        format!("unexpected '{}'", text)
+    }
+
+    fn program(&mut self) -> Result<AwkProgram, String> {
+        let mut program = AwkProgram { begin: Vec::new(), rules: Vec::new(), end: Vec::new() };
+        loop {
+            self.skip_separators();
+            match self.peek() {
+                None => return Ok(program),
+                Some(Token::Ident(name)) if name == "BEGIN" || name == "END" => {
+                    let is_begin = name == "BEGIN";
+                    self.pos += 1;
+                    let action = self.action()?;
+                    if is_begin { program.begin.extend(action); } else { program.end.extend(action); }
+                }
+                Some(Token::LBrace) => {
+                    let action = self.action()?;
+                    program.rules.push(Rule { pattern: None, action: Some(action) });
+                }
+                Some(_) => {
+                    let pattern = self.expr()?;
+                    let action = if self.peek() == Some(&Token::LBrace) { Some(self.action()?) } else { None };
+                    program.rules.push(Rule { pattern: Some(pattern), action });
+                }
+            }
+        }
+    }
+
+    fn action(&mut self) -> Result<Vec<Stmt>, String> {
+        if !self.eat(&Token::LBrace) { return Err(self.unexpected()); }
+        let mut stmts = Vec::new();
+        loop {
+            self.skip_separators();
+            if self.eat(&Token::RBrace) { return Ok(stmts); }
+            stmts.push(self.statement()?);
+            if !matches!(self.peek(), Some(Token::Semi) | Some(Token::RBrace)) {
+                return Err(self.unexpected());
+            }
+        }
+    }
+
+    fn statement(&mut self) -> Result<Stmt, String> {
+        let name = match self.peek() {
+            Some(Token::Ident(name)) => name.clone(),
+            _ => return Err(self.unexpected()),
+        };
+
+        // 1. print [expr, ...]
+        if name == "print" {
+            self.pos += 1;
+            let mut args = Vec::new();
+            if !matches!(self.peek(), None | Some(Token::Semi) | Some(Token::RBrace)) {
+                args.push(self.expr()?);
+                while self.eat(&Token::Comma) { args.push(self.expr()?); }
+            }
+            return Ok(Stmt::Print(args));
+        }
+
+        // 2. Assignment to a variable
+        let op = match self.tokens.get(self.pos + 1) {
+            Some(Token::Op(op @ ("=" | "+=" | "-=" | "++" | "--"))) => *op,
+            _ => {
+                self.pos += 1;
+                return Err(self.unexpected());
+            }
+        };
+        if name == "NR" || name == "NF" {
+            return Err(format!("cannot assign to {}", name));
+        }
+        self.pos += 2;
+        let value = if op == "++" || op == "--" { Expr::Num(1) } else { self.expr()? };
+        let op = match op {
+            "=" => None,
+            "+=" | "++" => Some(BinOp::Add),
+            _ => Some(BinOp::Sub),
+        };
+        Ok(Stmt::Assign(name, op, value))
+    }
+
+    fn expr(&mut self) -> Result<Expr, String> {
+        let mut lhs = self.and()?;
+        while self.eat_op("||") {
+            lhs = Expr::Binary(Box::new(lhs), BinOp::Or, Box::new(self.and()?));
+        }
+        Ok(lhs)
+    }
+
+    fn and(&mut self) -> Result<Expr, String> {
+        let mut lhs = self.matching()?;
+        while self.eat_op("&&") {
+            lhs = Expr::Binary(Box::new(lhs), BinOp::And, Box::new(self.matching()?));
+        }
+        Ok(lhs)
+    }
+
+    fn matching(&mut self) -> Result<Expr, String> {
+        let mut lhs = self.comparison()?;
+        loop {
+            let op = if self.eat_op("~") {
+                BinOp::Match
+            } else if self.eat_op("!~") {
+                BinOp::NotMatch
+            } else {
+                return Ok(lhs);
+            };
+            lhs = Expr::Binary(Box::new(lhs), op, Box::new(self.comparison()?));
+        }
+    }
+
+    /// Comparisons don't chain: `a < b < c` is an error, as in awk
+    fn comparison(&mut self) -> Result<Expr, String> {
+        let lhs = self.concat()?;
+        let op = match self.peek() {
+            Some(Token::Op("==")) => BinOp::Eq,
+            Some(Token::Op("!=")) => BinOp::Ne,
+            Some(Token::Op("<")) => BinOp::Lt,
+            Some(Token::Op("<=")) => BinOp::Le,
+            Some(Token::Op(">")) => BinOp::Gt,
+            Some(Token::Op(">=")) => BinOp::Ge,
+            _ => return Ok(lhs),
+        };
+        self.pos += 1;
+        Ok(Expr::Binary(Box::new(lhs), op, Box::new(self.concat()?)))
+    }
+
+    /// Juxtaposition: `$1 "-" $2`. Only tokens that can't continue the previous operand
+    /// start a new piece, so `$1 -1` stays a subtraction.
+    fn concat(&mut self) -> Result<Expr, String> {
+        let mut lhs = self.additive()?;
+        while matches!(self.peek(), Some(Token::Num(_) | Token::Str(_) | Token::Dollar | Token::Ident(_) | Token::LParen)) {
+            lhs = Expr::Binary(Box::new(lhs), BinOp::Concat, Box::new(self.additive()?));
+        }
+        Ok(lhs)
+    }
+
+    fn additive(&mut self) -> Result<Expr, String> {
+        let mut lhs = self.multiplicative()?;
+        loop {
+            let op = if self.eat_op("+") {
+                BinOp::Add
+            } else if self.eat_op("-") {
+                BinOp::Sub
+            } else {
+                return Ok(lhs);
+            };
+            lhs = Expr::Binary(Box::new(lhs), op, Box::new(self.multiplicative()?));
+        }
+    }
+
+    fn multiplicative(&mut self) -> Result<Expr, String> {
+        let mut lhs = self.unary()?;
+        loop {
+            let op = if self.eat_op("*") {
+                BinOp::Mul
+            } else if self.eat_op("/") {
+                BinOp::Div
+            } else if self.eat_op("%") {
+                BinOp::Mod
+            } else {
+                return Ok(lhs);
+            };
+            lhs = Expr::Binary(Box::new(lhs), op, Box::new(self.unary()?));
+        }
+    }
+
+    /// Runs `f` one nesting level deeper, refusing past MAX_DEPTH
+    fn nested(&mut self, f: fn(&mut Self) -> Result<Expr, String>) -> Result<Expr, String> {
+        if self.depth >= MAX_DEPTH { return Err("expression nested too deeply".to_string()); }
+        self.depth += 1;
+        let expr = f(self);
+        self.depth -= 1;
+        expr
+    }
+
+    fn unary(&mut self) -> Result<Expr, String> {
+        self.nested(Self::unary_inner)
+    }
+
+    fn unary_inner(&mut self) -> Result<Expr, String> {
+        if self.eat_op("-") { return Ok(Expr::Neg(Box::new(self.unary()?))); }
+        if self.eat_op("!") { return Ok(Expr::Not(Box::new(self.unary()?))); }
+        if self.eat_op("+") { return self.unary(); }
+        self.primary()
+    }
+
+    fn primary(&mut self) -> Result<Expr, String> {
+        self.nested(Self::primary_inner)
+    }
+
+    fn primary_inner(&mut self) -> Result<Expr, String> {
+        let expr = match self.peek() {
+            Some(Token::Num(n)) => Expr::Num(*n),
+            Some(Token::Str(s)) => Expr::Str(s.clone()),
+            Some(Token::Regex(r)) => Expr::Regex(r.clone()),
+            Some(Token::Ident(name)) if !matches!(name.as_str(), "BEGIN" | "END" | "print") => Expr::Var(name.clone()),
+            Some(Token::Dollar) => {
+                // `$` binds tighter than arithmetic: `$NF-1` is ($NF) - 1
+                self.pos += 1;
+                return Ok(Expr::Field(Box::new(self.primary()?)));
+            }
+            Some(Token::LParen) => {
+                self.pos += 1;
+                let inner = self.expr()?;
+                if !self.eat(&Token::RParen) { return Err(self.unexpected()); }
+                return Ok(inner);
+            }
+            _ => return Err(self.unexpected()),
+        };
+        self.pos += 1;
+        Ok(expr)
+    }
+}
+
+// --- REGEX ---
+// Pike's matcher: literal characters, `.`, `*` (repeat the previous item), `^` and `$`
+// anchors, and `\c` for a literal `c`.
+
+/// One position of a regex: the character it matches (None for `.`) and whether it is starred
+type Item = (Option<char>, bool);
+
+fn regex_match(re: &str, text: &str) -> bool {
+    // 1. Compile to items, peeling off the anchors
+    let mut chars = re.chars().peekable();
+    let anchored = chars.peek() == Some(&'^');
+    if anchored { chars.next(); }
+    let mut items: Vec<Item> = Vec::new();
+    let mut to_end = false;
+    while let Some(c) = chars.next() {
+        let item = match c {
+            '$' if chars.peek().is_none() => { to_end = true; break; }
+            '*' if !items.is_empty() && !items[items.len() - 1].1 => {
+                let last = items.len() - 1;
+                items[last].1 = true;
+                continue;
+            }
+            '\\' => Some(chars.next().unwrap_or('\\')),
+            '.' => None,
+            c => Some(c),
+        };
+        items.push((item, false));
+    }
+
+    // 2. Try every start position (only the first if anchored)
+    let text: Vec<char> = text.chars().collect();
+    let starts = if anchored { 0..=0 } else { 0..=text.len() };
+    starts.into_iter().any(|i| match_here(&items, &text[i..], to_end))
+}
+
+fn match_here(items: &[Item], text: &[char], to_end: bool) -> bool {
+    let matches = |want: Option<char>, t: Option<&char>| t.is_some_and(|t| want.is_none_or(|w| w == *t));
+    match items {
+        [] => !to_end || text.is_empty(),
+        [(want, true), rest @ ..] => {
+            // Starred: try every run length, shortest first
+            let mut i = 0;
+            loop {
+                if match_here(rest, &text[i..], to_end) { return true; }
+                if !matches(*want, text.get(i)) { return false; }
+                i += 1;
+            }
+        }
+        [(want, false), rest @ ..] => matches(*want, text.first()) && match_here(rest, &text[1..], to_end),
+    }
+}
+
+// --- INTERPRETER ---
+
+#[derive(Clone)]
+enum Value {
+    Num(i64),
+    Str(String),
+}
+
+impl Value {
+    /// Leading integer of a string ("12abc" is 12, "abc" is 0), like awk's conversion
+    fn to_num(&self) -> i64 {
+        match self {
+            Value::Num(n) => *n,
+            Value::Str(s) => {
+                let s = s.trim_start();
+                let digits = s.char_indices()
+                    .take_while(|&(i, c)| c.is_ascii_digit() || (i == 0 && (c == '-' || c == '+')))
+                    .count();
+                s[..digits].parse().unwrap_or(0)
+            }
+        }
+    }
+
+    fn to_str(&self) -> String {
+        match self {
+            Value::Num(n) => n.to_string(),
+            Value::Str(s) => s.clone(),
+        }
+    }
+
+    fn truthy(&self) -> bool {
+        match self {
+            Value::Num(n) => *n != 0,
+            Value::Str(s) => !s.is_empty(),
+        }
+    }
+
+    /// Numbers, and strings that are entirely an integer (fields like "42"), compare numerically
+    fn as_number(&self) -> Option<i64> {
+        match self {
+            Value::Num(n) => Some(*n),
+            Value::Str(s) => s.trim().parse().ok(),
+        }
+    }
+}
+
+struct Runtime<'a> {
+    vars: Vec<(String, Value)>,
+    line: &'a str,
+    fields: Vec<&'a str>,
+    nr: usize,
+    out: String,
+}
+
+impl<'a> Runtime<'a> {
+    fn set_record(&mut self, line: &'a str) {
+        self.line = line;
+        self.fields = line.split_whitespace().collect();
+        self.nr += 1;
+    }
+
+    fn get_var(&self, name: &str) -> Value {
+        match name {
+            "NR" => Value::Num(self.nr as i64),
+            "NF" => Value::Num(self.fields.len() as i64),
+            _ => self.vars.iter().find(|(n, _)| n == name).map(|(_, v)| v.clone()).unwrap_or(Value::Str(String::new())),
+        }
+    }
+
+    fn set_var(&mut self, name: &str, value: Value) {
+        match self.vars.iter_mut().find(|(n, _)| n == name) {
+            Some(entry) => entry.1 = value,
+            None => self.vars.push((name.to_string(), value)),
+        }
+    }
+
+    fn eval(&self, expr: &Expr) -> Result<Value, String> {
+        Ok(match expr {
+            Expr::Num(n) => Value::Num(*n),
+            Expr::Str(s) => Value::Str(s.clone()),
+            Expr::Regex(re) => Value::Num(regex_match(re, self.line) as i64),
+            Expr::Field(index) => {
+                let index = self.eval(index)?.to_num();
+                match index {
+                    i if i < 0 => return Err(format!("negative field index ${}", i)),
+                    0 => Value::Str(self.line.to_string()),
+                    i => Value::Str(self.fields.get(i as usize - 1).unwrap_or(&"").to_string()),
+                }
+            }
+            Expr::Var(name) => self.get_var(name),
+            Expr::Neg(inner) => Value::Num(self.eval(inner)?.to_num().wrapping_neg()),
+            Expr::Not(inner) => Value::Num(!self.eval(inner)?.truthy() as i64),
+            Expr::Binary(lhs, BinOp::And, rhs) => {
+                Value::Num((self.eval(lhs)?.truthy() && self.eval(rhs)?.truthy()) as i64)
+            }
+            Expr::Binary(lhs, BinOp::Or, rhs) => {
+                Value::Num((self.eval(lhs)?.truthy() || self.eval(rhs)?.truthy()) as i64)
+            }
+            Expr::Binary(lhs, op @ (BinOp::Match | BinOp::NotMatch), rhs) => {
+                let text = self.eval(lhs)?.to_str();
+                let re = match &**rhs {
+                    Expr::Regex(re) => re.clone(), // `$1 ~ /x/` matches $1, not $0
+                    other => self.eval(other)?.to_str(),
+                };
+                Value::Num((regex_match(&re, &text) == matches!(op, BinOp::Match)) as i64)
+            }
+            Expr::Binary(lhs, op, rhs) => {
+                let l = self.eval(lhs)?;
+                let r = self.eval(rhs)?;
+                binary(*op, &l, &r)?
+            }
+        })
+    }
+
+    fn exec(&mut self, stmts: &[Stmt]) -> Result<(), String> {
+        for stmt in stmts {
+            match stmt {
+                Stmt::Print(args) if args.is_empty() => {
+                    self.out.push_str(self.line);
+                    self.out.push('\n');
+                }
+                Stmt::Print(args) => {
+                    let mut words = Vec::with_capacity(args.len());
+                    for arg in args { words.push(self.eval(arg)?.to_str()); }
+                    self.out.push_str(&words.join(" "));
+                    self.out.push('\n');
+                }
+                Stmt::Assign(name, op, expr) => {
+                    let value = self.eval(expr)?;
+                    let value = match op {
+                        Some(op) => binary(*op, &self.get_var(name), &value)?,
+                        None => value,
+                    };
+                    self.set_var(name, value);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+fn binary(op: BinOp, l: &Value, r: &Value) -> Result<Value, String> {
+    let (a, b) = (l.to_num(), r.to_num());
+    let compare = || match (l.as_number(), r.as_number()) {
+        (Some(a), Some(b)) => a.cmp(&b),
+        _ => l.to_str().cmp(&r.to_str()),
+    };
+    Ok(match op {
+        BinOp::Add => Value::Num(a.wrapping_add(b)),
+        BinOp::Sub => Value::Num(a.wrapping_sub(b)),
+        BinOp::Mul => Value::Num(a.wrapping_mul(b)),
+        BinOp::Div | BinOp::Mod if b == 0 => return Err("division by zero".to_string()),
+        BinOp::Div => Value::Num(a.wrapping_div(b)),
+        BinOp::Mod => Value::Num(a.wrapping_rem(b)),
+        BinOp::Concat => Value::Str(l.to_str() + &r.to_str()),
+        BinOp::Eq => Value::Num(compare().is_eq() as i64),
+        BinOp::Ne => Value::Num(compare().is_ne() as i64),
+        BinOp::Lt => Value::Num(compare().is_lt() as i64),
+        BinOp::Le => Value::Num(compare().is_le() as i64),
+        BinOp::Gt => Value::Num(compare().is_gt() as i64),
+        BinOp::Ge => Value::Num(compare().is_ge() as i64),
+        BinOp::And | BinOp::Or | BinOp::Match | BinOp::NotMatch => unreachable!("short-circuit ops are handled in eval"),
+    })
+}
+
+impl AwkProgram {
+    pub fn parse(src: &str) -> Result<AwkProgram, String> {
+        let mut parser = Parser { tokens: tokenize(src)?, pos: 0, depth: 0 };
+        parser.program()
+    }
+
+    /// Runs the program over `input`, returning what it printed. A runtime error
+    /// stops it, with an `awk: ...` line after the output so far.
+    pub fn run(&self, input: &str) -> String {
+        let mut rt = Runtime { vars: Vec::new(), line: "", fields: Vec::new(), nr: 0, out: String::new() };
+        if let Err(e) = self.run_in(&mut rt, input) {
+            rt.out.push_str(&format!("awk: {}\n", e));
+        }
+        rt.out
+    }
+
+    fn run_in<'a>(&self, rt: &mut Runtime<'a>, input: &'a str) -> Result<(), String> {
+        // 1. BEGIN, before any input
+        rt.exec(&self.begin)?;
+
+        // 2. Every rule against every line
+        for line in input.lines() {
+            rt.set_record(line);
+            for rule in &self.rules {
+                let matched = match &rule.pattern {
+                    Some(pattern) => rt.eval(pattern)?.truthy(),
+                    None => true,
+                };
+                if !matched { continue; }
+                match &rule.action {
+                    Some(action) => rt.exec(action)?,
+                    None => rt.exec(&[Stmt::Print(Vec::new())])?,
+                }
+            }
+        }
+
+        // 3. END, still seeing the last record
+        rt.exec(&self.end)
+    }
+}
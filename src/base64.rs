@@ -0,0 +1,57 @@
+use alloc::string::String;
+use alloc::vec::Vec;
+
+// --- BASE64 (RFC 4648) ---
+// Every 3 input bytes become 4 six-bit symbols; a short final group is padded with '='.
+
+const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+pub fn encode(input: &[u8]) -> String {
+    let mut out = String::with_capacity(input.len().div_ceil(3) * 4);
+    for chunk in input.chunks(3) {
+        let b = [chunk[0], *chunk.get(1).unwrap_or(&0), *chunk.get(2).unwrap_or(&0)];
+        let n = ((b[0] as u32) << 16) | ((b[1] as u32) << 8) | b[2] as u32;
+        // chunk.len() + 1 symbols carry data; the rest of the group is padding
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(ALPHABET[((n >> (18 - 6 * i)) & 0x3F) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+fn symbol_value(c: u8) -> Option<u32> {
+    match c {
+        b'A'..=b'Z' => Some((c - b'A') as u32),
+        b'a'..=b'z' => Some((c - b'a' + 26) as u32),
+        b'0'..=b'9' => Some((c - b'0' + 52) as u32),
+        b'+' => Some(62),
+        b'/' => Some(63),
+        _ => None,
+    }
+}
+
+/// Whitespace anywhere is ignored; padding may only close the final group
+pub fn decode(input: &str) -> Result<Vec<u8>, &'static str> {
+    let symbols: Vec<u8> = input.bytes().filter(|c| !c.is_ascii_whitespace()).collect();
+    if !symbols.len().is_multiple_of(4) { return Err("length is not a multiple of 4"); }
+
+    let mut out = Vec::with_capacity(symbols.len() / 4 * 3);
+    let groups = symbols.len() / 4;
+    for (g, group) in symbols.chunks(4).enumerate() {
+        let pad = group.iter().rev().take_while(|&&c| c == b'=').count();
+        if pad > 2 || (pad > 0 && g + 1 != groups) { return Err("misplaced padding"); }
+
+        let mut n = 0u32;
+        for &c in &group[..4 - pad] {
+            n = (n << 6) | symbol_value(c).ok_or("invalid character")?;
+        }
+        n <<= 6 * pad as u32;
+        let bytes = [(n >> 16) as u8, (n >> 8) as u8, n as u8];
+        out.extend_from_slice(&bytes[..3 - pad]);
+    }
+    Ok(out)
+}
@@ -0,0 +1,149 @@
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use spin::Mutex;
+use lazy_static::lazy_static;
+use crate::ata::AtaDrive;
+
+// --- BLOCK DEVICES ---
+// One 512-byte-sector interface over every disk driver, so the VFS image and FAT
+// don't care what the boot disk is. `init` picks the device once: NVMe, then VirtIO,
+// then the ATA primary master.
+
+pub const SECTOR_SIZE: usize = 512;
+
+pub trait BlockDevice: Send {
+    /// `count` sectors from `lba`; empty on error
+    fn read_sectors(&self, lba: u64, count: u16) -> Vec<u8>;
+    /// Whole sectors from `data`; a partial last sector is zero-padded
+    fn write_sectors(&self, lba: u64, data: &[u8]);
+    fn sector_count(&self) -> u64;
+    /// Whether the device is present and answering
+    fn identify(&self) -> bool;
+    fn name(&self) -> &'static str;
+}
+
+impl BlockDevice for AtaDrive {
+    fn read_sectors(&self, lba: u64, count: u16) -> Vec<u8> {
+        // The ATA command carries at most 255 sectors and 28 bits of LBA
+        let mut out = Vec::with_capacity(count as usize * SECTOR_SIZE);
+        let mut done = 0u16;
+        while done < count {
+            let n = (count - done).min(255);
+            let chunk = AtaDrive::read_sectors(self, (lba + done as u64) as u32, n as u8);
+            if chunk.is_empty() { return Vec::new(); }
+            out.extend_from_slice(&chunk);
+            done += n;
+        }
+        out
+    }
+
+    fn write_sectors(&self, lba: u64, data: &[u8]) {
+        AtaDrive::write_sectors(self, lba as u32, data);
+    }
+
+    fn sector_count(&self) -> u64 {
+        AtaDrive::sector_count(self)
+    }
+
+    fn identify(&self) -> bool {
+        AtaDrive::identify(self)
+    }
+
+    fn name(&self) -> &'static str { "ATA" }
+}
+
+/// The VirtIO disk lives in `virtio_blk::BLK`; this forwards to it
+pub struct VirtioDisk;
+
+impl BlockDevice for VirtioDisk {
+    fn read_sectors(&self, lba: u64, count: u16) -> Vec<u8> {
+        let max = crate::virtio_blk::MAX_SECTORS;
+        let mut out = Vec::with_capacity(count as usize * SECTOR_SIZE);
+        let mut done = 0u16;
+        while done < count {
+            let n = (count - done).min(max);
+            let chunk = crate::virtio_blk::with_blk(|blk| blk.read_sectors(lba + done as u64, n)).unwrap_or_default();
+            if chunk.is_empty() { return Vec::new(); }
+            out.extend_from_slice(&chunk);
+            done += n;
+        }
+        out
+    }
+
+    fn write_sectors(&self, lba: u64, data: &[u8]) {
+        crate::virtio_blk::with_blk(|blk| blk.write_sectors(lba, data));
+    }
+
+    fn sector_count(&self) -> u64 {
+        crate::virtio_blk::with_blk(|blk| blk.capacity).unwrap_or(0)
+    }
+
+    fn identify(&self) -> bool {
+        self.sector_count() > 0
+    }
+
+    fn name(&self) -> &'static str { "VirtIO" }
+}
+
+/// Forwards to `nvme::NVME`. Only used for 512-byte-block namespaces.
+pub struct NvmeDisk;
+
+impl BlockDevice for NvmeDisk {
+    fn read_sectors(&self, lba: u64, count: u16) -> Vec<u8> {
+        crate::nvme::with_nvme(|nvme| nvme.read_lba(lba, count as u32)).unwrap_or_default()
+    }
+
+    fn write_sectors(&self, lba: u64, data: &[u8]) {
+        crate::nvme::with_nvme(|nvme| nvme.write_lba(lba, data));
+    }
+
+    fn sector_count(&self) -> u64 {
+        crate::nvme::with_nvme(|nvme| nvme.capacity).unwrap_or(0)
+    }
+
+    fn identify(&self) -> bool {
+        self.sector_count() > 0
+    }
+
+    fn name(&self) -> &'static str { "NVMe" }
+}
+
+lazy_static! {
+    pub static ref BLOCK_DEVICE: Mutex<Option<Box<dyn BlockDevice>>> = Mutex::new(None);
+}
+
+/// Picks the boot disk: NVMe, then VirtIO, then ATA
+pub fn init() {
+    let nvme_ok = crate::nvme::with_nvme(|nvme| nvme.block_size == SECTOR_SIZE).unwrap_or(false);
+    let device: Option<Box<dyn BlockDevice>> = if nvme_ok {
+        Some(Box::new(NvmeDisk))
+    } else if VirtioDisk.identify() {
+        Some(Box::new(VirtioDisk))
+    } else if AtaDrive::new(true).identify() {
+        Some(Box::new(AtaDrive::new(true)))
+    } else {
+        None
+    };
+    match &device {
+        Some(dev) => crate::klog_info!("[BLOCK] Using {} disk, {} sectors.\n", dev.name(), dev.sector_count()),
+        None => crate::klog_warn!("[BLOCK] No disk found.\n"),
+    }
+    *BLOCK_DEVICE.lock() = device;
+}
+
+/// Reads from the boot disk; empty if there is none
+pub fn read_sectors(lba: u64, count: u16) -> Vec<u8> {
+    BLOCK_DEVICE.lock().as_ref().map(|dev| dev.read_sectors(lba, count)).unwrap_or_default()
+}
+
+/// Writes to the boot disk; dropped if there is none
+pub fn write_sectors(lba: u64, data: &[u8]) {
+    if let Some(dev) = BLOCK_DEVICE.lock().as_ref() {
+        dev.write_sectors(lba, data);
+    }
+}
+
+/// Whether a boot disk was found and still answers
+pub fn present() -> bool {
+    BLOCK_DEVICE.lock().as_ref().is_some_and(|dev| dev.identify())
+}
@@ -0,0 +1,130 @@
+use alloc::string::{String, ToString};
+use alloc::format;
+
+// --- INTEGER CALCULATOR ---
+// Recursive descent over:
+//   expr   := term (('+' | '-') term)*
+//   term   := factor (('*' | '/' | '%') factor)*
+//   factor := '-' factor | '(' expr ')' | number
+// Numbers are decimal, 0x hex or 0b binary. Arithmetic is i64 and overflow is an error.
+
+// Nested '(' and unary '-' recurse; past this many the task stack is at risk
+const MAX_DEPTH: usize = 64;
+
+pub struct Parser<'a> {
+    input: &'a str,
+    pos: usize,
+    depth: usize,
+}
+
+impl<'a> Parser<'a> {
+    pub fn new(input: &'a str) -> Self {
+        Parser { input, pos: 0, depth: 0 }
+    }
+
+    fn skip_spaces(&mut self) {
+        while self.peek() == Some(b' ') || self.peek() == Some(b'\t') { self.pos += 1; }
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.input.as_bytes().get(self.pos).copied()
+    }
+
+    /// Next non-space byte, left in place
+    fn next_token(&mut self) -> Option<u8> {
+        self.skip_spaces();
+        self.peek()
+    }
+
+    fn unexpected(&self) -> String {
+        match self.input[self.pos..].chars().next() {
+            Some(c) => format!("unexpected '{}' at position {}", c, self.pos + 1),
+            None => "unexpected end of expression".to_string(),
+        }
+    }
+
+    pub fn expr(&mut self) -> Result<i64, String> {
+        let mut value = self.term()?;
+        loop {
+            let op = match self.next_token() {
+                Some(op @ (b'+' | b'-')) => op,
+                _ => return Ok(value),
+            };
+            self.pos += 1;
+            let rhs = self.term()?;
+            value = if op == b'+' { value.checked_add(rhs) } else { value.checked_sub(rhs) }
+                .ok_or_else(|| "overflow".to_string())?;
+        }
+    }
+
+    pub fn term(&mut self) -> Result<i64, String> {
+        let mut value = self.factor()?;
+        loop {
+            let op = match self.next_token() {
+                Some(op @ (b'*' | b'/' | b'%')) => op,
+                _ => return Ok(value),
+            };
+            self.pos += 1;
+            let rhs = self.factor()?;
+            if op != b'*' && rhs == 0 { return Err("division by zero".to_string()); }
+            value = match op {
+                b'*' => value.checked_mul(rhs),
+                b'/' => value.checked_div(rhs),
+                _ => value.checked_rem(rhs),
+            }.ok_or_else(|| "overflow".to_string())?;
+        }
+    }
+
+    pub fn factor(&mut self) -> Result<i64, String> {
+        if self.depth >= MAX_DEPTH { return Err("expression nested too deeply".to_string()); }
+        self.depth += 1;
+        let value = self.factor_inner();
+        self.depth -= 1;
+        value
+    }
+
+    fn factor_inner(&mut self) -> Result<i64, String> {
+        match self.next_token() {
+            Some(b'-') => {
+                self.pos += 1;
+                self.factor()?.checked_neg().ok_or_else(|| "overflow".to_string())
+            }
+            Some(b'(') => {
+                self.pos += 1;
+                let value = self.expr()?;
+                if self.next_token() != Some(b')') { return Err(self.unexpected()); }
+                self.pos += 1;
+                Ok(value)
+            }
+            Some(b'0'..=b'9') => self.number(),
+            _ => Err(self.unexpected()),
+        }
+    }
+
+    fn number(&mut self) -> Result<i64, String> {
+        let rest = &self.input[self.pos..];
+        let (radix, prefix) = if rest.starts_with("0x") || rest.starts_with("0X") {
+            (16, 2)
+        } else if rest.starts_with("0b") || rest.starts_with("0B") {
+            (2, 2)
+        } else {
+            (10, 0)
+        };
+        let start = self.pos + prefix;
+        let len = self.input[start..].bytes().take_while(|b| (*b as char).is_digit(radix)).count();
+        if len == 0 {
+            self.pos = start;
+            return Err(self.unexpected());
+        }
+        self.pos = start + len;
+        i64::from_str_radix(&self.input[start..start + len], radix).map_err(|_| "number too large".to_string())
+    }
+}
+
+/// Evaluates a whole expression; trailing input is an error
+pub fn eval(input: &str) -> Result<i64, String> {
+    let mut parser = Parser::new(input);
+    let value = parser.expr()?;
+    if parser.next_token().is_some() { return Err(parser.unexpected()); }
+    Ok(value)
+}
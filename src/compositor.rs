@@ -9,6 +9,10 @@ const TITLE_COLOR: u32 = 0xFF000080;  // Navy Blue
 const CONTENT_COLOR: u32 = 0xFF000000; // Black
 pub const BORDER_WIDTH: usize = 2;
 pub const TITLE_HEIGHT: usize = 20;
+pub const RESIZE_GRIP: usize = 12; // Bottom-right drag zone for resizing
+pub const MIN_WIDTH: usize = 200;
+pub const MIN_HEIGHT: usize = 150;
+const LINE_CACHE_MAX: usize = 1000; // Scrollback depth, in logical lines
 
 pub struct Window {
     pub x: usize,
@@ -22,6 +26,8 @@ pub struct Window {
     pub title: alloc::string::String,
     // New Fields for Window Management
     pub maximized: bool,
+    pub minimized: bool, // Hidden; restored from its taskbar button
+    pub workspace: usize, // Only drawn while this workspace (0..WORKSPACES) is active
     pub saved_rect: Option<(usize, usize, usize, usize)>, // x, y, w, h
     pub text_buffer: alloc::string::String,
     pub cursor_visible: bool,
@@ -29,6 +35,13 @@ pub struct Window {
     pub selection_end: Option<usize>,
     pub is_selecting: bool,
     pub border_color: u32,
+    pub alpha: u8, // 255 = opaque, 0 = fully transparent
+    pub current_fg_color: u32, // Text colour, changed by ANSI SGR sequences
+    // Scrollback: every logical line printed (last one still in progress) and how many
+    // lines the view is scrolled back from the bottom (0 = live)
+    pub line_cache: Vec<alloc::string::String>,
+    pub scroll_offset: usize,
+    replaying: bool, // Redrawing from line_cache: don't record the text again
 }
 
 impl Window {
@@ -41,6 +54,8 @@ impl Window {
             cursor_y: TITLE_HEIGHT + 4,
             title: alloc::string::String::from(title),
             maximized: false,
+            minimized: false,
+            workspace: 0,
             saved_rect: None,
             text_buffer: alloc::string::String::new(),
             cursor_visible: true,
@@ -48,6 +63,11 @@ impl Window {
             selection_end: None,
             is_selecting: false,
             border_color: BORDER_COLOR,
+            alpha: 255,
+            current_fg_color: writer::DEFAULT_FG,
+            line_cache: vec![alloc::string::String::new()],
+            scroll_offset: 0,
+            replaying: false,
         };
         
         win.draw_decorations();
@@ -80,6 +100,32 @@ impl Window {
         // Maximize Button [ ]
         let max_x = close_x - btn_w - 4;
         self.draw_rect(max_x, btn_y, btn_w, btn_h, 0xFFCCCCCC); // Grey
+
+        // Minimize Button [_]
+        let min_x = max_x - btn_w - 4;
+        self.draw_rect(min_x, btn_y, btn_w, btn_h, 0xFFFFCC00); // Yellow
+
+        // 4. Title text, cut short before it reaches the buttons
+        let text_x = BORDER_WIDTH + 4;
+        let max_chars = min_x.saturating_sub(text_x + 4) / 9;
+        let title: alloc::string::String = self.title.chars().take(max_chars).collect();
+        self.print_fixed(text_x, BORDER_WIDTH + 2, &title, 0xFFFFFFFF);
+
+        // 5. Resize grip: grey triangle filling the bottom-right corner
+        for i in 0..RESIZE_GRIP {
+            let row = self.height - RESIZE_GRIP + i;
+            self.draw_rect(self.width - i - 1, row, i + 1, 1, 0xFF808080);
+        }
+    }
+
+    /// Renames the window and redraws its frame; the content area is left as it is
+    pub fn set_title(&mut self, new_title: &str) {
+        self.title = alloc::string::String::from(new_title);
+        self.draw_decorations();
+    }
+
+    pub fn set_alpha(&mut self, a: u8) {
+        self.alpha = a;
     }
 
     pub fn set_load_color(&mut self, usage_percent: usize) {
@@ -114,6 +160,60 @@ impl Window {
         self.cursor_x = BORDER_WIDTH + 4;
         self.cursor_y = TITLE_HEIGHT + 4;
         self.text_buffer.clear();
+        self.line_cache = vec![alloc::string::String::new()];
+        self.scroll_offset = 0;
+    }
+
+    // --- SCROLLBACK ---
+
+    /// Records printed text into the line cache, dropping the oldest lines past the cap
+    fn cache_char(&mut self, c: char) {
+        if c == '\n' {
+            self.line_cache.push(alloc::string::String::new());
+            if self.line_cache.len() > LINE_CACHE_MAX {
+                self.line_cache.remove(0);
+            }
+        } else if let Some(line) = self.line_cache.last_mut() {
+            line.push(c);
+        }
+    }
+
+    pub fn scroll_up(&mut self, lines: usize) {
+        let max = self.line_cache.len().saturating_sub(1);
+        let offset = core::cmp::min(self.scroll_offset + lines, max);
+        if offset != self.scroll_offset {
+            self.scroll_offset = offset;
+            self.render_scrollback();
+        }
+    }
+
+    pub fn scroll_down(&mut self, lines: usize) {
+        if self.scroll_offset == 0 { return; }
+        self.scroll_offset = self.scroll_offset.saturating_sub(lines);
+        self.render_scrollback();
+    }
+
+    /// Redraws the content area from the line cache, ending `scroll_offset` lines above the
+    /// newest one. Pixels only: `text_buffer` (and the caller's prompt bookkeeping) is untouched.
+    fn render_scrollback(&mut self) {
+        let bottom_margin = if self.title.starts_with("Nano - ") { 55 } else { BORDER_WIDTH };
+        let visible = (self.height - bottom_margin).saturating_sub(TITLE_HEIGHT + 4) / 18;
+        let end = self.line_cache.len() - self.scroll_offset;
+        let start = end.saturating_sub(visible);
+
+        self.draw_rect(BORDER_WIDTH, TITLE_HEIGHT, self.width - 2 * BORDER_WIDTH, self.height - TITLE_HEIGHT - BORDER_WIDTH, CONTENT_COLOR);
+        self.cursor_x = BORDER_WIDTH + 4;
+        self.cursor_y = TITLE_HEIGHT + 4;
+
+        let lines: Vec<alloc::string::String> = self.line_cache[start..end].to_vec();
+        self.replaying = true;
+        // Colour changes are kept in the cache, but one from above the view is lost
+        self.current_fg_color = writer::DEFAULT_FG;
+        for (i, line) in lines.iter().enumerate() {
+            self.draw_text(line);
+            if i + 1 < lines.len() { self.draw_char('\n'); }
+        }
+        self.replaying = false;
     }
 
     // Only clear the Black Area, don't wipe the borders!
@@ -146,6 +246,14 @@ impl Window {
         let chars: alloc::vec::Vec<char> = self.text_buffer.chars().collect();
         if len < chars.len() {
             self.text_buffer = chars[..len].iter().collect();
+            // Un-record the same characters from the scrollback
+            for c in chars[len..].iter().rev() {
+                if *c == '\n' {
+                    if self.line_cache.len() > 1 { self.line_cache.pop(); }
+                } else if let Some(line) = self.line_cache.last_mut() {
+                    line.pop();
+                }
+            }
         }
     }
 
@@ -162,13 +270,19 @@ impl Window {
         let bottom_margin = if self.title.starts_with("Nano - ") { 55 } else { BORDER_WIDTH };
         match c {
             '\n' => {
-                self.text_buffer.push(c);
+                if !self.replaying {
+                    self.text_buffer.push(c);
+                    self.cache_char(c);
+                }
                 self.cursor_x = BORDER_WIDTH + 4;
                 self.cursor_y += 18;
             }
             '\r' => {
                 self.cursor_x = BORDER_WIDTH + 4;
             }
+            '\x07' => { // BEL: ring the PC speaker, draw nothing
+                if !self.replaying { crate::speaker::beep(880, 100); }
+            }
             '\x08' => { // Backspace (Visual only, buffer handled by caller usually)
                 if self.cursor_x >= (BORDER_WIDTH + 4 + 9) {
                     self.cursor_x -= 9;
@@ -176,8 +290,9 @@ impl Window {
                 }
             }
             _ => {
-                if c >= ' ' {
+                if c >= ' ' && !self.replaying {
                     self.text_buffer.push(c);
+                    self.cache_char(c);
                 }
                 let raster = get_raster(c, FontWeight::Regular, RasterHeight::Size16).unwrap_or(
                     get_raster('?', FontWeight::Regular, RasterHeight::Size16).unwrap()
@@ -191,7 +306,7 @@ impl Window {
                             // Bounds Check
                             if px < self.width && py < self.height {
                                 let idx = py * self.width + px;
-                                self.data[idx] = 0xFFFFFFFF; // White Text
+                                self.data[idx] = self.current_fg_color;
                             }
                         }
                     }
@@ -211,9 +326,28 @@ impl Window {
     }
 
     pub fn print(&mut self, text: &str) {
-        for c in text.chars() {
-            self.draw_char(c);
+        // New output always shows up in the live view
+        if self.scroll_offset > 0 {
+            self.scroll_offset = 0;
+            self.render_scrollback();
+        }
+        self.draw_text(text);
+    }
+
+    /// Draws `text`, applying `\x1b[..m` colour sequences instead of printing them.
+    /// The sequences go into the line cache (not `text_buffer`) so scrollback keeps colour.
+    fn draw_text(&mut self, text: &str) {
+        let mut rest = text;
+        while let Some(pos) = rest.find('\x1b') {
+            for c in rest[..pos].chars() { self.draw_char(c); }
+            let (len, color) = writer::parse_escape(&rest[pos..], self.current_fg_color);
+            self.current_fg_color = color;
+            if !self.replaying {
+                if let Some(line) = self.line_cache.last_mut() { line.push_str(&rest[pos..pos + len]); }
+            }
+            rest = &rest[pos + len..];
         }
+        for c in rest.chars() { self.draw_char(c); }
     }
 
     pub fn draw_char_no_buf(&mut self, c: char) {
@@ -305,7 +439,13 @@ impl Window {
         rel_y < TITLE_HEIGHT
     }
 
-    // Returns: 0 = None, 1 = Close, 2 = Maximize
+    // Check if clicking the resize grip
+    pub fn resize_corner_contains(&self, px: usize, py: usize) -> bool {
+        if !self.contains(px, py) { return false; }
+        px - self.x >= self.width - RESIZE_GRIP && py - self.y >= self.height - RESIZE_GRIP
+    }
+
+    // Returns: 0 = None, 1 = Close, 2 = Maximize, 3 = Minimize
     pub fn handle_title_bar_click(&self, px: usize, py: usize) -> u8 {
         if !self.is_title_bar(px, py) { return 0; }
         
@@ -318,12 +458,18 @@ impl Window {
         let max_x_start = close_x_start - btn_w - 4;
         let max_x_end = max_x_start + btn_w;
 
+        let min_x_start = max_x_start - btn_w - 4;
+        let min_x_end = min_x_start + btn_w;
+
         if rel_x >= close_x_start && rel_x <= close_x_end {
             return 1; // Close
         }
         if rel_x >= max_x_start && rel_x <= max_x_end {
             return 2; // Maximize
         }
+        if rel_x >= min_x_start && rel_x <= min_x_end {
+            return 3; // Minimize
+        }
         0
     }
 
@@ -429,6 +575,51 @@ impl Window {
 trait AsI32 { fn as_i32(self) -> i32; }
 impl AsI32 for usize { fn as_i32(self) -> i32 { self as i32 } }
 
+// --- CONTEXT MENU ---
+pub const MENU_ROW_HEIGHT: usize = 18;
+const MENU_BG: u32 = 0xFF202030;
+const MENU_TEXT: u32 = 0xFFFFFFFF;
+
+/// A borderless popup listing one item per row
+pub struct ContextMenu {
+    pub window: Window,
+    pub items: Vec<alloc::string::String>,
+}
+
+impl ContextMenu {
+    pub fn new(x: usize, y: usize, items: &[&str]) -> Self {
+        let longest = items.iter().map(|s| s.chars().count()).max().unwrap_or(0);
+        let w = longest * 9 + 2 * BORDER_WIDTH + 16;
+        let h = items.len() * MENU_ROW_HEIGHT + 2 * BORDER_WIDTH;
+        let mut window = Window::new(x, y, w, h, "Context Menu");
+        window.draw_rect(0, 0, w, h, BORDER_COLOR);
+        window.draw_rect(BORDER_WIDTH, BORDER_WIDTH, w - 2 * BORDER_WIDTH, h - 2 * BORDER_WIDTH, MENU_BG);
+        for (i, item) in items.iter().enumerate() {
+            window.print_fixed(BORDER_WIDTH + 8, BORDER_WIDTH + i * MENU_ROW_HEIGHT + 1, item, MENU_TEXT);
+        }
+        window.cursor_visible = false;
+        ContextMenu { window, items: items.iter().map(|s| alloc::string::String::from(*s)).collect() }
+    }
+
+    /// Index of the row under (px, py), if any
+    pub fn item_at(&self, px: usize, py: usize) -> Option<usize> {
+        if !self.window.contains(px, py) { return None; }
+        let row = (py - self.window.y).checked_sub(BORDER_WIDTH)? / MENU_ROW_HEIGHT;
+        if row < self.items.len() { Some(row) } else { None }
+    }
+}
+
+/// Binary PPM (P6): a text header, then 3 bytes of RGB per 0x00RRGGBB pixel
+pub fn encode_ppm(pixels: &[u32], width: usize, height: usize) -> Vec<u8> {
+    let header = alloc::format!("P6\n{} {}\n255\n", width, height);
+    let mut out = Vec::with_capacity(header.len() + width * height * 3);
+    out.extend_from_slice(header.as_bytes());
+    for &p in &pixels[..width * height] {
+        out.extend_from_slice(&[(p >> 16) as u8, (p >> 8) as u8, p as u8]);
+    }
+    out
+}
+
 pub struct Compositor {
     width: usize,
     height: usize,
@@ -436,6 +627,17 @@ pub struct Compositor {
     pub frame_count: u64,
 }
 
+/// Per-channel `src * a + dst * (255 - a)`, rounded, in integer math
+fn blend(src: u32, dst: u32, a: u32) -> u32 {
+    let mut out = 0;
+    for shift in [0, 8, 16] {
+        let s = (src >> shift) & 0xFF;
+        let d = (dst >> shift) & 0xFF;
+        out |= ((s * a + d * (255 - a) + 127) / 255) << shift;
+    }
+    out
+}
+
 impl Compositor {
     pub fn new(width: usize, height: usize) -> Self {
         let size = width * height;
@@ -448,6 +650,7 @@ impl Compositor {
         self.backbuffer.fill(0x00102040); // Clear to Blue
 
         for (i, win) in windows.iter().enumerate() {
+            if win.minimized { continue; }
             // Draw window content
             for row in 0..win.height {
                 for col in 0..win.width {
@@ -457,7 +660,11 @@ impl Compositor {
                     if screen_x < self.width && screen_y < self.height {
                         let idx = screen_y * self.width + screen_x;
                         let win_idx = row * win.width + col;
-                        self.backbuffer[idx] = win.data[win_idx];
+                        if win.alpha == 255 {
+                            self.backbuffer[idx] = win.data[win_idx];
+                        } else {
+                            self.backbuffer[idx] = blend(win.data[win_idx], self.backbuffer[idx], win.alpha as u32);
+                        }
                     }
                 }
             }
@@ -548,15 +755,18 @@ impl Compositor {
             }
         }
 
-        // Flip
-        if let Some(mut w) = writer::WRITER.lock().as_mut() {
-            unsafe {
-                core::ptr::copy_nonoverlapping(
-                    self.backbuffer.as_ptr(),
-                    w.video_ptr,
-                    self.backbuffer.len()
-                );
+        // Keep a copy for `screenshot`; same size every frame, so no reallocation
+        {
+            let mut last = crate::state::LAST_FRAME.lock();
+            if last.len() != self.backbuffer.len() {
+                last.resize(self.backbuffer.len(), 0);
             }
+            last.copy_from_slice(&self.backbuffer);
+        }
+
+        // Flip
+        if let Some(w) = writer::WRITER.lock().as_mut() {
+            w.blit(&self.backbuffer, self.width, self.height);
         }
     }
 }
\ No newline at end of file
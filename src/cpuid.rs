@@ -0,0 +1,155 @@
+use core::arch::x86_64::__cpuid_count;
+use core::sync::atomic::Ordering;
+use crate::state;
+
+/// Raw CPUID: (eax, ebx, ecx, edx)
+pub fn cpuid(leaf: u32, subleaf: u32) -> (u32, u32, u32, u32) {
+    let r = __cpuid_count(leaf, subleaf);
+    (r.eax, r.ebx, r.ecx, r.edx)
+}
+
+/// What the CPU reports. Note AVX/AVX2 also need the OS to enable XSAVE state before use.
+#[derive(Clone, Copy, Default)]
+pub struct CpuFeatures {
+    pub rdtsc: bool,
+    pub sse: bool,
+    pub sse2: bool,
+    pub sse3: bool,
+    pub ssse3: bool,
+    pub sse4_1: bool,
+    pub sse4_2: bool,
+    pub avx: bool,
+    pub avx2: bool,
+    pub aes_ni: bool,
+    pub rdrand: bool,
+    pub x2apic: bool,
+    pub tsc_deadline: bool,
+    pub fsgsbase: bool,
+    pub smep: bool,
+    pub smap: bool,
+    pub nx: bool,
+}
+
+impl CpuFeatures {
+    /// (name, present) pairs in display order; also the bit order of `state::CPU_FEATURES`
+    pub fn list(&self) -> [(&'static str, bool); 17] {
+        [
+            ("rdtsc", self.rdtsc), ("sse", self.sse), ("sse2", self.sse2), ("sse3", self.sse3),
+            ("ssse3", self.ssse3), ("sse4_1", self.sse4_1), ("sse4_2", self.sse4_2), ("avx", self.avx),
+            ("avx2", self.avx2), ("aes", self.aes_ni), ("rdrand", self.rdrand), ("x2apic", self.x2apic),
+            ("tsc_deadline", self.tsc_deadline), ("fsgsbase", self.fsgsbase), ("smep", self.smep),
+            ("smap", self.smap), ("nx", self.nx),
+        ]
+    }
+
+    fn bits(&self) -> u32 {
+        self.list().iter().enumerate().fold(0, |acc, (i, (_, on))| acc | ((*on as u32) << i))
+    }
+}
+
+pub struct CpuInfo {
+    pub vendor: [u8; 12],
+    pub brand: [u8; 48],
+    pub family: u8,
+    pub model: u8,
+    pub stepping: u8,
+    pub features: CpuFeatures,
+}
+
+impl CpuInfo {
+    pub fn vendor_str(&self) -> &str {
+        core::str::from_utf8(&self.vendor).unwrap_or("?")
+    }
+
+    pub fn brand_str(&self) -> &str {
+        core::str::from_utf8(&self.brand).unwrap_or("?").trim_matches(|c: char| c == '\0' || c == ' ')
+    }
+}
+
+fn bit(reg: u32, n: u32) -> bool {
+    reg & (1 << n) != 0
+}
+
+/// Queries leaves 0, 1, 7, 0x80000001 and the 0x80000002-4 brand string
+pub fn read() -> CpuInfo {
+    // 1. Vendor: EBX, EDX, ECX of leaf 0
+    let (max_leaf, b, c, d) = cpuid(0, 0);
+    let mut vendor = [0u8; 12];
+    vendor[0..4].copy_from_slice(&b.to_le_bytes());
+    vendor[4..8].copy_from_slice(&d.to_le_bytes());
+    vendor[8..12].copy_from_slice(&c.to_le_bytes());
+
+    // 2. Signature and the basic feature flags
+    let (sig, _, ecx1, edx1) = cpuid(1, 0);
+    let base_family = ((sig >> 8) & 0xF) as u8;
+    let base_model = ((sig >> 4) & 0xF) as u8;
+    let family = if base_family == 0xF { base_family + ((sig >> 20) & 0xFF) as u8 } else { base_family };
+    let model = if base_family == 0x6 || base_family == 0xF {
+        (((sig >> 16) & 0xF) as u8) << 4 | base_model
+    } else {
+        base_model
+    };
+
+    let ebx7 = if max_leaf >= 7 { cpuid(7, 0).1 } else { 0 };
+    let max_ext = cpuid(0x8000_0000, 0).0;
+    let edx_ext = if max_ext >= 0x8000_0001 { cpuid(0x8000_0001, 0).3 } else { 0 };
+
+    let features = CpuFeatures {
+        rdtsc: bit(edx1, 4),
+        sse: bit(edx1, 25),
+        sse2: bit(edx1, 26),
+        sse3: bit(ecx1, 0),
+        ssse3: bit(ecx1, 9),
+        sse4_1: bit(ecx1, 19),
+        sse4_2: bit(ecx1, 20),
+        x2apic: bit(ecx1, 21),
+        tsc_deadline: bit(ecx1, 24),
+        aes_ni: bit(ecx1, 25),
+        avx: bit(ecx1, 28),
+        rdrand: bit(ecx1, 30),
+        fsgsbase: bit(ebx7, 0),
+        avx2: bit(ebx7, 5),
+        smep: bit(ebx7, 7),
+        smap: bit(ebx7, 20),
+        nx: bit(edx_ext, 20),
+    };
+
+    // 3. Brand string: 16 bytes from each of three leaves
+    let mut brand = [0u8; 48];
+    if max_ext >= 0x8000_0004 {
+        for (i, leaf) in (0x8000_0002..=0x8000_0004u32).enumerate() {
+            let (a, b, c, d) = cpuid(leaf, 0);
+            for (j, reg) in [a, b, c, d].iter().enumerate() {
+                let at = i * 16 + j * 4;
+                brand[at..at + 4].copy_from_slice(&reg.to_le_bytes());
+            }
+        }
+    }
+
+    CpuInfo { vendor, brand, family, model, stepping: (sig & 0xF) as u8, features }
+}
+
+/// Caches the feature flags for the `has_*` checks. Called once from `_start`, before the heap exists.
+pub fn init() {
+    state::CPU_FEATURES.store(read().features.bits(), Ordering::Relaxed);
+    crate::serial_print!("[CPU] AVX2: {}, AES-NI: {}\n",
+        if has_avx2() { "yes" } else { "no" }, if has_aes_ni() { "yes" } else { "no" });
+}
+
+fn has(name: &str) -> bool {
+    let bits = state::CPU_FEATURES.load(Ordering::Relaxed);
+    CpuFeatures::default().list().iter().position(|(n, _)| *n == name)
+        .is_some_and(|i| bits & (1 << i) != 0)
+}
+
+pub fn has_avx2() -> bool {
+    has("avx2")
+}
+
+pub fn has_aes_ni() -> bool {
+    has("aes")
+}
+
+pub fn has_nx() -> bool {
+    has("nx")
+}
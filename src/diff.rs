@@ -0,0 +1,153 @@
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use alloc::format;
+
+// --- LINE DIFF ---
+// Myers' O((N+M)·D) greedy algorithm. Each round d extends every diagonal k = x - y
+// as far as the common "snake" reaches; the furthest x per diagonal is kept in V.
+// Only the window of V that backtracking reads (diagonals -(d+1)..=d+1) is saved per
+// round, so the trace costs O(D²) rather than O(D·(N+M)).
+
+/// Largest input (per side) `diff` will accept
+pub const MAX_LINES: usize = 500;
+
+/// Lines of context around each change in unified output
+const CONTEXT: usize = 3;
+
+#[derive(Clone, PartialEq)]
+pub enum DiffLine {
+    Removed(String),
+    Added(String),
+    Equal(String),
+}
+
+/// Edit script turning `a` into `b`
+pub fn diff(a: &[&str], b: &[&str]) -> Vec<DiffLine> {
+    let (n, m) = (a.len() as isize, b.len() as isize);
+    let max = n + m;
+    if max == 0 { return Vec::new(); }
+
+    // V is indexed by diagonal k, offset so that k = -max lands on 0
+    let off = max + 1;
+    let mut v: Vec<isize> = alloc::vec![0; (2 * max + 3) as usize];
+    let mut trace: Vec<Vec<isize>> = Vec::new();
+
+    // 1. Forward pass: find the shortest edit distance, snapshotting V each round
+    'search: for d in 0..=max {
+        let lo = (off - d - 1) as usize;
+        let hi = (off + d + 1) as usize;
+        trace.push(v[lo..=hi].to_vec());
+
+        let mut k = -d;
+        while k <= d {
+            let i = (off + k) as usize;
+            let mut x = if k == -d || (k != d && v[i - 1] < v[i + 1]) {
+                v[i + 1] // Step down: insertion from b
+            } else {
+                v[i - 1] + 1 // Step right: deletion from a
+            };
+            let mut y = x - k;
+            while x < n && y < m && a[x as usize] == b[y as usize] {
+                x += 1;
+                y += 1;
+            }
+            v[i] = x;
+            if x >= n && y >= m { break 'search; }
+            k += 2;
+        }
+    }
+
+    // 2. Backtrack from (n, m) through the saved rounds
+    let mut out = Vec::new();
+    let (mut x, mut y) = (n, m);
+    for (d, saved) in trace.iter().enumerate().rev() {
+        let d = d as isize;
+        // saved[0] is diagonal -(d+1)
+        let at = |k: isize| saved[(k + d + 1) as usize];
+        let k = x - y;
+        let prev_k = if k == -d || (k != d && at(k - 1) < at(k + 1)) { k + 1 } else { k - 1 };
+        let prev_x = at(prev_k);
+        let prev_y = prev_x - prev_k;
+
+        while x > prev_x && y > prev_y {
+            out.push(DiffLine::Equal(a[(x - 1) as usize].to_string()));
+            x -= 1;
+            y -= 1;
+        }
+        if d > 0 {
+            if x == prev_x {
+                out.push(DiffLine::Added(b[(y - 1) as usize].to_string()));
+            } else {
+                out.push(DiffLine::Removed(a[(x - 1) as usize].to_string()));
+            }
+        }
+        x = prev_x;
+        y = prev_y;
+    }
+    out.reverse();
+    out
+}
+
+/// Plain listing: every line with its ` `/`-`/`+` marker
+pub fn format_normal(lines: &[DiffLine]) -> String {
+    let mut out = String::new();
+    for line in lines {
+        let (mark, text) = match line {
+            DiffLine::Removed(t) => ('-', t),
+            DiffLine::Added(t) => ('+', t),
+            DiffLine::Equal(t) => (' ', t),
+        };
+        out.push(mark);
+        out.push_str(text);
+        out.push('\n');
+    }
+    out
+}
+
+/// Unified format: changes grouped into `@@ -a,b +c,d @@` hunks with 3 lines of context
+pub fn format_unified(lines: &[DiffLine], name_a: &str, name_b: &str) -> String {
+    let mut out = String::new();
+    let changed: Vec<usize> = lines.iter().enumerate()
+        .filter(|(_, l)| !matches!(l, DiffLine::Equal(_)))
+        .map(|(i, _)| i)
+        .collect();
+    if changed.is_empty() { return out; }
+
+    out.push_str(&format!("--- {}\n+++ {}\n", name_a, name_b));
+
+    // 1. Merge changes whose context windows touch into one hunk
+    let mut hunks: Vec<(usize, usize)> = Vec::new();
+    for &i in &changed {
+        let start = i.saturating_sub(CONTEXT);
+        let end = (i + CONTEXT + 1).min(lines.len());
+        match hunks.last_mut() {
+            Some(last) if start <= last.1 => last.1 = end,
+            _ => hunks.push((start, end)),
+        }
+    }
+
+    // 2. Emit each hunk, tracking line numbers on both sides
+    let (mut pos, mut line_a, mut line_b) = (0, 1, 1);
+    for (start, end) in hunks {
+        for line in &lines[pos..start] {
+            match line {
+                DiffLine::Removed(_) => line_a += 1,
+                DiffLine::Added(_) => line_b += 1,
+                DiffLine::Equal(_) => { line_a += 1; line_b += 1; }
+            }
+        }
+        let hunk = &lines[start..end];
+        let len_a = hunk.iter().filter(|l| !matches!(l, DiffLine::Added(_))).count();
+        let len_b = hunk.iter().filter(|l| !matches!(l, DiffLine::Removed(_))).count();
+        // An empty side is numbered from the line before it, as GNU diff does
+        let from_a = if len_a == 0 { line_a - 1 } else { line_a };
+        let from_b = if len_b == 0 { line_b - 1 } else { line_b };
+        out.push_str(&format!("@@ -{},{} +{},{} @@\n", from_a, len_a, from_b, len_b));
+        out.push_str(&format_normal(hunk));
+
+        line_a += len_a;
+        line_b += len_b;
+        pos = end;
+    }
+    out
+}
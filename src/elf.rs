@@ -1,5 +1,8 @@
 use crate::{writer, memory, state};
 use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use x86_64::PhysAddr;
 use core::sync::atomic::Ordering;
 
 #[repr(C, packed)]
@@ -39,27 +42,47 @@ struct ProgramHeader {
 }
 
 const PT_LOAD: u32 = 1;
+const PF_X: u32 = 1; // Segment is executable
+
+// The environment is handed over in one page: "KEY=VALUE\0" strings, then an empty one.
+// The entry point gets its address in RDI and the number of variables in RSI.
+const ENV_BLOCK: u64 = 0x0000_5000_0000_0000;
+
+/// Frees a half-loaded image: its demand regions, the frames loaded so far and its tables
+fn discard(page_table: PhysAddr, frames: &[u64]) {
+    memory::release_demand_regions(page_table);
+    unsafe {
+        for &frame in frames {
+            memory::free_frame(PhysAddr::new(frame));
+        }
+        memory::free_user_page_table(page_table);
+    }
+}
 
-pub fn load_and_run(data: &[u8]) {
+/// Loads the ELF image and spawns it as a task with `env` as its environment.
+/// Returns the new task's scheduler index.
+pub fn load_and_run(data: &[u8], env: &[(String, String)]) -> Option<usize> {
     let header = unsafe { &*(data.as_ptr() as *const ElfHeader) };
 
     if header.magic != [0x7f, 0x45, 0x4c, 0x46] {
         crate::serial_print!("[ELF] Error: Invalid Magic Number.\n");
-        return;
+        return None;
     }
     if header.class != 2 { // ELF64
         crate::serial_print!("[ELF] Error: Not 64-bit.\n");
-        return;
+        return None;
     }
     if header.e_type != 2 && header.e_type != 3 { // EXEC or DYN
         crate::serial_print!("[ELF] Error: Not executable.\n");
-        return;
+        return None;
     }
 
     let hhdm = state::HHDM_OFFSET.load(Ordering::Relaxed);
+    let page_table = memory::create_user_page_table(); // The process's own address space
     let ph_offset = header.phoff as usize;
     let ph_count = header.phnum as usize;
     let ph_size = header.phentsize as usize;
+    let mut frames = Vec::new(); // Handed to the task, which frees them when it exits
 
     crate::serial_print!("[ELF] Loading {} segments...\n", ph_count);
 
@@ -67,7 +90,8 @@ pub fn load_and_run(data: &[u8]) {
         let offset = ph_offset + (i * ph_size);
         if offset + core::mem::size_of::<ProgramHeader>() > data.len() {
              crate::serial_print!("[ELF] Error: PHDR out of bounds.\n");
-             return;
+             discard(page_table, &frames);
+             return None;
         }
         
         let ph = unsafe { &*(data.as_ptr().add(offset) as *const ProgramHeader) };
@@ -84,13 +108,21 @@ pub fn load_and_run(data: &[u8]) {
             // Align to 4KB pages
             let start_page = start_vaddr & !0xFFF;
             let end_page = (end_vaddr + 0xFFF) & !0xFFF;
-            let page_count = (end_page - start_page) / 4096;
 
+            // Pages past the file data are pure BSS: leave them to the page fault handler
+            let data_end_page = (start_vaddr + ph.p_filesz + 0xFFF) & !0xFFF;
+            let eager_count = (data_end_page.min(end_page) - start_page) / 4096;
+            if data_end_page < end_page {
+                unsafe { memory::map_demand_region(page_table, data_end_page, end_page); }
+            }
+
+            let executable = ph.p_flags & PF_X != 0;
             unsafe {
-                for p in 0..page_count {
+                for p in 0..eager_count {
                     let vaddr = start_page + (p * 4096);
                     let frame = memory::alloc_frame();
-                    memory::map_user_page(vaddr, frame.as_u64());
+                    memory::map_user_page_in(page_table, vaddr, frame.as_u64(), executable);
+                    frames.push(frame.as_u64());
                     
                     // Destination pointer (virtual address view for kernel, via HHDM)
                     let dst_ptr = (frame.as_u64() + hhdm) as *mut u8;
@@ -128,12 +160,35 @@ pub fn load_and_run(data: &[u8]) {
         }
     }
 
+    // Environment page; variables that don't fit are left out
+    let env_frame = memory::alloc_frame();
+    frames.push(env_frame.as_u64());
+    let mut env_count = 0u64;
+    unsafe {
+        memory::map_user_page_in(page_table, ENV_BLOCK, env_frame.as_u64(), false);
+        let block = core::slice::from_raw_parts_mut((env_frame.as_u64() + hhdm) as *mut u8, 4096);
+        block.fill(0);
+        let mut pos = 0;
+        for (key, value) in env {
+            let entry = format!("{}={}", key, value);
+            if pos + entry.len() + 2 > block.len() { break; } // Its NUL and the final one
+            block[pos..pos + entry.len()].copy_from_slice(entry.as_bytes());
+            pos += entry.len() + 1;
+            env_count += 1;
+        }
+    }
+
     let entry_point = header.entry_point;
     crate::serial_print!("[ELF] Entry Point: {:x}\n", entry_point);
     
     // Spawn in a separate task so Shell doesn't die!
-    crate::scheduler::SCHEDULER.lock().add_task("UserApp", 1_000_000, 
-        crate::shell::Shell::run_user_trampoline, 
-        entry_point
-    );
+    let page_table_phys = page_table.as_u64();
+    let idx = x86_64::instructions::interrupts::without_interrupts(|| {
+        let mut sched = crate::scheduler::SCHEDULER.lock();
+        let idx = sched.spawn_process("UserApp", entry_point, page_table_phys, frames, 1_000_000, 4);
+        sched.tasks[idx].context.rdi = ENV_BLOCK;
+        sched.tasks[idx].context.rsi = env_count;
+        idx
+    });
+    Some(idx)
 }
\ No newline at end of file
@@ -1,4 +1,4 @@
-use crate::ata;
+use crate::block;
 use crate::writer;
 use alloc::vec::Vec;
 use alloc::string::String;
@@ -56,20 +56,52 @@ struct DirectoryEntry {
 }
 
 pub struct Fat32 {
-    drive: ata::AtaDrive,
     partition_offset: u32,
     data_start: u32,
     sectors_per_cluster: u32,
     root_cluster: u32,
     fat_start: u32,
+    num_fats: u32,
+    fat_size: u32,
+    total_clusters: u32,
+    fs_info: u32,
 }
 
+// FAT entry values (only the low 28 bits are meaningful)
+const FAT_FREE: u32 = 0;
+const FAT_EOC: u32 = 0x0FFFFFFF;
+const ATTR_ARCHIVE: u8 = 0x20;
+
+/// LBA start of the first FAT32 partition (type 0x0B or 0x0C) in the MBR at LBA 0.
+/// None for an unpartitioned disk, where the BPB itself sits at LBA 0.
+pub fn read_mbr_partition() -> Option<u32> {
+    let mbr = block::read_sectors(0, 1);
+    if mbr.len() < 512 || mbr[510] != 0x55 || mbr[511] != 0xAA { return None; }
+    // A bare volume boot sector carries the same signature; its boot code isn't a table
+    if &mbr[82..90] == b"FAT32   " { return None; }
+
+    for entry in mbr[446..510].chunks(16) {
+        if entry[4] == 0x0B || entry[4] == 0x0C {
+            return Some(u32::from_le_bytes(entry[8..12].try_into().ok()?));
+        }
+    }
+    None
+}
+
+// (LBA of the directory cluster, byte offset of the 32-byte entry)
+type DirSlot = (u32, usize);
+
 impl Fat32 {
     pub fn new() -> Option<Self> {
-        let drive = ata::AtaDrive::new(true);
-        if !drive.identify() { return None; }
+        if !block::present() { return None; }
 
-        let sector0 = drive.read_sectors(0, 1);
+        // Partitioned disk: the volume starts at the first FAT32 partition, not LBA 0
+        let partition_offset = read_mbr_partition().unwrap_or(0);
+        if partition_offset != 0 {
+            writer::print(&format!("[FAT] FAT32 partition at LBA {}.\n", partition_offset));
+        }
+
+        let sector0 = block::read_sectors(partition_offset as u64, 1);
         if sector0.is_empty() {
             writer::print("[FAT] Error: Could not read boot sector.\n");
             return None;
@@ -83,6 +115,8 @@ impl Fat32 {
         let fat32_size = bpb.fat_size_32;
         let root_cluster = bpb.root_cluster;
         let spc = bpb.sectors_per_cluster as u32;
+        let total_sectors = bpb.total_sectors_32;
+        let fs_info = bpb.fs_info as u32;
 
         if bytes_per_sec != 512 {
             writer::print(&format!("[FAT] Error: Non-512 byte sectors (found {}).\n", bytes_per_sec));
@@ -92,16 +126,20 @@ impl Fat32 {
         let fat_area_size = num_fats * fat32_size;
         let data_start = rsvd_sec + fat_area_size;
         let fat_start = rsvd_sec;
+        let total_clusters = total_sectors.saturating_sub(data_start) / spc.max(1);
 
         writer::print(&format!("[FAT] Mounted. Root Cluster: {}\n", root_cluster));
 
         Some(Fat32 {
-            drive,
-            partition_offset: 0,
+            partition_offset,
             data_start,
             sectors_per_cluster: spc,
             root_cluster,
             fat_start,
+            num_fats,
+            fat_size: fat32_size,
+            total_clusters,
+            fs_info,
         })
     }
 
@@ -116,9 +154,58 @@ impl Fat32 {
         }
     }
 
+    /// Long File Name for the short entry at `slot_idx` (32-byte slots into `data`).
+    /// LFN slots sit just before it, last fragment first, each holding 13 UCS-2 characters.
+    /// Only ASCII is decoded; anything else becomes '?'.
+    fn read_lfn_entry(data: &[u8], slot_idx: usize) -> Option<String> {
+        let short = data.get(slot_idx * 32..slot_idx * 32 + 11)?;
+
+        // Checksum of the 8.3 name, stored in every slot of its LFN chain
+        let mut sum: u8 = 0;
+        for &b in short {
+            sum = sum.rotate_right(1).wrapping_add(b);
+        }
+
+        let mut name = String::new();
+        let mut ordinal = 1;
+        let mut idx = slot_idx;
+        while idx > 0 {
+            idx -= 1;
+            let slot = &data[idx * 32..idx * 32 + 32];
+            if slot[11] != 0x0F || slot[0] == 0xE5 || (slot[0] & 0x1F) != ordinal || slot[13] != sum {
+                return None;
+            }
+
+            for range in [1..11, 14..26, 28..32] {
+                for pair in slot[range].chunks(2) {
+                    match u16::from_le_bytes([pair[0], pair[1]]) {
+                        0x0000 | 0xFFFF => return Some(name), // Terminator / padding
+                        c if c < 0x80 => name.push(c as u8 as char),
+                        _ => name.push('?'),
+                    }
+                }
+            }
+
+            if slot[0] & 0x40 != 0 { return Some(name); } // Last fragment
+            ordinal += 1;
+        }
+        None
+    }
+
+    /// The whole root directory, every cluster of its chain back to back (an LFN chain can
+    /// straddle a cluster boundary). Empty if a cluster can't be read.
+    fn read_root(&self) -> Vec<u8> {
+        let mut data = Vec::new();
+        for c in self.get_clusters(self.root_cluster) {
+            let cluster = block::read_sectors(self.cluster_to_lba(c) as u64, self.sectors_per_cluster as u16);
+            if cluster.is_empty() { return Vec::new(); }
+            data.extend_from_slice(&cluster);
+        }
+        data
+    }
+
     pub fn list_root(&self) {
-        let root_lba = self.cluster_to_lba(self.root_cluster);
-        let data = self.drive.read_sectors(root_lba, self.sectors_per_cluster as u8);
+        let data = self.read_root();
         if data.is_empty() {
             writer::print("[FAT] Error: Could not read root directory.\n");
             return;
@@ -143,8 +230,14 @@ impl Fat32 {
             // Print raw name bytes
             let name = core::str::from_utf8(&entry.name).unwrap_or("INVALID");
             
-            writer::print(&alloc::format!("[IDX {:02}] {:02x} | Attr: {:02x} | Name: {}\n", 
+            writer::print(&alloc::format!("[IDX {:02}] {:02x} | Attr: {:02x} | Name: {}", 
                 i/32, first_byte, attr, name));
+            if attr != 0x0F && first_byte != 0xE5 {
+                if let Some(long) = Self::read_lfn_entry(&data, i / 32) {
+                    writer::print(&alloc::format!(" ({})", long));
+                }
+            }
+            writer::print("\n");
         }
     }
 
@@ -153,19 +246,13 @@ impl Fat32 {
         let mut current = start_cluster;
         while current < 0x0FFFFFF8 && current != 0 {
             clusters.push(current);
-            let fat_offset = current * 4;
-            let fat_sector = self.fat_start + (fat_offset / 512);
-            let sector_offset = (fat_offset % 512) as usize;
-            let data = self.drive.read_sectors(fat_sector, 1);
-            let next = u32::from_le_bytes(data[sector_offset..sector_offset + 4].try_into().unwrap()) & 0x0FFFFFFF;
-            current = next;
+            current = self.read_fat_entry(current);
         }
         clusters
     }
 
     pub fn read_file(&self, filename: &str) -> Option<Vec<u8>> {
-        let root_lba = self.cluster_to_lba(self.root_cluster);
-        let data = self.drive.read_sectors(root_lba, self.sectors_per_cluster as u8);
+        let data = self.read_root();
         if data.is_empty() { return None; }
 
         // 1. Find the file entry
@@ -177,9 +264,10 @@ impl Fat32 {
             if entry.name[0] == 0xE5 || entry.attr == 0x0F { continue; }
 
             let name_str = Self::format_name(&entry.name);
+            let long_name = Self::read_lfn_entry(&data, i / 32);
             
-            // Case-insensitive match
-            if name_str.eq_ignore_ascii_case(filename) {
+            // Case-insensitive match, long name first
+            if long_name.is_some_and(|n| n.eq_ignore_ascii_case(filename)) || name_str.eq_ignore_ascii_case(filename) {
                 // FOUND IT!
                 let cluster = ((entry.cluster_high as u32) << 16) | (entry.cluster_low as u32);
                 let size = entry.size as usize;
@@ -189,7 +277,7 @@ impl Fat32 {
                 let mut raw_data = Vec::new();
                 for c in clusters {
                     let file_lba = self.cluster_to_lba(c);
-                    let data = self.drive.read_sectors(file_lba, self.sectors_per_cluster as u8);
+                    let data = block::read_sectors(file_lba as u64, self.sectors_per_cluster as u16);
                     raw_data.extend_from_slice(&data);
                 }
                 
@@ -203,6 +291,225 @@ impl Fat32 {
         None
     }
 
+    // --- WRITE SUPPORT ---
+
+    // Helper: "readme.txt" -> "README  TXT". None if it doesn't fit 8.3.
+    fn to_short_name(name: &str) -> Option<[u8; 11]> {
+        let (base, ext) = match name.rfind('.') {
+            Some(idx) => (&name[..idx], &name[idx + 1..]),
+            None => (name, ""),
+        };
+        if base.is_empty() || base.len() > 8 || ext.len() > 3 { return None; }
+
+        let mut raw = [b' '; 11];
+        for (i, b) in base.bytes().enumerate() {
+            if !b.is_ascii_alphanumeric() && !b"_-~!#$%&'()@^{}".contains(&b) { return None; }
+            raw[i] = b.to_ascii_uppercase();
+        }
+        for (i, b) in ext.bytes().enumerate() {
+            if !b.is_ascii_alphanumeric() { return None; }
+            raw[8 + i] = b.to_ascii_uppercase();
+        }
+        Some(raw)
+    }
+
+    fn cluster_bytes(&self) -> usize {
+        self.sectors_per_cluster as usize * 512
+    }
+
+    fn read_fat_entry(&self, cluster: u32) -> u32 {
+        let fat_offset = cluster * 4;
+        let lba = self.partition_offset + self.fat_start + (fat_offset / 512);
+        let off = (fat_offset % 512) as usize;
+        let data = block::read_sectors(lba as u64, 1);
+        if data.len() < 512 { return FAT_EOC; }
+        u32::from_le_bytes(data[off..off + 4].try_into().unwrap()) & 0x0FFFFFFF
+    }
+
+    /// Updates an entry in every FAT copy, preserving the reserved top 4 bits
+    fn write_fat_entry(&self, cluster: u32, value: u32) {
+        let fat_offset = cluster * 4;
+        let off = (fat_offset % 512) as usize;
+        for copy in 0..self.num_fats {
+            let lba = self.partition_offset + self.fat_start + copy * self.fat_size + (fat_offset / 512);
+            let mut data = block::read_sectors(lba as u64, 1);
+            if data.len() < 512 { continue; }
+            let old = u32::from_le_bytes(data[off..off + 4].try_into().unwrap());
+            let new = (old & 0xF0000000) | (value & 0x0FFFFFFF);
+            data[off..off + 4].copy_from_slice(&new.to_le_bytes());
+            block::write_sectors(lba as u64, &data);
+        }
+    }
+
+    /// Finds a free cluster, marks it end-of-chain and returns it
+    fn alloc_cluster(&self) -> Option<u32> {
+        let last = self.total_clusters + 2;
+        let mut cluster = 2;
+        while cluster < last {
+            // Scan one FAT sector (128 entries) per disk read
+            let fat_offset = cluster * 4;
+            let lba = self.partition_offset + self.fat_start + (fat_offset / 512);
+            let data = block::read_sectors(lba as u64, 1);
+            if data.len() < 512 { return None; }
+            let first_in_sector = (fat_offset / 512) * 128;
+            for c in cluster..core::cmp::min(first_in_sector + 128, last) {
+                let off = ((c * 4) % 512) as usize;
+                let entry = u32::from_le_bytes(data[off..off + 4].try_into().unwrap()) & 0x0FFFFFFF;
+                if entry == FAT_FREE {
+                    self.write_fat_entry(c, FAT_EOC);
+                    return Some(c);
+                }
+            }
+            cluster = first_in_sector + 128;
+        }
+        None
+    }
+
+    fn free_chain(&self, start_cluster: u32) {
+        for c in self.get_clusters(start_cluster) {
+            self.write_fat_entry(c, FAT_FREE);
+        }
+    }
+
+    /// Our allocations make the FSInfo free-cluster hints stale; mark them unknown
+    fn invalidate_fs_info(&self) {
+        if self.fs_info == 0 || self.fs_info == 0xFFFF { return; }
+        let lba = self.partition_offset + self.fs_info;
+        let mut data = block::read_sectors(lba as u64, 1);
+        if data.len() < 512 || data[0..4] != [0x52, 0x52, 0x61, 0x41] { return; }
+        data[488..492].copy_from_slice(&0xFFFFFFFFu32.to_le_bytes());
+        data[492..496].copy_from_slice(&0xFFFFFFFFu32.to_le_bytes());
+        block::write_sectors(lba as u64, &data);
+    }
+
+    /// Locates `raw` in the root directory. Returns the matching entry (if any) and the
+    /// first free slot seen, for creating it.
+    fn find_root_slot(&self, raw: &[u8; 11]) -> (Option<DirSlot>, Option<DirSlot>) {
+        let mut free_slot = None;
+        for c in self.get_clusters(self.root_cluster) {
+            let lba = self.cluster_to_lba(c);
+            let data = block::read_sectors(lba as u64, self.sectors_per_cluster as u16);
+            for i in (0..data.len()).step_by(32) {
+                let first = data[i];
+                if first == 0x00 || first == 0xE5 {
+                    if free_slot.is_none() { free_slot = Some((lba, i)); }
+                    if first == 0x00 { return (None, free_slot); }
+                    continue;
+                }
+                if data[i + 11] != 0x0F && data[i..i + 11] == raw[..] {
+                    return (Some((lba, i)), free_slot);
+                }
+            }
+        }
+        (None, free_slot)
+    }
+
+    /// Grows the root directory by one zeroed cluster and returns its first slot
+    fn extend_root(&self) -> Option<DirSlot> {
+        let last = *self.get_clusters(self.root_cluster).last()?;
+        let new = self.alloc_cluster()?;
+        self.write_fat_entry(last, new);
+        let lba = self.cluster_to_lba(new);
+        block::write_sectors(lba as u64, &alloc::vec![0u8; self.cluster_bytes()]);
+        Some((lba, 0))
+    }
+
+    fn write_dir_entry(&self, lba: u32, offset: usize, entry: &[u8; 32]) {
+        let mut data = block::read_sectors(lba as u64, self.sectors_per_cluster as u16);
+        if data.len() < offset + 32 { return; }
+        data[offset..offset + 32].copy_from_slice(entry);
+        block::write_sectors(lba as u64, &data);
+    }
+
+    /// Creates or overwrites an 8.3 file in the root directory
+    pub fn write_file(&self, name: &str, data: &[u8]) -> bool {
+        let raw = match Self::to_short_name(name) {
+            Some(r) => r,
+            None => {
+                writer::print("[FAT] Error: Name must be 8.3.\n");
+                return false;
+            }
+        };
+
+        // 1. Reuse the existing entry or claim a free slot. An old chain is only freed once
+        // the new one is on disk, so a full disk leaves the file as it was.
+        let (existing, free_slot) = self.find_root_slot(&raw);
+        let (slot, old_cluster) = match existing {
+            Some((lba, off)) => {
+                let dir = block::read_sectors(lba as u64, self.sectors_per_cluster as u16);
+                let e = unsafe { &*(dir.as_ptr().add(off) as *const DirectoryEntry) };
+                ((lba, off), ((e.cluster_high as u32) << 16) | (e.cluster_low as u32))
+            }
+            None => match free_slot.or_else(|| self.extend_root()) {
+                Some(s) => (s, 0),
+                None => {
+                    writer::print("[FAT] Error: Root directory full.\n");
+                    return false;
+                }
+            },
+        };
+
+        // 2. Allocate the new chain and write data cluster by cluster; on failure give back
+        // what was allocated so far
+        let mut first_cluster = 0;
+        let mut prev = 0;
+        for chunk in data.chunks(self.cluster_bytes()) {
+            let cluster = match self.alloc_cluster() {
+                Some(c) => c,
+                None => {
+                    writer::print("[FAT] Error: Disk full.\n");
+                    if first_cluster != 0 { self.free_chain(first_cluster); }
+                    return false;
+                }
+            };
+            if prev == 0 { first_cluster = cluster; } else { self.write_fat_entry(prev, cluster); }
+            prev = cluster;
+
+            let mut buf = alloc::vec![0u8; self.cluster_bytes()];
+            buf[..chunk.len()].copy_from_slice(chunk);
+            block::write_sectors(self.cluster_to_lba(cluster) as u64, &buf);
+        }
+
+        // 3. Directory entry
+        let mut entry = [0u8; 32];
+        entry[0..11].copy_from_slice(&raw);
+        entry[11] = ATTR_ARCHIVE;
+        entry[20..22].copy_from_slice(&((first_cluster >> 16) as u16).to_le_bytes());
+        entry[26..28].copy_from_slice(&((first_cluster & 0xFFFF) as u16).to_le_bytes());
+        entry[28..32].copy_from_slice(&(data.len() as u32).to_le_bytes());
+        self.write_dir_entry(slot.0, slot.1, &entry);
+
+        // 4. Nothing points at the old chain any more
+        if old_cluster >= 2 { self.free_chain(old_cluster); }
+
+        self.invalidate_fs_info();
+        true
+    }
+
+    pub fn delete_file(&self, name: &str) -> bool {
+        let raw = match Self::to_short_name(name) {
+            Some(r) => r,
+            None => return false,
+        };
+        let (lba, off) = match self.find_root_slot(&raw).0 {
+            Some(s) => s,
+            None => return false,
+        };
+
+        let dir = block::read_sectors(lba as u64, self.sectors_per_cluster as u16);
+        let mut entry = [0u8; 32];
+        entry.copy_from_slice(&dir[off..off + 32]);
+        let cluster = ((u16::from_le_bytes([entry[20], entry[21]]) as u32) << 16)
+            | u16::from_le_bytes([entry[26], entry[27]]) as u32;
+
+        entry[0] = 0xE5;
+        self.write_dir_entry(lba, off, &entry);
+        if cluster >= 2 { self.free_chain(cluster); }
+
+        self.invalidate_fs_info();
+        true
+    }
+
     fn cluster_to_lba(&self, cluster: u32) -> u32 {
         self.partition_offset + self.data_start + ((cluster - 2) * self.sectors_per_cluster)
     }
@@ -11,8 +11,105 @@ static MODULE_REQUEST: ModuleRequest = ModuleRequest::new();
 
 #[derive(Clone)]
 pub enum Node {
-    File { name: String, data: Vec<u8> },
-    Directory { name: String, children: Vec<Node> },
+    File { name: String, data: Vec<u8>, created_ticks: u64, modified_ticks: u64, permissions: u16 },
+    Directory { name: String, children: Vec<NodeBox>, created_ticks: u64, modified_ticks: u64, permissions: u16 },
+    /// `target` is an absolute VFS path
+    Symlink { name: String, target: String },
+    /// Special file whose contents come from the kernel; see `Device`
+    Device { name: String, device: Device },
+}
+
+/// What a device node reads from. Devices live in /dev, are made at boot by `make_devices`
+/// and are never saved to disk.
+#[derive(Clone, Copy)]
+pub enum Device {
+    /// Keystrokes typed ahead and not yet consumed; reading takes them
+    Stdin,
+}
+
+impl Device {
+    fn read(&self) -> Vec<u8> {
+        match self {
+            Device::Stdin => {
+                let mut data = String::new();
+                while let Some(c) = crate::input::pop_key() {
+                    data.push(c);
+                }
+                data.into_bytes()
+            }
+        }
+    }
+}
+
+/// A directory entry, taken from `slab::NODE_SLAB` (or the heap once that's full), so
+/// creating and deleting files doesn't churn the general heap
+pub struct NodeBox(*mut Node);
+
+// Owned exclusively by its parent directory
+unsafe impl Send for NodeBox {}
+
+impl NodeBox {
+    pub fn new(node: Node) -> Self {
+        let ptr = x86_64::instructions::interrupts::without_interrupts(|| {
+            crate::slab::NODE_SLAB.lock().alloc()
+        }) as *mut Node;
+        if ptr.is_null() {
+            return NodeBox(alloc::boxed::Box::into_raw(alloc::boxed::Box::new(node)));
+        }
+        unsafe { ptr.write(node); }
+        NodeBox(ptr)
+    }
+}
+
+impl core::ops::Deref for NodeBox {
+    type Target = Node;
+    fn deref(&self) -> &Node {
+        unsafe { &*self.0 }
+    }
+}
+
+impl core::ops::DerefMut for NodeBox {
+    fn deref_mut(&mut self) -> &mut Node {
+        unsafe { &mut *self.0 }
+    }
+}
+
+impl Clone for NodeBox {
+    fn clone(&self) -> Self {
+        NodeBox::new((**self).clone()) // Deep: a Directory clones its whole subtree
+    }
+}
+
+impl Drop for NodeBox {
+    fn drop(&mut self) {
+        unsafe { core::ptr::drop_in_place(self.0); }
+        let ptr = self.0;
+        let in_slab = x86_64::instructions::interrupts::without_interrupts(|| {
+            let mut slab = crate::slab::NODE_SLAB.lock();
+            let owned = slab.owns(ptr as *mut u8);
+            if owned { slab.free(ptr as *mut u8); }
+            owned
+        });
+        if !in_slab {
+            // The node is gone already: free the allocation without dropping it again
+            drop(unsafe { alloc::boxed::Box::from_raw(ptr as *mut core::mem::MaybeUninit<Node>) });
+        }
+    }
+}
+
+// Unix-style owner/group/other rwx bits
+pub const DEFAULT_FILE_PERMS: u16 = 0o644;
+pub const DEFAULT_DIR_PERMS: u16 = 0o755;
+pub const SYMLINK_PERMS: u16 = 0o777;
+pub const DEVICE_PERMS: u16 = 0o444;
+
+/// How many links `read` follows before giving up (catches cycles)
+const MAX_SYMLINK_DEPTH: usize = 8;
+
+/// Timestamps are PIT ticks since the Unix epoch (`time::wall_ticks`), so they still mean
+/// the same moment after the tree is saved and loaded in a later boot
+fn now_ticks() -> u64 {
+    crate::time::wall_ticks()
 }
 
 impl Node {
@@ -20,24 +117,90 @@ impl Node {
         match self {
             Node::File { name, .. } => name,
             Node::Directory { name, .. } => name,
+            Node::Symlink { name, .. } => name,
+            Node::Device { name, .. } => name,
         }
     }
 
     pub fn is_dir(&self) -> bool {
         matches!(self, Node::Directory { .. })
     }
+
+    pub fn new_file(name: &str, data: Vec<u8>) -> Node {
+        let now = now_ticks();
+        Node::File { name: name.to_string(), data, created_ticks: now, modified_ticks: now, permissions: DEFAULT_FILE_PERMS }
+    }
+
+    pub fn new_dir(name: &str) -> Node {
+        let now = now_ticks();
+        Node::Directory { name: name.to_string(), children: Vec::new(), created_ticks: now, modified_ticks: now, permissions: DEFAULT_DIR_PERMS }
+    }
+
+    /// (created, modified) ticks
+    pub fn times(&self) -> (u64, u64) {
+        match self {
+            Node::File { created_ticks, modified_ticks, .. } => (*created_ticks, *modified_ticks),
+            Node::Directory { created_ticks, modified_ticks, .. } => (*created_ticks, *modified_ticks),
+            Node::Symlink { .. } | Node::Device { .. } => (0, 0),
+        }
+    }
+
+    pub fn permissions(&self) -> u16 {
+        match self {
+            Node::File { permissions, .. } | Node::Directory { permissions, .. } => *permissions,
+            Node::Symlink { .. } => SYMLINK_PERMS,
+            Node::Device { .. } => DEVICE_PERMS,
+        }
+    }
+
+    /// Links and devices have no mode of their own, so there is nothing to change
+    fn permissions_mut(&mut self) -> Option<&mut u16> {
+        match self {
+            Node::File { permissions, .. } | Node::Directory { permissions, .. } => Some(permissions),
+            Node::Symlink { .. } | Node::Device { .. } => None,
+        }
+    }
+}
+
+/// `ls -l` style mode string, e.g. `drwxr-xr-x`
+pub fn permission_string(is_dir: bool, perms: u16) -> String {
+    let mut s = String::with_capacity(10);
+    s.push(if is_dir { 'd' } else { '-' });
+    for shift in [6, 3, 0] {
+        let bits = (perms >> shift) & 0o7;
+        s.push(if bits & 0o4 != 0 { 'r' } else { '-' });
+        s.push(if bits & 0o2 != 0 { 'w' } else { '-' });
+        s.push(if bits & 0o1 != 0 { 'x' } else { '-' });
+    }
+    s
 }
 
 lazy_static! {
-    pub static ref ROOT: Mutex<Node> = Mutex::new(Node::Directory {
-        name: "/".to_string(),
-        children: Vec::new(),
-    });
+    pub static ref ROOT: Mutex<Node> = Mutex::new(Node::new_dir("/"));
+}
+
+/// Collapses `.`, `..` and repeated slashes into a canonical absolute path.
+/// `..` at the root stays at the root, like a real Unix path walk.
+pub fn normalize_path(path: &str) -> String {
+    let mut stack: Vec<&str> = Vec::new();
+    for part in path.split('/') {
+        match part {
+            "" | "." => {}
+            ".." => { stack.pop(); }
+            _ => stack.push(part),
+        }
+    }
+    if stack.is_empty() {
+        "/".to_string()
+    } else {
+        format!("/{}", stack.join("/"))
+    }
 }
 
 // Helper to find a directory by path (simple absolute path for now)
 pub fn find_dir_mut<'a>(root: &'a mut Node, path: &str) -> Option<&'a mut Node> {
-    if path == "/" || path == "" {
+    let path = normalize_path(path);
+    if path == "/" {
         return Some(root);
     }
 
@@ -72,24 +235,35 @@ pub fn mkdir(path: &str, name: &str) -> bool {
             if children.iter().any(|c| c.name() == name) {
                 return false;
             }
-            children.push(Node::Directory {
-                name: name.to_string(),
-                children: Vec::new(),
-            });
+            children.push(NodeBox::new(Node::new_dir(name)));
             return true;
         }
     }
     false
 }
 
+/// Creates or overwrites a file. Symlinks are followed, so writing through a link
+/// updates its target (or creates it, if the link dangles).
 pub fn touch(path: &str, name: &str, data: Vec<u8>) -> bool {
     let mut root = ROOT.lock();
-    if let Some(dir) = find_dir_mut(&mut root, path) {
+    let (path, name) = match resolve_in(&mut root, path, name) {
+        Some(r) => r,
+        None => return false,
+    };
+    let name = name.as_str();
+    if let Some(dir) = find_dir_mut(&mut root, &path) {
         if let Node::Directory { children, .. } = dir {
             if let Some(pos) = children.iter().position(|c| c.name() == name) {
-                children[pos] = Node::File { name: name.to_string(), data };
+                if matches!(*children[pos], Node::Device { .. }) { return false; }
+                // Overwrite: keep the original creation time and mode
+                let (created, perms) = (children[pos].times().0, children[pos].permissions());
+                *children[pos] = Node::new_file(name, data);
+                if let Node::File { created_ticks, permissions, .. } = &mut *children[pos] {
+                    *created_ticks = created;
+                    *permissions = perms;
+                }
             } else {
-                children.push(Node::File { name: name.to_string(), data });
+                children.push(NodeBox::new(Node::new_file(name, data)));
             }
             return true;
         }
@@ -97,11 +271,15 @@ pub fn touch(path: &str, name: &str, data: Vec<u8>) -> bool {
     false
 }
 
+/// Removes a file or an empty directory. Non-empty directories need `rm_recursive`.
 pub fn rm(path: &str, name: &str) -> bool {
     let mut root = ROOT.lock();
     if let Some(dir) = find_dir_mut(&mut root, path) {
         if let Node::Directory { children, .. } = dir {
             if let Some(pos) = children.iter().position(|c| c.name() == name) {
+                if let Node::Directory { children: sub, .. } = &*children[pos] {
+                    if !sub.is_empty() { return false; }
+                }
                 children.remove(pos);
                 return true;
             }
@@ -110,6 +288,39 @@ pub fn rm(path: &str, name: &str) -> bool {
     false
 }
 
+/// Removes a node and everything below it: files first, then the directories holding them
+pub fn rm_recursive(path: &str, name: &str) -> bool {
+    let target = if path == "/" { format!("/{}", name) } else { format!("{}/{}", path, name) };
+    let mut root = ROOT.lock();
+
+    // 1. Collect the subtree in pre-order (every directory before its contents)
+    let mut entries: Vec<String> = Vec::new();
+    {
+        let parent = match find_dir_mut(&mut root, path) {
+            Some(Node::Directory { children, .. }) => children,
+            _ => return false,
+        };
+        let node = match parent.iter().find(|c| c.name() == name) {
+            Some(n) => n,
+            None => return false,
+        };
+        walk_recursive(&target, node, 0, None, &mut |p: &str, _: &Node| entries.push(p.to_string()));
+    }
+
+    // 2. Remove in reverse: children always go before their parent
+    for entry in entries.iter().rev() {
+        let (parent_path, child) = match entry.rfind('/') {
+            Some(0) => ("/", &entry[1..]),
+            Some(i) => (&entry[..i], &entry[i + 1..]),
+            None => continue,
+        };
+        if let Some(Node::Directory { children, .. }) = find_dir_mut(&mut root, parent_path) {
+            children.retain(|c| c.name() != child);
+        }
+    }
+    true
+}
+
 pub fn ls(path: &str) -> Option<Vec<(String, bool)>> {
     let mut root = ROOT.lock();
     if let Some(dir) = find_dir_mut(&mut root, path) {
@@ -120,25 +331,76 @@ pub fn ls(path: &str) -> Option<Vec<(String, bool)>> {
     None
 }
 
-pub fn read(path: &str, name: &str) -> Option<Vec<u8>> {
+/// Creates a link `name` in `path` pointing at the absolute path `target`.
+/// The target does not have to exist yet.
+pub fn symlink(path: &str, name: &str, target: &str) -> bool {
+    if !target.starts_with('/') { return false; }
     let mut root = ROOT.lock();
-    if let Some(dir) = find_dir_mut(&mut root, path) {
-        if let Node::Directory { children, .. } = dir {
-            for child in children {
-                if let Node::File { name: n, data } = child {
-                    if n == name {
-                        return Some(data.clone());
-                    }
-                }
-            }
+    if let Some(Node::Directory { children, .. }) = find_dir_mut(&mut root, path) {
+        if children.iter().any(|c| c.name() == name) {
+            return false;
         }
+        children.push(NodeBox::new(Node::Symlink { name: name.to_string(), target: normalize_path(target) }));
+        return true;
+    }
+    false
+}
+
+/// Where `name` in `path` really lives: symlinks are followed, at most MAX_SYMLINK_DEPTH
+/// of them, to the (directory, name) they point at. A dangling link resolves to its target
+/// and anything that isn't a link to itself. None for a loop or too long a chain.
+pub fn resolve(path: &str, name: &str) -> Option<(String, String)> {
+    let mut root = ROOT.lock();
+    resolve_in(&mut root, path, name)
+}
+
+fn resolve_in(root: &mut Node, path: &str, name: &str) -> Option<(String, String)> {
+    let (mut dir, mut name) = (normalize_path(path), name.to_string());
+    let mut hops = 0;
+    loop {
+        let target = match find_dir_mut(root, &dir) {
+            Some(Node::Directory { children, .. }) => match children.iter().find(|c| c.name() == name).map(|c| &**c) {
+                Some(Node::Symlink { target, .. }) => target.clone(),
+                _ => return Some((dir, name)),
+            },
+            _ => return Some((dir, name)),
+        };
+        if hops == MAX_SYMLINK_DEPTH { return None; }
+        hops += 1;
+        // Targets are stored absolute and normalized
+        let split = target.rfind('/')?;
+        dir = if split == 0 { "/".to_string() } else { target[..split].to_string() };
+        name = target[split + 1..].to_string();
+    }
+}
+
+/// Reads a file or device, following symlinks
+pub fn read(path: &str, name: &str) -> Option<Vec<u8>> {
+    let mut root = ROOT.lock();
+    let (path, name) = resolve_in(&mut root, path, name)?;
+    match find_dir_mut(&mut root, &path)? {
+        Node::Directory { children, .. } => match &**children.iter().find(|c| c.name() == name)? {
+            Node::File { data, .. } => Some(data.clone()),
+            Node::Device { device, .. } => Some(device.read()),
+            _ => None,
+        },
+        _ => None,
     }
-    None
 }
 
 // --- NEW CORE FUNCTIONS ---
 
+/// Copies a single file. Directories need `cp_recursive`.
 pub fn copy_node(src_path: &str, src_name: &str, dest_path: &str, dest_name: &str) -> bool {
+    copy_impl(src_path, src_name, dest_path, dest_name, false)
+}
+
+/// Copies a file or deep-clones a whole directory subtree
+pub fn cp_recursive(src_path: &str, src_name: &str, dest_path: &str, dest_name: &str) -> bool {
+    copy_impl(src_path, src_name, dest_path, dest_name, true)
+}
+
+fn copy_impl(src_path: &str, src_name: &str, dest_path: &str, dest_name: &str, allow_dir: bool) -> bool {
     let mut root = ROOT.lock();
     
     // 1. Get source node
@@ -146,7 +408,8 @@ pub fn copy_node(src_path: &str, src_name: &str, dest_path: &str, dest_name: &st
         if let Some(dir) = find_dir_mut(&mut root, src_path) {
             if let Node::Directory { children, .. } = dir {
                 if let Some(node) = children.iter().find(|c| c.name() == src_name) {
-                    node.clone()
+                    if node.is_dir() && !allow_dir { return false; }
+                    node.clone() // Deep: a Directory clones its whole subtree
                 } else {
                     return false;
                 }
@@ -160,9 +423,11 @@ pub fn copy_node(src_path: &str, src_name: &str, dest_path: &str, dest_name: &st
 
     // 2. Rename if needed
     let mut new_node = src_node;
-    match &mut new_node {
+    match &mut *new_node {
         Node::File { name, .. } => *name = dest_name.to_string(),
         Node::Directory { name, .. } => *name = dest_name.to_string(),
+        Node::Symlink { name, .. } => *name = dest_name.to_string(),
+        Node::Device { name, .. } => *name = dest_name.to_string(),
     }
 
     // 3. Place in destination
@@ -200,9 +465,11 @@ pub fn move_node(src_path: &str, src_name: &str, dest_path: &str, dest_name: &st
     };
 
     // 2. Rename
-    match &mut src_node {
+    match &mut *src_node {
         Node::File { name, .. } => *name = dest_name.to_string(),
         Node::Directory { name, .. } => *name = dest_name.to_string(),
+        Node::Symlink { name, .. } => *name = dest_name.to_string(),
+        Node::Device { name, .. } => *name = dest_name.to_string(),
     }
 
     // 3. Place in destination
@@ -218,11 +485,32 @@ pub fn move_node(src_path: &str, src_name: &str, dest_path: &str, dest_name: &st
     false
 }
 
+/// Changes the mode bits of `name` in `path` (of a link's target, for a symlink); only
+/// the low 9 bits are kept
+pub fn set_permissions(path: &str, name: &str, perms: u16) -> bool {
+    let mut root = ROOT.lock();
+    let (path, name) = match resolve_in(&mut root, path, name) {
+        Some(r) => r,
+        None => return false,
+    };
+    if let Some(Node::Directory { children, .. }) = find_dir_mut(&mut root, &path) {
+        if let Some(p) = children.iter_mut().find(|c| c.name() == name).and_then(|c| c.permissions_mut()) {
+            *p = perms & 0o777;
+            return true;
+        }
+    }
+    false
+}
+
 pub struct NodeInfo {
     pub name: String,
     pub is_dir: bool,
     pub size: usize,
     pub child_count: usize,
+    pub created_ticks: u64,
+    pub modified_ticks: u64,
+    pub permissions: u16,
+    pub link_target: Option<String>,
 }
 
 pub fn get_node_info(path: &str, name: &str) -> Option<NodeInfo> {
@@ -230,18 +518,46 @@ pub fn get_node_info(path: &str, name: &str) -> Option<NodeInfo> {
     let dir = find_dir_mut(&mut root, path)?;
     if let Node::Directory { children, .. } = dir {
         let node = children.iter().find(|c| c.name() == name)?;
-        match node {
-            Node::File { name, data } => Some(NodeInfo {
+        match &**node {
+            Node::File { name, data, created_ticks, modified_ticks, permissions } => Some(NodeInfo {
                 name: name.clone(),
                 is_dir: false,
                 size: data.len(),
                 child_count: 0,
+                created_ticks: *created_ticks,
+                modified_ticks: *modified_ticks,
+                permissions: *permissions,
+                link_target: None,
             }),
-            Node::Directory { name, children } => Some(NodeInfo {
+            Node::Directory { name, children, created_ticks, modified_ticks, permissions } => Some(NodeInfo {
                 name: name.clone(),
                 is_dir: true,
                 size: 0, // Directories don't have "size" in this simple VFS
                 child_count: children.len(),
+                created_ticks: *created_ticks,
+                modified_ticks: *modified_ticks,
+                permissions: *permissions,
+                link_target: None,
+            }),
+            Node::Symlink { name, target } => Some(NodeInfo {
+                name: name.clone(),
+                is_dir: false,
+                size: target.len(),
+                child_count: 0,
+                created_ticks: 0,
+                modified_ticks: 0,
+                permissions: SYMLINK_PERMS,
+                link_target: Some(target.clone()),
+            }),
+            Node::Device { name, .. } => Some(NodeInfo {
+                name: name.clone(),
+                is_dir: false,
+                size: 0,
+                child_count: 0,
+                created_ticks: 0,
+                modified_ticks: 0,
+                permissions: DEVICE_PERMS,
+                link_target: None,
             }),
         }
     } else {
@@ -249,25 +565,28 @@ pub fn get_node_info(path: &str, name: &str) -> Option<NodeInfo> {
     }
 }
 
-pub fn walk_tree<F>(path: &str, mut callback: F) 
+/// Visits `path` and everything below it, pre-order. `max_depth` limits how far down
+/// to go (0 = only `path` itself).
+pub fn walk_tree<F>(path: &str, max_depth: Option<usize>, mut callback: F) 
 where F: FnMut(&str, &Node) {
     let mut root = ROOT.lock();
     if let Some(start_node) = find_dir_mut(&mut root, path) {
-        walk_recursive(path, start_node, &mut callback);
+        walk_recursive(path, start_node, 0, max_depth, &mut callback);
     }
 }
 
-fn walk_recursive<F>(current_path: &str, node: &Node, callback: &mut F)
+fn walk_recursive<F>(current_path: &str, node: &Node, depth: usize, max_depth: Option<usize>, callback: &mut F)
 where F: FnMut(&str, &Node) {
     callback(current_path, node);
-    if let Node::Directory { name: _, children } = node {
+    if max_depth.is_some_and(|max| depth >= max) { return; }
+    if let Node::Directory { children, .. } = node {
         for child in children {
             let next_path = if current_path == "/" {
                 format!("/{}", child.name())
             } else {
                 format!("{}/{}", current_path, child.name())
             };
-            walk_recursive(&next_path, child, callback);
+            walk_recursive(&next_path, child, depth + 1, max_depth, callback);
         }
     }
 }
@@ -296,21 +615,58 @@ pub fn init() {
             if let Node::Directory { children, .. } = &mut *root {
                 // If file already exists from disk, overwrite it with module version (likely newer)
                 if let Some(pos) = children.iter().position(|c| c.name() == clean_name) {
-                    children[pos] = Node::File { name: clean_name.to_string(), data };
+                    *children[pos] = Node::new_file(clean_name, data);
                 } else {
-                    children.push(Node::File {
-                        name: clean_name.to_string(),
-                        data,
-                    });
+                    children.push(NodeBox::new(Node::new_file(clean_name, data)));
                 }
             }
         }
     }
+
+    // 3. Device nodes
+    make_devices();
+}
+
+/// Creates /dev and its device nodes, replacing anything stored under their names
+fn make_devices() {
+    mkdir("/", "dev");
+    let mut root = ROOT.lock();
+    if let Some(Node::Directory { children, .. }) = find_dir_mut(&mut root, "/dev") {
+        children.retain(|c| c.name() != "stdin");
+        children.push(NodeBox::new(Node::Device { name: "stdin".to_string(), device: Device::Stdin }));
+    }
 }
 
 const DISK_LBA_START: u32 = 10000;
 const MAGIC: &[u8] = b"CHRONOSFS";
 
+const FORMAT_VERSION: u8 = 6; // 2: CRC32 of the payload after the tree, 3: node timestamps, 4: permissions, 5: symlinks, 6: timestamps from the Unix epoch
+
+// CRC-32 (IEEE, reflected polynomial 0xEDB88320), one table entry per byte value
+static CRC32_TABLE: [u32; 256] = {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut c = i as u32;
+        let mut k = 0;
+        while k < 8 {
+            c = if c & 1 != 0 { 0xEDB88320 ^ (c >> 1) } else { c >> 1 };
+            k += 1;
+        }
+        table[i] = c;
+        i += 1;
+    }
+    table
+};
+
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xFFFF_FFFFu32;
+    for &b in data {
+        crc = CRC32_TABLE[((crc ^ b as u32) & 0xFF) as usize] ^ (crc >> 8);
+    }
+    !crc
+}
+
 pub fn save_to_disk() {
     let root = ROOT.lock();
     let mut data = Vec::new();
@@ -318,12 +674,14 @@ pub fn save_to_disk() {
     // Header
     data.extend_from_slice(MAGIC);
     data.extend_from_slice(&0u32.to_le_bytes()); // Placeholder for size
-    data.push(1); // Version
+    data.push(FORMAT_VERSION);
 
     // Serialize tree
     serialize_node(&root, &mut data);
 
-    // Update size
+    // Checksum the payload, then record the size including it
+    let crc = crc32(&data[14..]);
+    data.extend_from_slice(&crc.to_le_bytes());
     let size = data.len() as u32;
     data[9..13].copy_from_slice(&size.to_le_bytes());
 
@@ -331,18 +689,16 @@ pub fn save_to_disk() {
     let padding = (512 - (data.len() % 512)) % 512;
     for _ in 0..padding { data.push(0); }
 
-    let drive = crate::ata::AtaDrive::new(true);
-    if drive.identify() {
-        drive.write_sectors(DISK_LBA_START, &data);
+    if crate::block::present() {
+        crate::block::write_sectors(DISK_LBA_START as u64, &data);
     }
 }
 
 pub fn load_from_disk() -> bool {
-    let drive = crate::ata::AtaDrive::new(true);
-    if !drive.identify() { return false; }
+    if !crate::block::present() { return false; }
 
     // Read header (first sector)
-    let header = drive.read_sectors(DISK_LBA_START, 1);
+    let header = crate::block::read_sectors(DISK_LBA_START as u64, 1);
     if header.len() < 14 || &header[0..9] != MAGIC {
         return false;
     }
@@ -353,11 +709,30 @@ pub fn load_from_disk() -> bool {
     }
 
     // Read full data
-    let sectors = ((total_size + 511) / 512) as u8;
-    let full_data = drive.read_sectors(DISK_LBA_START, sectors);
-    
+    let sectors = total_size.div_ceil(512) as u16;
+    let full_data = crate::block::read_sectors(DISK_LBA_START as u64, sectors);
+    if full_data.len() < total_size { return false; }
+
+    // Version 1 images predate the checksum
+    let version = header[13];
+    let payload_end = match version {
+        1 => total_size,
+        2..=6 => {
+            if total_size < 18 { return false; }
+            let end = total_size - 4;
+            let stored = u32::from_le_bytes(full_data[end..total_size].try_into().unwrap());
+            if crc32(&full_data[14..end]) != stored {
+                writer::print("[FS] Error: Disk image checksum mismatch, not loading.\n");
+                return false;
+            }
+            end
+        }
+        _ => return false,
+    };
+    let full_data = &full_data[..payload_end];
+
     let mut offset = 14; // After Magic, Size, Version
-    if let Some(new_root) = deserialize_node(&full_data, &mut offset) {
+    if let Some(new_root) = deserialize_node(full_data, &mut offset, version) {
         let mut root = ROOT.lock();
         *root = new_root;
         return true;
@@ -368,30 +743,71 @@ pub fn load_from_disk() -> bool {
 
 fn serialize_node(node: &Node, data: &mut Vec<u8>) {
     match node {
-        Node::File { name, data: file_data } => {
+        Node::File { name, data: file_data, created_ticks, modified_ticks, permissions } => {
             data.push(0); // Type: File
             serialize_string(name, data);
+            data.extend_from_slice(&created_ticks.to_le_bytes());
+            data.extend_from_slice(&modified_ticks.to_le_bytes());
+            data.extend_from_slice(&permissions.to_le_bytes());
             data.extend_from_slice(&(file_data.len() as u32).to_le_bytes());
             data.extend_from_slice(file_data);
         }
-        Node::Directory { name, children } => {
+        Node::Directory { name, children, created_ticks, modified_ticks, permissions } => {
             data.push(1); // Type: Directory
             serialize_string(name, data);
-            data.extend_from_slice(&(children.len() as u32).to_le_bytes());
-            for child in children {
+            data.extend_from_slice(&created_ticks.to_le_bytes());
+            data.extend_from_slice(&modified_ticks.to_le_bytes());
+            data.extend_from_slice(&permissions.to_le_bytes());
+            let stored: Vec<&Node> = children.iter().map(|c| &**c).filter(|c| !matches!(c, Node::Device { .. })).collect();
+            data.extend_from_slice(&(stored.len() as u32).to_le_bytes());
+            for child in stored {
                 serialize_node(child, data);
             }
         }
+        Node::Symlink { name, target } => {
+            data.push(2); // Type: Symlink
+            serialize_string(name, data);
+            data.extend_from_slice(&0u64.to_le_bytes());
+            data.extend_from_slice(&0u64.to_le_bytes());
+            data.extend_from_slice(&SYMLINK_PERMS.to_le_bytes());
+            serialize_string(target, data);
+        }
+        // Left out by the parent directory: `make_devices` recreates them at boot
+        Node::Device { .. } => {}
     }
 }
 
-fn deserialize_node(data: &[u8], offset: &mut usize) -> Option<Node> {
+fn deserialize_node(data: &[u8], offset: &mut usize, version: u8) -> Option<Node> {
     if *offset >= data.len() { return None; }
     let node_type = data[*offset];
     *offset += 1;
 
     let name = deserialize_string(data, offset)?;
 
+    // Timestamps arrived with version 3, counted from boot until version 6. Those
+    // belong to a boot that can't be placed any more, so they load as unknown (0).
+    let (created_ticks, modified_ticks) = if version >= 3 {
+        if *offset + 16 > data.len() { return None; }
+        let c = u64::from_le_bytes(data[*offset..*offset+8].try_into().unwrap());
+        let m = u64::from_le_bytes(data[*offset+8..*offset+16].try_into().unwrap());
+        *offset += 16;
+        if version >= 6 { (c, m) } else { (0, 0) }
+    } else {
+        (0, 0)
+    };
+
+    // Permissions arrived with version 4; older images get the defaults
+    let permissions = if version >= 4 {
+        if *offset + 2 > data.len() { return None; }
+        let p = u16::from_le_bytes(data[*offset..*offset+2].try_into().unwrap());
+        *offset += 2;
+        p
+    } else if node_type == 0 {
+        DEFAULT_FILE_PERMS
+    } else {
+        DEFAULT_DIR_PERMS
+    };
+
     if node_type == 0 { // File
         if *offset + 4 > data.len() { return None; }
         let size = u32::from_le_bytes(data[*offset..*offset+4].try_into().unwrap()) as usize;
@@ -399,16 +815,19 @@ fn deserialize_node(data: &[u8], offset: &mut usize) -> Option<Node> {
         if *offset + size > data.len() { return None; }
         let file_data = data[*offset..*offset+size].to_vec();
         *offset += size;
-        Some(Node::File { name, data: file_data })
+        Some(Node::File { name, data: file_data, created_ticks, modified_ticks, permissions })
+    } else if node_type == 2 { // Symlink: shares the header layout, times and mode are ignored
+        let target = deserialize_string(data, offset)?;
+        Some(Node::Symlink { name, target })
     } else { // Directory
         if *offset + 4 > data.len() { return None; }
         let count = u32::from_le_bytes(data[*offset..*offset+4].try_into().unwrap()) as u32;
         *offset += 4;
         let mut children = Vec::new();
         for _ in 0..count {
-            children.push(deserialize_node(data, offset)?);
+            children.push(NodeBox::new(deserialize_node(data, offset, version)?));
         }
-        Some(Node::Directory { name, children })
+        Some(Node::Directory { name, children, created_ticks, modified_ticks, permissions })
     }
 }
 
@@ -432,7 +851,7 @@ pub fn list_files() -> Vec<crate::fs::FileCompatibility> {
     let root = ROOT.lock();
     if let Node::Directory { children, .. } = &*root {
         children.iter().filter_map(|c| {
-            if let Node::File { name, data } = c {
+            if let Node::File { name, data, .. } = &**c {
                 Some(crate::fs::FileCompatibility { name: name.clone(), data: data.clone() })
             } else {
                 None
@@ -5,6 +5,7 @@ use lazy_static::lazy_static;
 
 pub const DOUBLE_FAULT_IST_INDEX: u16 = 0;
 pub const INTERRUPT_IST_INDEX: u16 = 1;
+pub const PAGE_FAULT_IST_INDEX: u16 = 2;
 
 lazy_static! {
     static ref TSS: TaskStateSegment = {
@@ -32,6 +33,14 @@ lazy_static! {
              let int_start = VirtAddr::from_ptr(unsafe { &INT_STACK });
              int_start + STACK_SIZE
         };
+
+
+        // 4. DEFINE THE PAGE FAULT STACK (Index 2) - A task that hit its stack guard has no stack left
+        tss.interrupt_stack_table[PAGE_FAULT_IST_INDEX as usize] = {
+             static mut PF_STACK: [u8; STACK_SIZE] = [0; STACK_SIZE];
+             let pf_start = VirtAddr::from_ptr(unsafe { &PF_STACK });
+             pf_start + STACK_SIZE
+        };
         
         tss
     };
@@ -70,6 +79,43 @@ pub fn init() {
     }
 }
 
+/// GDT and TSS for an application processor: the BSP's layout (so every selector matches)
+/// with its own TSS. IST stacks can't be shared between CPUs, and a loaded TSS is marked
+/// busy, so the BSP's can't be loaded a second time anyway.
+pub fn init_ap() {
+    use alloc::boxed::Box;
+    use x86_64::instructions::tables::load_tss;
+    use x86_64::instructions::segmentation::{CS, Segment, SS};
+
+    const STACK_SIZE: usize = 4096 * 5;
+    let new_stack = || {
+        let stack: &'static mut [u8] = Box::leak(alloc::vec![0u8; STACK_SIZE].into_boxed_slice());
+        VirtAddr::from_ptr(stack.as_ptr()) + STACK_SIZE
+    };
+
+    let mut tss = TaskStateSegment::new();
+    tss.privilege_stack_table[0] = new_stack();
+    tss.interrupt_stack_table[DOUBLE_FAULT_IST_INDEX as usize] = new_stack();
+    tss.interrupt_stack_table[INTERRUPT_IST_INDEX as usize] = new_stack();
+    tss.interrupt_stack_table[PAGE_FAULT_IST_INDEX as usize] = new_stack();
+    let tss: &'static TaskStateSegment = Box::leak(Box::new(tss));
+
+    let mut gdt = GlobalDescriptorTable::new();
+    let code_selector = gdt.add_entry(Descriptor::kernel_code_segment());
+    let data_selector = gdt.add_entry(Descriptor::kernel_data_segment());
+    gdt.add_entry(Descriptor::user_data_segment());
+    gdt.add_entry(Descriptor::user_code_segment());
+    let tss_selector = gdt.add_entry(Descriptor::tss_segment(tss));
+    let gdt: &'static GlobalDescriptorTable = Box::leak(Box::new(gdt));
+
+    gdt.load();
+    unsafe {
+        CS::set_reg(code_selector);
+        SS::set_reg(data_selector);
+        load_tss(tss_selector);
+    }
+}
+
 pub fn get_user_selectors() -> (u16, u16) {
     // RPL 3 is required for Ring 3
     (GDT.1.user_code_selector.0 | 3, GDT.1.user_data_selector.0 | 3)
@@ -0,0 +1,75 @@
+use crate::memory;
+use core::sync::atomic::{AtomicU64, Ordering};
+
+// --- REGISTERS (offsets into the HPET MMIO block) ---
+const REG_GCAP_ID: u64 = 0x000;   // Bits 63:32: counter tick period in femtoseconds
+const REG_GEN_CONF: u64 = 0x010;
+const REG_MAIN_COUNTER: u64 = 0x0F0;
+const REG_T0_CONF: u64 = 0x100;
+const REG_T0_COMPARATOR: u64 = 0x108;
+
+const GEN_CONF_ENABLE: u64 = 1 << 0;
+const TN_INT_TYPE_LEVEL: u64 = 1 << 1;
+const TN_TYPE_PERIODIC: u64 = 1 << 3;
+const TN_32BIT_MODE: u64 = 1 << 8;
+
+const FS_PER_MS: u64 = 1_000_000_000_000;
+
+static HPET_VIRT: AtomicU64 = AtomicU64::new(0);
+static PERIOD_FS: AtomicU64 = AtomicU64::new(0); // 0 = no HPET
+static START_NS: AtomicU64 = AtomicU64::new(0);  // PIT time when the counter was zeroed
+
+unsafe fn read(reg: u64) -> u64 {
+    core::ptr::read_volatile((HPET_VIRT.load(Ordering::Relaxed) + reg) as *const u64)
+}
+
+unsafe fn write(reg: u64, value: u64) {
+    core::ptr::write_volatile((HPET_VIRT.load(Ordering::Relaxed) + reg) as *mut u64, value);
+}
+
+/// Maps the HPET at `hpet_base_phys` (from the ACPI HPET table) and starts its main counter
+pub fn init(hpet_base_phys: u64) {
+    unsafe {
+        // 1. Registers and tick period
+        HPET_VIRT.store(memory::map_mmio_page(hpet_base_phys), Ordering::Relaxed);
+        let period_fs = read(REG_GCAP_ID) >> 32;
+        if period_fs == 0 || period_fs > 100_000_000 { // The spec caps the period at 100 ns
+            crate::serial_print!("[HPET] Bogus period {} fs, ignoring.\n", period_fs);
+            return;
+        }
+
+        // 2. Start the main counter from zero
+        write(REG_GEN_CONF, read(REG_GEN_CONF) & !GEN_CONF_ENABLE);
+        write(REG_MAIN_COUNTER, 0);
+        START_NS.store(crate::time::monotonic_ns(), Ordering::Relaxed);
+        write(REG_GEN_CONF, read(REG_GEN_CONF) | GEN_CONF_ENABLE);
+
+        // 3. Comparator 0: one-shot, 64-bit, 1 ms out. No interrupt is routed yet.
+        let conf = read(REG_T0_CONF) & !(TN_TYPE_PERIODIC | TN_32BIT_MODE | TN_INT_TYPE_LEVEL);
+        write(REG_T0_CONF, conf);
+        write(REG_T0_COMPARATOR, read(REG_MAIN_COUNTER) + FS_PER_MS / period_fs);
+
+        PERIOD_FS.store(period_fs, Ordering::Release);
+        crate::serial_print!("[HPET] Enabled, period {} fs ({} MHz).\n", period_fs, 1_000_000_000 / period_fs);
+    }
+}
+
+pub fn is_enabled() -> bool {
+    PERIOD_FS.load(Ordering::Acquire) != 0
+}
+
+/// Femtoseconds per main counter tick (0 without an HPET)
+pub fn period_fs() -> u64 {
+    PERIOD_FS.load(Ordering::Acquire)
+}
+
+/// Boot time already elapsed when the main counter started
+pub fn start_ns() -> u64 {
+    START_NS.load(Ordering::Relaxed)
+}
+
+/// The 64-bit main counter
+pub fn read_counter() -> u64 {
+    if !is_enabled() { return 0; }
+    unsafe { read(REG_MAIN_COUNTER) }
+}
@@ -15,6 +15,13 @@ pub fn push_key(c: char) {
     });
 }
 
+// Helper to look at the next key without taking it
+pub fn peek_key() -> Option<char> {
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        KEYBOARD_BUFFER.lock().front().copied()
+    })
+}
+
 // Helper to pop a key
 pub fn pop_key() -> Option<char> {
     x86_64::instructions::interrupts::without_interrupts(|| {
@@ -7,10 +7,17 @@ use x86_64::instructions::port::Port;
 use pc_keyboard::{layouts, DecodedKey, HandleControl, Keyboard, ScancodeSet1};
 use crate::{state, input, writer, gdt, scheduler};
 use core::sync::atomic::{Ordering, AtomicBool};
-use crate::scheduler::{TaskContext, SCHEDULER, SCHEDULER_CONTEXT};
+use crate::scheduler::{TaskContext, SCHEDULER};
 
 static CTRL_PRESSED: AtomicBool = AtomicBool::new(false);
 static SHIFT_PRESSED: AtomicBool = AtomicBool::new(false);
+static ALT_PRESSED: AtomicBool = AtomicBool::new(false);
+static CAPS_LOCK: AtomicBool = AtomicBool::new(false);
+static NUM_LOCK: AtomicBool = AtomicBool::new(true); // pc_keyboard starts with Num Lock on
+
+pub fn is_caps_lock() -> bool {
+    CAPS_LOCK.load(Ordering::Relaxed)
+}
 
 // --- CONFIGURATION ---
 pub const PIC_1_OFFSET: u8 = 32;
@@ -22,6 +29,7 @@ pub const SYSCALL_IRQ: u8 = 0x80;
 pub enum InterruptIndex {
     Timer = PIC_1_OFFSET,
     Keyboard = PIC_1_OFFSET + 1,
+    Serial = PIC_1_OFFSET + 4, // COM1
     Mouse = PIC_2_OFFSET + 4,
 }
 
@@ -29,10 +37,33 @@ pub static PICS: Mutex<ChainedPics> = Mutex::new(unsafe {
     ChainedPics::new(PIC_1_OFFSET, PIC_2_OFFSET) 
 });
 
+/// Acknowledges a hardware interrupt on whichever controller is delivering them
+fn end_of_interrupt(irq: InterruptIndex) {
+    if crate::lapic::is_enabled() {
+        crate::lapic::eoi();
+    } else {
+        unsafe { PICS.lock().notify_end_of_interrupt(irq as u8); }
+    }
+}
+
+/// Hands interrupt delivery from the 8259 PICs to the LAPIC + IOAPIC.
+/// The PICs stay remapped (so a stray IRQ can't land on an exception vector) but fully masked.
+pub fn switch_to_apic(ioapic_base: u64) {
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        unsafe {
+            Port::<u8>::new(0x21).write(0xFF);
+            Port::<u8>::new(0xA1).write(0xFF);
+        }
+        let id = crate::lapic::init();
+        crate::ioapic::init(ioapic_base);
+        writer::print(&alloc::format!("[APIC] LAPIC {} enabled, PIC masked\n", id));
+    });
+}
+
 pub fn enable_listening() {
     unsafe {
         let mut port = Port::<u8>::new(0x21);
-        port.write(0xF8); 
+        port.write(0xE8); // Timer, keyboard, cascade, COM1
         let mut port2 = Port::<u8>::new(0xA1);
         port2.write(0xEF);
     }
@@ -56,8 +87,16 @@ lazy_static! {
 lazy_static! {
     static ref IDT: InterruptDescriptorTable = {
         let mut idt = InterruptDescriptorTable::new();
-        idt.breakpoint.set_handler_fn(breakpoint_handler);
-        idt.page_fault.set_handler_fn(page_fault_handler);
+        let debug_stop = unsafe {
+            core::mem::transmute::<*const (), x86_64::structures::idt::HandlerFunc>(debug_stop_entry as *const ())
+        };
+        idt.breakpoint.set_handler_fn(debug_stop);
+        idt.debug.set_handler_fn(debug_stop);
+        idt.device_not_available.set_handler_fn(nm_handler);
+        unsafe {
+            idt.page_fault.set_handler_fn(page_fault_handler)
+                .set_stack_index(gdt::PAGE_FAULT_IST_INDEX);
+        }
         idt.general_protection_fault.set_handler_fn(general_protection_fault_handler);
         
         unsafe {
@@ -70,6 +109,10 @@ lazy_static! {
                 .set_handler_fn(keyboard_interrupt_handler)
                 .set_stack_index(gdt::INTERRUPT_IST_INDEX);
                 
+            idt[InterruptIndex::Serial as usize]
+                .set_handler_fn(serial_interrupt_handler)
+                .set_stack_index(gdt::INTERRUPT_IST_INDEX);
+
             idt[InterruptIndex::Mouse as usize]
                 .set_handler_fn(mouse_interrupt_handler)
                 .set_stack_index(gdt::INTERRUPT_IST_INDEX);
@@ -78,6 +121,12 @@ lazy_static! {
                 .set_handler_fn(core::mem::transmute(timer_interrupt_handler as *const ()))
                 .set_stack_index(gdt::INTERRUPT_IST_INDEX);
             
+            idt[crate::lapic::SPURIOUS_VECTOR as usize]
+                .set_handler_fn(spurious_interrupt_handler);
+
+            idt[crate::lapic::TLB_SHOOTDOWN_VECTOR as usize]
+                .set_handler_fn(tlb_shootdown_handler);
+
             // SYSTEM CALL (0x80)
             idt[SYSCALL_IRQ as usize]
                 .set_handler_fn(core::mem::transmute(syscall_handler as *const ()))
@@ -95,16 +144,147 @@ pub fn init_idt() {
 
 // --- HANDLERS ---
 
-extern "x86-interrupt" fn breakpoint_handler(_stack_frame: InterruptStackFrame) {}
+// #BP (int3) and #DB (raised after each instruction while RFLAGS.TF is set, gdb's single
+// step). Neither pushes an error code, so the stack is laid out like the timer's: push the
+// registers in TaskContext order and hand gdb the whole thing. Whatever it writes into the
+// context is popped back into the registers.
+#[unsafe(naked)]
+pub extern "C" fn debug_stop_entry() {
+    core::arch::naked_asm!(
+        "push rax",
+        "push rbx",
+        "push rcx",
+        "push rdx",
+        "push rsi",
+        "push rdi",
+        "push rbp",
+        "push r8",
+        "push r9",
+        "push r10",
+        "push r11",
+        "push r12",
+        "push r13",
+        "push r14",
+        "push r15",
+        "mov rdi, rsp",
+        "call {handle_stop}",
+        "pop r15",
+        "pop r14",
+        "pop r13",
+        "pop r12",
+        "pop r11",
+        "pop r10",
+        "pop r9",
+        "pop r8",
+        "pop rbp",
+        "pop rdi",
+        "pop rsi",
+        "pop rdx",
+        "pop rcx",
+        "pop rbx",
+        "pop rax",
+        "iretq",
+        handle_stop = sym handle_debug_stop,
+    );
+}
+
+extern "C" fn handle_debug_stop(context: *mut TaskContext) {
+    if crate::kdbg::is_active() {
+        crate::kdbg::enter(unsafe { &mut *context });
+    }
+}
+
+/// Points the interrupted frame at `task_exit` on the task's own kernel stack, so the
+/// running task removes itself via syscall 2 once the handler returns. Kernel selectors
+/// too: the fault may have come from ring 3.
+fn resume_in_task_exit(frame: &mut InterruptStackFrame, stack_top: u64) {
+    let (code, data) = gdt::get_kernel_selectors();
+    unsafe {
+        frame.as_mut().update(|f| {
+            f.instruction_pointer = x86_64::VirtAddr::new(scheduler::task_exit as *const () as u64);
+            f.stack_pointer = x86_64::VirtAddr::new(stack_top);
+            f.code_segment = code as u64;
+            f.stack_segment = data as u64;
+        });
+    }
+}
+
+// #NM: the running task touched the FPU while CR0.TS was set (see scheduler's lazy FPU
+// switching). Hand the FPU over from its last owner to this task.
+extern "x86-interrupt" fn nm_handler(_stack_frame: InterruptStackFrame) {
+    let cpu = crate::smp::cpu_id();
+    // 1. Clear TS first: FXSAVE/FXRSTOR would trap again otherwise
+    unsafe { core::arch::asm!("clts", options(nomem, nostack)); }
+
+    let mut sched = SCHEDULER.lock();
+    let current = match sched.current() {
+        Some(idx) => idx,
+        None => return,
+    };
+    let owner = scheduler::LAST_FPU_OWNER[cpu].load(Ordering::Relaxed);
+    if owner == current { return; }
+
+    // 2. Park the previous owner's registers in its FXSAVE area
+    if let Some(prev) = sched.tasks.get_mut(owner) {
+        if prev.fpu_used {
+            let area = &mut *prev.fpu_state as *mut scheduler::FpuState;
+            unsafe { core::arch::asm!("fxsave64 [{}]", in(reg) area, options(nostack)); }
+        }
+    }
+
+    // 3. Load this task's registers and make it the owner
+    let task = &mut sched.tasks[current];
+    let area = &*task.fpu_state as *const scheduler::FpuState;
+    unsafe { core::arch::asm!("fxrstor64 [{}]", in(reg) area, options(nostack)); }
+    task.fpu_used = true;
+    scheduler::LAST_FPU_OWNER[cpu].store(current, Ordering::Relaxed);
+}
 
 extern "x86-interrupt" fn page_fault_handler(
-    _stack_frame: InterruptStackFrame,
+    mut _stack_frame: InterruptStackFrame,
     error_code: PageFaultErrorCode,
 ) {
     x86_64::instructions::interrupts::disable();
     
     let cr2 = x86_64::registers::control::Cr2::read();
 
+    // First touch of a demand-paged (BSS) page: back it and retry the instruction
+    if !error_code.contains(PageFaultErrorCode::PROTECTION_VIOLATION) && crate::memory::handle_demand_fault(cr2.as_u64()) {
+        return;
+    }
+
+    // Stack overflow into the running task's guard page: kill just that task.
+    // try_lock: the fault may have happened while the scheduler lock was held.
+    if let Some(sched) = SCHEDULER.try_lock() {
+        if let Some(task) = sched.current().and_then(|idx| sched.tasks.get(idx)) {
+            if let Some(guard) = task.guard_page {
+                if (guard..guard + 4096).contains(&cr2.as_u64()) {
+                    crate::serial_print!("[SCHED] Stack Overflow in task {}\n", task.name);
+                    crate::logger::log(&alloc::format!("Stack Overflow in task {}\n", task.name));
+                    resume_in_task_exit(&mut _stack_frame, task.stack_top());
+                    return;
+                }
+            }
+        }
+    }
+
+    // With gdb attached, stop there instead of halting; it may fix RIP or memory first
+    if crate::kdbg::is_active() {
+        crate::serial_print!("[EXCEPTION: PAGE FAULT] CR2={:x}, entering debugger\n", cr2);
+        if crate::kdbg::enter_exception(&mut _stack_frame) {
+            return;
+        }
+        // Continued with nothing changed: retrying would fault again forever. Kill the task,
+        // or halt below if the kernel itself faulted.
+        let top = SCHEDULER.try_lock()
+            .and_then(|sched| sched.current().and_then(|idx| sched.tasks.get(idx)).map(|t| t.stack_top()));
+        if let Some(top) = top {
+            crate::serial_print!("[KDBG] Nothing changed, killing the faulting task\n");
+            resume_in_task_exit(&mut _stack_frame, top);
+            return;
+        }
+    }
+
     writer::print("\n\n[EXCEPTION: PAGE FAULT]\n");
     writer::print("-----------------------\n");
     
@@ -194,20 +374,23 @@ pub extern "C" fn timer_interrupt_handler() {
 }
 
 extern "C" fn handle_timer_preemption(context: *mut TaskContext) {
-
+    // APs get this vector from their LAPIC timers; only the BSP's PIT tick counts time
+    if crate::smp::cpu_id() == 0 {
+        state::TICK_COUNT.fetch_add(1, Ordering::Relaxed);
+    }
     
     let mut sched = SCHEDULER.lock();
-    if let Some(idx) = sched.current_task_idx {
+    if let Some(idx) = sched.current() {
         unsafe {
             // 1. Save Task Context
             sched.tasks[idx].context = *context;
             // 2. Load Scheduler Context (Swap!) with interrupts enabled
-            *context = SCHEDULER_CONTEXT;
+            *context = *scheduler::scheduler_context();
             (*context).rflags |= 0x200; // Force IF bit
         }
     }
 
-    unsafe { PICS.lock().notify_end_of_interrupt(InterruptIndex::Timer as u8); }
+    end_of_interrupt(InterruptIndex::Timer);
 }
 
 extern "x86-interrupt" fn keyboard_interrupt_handler(_stack_frame: InterruptStackFrame) {
@@ -215,6 +398,12 @@ extern "x86-interrupt" fn keyboard_interrupt_handler(_stack_frame: InterruptStac
     let mut port = Port::new(0x60);
     let scancode: u8 = unsafe { port.read() };
 
+    // Replies to LED commands aren't keystrokes
+    if scancode == crate::keyboard::ACK || scancode == crate::keyboard::RESEND {
+        end_of_interrupt(InterruptIndex::Keyboard);
+        return;
+    }
+
     state::KEY_COUNT.fetch_add(1, Ordering::Relaxed);
 
     if let Ok(Some(key_event)) = keyboard.add_byte(scancode) {
@@ -228,12 +417,45 @@ extern "x86-interrupt" fn keyboard_interrupt_handler(_stack_frame: InterruptStac
             KeyCode::LShift | KeyCode::RShift => {
                 SHIFT_PRESSED.store(key_event.state == pc_keyboard::KeyState::Down, Ordering::Relaxed);
             }
+            KeyCode::LAlt | KeyCode::RAltGr => {
+                ALT_PRESSED.store(key_event.state == pc_keyboard::KeyState::Down, Ordering::Relaxed);
+            }
+            KeyCode::CapsLock | KeyCode::NumpadLock if key_event.state == pc_keyboard::KeyState::Up => {
+                let lock = if key_event.code == KeyCode::CapsLock { &CAPS_LOCK } else { &NUM_LOCK };
+                lock.fetch_xor(true, Ordering::Relaxed);
+                crate::keyboard::set_leds(CAPS_LOCK.load(Ordering::Relaxed), NUM_LOCK.load(Ordering::Relaxed), false);
+            }
             _ => {}
         }
 
+        // Alt+1..4: switch workspace (U+E010..U+E013, handled by the shell)
+        if ALT_PRESSED.load(Ordering::Relaxed) && key_event.state == pc_keyboard::KeyState::Down {
+            let workspace = match key_event.code {
+                KeyCode::Key1 => Some('\u{E010}'),
+                KeyCode::Key2 => Some('\u{E011}'),
+                KeyCode::Key3 => Some('\u{E012}'),
+                KeyCode::Key4 => Some('\u{E013}'),
+                _ => None,
+            };
+            if let Some(key) = workspace {
+                input::push_key(key);
+                end_of_interrupt(InterruptIndex::Keyboard);
+                return;
+            }
+        }
+
         let ctrl = CTRL_PRESSED.load(Ordering::Relaxed);
         let shift = SHIFT_PRESSED.load(Ordering::Relaxed);
 
+        // Ctrl+Tab / Ctrl+Shift+Tab: next / previous window (U+E007 / U+E008)
+        if ctrl && key_event.code == KeyCode::Tab {
+            if key_event.state == pc_keyboard::KeyState::Down {
+                input::push_key(if shift { '\u{E008}' } else { '\u{E007}' });
+            }
+            end_of_interrupt(InterruptIndex::Keyboard);
+            return;
+        }
+
         if ctrl && shift && key_event.state == pc_keyboard::KeyState::Down {
             match key_event.code {
                 KeyCode::C => { input::push_key('\u{E004}'); },
@@ -274,7 +496,7 @@ extern "x86-interrupt" fn keyboard_interrupt_handler(_stack_frame: InterruptStac
             }
         }
     }
-    unsafe { PICS.lock().notify_end_of_interrupt(InterruptIndex::Keyboard as u8); }
+    end_of_interrupt(InterruptIndex::Keyboard);
 }
 
 #[unsafe(naked)]
@@ -317,6 +539,123 @@ pub extern "C" fn syscall_handler() {
     );
 }
 
+// --- SYSCALL / SYSRET ---
+const IA32_EFER: u32 = 0xC000_0080;
+const IA32_STAR: u32 = 0xC000_0081;
+const IA32_LSTAR: u32 = 0xC000_0082;
+const IA32_FMASK: u32 = 0xC000_0084;
+const EFER_SCE: u64 = 1;
+// RFLAGS bits SYSCALL clears on entry: TF, IF, DF, IOPL, NT and AC (what Linux masks).
+// The kernel must never run with a user's DF (string copies backwards) or AC left set.
+const SYSCALL_RFLAGS_MASK: u64 = 0x47700;
+
+// SYSCALL doesn't switch stacks, so the entry stub moves onto this one by hand.
+// Ring-3 tasks only run on the BSP (only it has the MSRs set up) and IF is masked on
+// entry, so one stack (and one scratch slot) is enough.
+const SYSCALL_STACK_SIZE: usize = 4096 * 5;
+#[repr(align(16))]
+struct SyscallStack([u8; SYSCALL_STACK_SIZE]);
+static mut SYSCALL_STACK: SyscallStack = SyscallStack([0; SYSCALL_STACK_SIZE]);
+static mut SYSCALL_STACK_TOP: u64 = 0;
+static mut SYSCALL_USER_RSP: u64 = 0;
+static mut SYSCALL_USER_CS: u64 = 0;
+static mut SYSCALL_USER_SS: u64 = 0;
+
+/// Enables the SYSCALL instruction, entering at `syscall_entry`
+pub fn init_syscall_msr() {
+    use x86_64::registers::model_specific::Msr;
+    let (kernel_cs, _) = gdt::get_kernel_selectors();
+    let (user_cs, user_ss) = gdt::get_user_selectors();
+    unsafe {
+        SYSCALL_STACK_TOP = core::ptr::addr_of!(SYSCALL_STACK) as u64 + SYSCALL_STACK_SIZE as u64;
+        SYSCALL_USER_CS = user_cs as u64;
+        SYSCALL_USER_SS = user_ss as u64;
+
+        // STAR[47:32]: SYSCALL loads CS from here and SS = CS + 8.
+        // STAR[63:48]: SYSRETQ loads CS = base + 16 and SS = base + 8, so the base is the
+        // selector just below user data (the GDT has user data right before user code).
+        let sysret_base = (user_ss - 8) as u64;
+        Msr::new(IA32_STAR).write((sysret_base << 48) | ((kernel_cs as u64) << 32));
+        Msr::new(IA32_LSTAR).write(syscall_entry as *const () as u64);
+        Msr::new(IA32_FMASK).write(SYSCALL_RFLAGS_MASK);
+
+        let mut efer = Msr::new(IA32_EFER);
+        let value = efer.read();
+        efer.write(value | EFER_SCE);
+    }
+}
+
+/// SYSCALL lands here with rcx = user RIP, r11 = user RFLAGS and the user stack still live.
+/// Builds the same frame `int 0x80` would (so yield/exit can swap it), runs the common
+/// dispatcher and leaves with SYSRETQ, or IRETQ if the frame now points into the kernel.
+#[unsafe(naked)]
+pub extern "C" fn syscall_entry() {
+    core::arch::naked_asm!(
+        // 1. Switch to the syscall stack
+        "mov [rip + {user_rsp}], rsp",
+        "mov rsp, [rip + {stack_top}]",
+
+        // 2. Fake interrupt frame: ss, rsp, rflags, cs, rip
+        "push qword ptr [rip + {user_ss}]",
+        "push qword ptr [rip + {user_rsp}]",
+        "push r11",
+        "push qword ptr [rip + {user_cs}]",
+        "push rcx",
+
+        // 3. Same register layout as TaskContext
+        "push rax",
+        "push rbx",
+        "push rcx",
+        "push rdx",
+        "push rsi",
+        "push rdi",
+        "push rbp",
+        "push r8",
+        "push r9",
+        "push r10",
+        "push r11",
+        "push r12",
+        "push r13",
+        "push r14",
+        "push r15",
+        "mov rdi, rsp",
+        "call {handle_syscall}",
+        "pop r15",
+        "pop r14",
+        "pop r13",
+        "pop r12",
+        "pop r11",
+        "pop r10",
+        "pop r9",
+        "pop r8",
+        "pop rbp",
+        "pop rdi",
+        "pop rsi",
+        "pop rdx",
+        "pop rcx",
+        "pop rbx",
+        "pop rax",
+
+        // 4. yield/exit swap in the scheduler's kernel context: that needs IRETQ
+        "push rax",
+        "mov rax, [rsp + 16]",             // cs
+        "cmp rax, [rip + {user_cs}]",
+        "pop rax",
+        "jne 2f",
+        "mov rcx, [rsp]",                  // rip
+        "mov r11, [rsp + 16]",             // rflags
+        "mov rsp, [rsp + 24]",             // user rsp
+        "sysretq",
+        "2:",
+        "iretq",
+        user_rsp = sym SYSCALL_USER_RSP,
+        stack_top = sym SYSCALL_STACK_TOP,
+        user_cs = sym SYSCALL_USER_CS,
+        user_ss = sym SYSCALL_USER_SS,
+        handle_syscall = sym handle_syscall_rust,
+    );
+}
+
 extern "C" fn handle_syscall_rust(context: *mut TaskContext) {
     let rax = unsafe { (*context).rax };
     let rdi = unsafe { (*context).rdi };
@@ -332,36 +671,113 @@ extern "C" fn handle_syscall_rust(context: *mut TaskContext) {
         }
         2 => { // exit
             let mut sched = SCHEDULER.lock();
-            if let Some(idx) = sched.current_task_idx {
-                sched.tasks.remove(idx);
-                sched.current_task_idx = None;
+            if sched.remove_current().is_some() {
                 // Switch back to scheduler with interrupts enabled!
                 unsafe { 
-                    *context = SCHEDULER_CONTEXT;
+                    *context = *scheduler::scheduler_context();
                     (*context).rflags |= 0x200; // Force IF bit
                 }
             }
         }
         3 => { // yield
             let mut sched = SCHEDULER.lock();
-            if let Some(idx) = sched.current_task_idx {
+            if let Some(idx) = sched.current() {
                 // 1. Save Task Context!
                 sched.tasks[idx].context = unsafe { *context };
                 
                 // 2. Switch back to scheduler with interrupts enabled!
                 unsafe { 
-                    *context = SCHEDULER_CONTEXT;
+                    *context = *scheduler::scheduler_context();
                     (*context).rflags |= 0x200; // Force IF bit
                 }
             }
         }
+        4 => { // kill: rdi = name ptr, rsi = name len; rax = 1 if a task was removed
+            let killed = crate::memory::is_user_range(rdi, rsi as usize, false) && {
+                let name = unsafe { core::str::from_utf8(core::slice::from_raw_parts(rdi as *const u8, rsi as usize)) };
+                name.is_ok_and(crate::scheduler::kill_by_name)
+            };
+            unsafe { (*context).rax = killed as u64; }
+        }
+        5 => { // shmem_create: rdi = key, rsi = size; rax = kernel virt address, 0 on failure
+            let virt = crate::shmem::shmem_create(rdi as u32, rsi as usize).unwrap_or(0);
+            unsafe { (*context).rax = virt; }
+        }
+        6 => { // shmem_attach: rdi = key, rsi = user virt address; rax = 1 on success
+            let ok = crate::shmem::shmem_attach(rdi as u32, rsi);
+            unsafe { (*context).rax = ok as u64; }
+        }
+        7 => { // pipe_create: rax = pipe ID
+            let id = crate::pipe::create();
+            unsafe { (*context).rax = id as u64; }
+        }
+        8 => { // pipe_write: rdi = ID, rsi = data ptr, rdx = len; rax = bytes written
+            let len = unsafe { (*context).rdx } as usize;
+            let written = match crate::pipe::get(rdi as usize) {
+                Some(pipe) if crate::memory::is_user_range(rsi, len, false) => {
+                    pipe.write(unsafe { core::slice::from_raw_parts(rsi as *const u8, len) })
+                }
+                _ => 0,
+            };
+            unsafe { (*context).rax = written as u64; }
+        }
+        9 => { // pipe_read: rdi = ID, rsi = buffer ptr, rdx = max; rax = bytes read
+            let max = unsafe { (*context).rdx } as usize;
+            // Checked before reading, so a bad buffer doesn't lose the pipe's data
+            let data = match crate::pipe::get(rdi as usize) {
+                Some(pipe) if crate::memory::is_user_range(rsi, max, true) => pipe.read(max),
+                _ => alloc::vec::Vec::new(),
+            };
+            unsafe {
+                core::ptr::copy_nonoverlapping(data.as_ptr(), rsi as *mut u8, data.len());
+                (*context).rax = data.len() as u64;
+            }
+        }
+        10 => { // mmap: rdi = virt hint (0 = any), rsi = length, rdx = flags, r10/r8 = path ptr/len; rax = address, 0 on failure
+            let (flags, path_ptr, path_len) = unsafe { ((*context).rdx, (*context).r10, (*context).r8 as usize) };
+            let path = if flags & crate::shmem::MAP_FILE != 0 && crate::memory::is_user_range(path_ptr, path_len, false) {
+                unsafe { core::str::from_utf8(core::slice::from_raw_parts(path_ptr as *const u8, path_len)).ok() }
+            } else {
+                None
+            };
+            let virt = crate::shmem::mmap(rdi, rsi as usize, flags, path).unwrap_or(0);
+            unsafe { (*context).rax = virt; }
+        }
+        11 => { // munmap: rdi = address returned by mmap; rax = 1 on success
+            let ok = crate::shmem::munmap(rdi);
+            unsafe { (*context).rax = ok as u64; }
+        }
+        12 => { // pipe_close: rdi = ID, rsi = 0 for the read end, 1 for the write end; rax = 1 on success
+            let ok = crate::pipe::close(rdi as usize, rsi != 0);
+            unsafe { (*context).rax = ok as u64; }
+        }
+        18 => { // select: next key routed to this task, 0 if none pending
+            let mut sched = SCHEDULER.lock();
+            let key = sched.current()
+                .and_then(|idx| sched.tasks[idx].input_buf.pop_front())
+                .map(|c| c as u64)
+                .unwrap_or(0);
+            unsafe { (*context).rax = key; }
+        }
         _ => {}
     }
 }
 
 extern "x86-interrupt" fn mouse_interrupt_handler(_stack_frame: InterruptStackFrame) {
     crate::mouse::handle_interrupt();
-    unsafe {
-        PICS.lock().notify_end_of_interrupt(InterruptIndex::Mouse as u8);
-    }
+    end_of_interrupt(InterruptIndex::Mouse);
+}
+
+extern "x86-interrupt" fn serial_interrupt_handler(_stack_frame: InterruptStackFrame) {
+    crate::serial::handle_interrupt();
+    end_of_interrupt(InterruptIndex::Serial);
+}
+
+// The LAPIC raises this when an interrupt is withdrawn before delivery. No EOI.
+extern "x86-interrupt" fn spurious_interrupt_handler(_stack_frame: InterruptStackFrame) {}
+
+/// Another CPU took access away from a kernel page (see `memory::flush_tlb_all_cpus`)
+extern "x86-interrupt" fn tlb_shootdown_handler(_stack_frame: InterruptStackFrame) {
+    crate::memory::flush_local_tlb();
+    crate::lapic::eoi();
 }
\ No newline at end of file
@@ -0,0 +1,66 @@
+use crate::{acpi, interrupts, memory, writer};
+use core::sync::atomic::{AtomicU64, Ordering};
+
+// --- REGISTERS ---
+// The IOAPIC is accessed indirectly: write the register index to IOREGSEL, then use IOWIN
+const IOREGSEL: u64 = 0x00;
+const IOWIN: u64 = 0x10;
+const REG_VER: u32 = 0x01;
+const REG_REDTBL: u32 = 0x10;
+
+// Redirection entry bits
+const ACTIVE_LOW: u64 = 1 << 13;
+const LEVEL_TRIGGERED: u64 = 1 << 15;
+const MASKED: u64 = 1 << 16;
+
+static IOAPIC_VIRT: AtomicU64 = AtomicU64::new(0);
+
+unsafe fn read(reg: u32) -> u32 {
+    let base = IOAPIC_VIRT.load(Ordering::Relaxed);
+    core::ptr::write_volatile((base + IOREGSEL) as *mut u32, reg);
+    core::ptr::read_volatile((base + IOWIN) as *const u32)
+}
+
+unsafe fn write(reg: u32, value: u32) {
+    let base = IOAPIC_VIRT.load(Ordering::Relaxed);
+    core::ptr::write_volatile((base + IOREGSEL) as *mut u32, reg);
+    core::ptr::write_volatile((base + IOWIN) as *mut u32, value);
+}
+
+fn set_redirection(gsi: u32, entry: u64) {
+    unsafe {
+        write(REG_REDTBL + gsi * 2, entry as u32);
+        write(REG_REDTBL + gsi * 2 + 1, (entry >> 32) as u32);
+    }
+}
+
+/// Routes an ISA IRQ to `vector` on LAPIC `dest`, honouring MADT source overrides
+/// (QEMU, for one, wires the PIT to GSI 2 instead of 0).
+fn route_isa_irq(irq: u8, vector: u8, dest: u8) {
+    let (gsi, flags) = acpi::isa_irq_to_gsi(irq);
+    let mut entry = vector as u64 | ((dest as u64) << 56);
+    // MPS INTI flags: polarity in bits 0-1 (3 = active low), trigger mode in bits 2-3 (3 = level)
+    if flags & 0x3 == 0x3 { entry |= ACTIVE_LOW; }
+    if (flags >> 2) & 0x3 == 0x3 { entry |= LEVEL_TRIGGERED; }
+    set_redirection(gsi, entry);
+}
+
+/// Maps the IOAPIC at `ioapic_base` and sends the timer, keyboard, COM1 and mouse to LAPIC 0.
+/// Everything else stays masked.
+pub fn init(ioapic_base: u64) {
+    IOAPIC_VIRT.store(unsafe { memory::map_mmio_page(ioapic_base) }, Ordering::Relaxed);
+
+    // 1. Mask every input first
+    let max_entry = (unsafe { read(REG_VER) } >> 16) & 0xFF;
+    for gsi in 0..=max_entry {
+        set_redirection(gsi, MASKED);
+    }
+
+    // 2. The devices we actually drive
+    route_isa_irq(0, interrupts::InterruptIndex::Timer as u8, 0);
+    route_isa_irq(1, interrupts::InterruptIndex::Keyboard as u8, 0);
+    route_isa_irq(4, interrupts::InterruptIndex::Serial as u8, 0);
+    route_isa_irq(12, interrupts::InterruptIndex::Mouse as u8, 0);
+
+    writer::print(&alloc::format!("[IOAPIC] {} inputs, IRQ 0/1/4/12 routed to LAPIC 0\n", max_entry + 1));
+}
@@ -0,0 +1,331 @@
+use crate::serial::{SerialPort, SERIAL1, SERIAL_RX_BUF};
+use crate::scheduler::TaskContext;
+use x86_64::structures::idt::InterruptStackFrame;
+use x86_64::registers::control::{Cr0, Cr0Flags};
+use core::sync::atomic::{AtomicBool, Ordering};
+
+// --- GDB REMOTE STUB ---
+// Speaks the GDB Remote Serial Protocol on COM1. Run QEMU with
+// `-serial tcp::1234,server,nowait` and `target remote :1234` from gdb.
+// Everything here runs inside exception handlers, so no heap: packets live in fixed buffers.
+
+static ACTIVE: AtomicBool = AtomicBool::new(false);
+
+const BUF_SIZE: usize = 1024;
+const MAX_MEM_READ: usize = (BUF_SIZE - 4) / 2; // Two hex digits per byte
+const STARTUP_POLLS: usize = 2_000_000;
+const RFLAGS_TF: u64 = 1 << 8; // Trap flag: #DB after the next instruction
+
+/// Whether gdb has said hello on the serial line
+pub fn is_active() -> bool {
+    ACTIVE.load(Ordering::Relaxed)
+}
+
+/// Listens briefly for a `$` from gdb. gdb opens with a packet (normally `qSupported`),
+/// which is acknowledged and answered with "unsupported" so it falls back to defaults.
+pub fn init() {
+    let connected = x86_64::instructions::interrupts::without_interrupts(|| {
+        let mut serial = SERIAL1.lock();
+        for _ in 0..STARTUP_POLLS {
+            if serial.try_receive() == Some(b'$') {
+                let mut buf = [0u8; BUF_SIZE];
+                let _ = read_packet_body(&mut serial, &mut buf);
+                send_packet(&mut serial, b"");
+                return true;
+            }
+            core::hint::spin_loop();
+        }
+        false
+    });
+    if connected {
+        ACTIVE.store(true, Ordering::Relaxed);
+        crate::klog_info!("[KDBG] GDB connected on COM1.\n");
+    }
+}
+
+/// Stops in the debugger from an `x86-interrupt` handler (the page fault). The general
+/// purpose registers aren't reachable from there: `g` reports them as unavailable and `G`
+/// is refused. RIP/RSP/RFLAGS/CS/SS are real, and `c`/`s` with an address move RIP.
+/// False if gdb continued without changing anything, so the faulting instruction would
+/// just fault again.
+pub fn enter_exception(frame: &mut InterruptStackFrame) -> bool {
+    let mut context = TaskContext {
+        rip: frame.instruction_pointer.as_u64(),
+        cs: frame.code_segment,
+        rflags: frame.cpu_flags,
+        rsp: frame.stack_pointer.as_u64(),
+        ss: frame.stack_segment,
+        ..TaskContext::default()
+    };
+    let changed = serve(&mut context, false);
+    unsafe {
+        frame.as_mut().update(|f| {
+            f.instruction_pointer = x86_64::VirtAddr::new(context.rip);
+            f.stack_pointer = x86_64::VirtAddr::new(context.rsp);
+            f.cpu_flags = context.rflags;
+        });
+    }
+    changed
+}
+
+/// Stops in the debugger with the full saved context (#BP and #DB come in through
+/// `interrupts::debug_stop_entry`, which pushes every register). `G` writes go straight
+/// into `context` and are restored on the way out.
+pub fn enter(context: &mut TaskContext) {
+    if !is_active() { return; }
+    serve(context, true);
+}
+
+/// Reports a stop to gdb and serves its requests until it continues (`c`) or steps (`s`).
+/// `gprs` says whether the general purpose registers in `context` are the real ones.
+/// Returns whether gdb changed registers or memory, or asked for a single step.
+fn serve(context: &mut TaskContext, gprs: bool) -> bool {
+    let mut changed = false;
+
+    // The interrupted code may have been printing; it won't run again until we return
+    if SERIAL1.is_locked() {
+        unsafe { SERIAL1.force_unlock(); }
+    }
+    let mut serial = SERIAL1.lock();
+
+    context.rflags &= !RFLAGS_TF; // A finished single step
+    send_packet(&mut serial, b"S05");
+
+    loop {
+        let mut buf = [0u8; BUF_SIZE];
+        let len = read_packet(&mut serial, &mut buf);
+        let packet = &buf[..len];
+        let mut reply = Reply::new();
+
+        match packet.first() {
+            Some(b'?') => reply.push_str(b"S05"),
+            Some(b'g') => {
+                let regs = registers(context);
+                for (i, value) in regs[..16].iter().enumerate() {
+                    // "xx" per byte marks a register gdb can't have; RSP (7) is in the frame
+                    if gprs || i == 7 { reply.push_hex_le(*value, 8); } else { reply.push_str(b"xxxxxxxxxxxxxxxx"); }
+                }
+                reply.push_hex_le(regs[16], 8); // rip
+                for value in [context.rflags, context.cs, context.ss, 0, 0, 0, 0] {
+                    reply.push_hex_le(value, 4); // eflags, cs, ss, ds, es, fs, gs
+                }
+            }
+            Some(b'G') => {
+                let ok = gprs && write_registers(context, &packet[1..]);
+                changed |= ok;
+                reply.push_str(if ok { b"OK" } else { b"E01" });
+            }
+            Some(b'm') => match parse_addr_len(&packet[1..]).map(|(a, l)| (a, l.min(MAX_MEM_READ))) {
+                Some((addr, len)) if readable(addr, len) => {
+                    for i in 0..len {
+                        reply.push_byte(unsafe { core::ptr::read_volatile((addr + i as u64) as *const u8) });
+                    }
+                }
+                _ => reply.push_str(b"E14"),
+            },
+            Some(b'M') => {
+                let ok = match split_once(&packet[1..], b':') {
+                    Some((head, data)) => match parse_addr_len(head) {
+                        Some((addr, len)) if data.len() == len * 2 && readable(addr, len)
+                            && data.iter().all(|&c| hex_digit(c).is_some()) => {
+                            // gdb plants breakpoints in kernel text, which is mapped read-only
+                            let cr0 = Cr0::read();
+                            unsafe { Cr0::write(cr0 - Cr0Flags::WRITE_PROTECT); }
+                            for (i, pair) in data.chunks(2).enumerate() {
+                                let byte = parse_hex(pair).unwrap_or(0) as u8;
+                                unsafe { core::ptr::write_volatile((addr + i as u64) as *mut u8, byte); }
+                            }
+                            unsafe { Cr0::write(cr0); }
+                            true
+                        }
+                        _ => false,
+                    },
+                    None => false,
+                };
+                changed |= ok;
+                reply.push_str(if ok { b"OK" } else { b"E14" });
+            }
+            Some(b'c') => {
+                // Optional resume address; iretq then restores the interrupted code's RFLAGS (and IF)
+                if let Some(addr) = parse_hex(&packet[1..]) {
+                    changed |= addr != context.rip;
+                    context.rip = addr;
+                }
+                return changed;
+            }
+            Some(b's') => {
+                if let Some(addr) = parse_hex(&packet[1..]) { context.rip = addr; }
+                context.rflags |= RFLAGS_TF;
+                return true; // The #DB after the step brings gdb straight back
+            }
+            _ => {} // Empty reply: not supported
+        }
+        send_packet(&mut serial, reply.as_bytes());
+    }
+}
+
+// gdb's amd64 order: rax rbx rcx rdx rsi rdi rbp rsp r8..r15 rip
+fn registers(c: &TaskContext) -> [u64; 17] {
+    [c.rax, c.rbx, c.rcx, c.rdx, c.rsi, c.rdi, c.rbp, c.rsp,
+     c.r8, c.r9, c.r10, c.r11, c.r12, c.r13, c.r14, c.r15, c.rip]
+}
+
+/// `G` payload: 17 little-endian u64s then eflags (u32). Segment registers are ignored.
+fn write_registers(c: &mut TaskContext, hex: &[u8]) -> bool {
+    if hex.len() < 17 * 16 + 8 { return false; }
+    let mut values = [0u64; 18];
+    for (i, value) in values.iter_mut().enumerate() {
+        let width = if i < 17 { 16 } else { 8 };
+        *value = match parse_hex_le(&hex[i * 16..i * 16 + width]) {
+            Some(v) => v,
+            None => return false,
+        };
+    }
+    let [rax, rbx, rcx, rdx, rsi, rdi, rbp, rsp, r8, r9, r10, r11, r12, r13, r14, r15, rip, rflags] = values;
+    c.rax = rax; c.rbx = rbx; c.rcx = rcx; c.rdx = rdx;
+    c.rsi = rsi; c.rdi = rdi; c.rbp = rbp; c.rsp = rsp;
+    c.r8 = r8; c.r9 = r9; c.r10 = r10; c.r11 = r11;
+    c.r12 = r12; c.r13 = r13; c.r14 = r14; c.r15 = r15;
+    c.rip = rip;
+    c.rflags = rflags;
+    true
+}
+
+/// Every page in [addr, addr+len) is mapped, so touching it can't fault inside the stub
+fn readable(addr: u64, len: usize) -> bool {
+    if len == 0 { return true; }
+    let end = match addr.checked_add(len as u64 - 1) {
+        Some(e) => e,
+        None => return false,
+    };
+    let mut page = addr & !0xFFF;
+    while page <= end {
+        if !crate::memory::is_mapped(page) { return false; }
+        page += 0x1000;
+    }
+    true
+}
+
+// --- PACKET FRAMING: $<data>#<checksum> ---
+
+/// Bytes the COM1 IRQ already took off the UART come first
+fn read_byte(serial: &mut SerialPort) -> u8 {
+    loop {
+        if let Some(b) = SERIAL_RX_BUF.try_lock().and_then(|mut buf| buf.pop_front()) { return b; }
+        if let Some(b) = serial.try_receive() { return b; }
+        core::hint::spin_loop();
+    }
+}
+
+/// Waits for the next packet, acks it and returns its length in `buf`. Bad checksums are
+/// nacked and the packet is waited for again.
+fn read_packet(serial: &mut SerialPort, buf: &mut [u8; BUF_SIZE]) -> usize {
+    loop {
+        while read_byte(serial) != b'$' {}
+        if let Some(len) = read_packet_body(serial, buf) {
+            return len;
+        }
+    }
+}
+
+/// Reads up to `#` plus the checksum (the `$` is already consumed) and acks or nacks
+fn read_packet_body(serial: &mut SerialPort, buf: &mut [u8; BUF_SIZE]) -> Option<usize> {
+    let mut len = 0;
+    let mut sum: u8 = 0;
+    loop {
+        let b = read_byte(serial);
+        if b == b'#' { break; }
+        sum = sum.wrapping_add(b);
+        if len < BUF_SIZE { buf[len] = b; }
+        len += 1;
+    }
+    let checksum = [read_byte(serial), read_byte(serial)];
+    if len > BUF_SIZE || parse_hex(&checksum) != Some(sum as u64) {
+        serial.send(b'-');
+        return None;
+    }
+    serial.send(b'+');
+    Some(len)
+}
+
+/// Sends `$data#xx`, resending until gdb acks with `+`
+fn send_packet(serial: &mut SerialPort, data: &[u8]) {
+    let sum = data.iter().fold(0u8, |s, &b| s.wrapping_add(b));
+    loop {
+        serial.send(b'$');
+        for &b in data { serial.send(b); }
+        serial.send(b'#');
+        serial.send(HEX[(sum >> 4) as usize]);
+        serial.send(HEX[(sum & 0xF) as usize]);
+        if read_byte(serial) == b'+' { return; }
+    }
+}
+
+// --- HEX HELPERS ---
+
+const HEX: &[u8; 16] = b"0123456789abcdef";
+
+struct Reply {
+    buf: [u8; BUF_SIZE],
+    len: usize,
+}
+
+impl Reply {
+    fn new() -> Self {
+        Reply { buf: [0; BUF_SIZE], len: 0 }
+    }
+
+    fn push_str(&mut self, s: &[u8]) {
+        for &b in s {
+            if self.len < BUF_SIZE { self.buf[self.len] = b; self.len += 1; }
+        }
+    }
+
+    fn push_byte(&mut self, b: u8) {
+        self.push_str(&[HEX[(b >> 4) as usize], HEX[(b & 0xF) as usize]]);
+    }
+
+    /// The low `bytes` bytes of `value`, least significant first (target byte order)
+    fn push_hex_le(&mut self, value: u64, bytes: usize) {
+        for i in 0..bytes { self.push_byte((value >> (i * 8)) as u8); }
+    }
+
+    fn as_bytes(&self) -> &[u8] {
+        &self.buf[..self.len]
+    }
+}
+
+fn hex_digit(c: u8) -> Option<u64> {
+    match c {
+        b'0'..=b'9' => Some((c - b'0') as u64),
+        b'a'..=b'f' => Some((c - b'a' + 10) as u64),
+        b'A'..=b'F' => Some((c - b'A' + 10) as u64),
+        _ => None,
+    }
+}
+
+/// Big-endian hex number as gdb writes addresses and lengths
+fn parse_hex(s: &[u8]) -> Option<u64> {
+    if s.is_empty() || s.len() > 16 { return None; }
+    s.iter().try_fold(0u64, |acc, &c| Some((acc << 4) | hex_digit(c)?))
+}
+
+/// Hex bytes in target (little-endian) order
+fn parse_hex_le(s: &[u8]) -> Option<u64> {
+    let mut value = 0u64;
+    for (i, pair) in s.chunks(2).enumerate() {
+        value |= parse_hex(pair)? << (i * 8);
+    }
+    Some(value)
+}
+
+fn split_once(s: &[u8], sep: u8) -> Option<(&[u8], &[u8])> {
+    let pos = s.iter().position(|&c| c == sep)?;
+    Some((&s[..pos], &s[pos + 1..]))
+}
+
+/// "addr,len"
+fn parse_addr_len(s: &[u8]) -> Option<(u64, usize)> {
+    let (addr, len) = split_once(s, b',')?;
+    Some((parse_hex(addr)?, parse_hex(len)? as usize))
+}
@@ -0,0 +1,35 @@
+use x86_64::instructions::port::Port;
+
+const DATA_PORT: u16 = 0x60;
+const STATUS_PORT: u16 = 0x64;
+const CMD_SET_LEDS: u8 = 0xED;
+pub const ACK: u8 = 0xFA;
+pub const RESEND: u8 = 0xFE;
+
+// --- LEDS ---
+/// Lights the keyboard's lock LEDs. Each byte sent to the keyboard must be ACKed (0xFA)
+/// before the next one; the ACKs are consumed here so the IRQ handler never decodes them.
+pub fn set_leds(caps: bool, num: bool, scroll: bool) {
+    let mask = (scroll as u8) | ((num as u8) << 1) | ((caps as u8) << 2);
+    unsafe {
+        let mut status = Port::<u8>::new(STATUS_PORT);
+        let mut data = Port::<u8>::new(DATA_PORT);
+        for byte in [CMD_SET_LEDS, mask] {
+            wait_write(&mut status);
+            data.write(byte);
+            if !wait_ack(&mut status, &mut data) { return; }
+        }
+    }
+}
+
+unsafe fn wait_write(port: &mut Port<u8>) {
+    let mut timeout = 100000;
+    while (port.read() & 0x02) != 0 { timeout -= 1; if timeout == 0 { return; } }
+}
+
+unsafe fn wait_ack(status: &mut Port<u8>, data: &mut Port<u8>) -> bool {
+    for _ in 0..100000 {
+        if status.read() & 0x01 != 0 && data.read() == ACK { return true; }
+    }
+    false
+}
@@ -0,0 +1,139 @@
+use crate::memory;
+use core::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering};
+use x86_64::registers::model_specific::Msr;
+
+// --- REGISTERS (offsets into the LAPIC MMIO page) ---
+const REG_ID: u64 = 0x20;
+const REG_TPR: u64 = 0x80;
+const REG_EOI: u64 = 0xB0;
+const REG_SVR: u64 = 0xF0;
+const REG_ICR_LOW: u64 = 0x300;
+const REG_ICR_HIGH: u64 = 0x310;
+const REG_LVT_TIMER: u64 = 0x320;
+const REG_TIMER_INIT: u64 = 0x380;
+const REG_TIMER_CURRENT: u64 = 0x390;
+const REG_TIMER_DIVIDE: u64 = 0x3E0;
+
+// ICR delivery modes (bits 8-10), plus level assert (bit 14)
+const ICR_INIT: u32 = 0x4500;
+const ICR_STARTUP: u32 = 0x4600;
+const ICR_PENDING: u32 = 1 << 12;
+
+const TIMER_PERIODIC: u32 = 1 << 17;
+const TIMER_DIVIDE_16: u32 = 0x3;
+
+const IA32_APIC_BASE: u32 = 0x1B;
+const APIC_BASE_ENABLE: u64 = 1 << 11;
+pub const SPURIOUS_VECTOR: u8 = 0xFF;
+pub const TLB_SHOOTDOWN_VECTOR: u8 = 0xFD;
+
+// ICR destination shorthand (bits 18-19): every CPU except the sender
+const ICR_ALL_BUT_SELF: u32 = 0b11 << 18;
+
+static LAPIC_VIRT: AtomicU64 = AtomicU64::new(0);
+static ENABLED: AtomicBool = AtomicBool::new(false);
+static TIMER_COUNT: AtomicU32 = AtomicU32::new(0); // Timer counts (divide by 16) per PIT tick
+
+unsafe fn read(reg: u64) -> u32 {
+    core::ptr::read_volatile((LAPIC_VIRT.load(Ordering::Relaxed) + reg) as *const u32)
+}
+
+unsafe fn write(reg: u64, value: u32) {
+    core::ptr::write_volatile((LAPIC_VIRT.load(Ordering::Relaxed) + reg) as *mut u32, value);
+}
+
+/// Enables the local APIC of the current CPU. Returns its APIC ID.
+pub fn init() -> u8 {
+    unsafe {
+        // 1. Globally enable the APIC and find its registers
+        let mut msr = Msr::new(IA32_APIC_BASE);
+        let base = msr.read();
+        msr.write(base | APIC_BASE_ENABLE);
+        let phys = base & 0xF_FFFF_F000;
+        LAPIC_VIRT.store(memory::map_mmio_page(phys), Ordering::Relaxed);
+
+        // 2. Accept every priority, software-enable via the spurious vector register
+        write(REG_TPR, 0);
+        write(REG_SVR, 0x100 | SPURIOUS_VECTOR as u32);
+
+        ENABLED.store(true, Ordering::Release);
+        (read(REG_ID) >> 24) as u8
+    }
+}
+
+/// Enables an application processor's own LAPIC. Every CPU sees its LAPIC at the same
+/// address, so the BSP's mapping from `init` is reused.
+pub fn init_ap() -> u8 {
+    unsafe {
+        let mut msr = Msr::new(IA32_APIC_BASE);
+        let base = msr.read();
+        msr.write(base | APIC_BASE_ENABLE);
+        write(REG_TPR, 0);
+        write(REG_SVR, 0x100 | SPURIOUS_VECTOR as u32);
+        (read(REG_ID) >> 24) as u8
+    }
+}
+
+/// APIC ID of the CPU this runs on (0 while the legacy PIC is in charge)
+pub fn id() -> u8 {
+    if !is_enabled() { return 0; }
+    unsafe { (read(REG_ID) >> 24) as u8 }
+}
+
+/// Sends an IPI and waits until the LAPIC has delivered it
+unsafe fn send_ipi(apic_id: u8, icr_low: u32) {
+    write(REG_ICR_HIGH, (apic_id as u32) << 24);
+    write(REG_ICR_LOW, icr_low);
+    while read(REG_ICR_LOW) & ICR_PENDING != 0 { core::hint::spin_loop(); }
+}
+
+/// Fixed-delivery IPI with `vector` to every other CPU
+pub fn send_ipi_all_but_self(vector: u8) {
+    unsafe { send_ipi(0, ICR_ALL_BUT_SELF | vector as u32); }
+}
+
+/// INIT IPI: puts the target CPU into wait-for-SIPI
+pub fn send_init(apic_id: u8) {
+    unsafe { send_ipi(apic_id, ICR_INIT); }
+}
+
+/// Startup IPI: the target starts in real mode at `page` * 4 KiB
+pub fn send_sipi(apic_id: u8, page: u8) {
+    unsafe { send_ipi(apic_id, ICR_STARTUP | page as u32); }
+}
+
+/// Measures the LAPIC timer against 10 PIT ticks so `start_timer` can match the PIT rate.
+/// Needs interrupts on (the PIT drives TICK_COUNT).
+pub fn calibrate_timer() {
+    let wait_tick = |from: u64| {
+        while crate::state::TICK_COUNT.load(Ordering::Relaxed) == from { core::hint::spin_loop(); }
+    };
+    unsafe {
+        write(REG_TIMER_DIVIDE, TIMER_DIVIDE_16);
+        wait_tick(crate::state::TICK_COUNT.load(Ordering::Relaxed));
+        write(REG_TIMER_INIT, u32::MAX);
+        let start = crate::state::TICK_COUNT.load(Ordering::Relaxed);
+        for t in 0..10 { wait_tick(start + t); }
+        let elapsed = u32::MAX - read(REG_TIMER_CURRENT);
+        write(REG_TIMER_INIT, 0); // Stop it again
+        TIMER_COUNT.store(elapsed / 10, Ordering::Relaxed);
+    }
+}
+
+/// Starts this CPU's LAPIC timer firing `vector` periodically, once per PIT tick
+pub fn start_timer(vector: u8) {
+    unsafe {
+        write(REG_TIMER_DIVIDE, TIMER_DIVIDE_16);
+        write(REG_LVT_TIMER, vector as u32 | TIMER_PERIODIC);
+        write(REG_TIMER_INIT, TIMER_COUNT.load(Ordering::Relaxed).max(1));
+    }
+}
+
+pub fn is_enabled() -> bool {
+    ENABLED.load(Ordering::Acquire)
+}
+
+/// Signals end of interrupt for the in-service vector
+pub fn eoi() {
+    unsafe { write(REG_EOI, 0); }
+}
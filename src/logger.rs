@@ -1,15 +1,28 @@
 use alloc::vec::Vec;
 use alloc::string::String;
+use alloc::format;
+use core::sync::atomic::Ordering;
 use spin::Mutex;
 use lazy_static::lazy_static;
+use crate::state;
+
+const HISTORY_LEN: usize = 200;
 
 lazy_static! {
     // A queue to hold messages from drivers until the Shell is ready to print them
     pub static ref LOG_QUEUE: Mutex<Vec<String>> = Mutex::new(Vec::new());
+    // The last HISTORY_LEN messages as (tick, level, text), kept after the shell drains
+    // the queue (for `dmesg`)
+    pub static ref LOG_HISTORY: Mutex<Vec<(u64, LogLevel, String)>> = Mutex::new(Vec::new());
 }
 
 // Drivers call this instead of printing directly
 pub fn log(msg: &str) {
+    record(LogLevel::Info, msg);
+}
+
+fn record(level: LogLevel, msg: &str) {
+    let tick = state::TICK_COUNT.load(Ordering::Relaxed);
     x86_64::instructions::interrupts::without_interrupts(|| {
         let mut queue = LOG_QUEUE.lock();
         // Prevent infinite memory growth: Keep last 50 messages
@@ -17,9 +30,113 @@ pub fn log(msg: &str) {
             queue.remove(0);
         }
         queue.push(String::from(msg));
+
+        let mut history = LOG_HISTORY.lock();
+        if history.len() >= HISTORY_LEN {
+            history.remove(0);
+        }
+        history.push((tick, level, String::from(msg)));
     });
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
+#[repr(u8)]
+pub enum LogLevel {
+    Debug = 0,
+    Info = 1,
+    Warn = 2,
+    Error = 3,
+}
+
+impl LogLevel {
+    /// ANSI colour the prefix is shown in, if any
+    pub fn color(&self) -> Option<&'static str> {
+        match self {
+            LogLevel::Warn => Some("\x1b[33m"),
+            LogLevel::Error => Some("\x1b[31m"),
+            _ => None,
+        }
+    }
+
+    pub fn prefix(&self) -> &'static str {
+        match self {
+            LogLevel::Debug => "[DEBUG] ",
+            LogLevel::Info => "[INFO] ",
+            LogLevel::Warn => "[WARN] ",
+            LogLevel::Error => "[ERROR] ",
+        }
+    }
+
+    /// "debug" / "info" / "warn" / "error", as typed in the shell
+    pub fn parse(s: &str) -> Option<LogLevel> {
+        match s {
+            "debug" => Some(LogLevel::Debug),
+            "info" => Some(LogLevel::Info),
+            "warn" => Some(LogLevel::Warn),
+            "error" | "err" => Some(LogLevel::Error),
+            _ => None,
+        }
+    }
+}
+
+/// Messages below the global filter (`state::LOG_LEVEL_FILTER`) are dropped on the spot
+pub fn log_level(level: LogLevel, msg: &str) {
+    if (level as u8) < state::LOG_LEVEL_FILTER.load(Ordering::Relaxed) { return; }
+
+    crate::serial_print!("{}", msg);
+    record(level, msg);
+}
+
+pub fn set_filter(level: LogLevel) {
+    state::LOG_LEVEL_FILTER.store(level as u8, Ordering::Relaxed);
+}
+
+/// Every remembered message with the tick it was logged at, oldest first
+pub fn get_history() -> Vec<(u64, String)> {
+    history(LogLevel::Debug)
+}
+
+/// Remembered messages at `min` or above, oldest first
+pub fn history(min: LogLevel) -> Vec<(u64, String)> {
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        LOG_HISTORY.lock().iter().filter(|(_, l, _)| *l >= min).map(|(t, _, m)| (*t, m.clone())).collect()
+    })
+}
+
+pub fn clear_history() {
+    x86_64::instructions::interrupts::without_interrupts(|| LOG_HISTORY.lock().clear());
+}
+
+#[macro_export]
+macro_rules! klog_debug {
+    ($($arg:tt)*) => ($crate::logger::_klog($crate::logger::LogLevel::Debug, format_args!($($arg)*)));
+}
+
+#[macro_export]
+macro_rules! klog_info {
+    ($($arg:tt)*) => ($crate::logger::_klog($crate::logger::LogLevel::Info, format_args!($($arg)*)));
+}
+
+#[macro_export]
+macro_rules! klog_warn {
+    ($($arg:tt)*) => ($crate::logger::_klog($crate::logger::LogLevel::Warn, format_args!($($arg)*)));
+}
+
+#[macro_export]
+macro_rules! klog_error {
+    ($($arg:tt)*) => ($crate::logger::_klog($crate::logger::LogLevel::Error, format_args!($($arg)*)));
+}
+
+#[doc(hidden)]
+pub fn _klog(level: LogLevel, args: core::fmt::Arguments) {
+    // Skip the formatting work entirely for filtered levels
+    if (level as u8) < state::LOG_LEVEL_FILTER.load(Ordering::Relaxed) { return; }
+    match level.color() {
+        Some(color) => log_level(level, &format!("{}{}\x1b[0m{}", color, level.prefix(), args)),
+        None => log_level(level, &format!("{}{}", level.prefix(), args)),
+    }
+}
+
 // The Shell calls this to get new messages
 pub fn drain() -> Vec<String> {
     x86_64::instructions::interrupts::without_interrupts(|| {
@@ -28,4 +145,4 @@ pub fn drain() -> Vec<String> {
         queue.clear();
         items
     })
-}
\ No newline at end of file
+}
@@ -0,0 +1,98 @@
+use alloc::vec::Vec;
+
+// --- FORMAT ---
+// [original length: u32 LE] then groups of up to 8 tokens, each group led by a flag byte.
+// Flag bit i (LSB first) = 1: token i is a back-reference (offset: u8, length: u8)
+//                        = 0: token i is a single literal byte
+const WINDOW: usize = 255;
+const MIN_MATCH: usize = 3;
+const MAX_MATCH: usize = 255;
+// Best case per 8 tokens: a flag byte + 8 two-byte references expanding to 8 * MAX_MATCH
+const MAX_RATIO: usize = 8 * MAX_MATCH / 17 + 1;
+// The header is untrusted; never allocate more than this for one stream
+const MAX_OUTPUT: usize = 16 * 1024 * 1024;
+
+pub fn compress(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(data.len() / 2 + 8);
+    out.extend_from_slice(&(data.len() as u32).to_le_bytes());
+
+    let mut pos = 0;
+    let mut flag_idx = 0;
+    let mut bit = 8;
+
+    while pos < data.len() {
+        // 1. Start a new group every 8 tokens
+        if bit == 8 {
+            flag_idx = out.len();
+            out.push(0);
+            bit = 0;
+        }
+
+        // 2. Find the longest match inside the sliding window
+        let window_start = pos.saturating_sub(WINDOW);
+        let max_len = core::cmp::min(MAX_MATCH, data.len() - pos);
+        let mut best_len = 0;
+        let mut best_off = 0;
+        for start in window_start..pos {
+            let mut len = 0;
+            // Matches may run past `pos` (overlapping copy), like "aaaa..."
+            while len < max_len && data[start + len] == data[pos + len] {
+                len += 1;
+            }
+            if len > best_len {
+                best_len = len;
+                best_off = pos - start;
+                if len == max_len { break; }
+            }
+        }
+
+        // 3. Emit a back-reference or a literal
+        if best_len >= MIN_MATCH {
+            out[flag_idx] |= 1 << bit;
+            out.push(best_off as u8);
+            out.push(best_len as u8);
+            pos += best_len;
+        } else {
+            out.push(data[pos]);
+            pos += 1;
+        }
+        bit += 1;
+    }
+    out
+}
+
+/// Returns None if the stream is truncated, references data before the start, or claims
+/// a length its size can't produce (or over MAX_OUTPUT)
+pub fn decompress(data: &[u8]) -> Option<Vec<u8>> {
+    if data.len() < 4 { return None; }
+    let expected = u32::from_le_bytes([data[0], data[1], data[2], data[3]]) as usize;
+    if expected > MAX_OUTPUT || expected > (data.len() - 4).saturating_mul(MAX_RATIO) { return None; }
+    let mut out: Vec<u8> = Vec::with_capacity(expected);
+
+    let mut i = 4;
+    while out.len() < expected {
+        let flags = *data.get(i)?;
+        i += 1;
+        for bit in 0..8 {
+            if out.len() >= expected { break; }
+            if flags & (1 << bit) != 0 {
+                let offset = *data.get(i)? as usize;
+                let len = *data.get(i + 1)? as usize;
+                i += 2;
+                if offset == 0 || offset > out.len() || out.len() + len > expected { return None; }
+                let start = out.len() - offset;
+                // Byte-by-byte so overlapping references repeat correctly
+                for k in 0..len {
+                    let b = out[start + k];
+                    out.push(b);
+                }
+            } else {
+                out.push(*data.get(i)?);
+                i += 1;
+            }
+        }
+    }
+
+    if out.len() != expected { return None; }
+    Some(out)
+}
@@ -33,6 +33,29 @@ mod serial; // NEW
 mod ata;
 mod fat;
 mod acpi;
+mod lz;
+mod lapic;
+mod hpet;
+mod ioapic;
+mod sync;
+mod keyboard;
+mod virtio_blk;
+mod nvme;
+mod block;
+mod smp;
+mod pipe;
+mod speaker;
+mod virtio_net;
+mod nic;
+mod kdbg;
+mod shmem;
+mod slab;
+mod calc;
+mod cpuid;
+mod diff;
+mod awk;
+mod base64;
+mod tar;
 
 #[used]
 static BASE_REVISION: BaseRevision = BaseRevision::new();
@@ -72,11 +95,22 @@ fn panic(info: &core::panic::PanicInfo) -> ! {
 
 #[no_mangle]
 pub extern "C" fn _start() -> ! {
+    state::BOOT_TSC.store(unsafe { core::arch::x86_64::_rdtsc() }, Ordering::Relaxed);
+
     // 1. HARDWARE INIT
     gdt::init(); 
+    cpuid::init();
+    if !memory::enable_nx() {
+        crate::serial_print!("[MEM] No NX support: user data pages stay executable.\n");
+    }
+    scheduler::init_fpu();
     interrupts::init_idt();
+    interrupts::init_syscall_msr();
     unsafe { interrupts::PICS.lock().initialize() };
     interrupts::init_pit();
+    time::calibrate_tsc_with_pit();
+    time::init_wall_clock();
+    kdbg::init();
     interrupts::enable_listening();
     x86_64::instructions::interrupts::enable(); 
 
@@ -86,18 +120,31 @@ pub extern "C" fn _start() -> ! {
     let video_ptr = fb.addr() as *mut u32;
     let width = fb.width() as usize;
     let height = fb.height() as usize;
-    let pitch = fb.pitch() as usize / 4;
+    // Every pixel writer assumes 0x00RRGGBB words; 24bpp modes get the same layout in 3 bytes
+    let bytes_per_pixel = if fb.bpp() == 24 { 3 } else { 4 };
+    state::BYTES_PER_PIXEL.store(bytes_per_pixel, Ordering::Relaxed);
+    let pitch_bytes = fb.pitch() as usize; // Not always width * bytes_per_pixel: rows may be padded
 
     // SAVE VIDEO STATE
     state::VIDEO_PTR.store(video_ptr as u64, Ordering::Relaxed);
     state::SCREEN_WIDTH.store(width, Ordering::Relaxed);
     state::SCREEN_HEIGHT.store(height, Ordering::Relaxed);
 
-    writer::Writer::init(video_ptr, width, height, pitch);
+    writer::Writer::init(video_ptr, width, height, pitch_bytes);
     if let Some(w) = writer::WRITER.lock().as_mut() { w.clear(); }
 
     allocator::init_heap();
 
+    // Warnings need the heap: the logger keeps a copy
+    if fb.bpp() != 32 {
+        writer::print(&alloc::format!("WARNING: Framebuffer is {}bpp, not 32 ({}).\n", fb.bpp(),
+            if fb.bpp() == 24 { "drawing 3-byte pixels" } else { "colours will be wrong" }));
+    }
+    let xrgb = fb.red_mask_shift() == 16 && fb.green_mask_shift() == 8 && fb.blue_mask_shift() == 0;
+    if fb.memory_model() != limine::framebuffer::MemoryModel::RGB || !xrgb {
+        writer::print("WARNING: Framebuffer is not 0xRRGGBB, colours will be wrong.\n");
+    }
+
     // 3. MEMORY INIT
     let hhdm_offset = HHDM_REQUEST.get_response().unwrap().offset();
     let memmap = MEMMAP_REQUEST.get_response().unwrap();
@@ -105,6 +152,9 @@ pub extern "C" fn _start() -> ! {
 
     state::HHDM_OFFSET.store(hhdm_offset, Ordering::Relaxed);
     state::KERNEL_DELTA.store(kernel_response.virtual_base() - kernel_response.physical_base(), Ordering::Relaxed);
+    // The image is a PIE, so Limine relocates it to a random 2 MiB-aligned slide
+    // above its default base (KASLR=yes in limine.cfg)
+    state::KASLR_OFFSET.store(kernel_response.virtual_base().wrapping_sub(memory::KERNEL_DEFAULT_BASE), Ordering::Relaxed);
 
     unsafe { memory::init(hhdm_offset, memmap) };
     
@@ -113,6 +163,20 @@ pub extern "C" fn _start() -> ! {
         acpi::init(rsdp_response.address() as u64);
     }
 
+    if let Some(hpet_base) = acpi::hpet_base() {
+        hpet::init(hpet_base);
+    }
+
+    // 3.6 APIC INIT (falls back to the legacy PIC without a MADT)
+    if let Some(ioapic_base) = acpi::ioapic_base() {
+        interrupts::switch_to_apic(ioapic_base);
+    }
+
+    // 3.65 SMP: start the other cores (they need the heap and the LAPIC)
+    smp::init(acpi::cpu_apic_ids().len());
+
+    // 3.7 BLOCK DEVICE (the persistent VFS lives on it)
+    block::init();
     fs::init();
 
     // 4. GUI INIT
@@ -123,10 +187,13 @@ pub extern "C" fn _start() -> ! {
     // We use a block {} to lock, add tasks, and then release the lock immediately
     {
         let mut sched = scheduler::SCHEDULER.lock();
-        sched.add_task("Shell", 10_000_000, shell::shell_task, 0);
+        sched.add_task("Shell", 10_000_000, shell::shell_task, 0, 8);
         
-        extern "C" fn idle_task(_arg: u64) { core::hint::black_box(0); }
-        sched.add_task("Idle", 10_000, idle_task, 0);
+        extern "C" fn idle_task(_arg: u64) {
+            loop { scheduler::sleep(1); }
+        }
+        sched.add_task("Idle", 10_000, idle_task, 0, 1);
+        sched.add_task("DiskFlush", 10_000_000, ata::disk_flush_task, 0, 2);
         
 
     }
@@ -137,6 +204,12 @@ pub extern "C" fn _start() -> ! {
     let mut is_dragging = false;
     let mut drag_offset_x = 0;
     let mut drag_offset_y = 0;
+    let mut is_resizing = false;
+    let mut resize_anchor = (0usize, 0usize); // Grab point's distance from the bottom-right corner
+    let mut prev_btn = false;
+    let mut prev_right = false;
+    let mut context_menu: Option<compositor::ContextMenu> = None;
+    let mut menu_press = false; // Left button went down on the menu; ignore it until release
 
     // 6. MAIN LOOP
     const FRAME_BUDGET_CYCLES: u64 = 50_000_000;
@@ -148,16 +221,21 @@ pub extern "C" fn _start() -> ! {
         scheduler::step();
 
         // --- GUI LOGIC ---
-        let (mx, my, btn) = mouse::get_state();
+        let (mx, my, btn, right_btn) = mouse::get_state();
 
         // 1. Taskbar (Always available)
         let mut taskbar = compositor::Window::new(0, height - 30, width, 30, "Taskbar");
+        taskbar.set_alpha(220); // Translucent glass
         let time = time::read_rtc();
         use alloc::format;
         let time_str = format!("{:02}:{:02}:{:02}", time.hours, time.minutes, time.seconds);
         taskbar.cursor_x = width - 100;
         taskbar.cursor_y = 5;
         taskbar.print(&time_str);
+        if interrupts::is_caps_lock() {
+            taskbar.cursor_x = width - 160;
+            taskbar.print("CAPS");
+        }
 
         // 2. Try to render Shell Windows (Non-blocking to avoid deadlock with preempted Shell task)
         if let Some(mut shell_lock) = shell::SHELL.try_lock() {
@@ -168,11 +246,51 @@ pub extern "C" fn _start() -> ! {
                 let mut drag_offset_x_local = drag_offset_x; // local copy
                 let mut drag_offset_y_local = drag_offset_y;
 
+                // Context menu: a left press picks a row or dismisses the menu
+                if btn && !prev_btn {
+                    if let Some(menu) = context_menu.take() {
+                        if let Some(item) = menu.item_at(mx, my) {
+                            menu_press = true;
+                            let idx = shell_mutex.active_idx;
+                            match menu.items[item].as_str() {
+                                "Close" => shell_mutex.close_window(idx),
+                                "Maximize" => shell_mutex.toggle_maximize(idx, width, height),
+                                "New Terminal" => shell_mutex.spawn_terminal(),
+                                "Copy" => input::push_key('\u{E004}'),
+                                "Paste" => input::push_key('\u{E005}'),
+                                _ => {}
+                            }
+                        } else if menu.window.contains(mx, my) {
+                            menu_press = true;
+                        }
+                    }
+                } else if !btn {
+                    menu_press = false;
+                }
+
+                // Right-click on a window's content focuses it and opens the menu there
+                if right_btn && !prev_right && !btn {
+                    let hit = shell_mutex.windows.iter().rposition(|w| shell_mutex.is_visible(w) && w.contains(mx, my));
+                    context_menu = None;
+                    if let Some(idx) = hit.filter(|&i| !shell_mutex.windows[i].is_title_bar(mx, my)) {
+                        let win = shell_mutex.windows.remove(idx);
+                        shell_mutex.windows.push(win);
+                        shell_mutex.active_idx = shell_mutex.windows.len() - 1;
+                        let mut menu = compositor::ContextMenu::new(mx, my, &["Close", "Maximize", "New Terminal", "Copy", "Paste"]);
+                        menu.window.x = mx.min(width.saturating_sub(menu.window.width));
+                        menu.window.y = my.min((height - 30).saturating_sub(menu.window.height));
+                        context_menu = Some(menu);
+                    }
+                }
+
+                // Taskbar buttons restore minimized windows (on press only)
+                let taskbar_hit = btn && !prev_btn && my >= height - 30 && shell_mutex.taskbar_click(mx);
+
                  // A. Focus / Z-Order
-                if btn && !is_dragging_local {
+                if btn && !taskbar_hit && !menu_press && !is_dragging_local && !is_resizing {
                     let mut clicked_idx = None;
                     for (i, win) in shell_mutex.windows.iter().enumerate().rev() {
-                        if win.contains(mx, my) {
+                        if shell_mutex.is_visible(win) && win.contains(mx, my) {
                             clicked_idx = Some(i);
                             break;
                         }
@@ -187,28 +305,17 @@ pub extern "C" fn _start() -> ! {
                         let action = win.handle_title_bar_click(mx, my);
 
                         if action == 1 {
-                             if shell_mutex.windows.len() > 1 {
-                                 shell_mutex.windows.remove(new_idx);
-                                 if shell_mutex.active_idx >= shell_mutex.windows.len() {
-                                     shell_mutex.active_idx = if shell_mutex.windows.is_empty() { 0 } else { shell_mutex.windows.len() - 1 };
-                                 }
-                                 writer::print("Window Closed via X Button\n");
-                             } else {
-                                  // writer::print("Cannot close last window!\n");
-                             }
+                            if shell_mutex.windows.len() > 1 {
+                                shell_mutex.close_window(new_idx);
+                                writer::print("Window Closed via X Button\n");
+                            }
                         } else if action == 2 {
-                             if win.maximized {
-                                 if let Some((x, y, w, h)) = win.saved_rect {
-                                     win.x = x; win.y = y; win.width = w; win.height = h;
-                                     win.maximized = false; win.saved_rect = None;
-                                     win.realloc_buffer(); win.draw_decorations();
-                                 }
-                             } else {
-                                 win.saved_rect = Some((win.x, win.y, win.width, win.height));
-                                 win.x = 0; win.y = 0; win.width = width; win.height = height - 30;
-                                 win.maximized = true;
-                                 win.realloc_buffer(); win.draw_decorations();
-                             }
+                            shell_mutex.toggle_maximize(new_idx, width, height);
+                        } else if action == 3 {
+                            shell_mutex.minimize_window(new_idx);
+                        } else if win.resize_corner_contains(mx, my) && !win.maximized {
+                            is_resizing = true;
+                            resize_anchor = (win.x + win.width - mx, win.y + win.height - my);
                         } else if win.is_title_bar(mx, my) {
                             is_dragging_local = true;
                             drag_offset_x_local = mx - win.x;
@@ -219,11 +326,21 @@ pub extern "C" fn _start() -> ! {
                     }
                 } else if !btn {
                     is_dragging_local = false;
+                    is_resizing = false;
                     let idx = shell_mutex.active_idx;
                     // Check bounds just in case
                     if idx < shell_mutex.windows.len() {
                          shell_mutex.windows[idx].handle_mouse(mx, my, btn);
                     }
+                } else if btn && is_resizing {
+                    let idx = shell_mutex.active_idx;
+                    if let Some(win) = shell_mutex.windows.get(idx) {
+                        let new_w = (mx + resize_anchor.0).saturating_sub(win.x).max(compositor::MIN_WIDTH);
+                        let new_h = (my + resize_anchor.1).saturating_sub(win.y).max(compositor::MIN_HEIGHT);
+                        if new_w != win.width || new_h != win.height {
+                            shell_mutex.resize_window(idx, new_w, new_h);
+                        }
+                    }
                 } else if btn && is_dragging_local {
                     let idx = shell_mutex.active_idx;
                     if idx < shell_mutex.windows.len() {
@@ -233,17 +350,28 @@ pub extern "C" fn _start() -> ! {
                     }
                 }
                 
+                // Mouse wheel scrolls the active window's history (3 lines per notch)
+                let wheel = mouse::get_scroll_delta();
+                if wheel != 0 {
+                    let idx = shell_mutex.active_idx;
+                    if let Some(win) = shell_mutex.windows.get_mut(idx) {
+                        let lines = wheel.unsigned_abs() as usize * 3;
+                        if wheel < 0 { win.scroll_up(lines); } else { win.scroll_down(lines); }
+                    }
+                }
+
                 // Write back drag state
                 is_dragging = is_dragging_local;
                 drag_offset_x = drag_offset_x_local;
                 drag_offset_y = drag_offset_y_local;
 
                 // C. UPDATE TASK MANAGER windows
+                let cwd = shell_mutex.cwd();
                 for win in shell_mutex.windows.iter_mut() {
                     if win.title == "System Monitor" {
                         shell::Shell::update_monitor(win);
                     } else if win.title == "File Explorer" {
-                        shell::Shell::update_explorer(win, &shell_mutex.current_dir);
+                        shell::Shell::update_explorer(win, &cwd);
                     } else if win.title.starts_with("Nano - ") {
                         shell::Shell::update_nano(win, &shell_mutex.nano_status);
                     }
@@ -263,11 +391,15 @@ pub extern "C" fn _start() -> ! {
                 }
 
                 // D. RENDER EVERYTHING
+                shell_mutex.draw_taskbar_buttons(&mut taskbar);
                 let mut draw_list: alloc::vec::Vec<&compositor::Window> = alloc::vec::Vec::new();
                 draw_list.push(&taskbar);
-                for win in &shell_mutex.windows {
+                for win in shell_mutex.windows.iter().filter(|w| shell_mutex.is_visible(w)) {
                     draw_list.push(win);
                 }
+                if let Some(menu) = &context_menu {
+                    draw_list.push(&menu.window);
+                }
                 desktop.render(&draw_list, Some(shell_mutex.active_idx), mx, my);
             } else {
                 // Shell is None (Initializing)
@@ -281,6 +413,9 @@ pub extern "C" fn _start() -> ! {
         }
 
 
+        prev_btn = btn;
+        prev_right = right_btn;
+
         let end_work = unsafe { core::arch::x86_64::_rdtsc() };
         let elapsed = end_work - start;
 
@@ -292,11 +427,11 @@ pub extern "C" fn _start() -> ! {
         for y in (height-8)..height {
             for x in 0..width {
                 unsafe {
-                    let offset = y * pitch + x;
+                    let offset = y * pitch_bytes + x * bytes_per_pixel;
                     if x < bar_width { 
-                        *video_ptr.add(offset) = color; 
+                        writer::put_pixel(video_ptr, offset, color); 
                     } else {
-                        *video_ptr.add(offset) = 0x00222222; // Dark background
+                        writer::put_pixel(video_ptr, offset, 0x00222222); // Dark background
                     }
                 }
             }
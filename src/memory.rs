@@ -2,29 +2,180 @@ use x86_64::structures::paging::{PageTable, PageTableFlags, PhysFrame, Size4KiB,
 use x86_64::{PhysAddr, VirtAddr};
 use limine::response::MemoryMapResponse;
 use limine::memory_map::EntryType; 
+use alloc::vec::Vec;
+use spin::Mutex;
+use lazy_static::lazy_static;
+use core::sync::atomic::{AtomicBool, Ordering};
 
-static mut FRAME_ALLOCATOR: Option<BootFrameAllocator> = None;
+// Both locks are only taken with interrupts off (see `with_page_tables`). Order:
+// PAGE_TABLE_LOCK, then FRAME_ALLOCATOR.
+static FRAME_ALLOCATOR: Mutex<Option<BootFrameAllocator>> = Mutex::new(None);
+// Held around every page-table change that can create a table or touches the shared
+// kernel half, so two CPUs never install different tables in the same slot
+static PAGE_TABLE_LOCK: Mutex<()> = Mutex::new(());
 static mut HHDM: u64 = 0;
+static mut KERNEL_PML4: u64 = 0; // The page table Limine booted us on
+static NX_ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// Where Limine puts a relocatable kernel when KASLR is off; any slide is on top of this
+pub const KERNEL_DEFAULT_BASE: u64 = 0xffff_ffff_8000_0000;
 
 pub unsafe fn init(hhdm_offset: u64, memmap: &'static MemoryMapResponse) {
     HHDM = hhdm_offset;
-    FRAME_ALLOCATOR = Some(BootFrameAllocator::new(memmap));
+    KERNEL_PML4 = x86_64::registers::control::Cr3::read().0.start_address().as_u64();
+    *FRAME_ALLOCATOR.lock() = Some(BootFrameAllocator::new(memmap));
+}
+
+/// Runs `f` holding the page-table lock, interrupts off
+fn with_page_tables<R>(f: impl FnOnce() -> R) -> R {
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        let _tables = PAGE_TABLE_LOCK.lock();
+        f()
+    })
+}
+
+/// Turns on EFER.NXE so PTEs may carry NO_EXECUTE. Without CPU support the bit is
+/// reserved (setting it faults), so user data pages then stay executable.
+pub fn enable_nx() -> bool {
+    use x86_64::registers::model_specific::{Efer, EferFlags};
+    if !crate::cpuid::has_nx() { return false; }
+    unsafe { Efer::update(|flags| flags.insert(EferFlags::NO_EXECUTE_ENABLE)); }
+    NX_ENABLED.store(true, Ordering::Relaxed);
+    true
+}
+
+/// Leaf flags for a user page; data pages are non-executable when NX is on
+fn user_leaf_flags(executable: bool) -> PageTableFlags {
+    let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::USER_ACCESSIBLE;
+    if executable || !NX_ENABLED.load(Ordering::Relaxed) { flags } else { flags | PageTableFlags::NO_EXECUTE }
+}
+
+/// PML4 of the kernel and its kernel-mode tasks
+pub fn kernel_pml4() -> PhysAddr {
+    PhysAddr::new(unsafe { KERNEL_PML4 })
+}
+
+/// A fresh address space for a user process: an empty lower half plus the kernel's higher
+/// half (PML4 entries 256-511). Those entries are shared, not copied, so every higher-half
+/// L4 slot is populated first; kernel mappings made later then show up in every process.
+pub fn create_user_page_table() -> PhysAddr {
+    with_page_tables(|| unsafe {
+        let kernel = &mut *((KERNEL_PML4 + HHDM) as *mut PageTable);
+        for i in 256..512 {
+            if kernel[i].is_unused() {
+                let frame = alloc_frame();
+                zero_frame(frame.as_u64());
+                kernel[i].set_addr(frame, PageTableFlags::PRESENT | PageTableFlags::WRITABLE);
+            }
+        }
+
+        let frame = alloc_frame();
+        zero_frame(frame.as_u64());
+        let pml4 = &mut *((frame.as_u64() + HHDM) as *mut PageTable);
+        for i in 256..512 {
+            pml4[i] = kernel[i].clone();
+        }
+        frame
+    })
 }
 
-/// Gets a fresh physical frame from the system memory map
+/// Frees the lower-half tables of a dead process's address space, then its PML4. Leaf
+/// frames are the owner's business: the process's own are freed before this, and shared
+/// memory still mapped here belongs to its region. The kernel half is shared and untouched.
+pub unsafe fn free_user_page_table(pml4_phys: PhysAddr) {
+    with_page_tables(|| {
+        let hhdm = HHDM;
+        let is_table = |e: &x86_64::structures::paging::page_table::PageTableEntry| {
+            e.flags().contains(PageTableFlags::PRESENT) && !e.flags().contains(PageTableFlags::HUGE_PAGE)
+        };
+        let pml4 = &mut *((pml4_phys.as_u64() + hhdm) as *mut PageTable);
+        for l4 in pml4.iter_mut().take(256).filter(|e| is_table(e)) {
+            let pdpt = &*((l4.addr().as_u64() + hhdm) as *const PageTable);
+            for l3 in pdpt.iter().filter(|e| is_table(e)) {
+                let pd = &*((l3.addr().as_u64() + hhdm) as *const PageTable);
+                for l2 in pd.iter().filter(|e| is_table(e)) {
+                    free_frame(l2.addr());
+                }
+                free_frame(l3.addr());
+            }
+            free_frame(l4.addr());
+            l4.set_unused();
+        }
+        free_frame(pml4_phys);
+    });
+}
+
+/// Loads `pml4_phys` into CR3 (flushing all non-global TLB entries)
+pub unsafe fn switch_page_table(pml4_phys: PhysAddr) {
+    use x86_64::registers::control::{Cr3, Cr3Flags};
+    Cr3::write(PhysFrame::containing_address(pml4_phys), Cr3Flags::empty());
+}
+
+/// A usable page below 1 MiB (where real-mode code can run). The frame allocator never
+/// hands these out, so the caller owns it. None if the memory map has no such page.
+pub fn low_memory_page() -> Option<u64> {
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        let guard = FRAME_ALLOCATOR.lock();
+        let allocator = guard.as_ref()?;
+        allocator.memmap.entries().iter()
+            .filter(|e| e.entry_type == EntryType::USABLE)
+            .map(|e| (e.base.max(0x1000).next_multiple_of(4096), e.base + e.length))
+            .find(|&(start, end)| start + 4096 <= end && start + 4096 <= 0x100_000)
+            .map(|(start, _)| start)
+    })
+}
+
+/// Gets a physical frame (a recycled one first, else a fresh one from the memory map).
+/// Panics when memory runs out; paths user programs can drive use `try_alloc_frame`.
 pub fn alloc_frame() -> PhysAddr {
-    unsafe {
-        let allocator = FRAME_ALLOCATOR.as_mut().expect("PMM not init");
-        let frame = allocator.allocate_frame().expect("OUT OF RAM");
-        frame.start_address()
-    }
+    try_alloc_frame().expect("OUT OF RAM")
 }
 
-/// Maps a page and manually unlocks the entire 4-level hierarchy for Ring 3
-pub unsafe fn map_user_page(virt: u64, phys: u64) {
+/// Like `alloc_frame`, but None once physical memory is exhausted
+pub fn try_alloc_frame() -> Option<PhysAddr> {
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        let mut allocator = FRAME_ALLOCATOR.lock();
+        allocator.as_mut().expect("PMM not init").allocate_frame().map(|f| f.start_address())
+    })
+}
+
+/// `count` physically contiguous frames, for DMA structures a device reads as one block
+/// (legacy virtqueues). Always fresh frames from the memory map, never recycled ones.
+pub fn alloc_contiguous_frames(count: usize) -> Option<PhysAddr> {
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        let mut allocator = FRAME_ALLOCATOR.lock();
+        allocator.as_mut().expect("PMM not init").allocate_contiguous(count)
+    })
+}
+
+/// Hands a frame back for reuse. The caller must have unmapped it everywhere.
+pub unsafe fn free_frame(frame: PhysAddr) {
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        let mut allocator = FRAME_ALLOCATOR.lock();
+        allocator.as_mut().expect("PMM not init").free(frame);
+    });
+}
+
+/// Maps a non-executable user page (stack, heap, data) in the active address space
+pub unsafe fn map_user_data_page(virt: u64, phys: u64) {
+    map_user_page_in(x86_64::registers::control::Cr3::read().0.start_address(), virt, phys, false);
+}
+
+/// Maps a user page into the address space rooted at `pml4_phys`, active or not
+pub unsafe fn map_user_page_in(pml4_phys: PhysAddr, virt: u64, phys: u64, executable: bool) {
+    with_page_tables(|| {
+        let entry = user_pte(pml4_phys, virt);
+        entry.set_addr(PhysAddr::new(phys), user_leaf_flags(executable));
+        x86_64::instructions::tlb::flush(VirtAddr::new(virt));
+    });
+}
+
+/// Level-1 entry for user page `virt`, creating (and opening up to Ring 3) the tables above it.
+/// Caller holds the page-table lock.
+unsafe fn user_pte(pml4_phys: PhysAddr, virt: u64) -> &'static mut x86_64::structures::paging::page_table::PageTableEntry {
     let hhdm = HHDM;
     let addr = VirtAddr::new(virt);
-    let l4_table_phys = x86_64::registers::control::Cr3::read().0.start_address().as_u64();
+    let l4_table_phys = pml4_phys.as_u64();
     let pml4 = &mut *((l4_table_phys + hhdm) as *mut PageTable);
 
     // Level 4
@@ -67,13 +218,132 @@ pub unsafe fn map_user_page(virt: u64, phys: u64) {
     // Level 1
     let pt_phys = pd[p2_idx].addr();
     let pt = &mut *((pt_phys.as_u64() + hhdm) as *mut PageTable);
-    pt[addr.p1_index()].set_addr(PhysAddr::new(phys), PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::USER_ACCESSIBLE);
+    &mut pt[addr.p1_index()]
+}
 
-    x86_64::instructions::tlb::flush(addr);
+// --- DEMAND PAGING ---
+// Pages of a region start out as not-present sentinels: BIT_9 marks them, and the address
+// field (ignored by the CPU while PRESENT is clear) holds the region index (high 20 bits)
+// and the page's index within the region (low 20 bits). The first touch faults, and
+// `handle_demand_fault` backs the page with a zeroed frame.
+const DEMAND_MARKER: PageTableFlags = PageTableFlags::BIT_9;
+
+pub struct DemandRegion {
+    pub start: u64,         // Page aligned
+    pub end: u64,           // Exclusive, page aligned
+    pub pages_backed: usize, // Frames handed out so far
+    pub page_table_phys: u64, // Owning address space; 0 once released (the slot is reused)
+}
+
+lazy_static! {
+    pub static ref DEMAND_REGIONS: Mutex<Vec<DemandRegion>> = Mutex::new(Vec::new());
+}
+
+/// Registers [start, end) as demand-zero user memory in the page tables at `pml4_phys`.
+/// Both bounds must be page aligned.
+pub unsafe fn map_demand_region(pml4_phys: PhysAddr, start: u64, end: u64) {
+    let region_idx = x86_64::instructions::interrupts::without_interrupts(|| {
+        let mut regions = DEMAND_REGIONS.lock();
+        let region = DemandRegion { start, end, pages_backed: 0, page_table_phys: pml4_phys.as_u64() };
+        // Sentinels hold the index, so live regions never move; released slots are refilled
+        match regions.iter().position(|r| r.page_table_phys == 0) {
+            Some(i) => { regions[i] = region; i as u64 }
+            None => { regions.push(region); regions.len() as u64 - 1 }
+        }
+    });
+    with_page_tables(|| {
+        for (page_idx, virt) in (start..end).step_by(4096).enumerate() {
+            let entry = user_pte(pml4_phys, virt);
+            if entry.flags().contains(PageTableFlags::PRESENT) { continue; } // Shared with another segment
+            let sentinel = ((region_idx << 20) | page_idx as u64) << 12;
+            entry.set_addr(PhysAddr::new(sentinel), DEMAND_MARKER);
+            x86_64::instructions::tlb::flush(VirtAddr::new(virt));
+        }
+    });
+}
+
+/// Backs the page under `cr2` if it's a demand sentinel. False if `cr2` isn't ours,
+/// in which case the fault is a real one. Only an existing leaf entry is rewritten (the
+/// tables above it were built by `map_demand_region`), so the page-table lock isn't needed.
+pub fn handle_demand_fault(cr2: u64) -> bool {
+    unsafe {
+        let entry = match find_pte(cr2 & !0xFFF) {
+            Some(e) => e,
+            None => return false,
+        };
+        let flags = entry.flags();
+        if flags.contains(PageTableFlags::PRESENT) || !flags.contains(DEMAND_MARKER) {
+            return false;
+        }
+        let sentinel = entry.addr().as_u64() >> 12;
+        let (region_idx, page_idx) = ((sentinel >> 20) as usize, sentinel & 0xF_FFFF);
+
+        // try_lock: never spin inside the fault handler
+        let mut regions = match DEMAND_REGIONS.try_lock() {
+            Some(r) => r,
+            None => return false,
+        };
+        let cr3 = x86_64::registers::control::Cr3::read().0.start_address().as_u64();
+        let region = match regions.get_mut(region_idx) {
+            Some(r) if r.page_table_phys == cr3 && (r.start..r.end).contains(&cr2)
+                && r.start + page_idx * 4096 == cr2 & !0xFFF => r,
+            _ => return false,
+        };
+
+        // Out of memory is the program's problem: the fault becomes a real one
+        let frame = match try_alloc_frame() {
+            Some(f) => f,
+            None => return false,
+        };
+        zero_frame(frame.as_u64());
+        entry.set_addr(frame, user_leaf_flags(false));
+        x86_64::instructions::tlb::flush(VirtAddr::new(cr2 & !0xFFF));
+        region.pages_backed += 1;
+        true
+    }
+}
+
+/// Drops every demand region of the address space at `pml4_phys` (its process exited) and
+/// frees the frames mapped in them
+pub fn release_demand_regions(pml4_phys: PhysAddr) {
+    let ranges: Vec<(u64, u64)> = x86_64::instructions::interrupts::without_interrupts(|| {
+        let mut regions = DEMAND_REGIONS.lock();
+        regions.iter_mut()
+            .filter(|r| r.page_table_phys == pml4_phys.as_u64())
+            .map(|r| {
+                r.page_table_phys = 0;
+                r.pages_backed = 0;
+                (r.start, r.end)
+            })
+            .collect()
+    });
+    for (start, end) in ranges {
+        for virt in (start..end).step_by(4096) {
+            unsafe {
+                if let Some(frame) = unmap_user_page_in(pml4_phys, virt) {
+                    free_frame(frame);
+                }
+            }
+        }
+    }
 }
 
 /// Maps a kernel page (No Ring 3 access)
 pub unsafe fn map_kernel_page(virt: u64, phys: u64) {
+    with_page_tables(|| map_kernel_page_locked(virt, phys));
+}
+
+unsafe fn map_kernel_page_locked(virt: u64, phys: u64) {
+    // Never silently replace a live translation
+    if is_mapped(virt) {
+        let current = find_pte(virt).map(|e| e.addr().as_u64());
+        if current.is_some_and(|p| p != phys & !0xFFF) {
+            crate::serial_print!("[MEM] Refusing to remap {:#x} (-> {:#x}) to {:#x}\n", virt, current.unwrap_or(0), phys);
+        }
+        return;
+    }
+    if in_mmio_region(virt) { return; }
+
     let hhdm = HHDM;
     let addr = VirtAddr::new(virt);
     let l4_table_phys = x86_64::registers::control::Cr3::read().0.start_address().as_u64();
@@ -115,6 +385,268 @@ pub unsafe fn map_kernel_page(virt: u64, phys: u64) {
     x86_64::instructions::tlb::flush(addr);
 }
 
+/// Walks the active page tables to the level-1 entry for `virt`.
+/// Returns None if a level is missing or the address sits in a huge page.
+unsafe fn find_pte(virt: u64) -> Option<&'static mut x86_64::structures::paging::page_table::PageTableEntry> {
+    find_pte_in(x86_64::registers::control::Cr3::read().0.start_address(), virt)
+}
+
+/// `find_pte` for the address space rooted at `pml4_phys`, active or not
+unsafe fn find_pte_in(pml4_phys: PhysAddr, virt: u64) -> Option<&'static mut x86_64::structures::paging::page_table::PageTableEntry> {
+    let hhdm = HHDM;
+    let addr = VirtAddr::new(virt);
+    let l4_table_phys = pml4_phys.as_u64();
+    let mut table = &mut *((l4_table_phys + hhdm) as *mut PageTable);
+
+    for idx in [addr.p4_index(), addr.p3_index(), addr.p2_index()] {
+        let entry = &table[idx];
+        if entry.is_unused() || entry.flags().contains(PageTableFlags::HUGE_PAGE) {
+            return None;
+        }
+        table = &mut *((entry.addr().as_u64() + hhdm) as *mut PageTable);
+    }
+    let entry = &mut table[addr.p1_index()];
+    if entry.is_unused() { None } else { Some(entry) }
+}
+
+/// Whether `virt` is present in the active page tables (huge pages included)
+pub fn is_mapped(virt: u64) -> bool {
+    let addr = match VirtAddr::try_new(virt) {
+        Ok(a) => a,
+        Err(_) => return false,
+    };
+    unsafe {
+        let hhdm = HHDM;
+        let l4_table_phys = x86_64::registers::control::Cr3::read().0.start_address().as_u64();
+        let mut table = &*((l4_table_phys + hhdm) as *const PageTable);
+        for idx in [addr.p4_index(), addr.p3_index(), addr.p2_index()] {
+            let entry = &table[idx];
+            if !entry.flags().contains(PageTableFlags::PRESENT) { return false; }
+            if entry.flags().contains(PageTableFlags::HUGE_PAGE) { return true; }
+            table = &*((entry.addr().as_u64() + hhdm) as *const PageTable);
+        }
+        table[addr.p1_index()].flags().contains(PageTableFlags::PRESENT)
+    }
+}
+
+/// First address past the lower (user) half of the address space
+const USER_SPACE_END: u64 = 0x0000_8000_0000_0000;
+
+/// Whether [ptr, ptr + len) lies in the user half and every page of it is mapped for
+/// Ring 3 in the active page tables (and writable too, if `write`). Syscalls check user
+/// buffers with this before the kernel touches them. Untouched demand pages don't count.
+pub fn is_user_range(ptr: u64, len: usize, write: bool) -> bool {
+    if len == 0 { return true; }
+    let end = match ptr.checked_add(len as u64) {
+        Some(e) if e <= USER_SPACE_END => e,
+        _ => return false,
+    };
+    (ptr & !0xFFF..end).step_by(4096).all(|page| is_user_page(page, write))
+}
+
+/// `is_user_range` for the single page at `virt`: every level must allow Ring 3 access
+fn is_user_page(virt: u64, write: bool) -> bool {
+    let mut needed = PageTableFlags::PRESENT | PageTableFlags::USER_ACCESSIBLE;
+    if write { needed |= PageTableFlags::WRITABLE; }
+    let addr = VirtAddr::new(virt);
+    unsafe {
+        let hhdm = HHDM;
+        let l4_table_phys = x86_64::registers::control::Cr3::read().0.start_address().as_u64();
+        let mut table = &*((l4_table_phys + hhdm) as *const PageTable);
+        for idx in [addr.p4_index(), addr.p3_index(), addr.p2_index()] {
+            let entry = &table[idx];
+            if !entry.flags().contains(needed) { return false; }
+            if entry.flags().contains(PageTableFlags::HUGE_PAGE) { return true; }
+            table = &*((entry.addr().as_u64() + hhdm) as *const PageTable);
+        }
+        table[addr.p1_index()].flags().contains(needed)
+    }
+}
+
+/// Whether `virt` is a not-yet-touched page of a demand region
+pub fn is_demand_page(virt: u64) -> bool {
+    unsafe { find_pte(virt) }.is_some_and(|e| {
+        let flags = e.flags();
+        !flags.contains(PageTableFlags::PRESENT) && flags.contains(DEMAND_MARKER)
+    })
+}
+
+/// Marks an already-mapped kernel page not-present so any access faults.
+/// Returns false if the page can't be guarded (unmapped or part of a huge page).
+pub unsafe fn map_guard_page(virt: u64) -> bool {
+    with_page_tables(|| match find_pte(virt) {
+        Some(entry) => {
+            let flags = entry.flags() & !(PageTableFlags::PRESENT | PageTableFlags::WRITABLE);
+            entry.set_flags(flags);
+            flush_tlb_all_cpus(virt);
+            true
+        }
+        None => false,
+    })
+}
+
+/// Removes the page at `virt` from the active page tables and returns its frame
+pub unsafe fn unmap_user_page(virt: u64) -> Option<PhysAddr> {
+    unmap_user_page_in(x86_64::registers::control::Cr3::read().0.start_address(), virt)
+}
+
+/// Removes the page at `virt` from the address space at `pml4_phys` and returns its frame.
+/// None if nothing present was mapped there.
+pub unsafe fn unmap_user_page_in(pml4_phys: PhysAddr, virt: u64) -> Option<PhysAddr> {
+    with_page_tables(|| {
+        let entry = find_pte_in(pml4_phys, virt)?;
+        if !entry.flags().contains(PageTableFlags::PRESENT) { return None; }
+        let frame = entry.addr();
+        entry.set_unused();
+        x86_64::instructions::tlb::flush(VirtAddr::new(virt));
+        Some(frame)
+    })
+}
+
+// --- ACCESSED / DIRTY TRACKING ---
+// The CPU sets Accessed on any use of a 4 KiB page and Dirty on the first write through it.
+// Pages that aren't mapped (or sit in a huge page) read as clean and untouched.
+
+/// Whether `virt` has been written since it was mapped or last cleaned
+pub fn get_page_dirty(virt: u64) -> bool {
+    unsafe { find_pte(virt) }.is_some_and(|e| e.flags().contains(PageTableFlags::DIRTY))
+}
+
+/// Marks `virt` clean again, e.g. after its contents were written back
+pub fn clear_page_dirty(virt: u64) {
+    with_page_tables(|| {
+        if let Some(entry) = unsafe { find_pte(virt) } {
+            let flags = entry.flags() & !PageTableFlags::DIRTY;
+            entry.set_flags(flags);
+            // A cached translation would let the next write skip setting the bit
+            x86_64::instructions::tlb::flush(VirtAddr::new(virt));
+        }
+    });
+}
+
+/// Whether `virt` has been read or written since it was mapped
+pub fn get_page_accessed(virt: u64) -> bool {
+    unsafe { find_pte(virt) }.is_some_and(|e| e.flags().contains(PageTableFlags::ACCESSED))
+}
+
+/// Undoes `map_guard_page` so the memory can go back to the heap
+pub unsafe fn unmap_guard_page(virt: u64) {
+    with_page_tables(|| {
+        if let Some(entry) = find_pte(virt) {
+            let flags = entry.flags() | PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
+            entry.set_flags(flags);
+            // Not-present entries are never cached, so other CPUs need no flush here
+            x86_64::instructions::tlb::flush(VirtAddr::new(virt));
+        }
+    });
+}
+
+// --- TLB SHOOTDOWN ---
+// Only kernel-half changes that take access away need other CPUs flushed: that is the
+// guard page. User-half changes don't, because ring-3 tasks only run on the BSP and the
+// APs never load a process page table.
+//
+// The initiator does not wait for the other CPUs. It may hold the scheduler lock (guard
+// pages are made in `add_task`), and a CPU spinning on that lock with interrupts off could
+// never take the IPI. Waiting isn't needed either: the IPI stays pending until the target
+// enables interrupts, and it has to do that before it can run the task the change is for.
+
+/// Flushes `virt` here and asks every other CPU to flush its whole TLB
+fn flush_tlb_all_cpus(virt: u64) {
+    x86_64::instructions::tlb::flush(VirtAddr::new(virt));
+    if crate::smp::cpus_online() > 1 {
+        crate::lapic::send_ipi_all_but_self(crate::lapic::TLB_SHOOTDOWN_VECTOR);
+    }
+}
+
+/// Handler side of `flush_tlb_all_cpus`: drops every cached translation, globals included
+pub fn flush_local_tlb() {
+    use x86_64::registers::control::{Cr4, Cr4Flags};
+    unsafe {
+        let cr4 = Cr4::read();
+        if cr4.contains(Cr4Flags::PAGE_GLOBAL) {
+            Cr4::write(cr4 - Cr4Flags::PAGE_GLOBAL);
+            Cr4::write(cr4);
+        } else {
+            x86_64::instructions::tlb::flush_all();
+        }
+    }
+}
+
+// --- MMIO WINDOW ---
+// `map_kernel_range` and `map_mmio_range` hand out virtual space from here, above the HHDM,
+// so device and firmware regions never alias (or clobber) the HHDM's own mappings.
+const MMIO_WINDOW_BASE: u64 = 0xFFFF_8800_0000_0000;
+static MMIO_NEXT: Mutex<u64> = Mutex::new(MMIO_WINDOW_BASE);
+// (virt_start, virt_end, phys_start, uncached) of every range mapped into the window
+pub static MMIO_REGIONS: Mutex<Vec<(u64, u64, u64, bool)>> = Mutex::new(Vec::new());
+
+fn in_mmio_region(virt: u64) -> bool {
+    MMIO_REGIONS.lock().iter().any(|&(start, end, _, _)| virt >= start && virt < end)
+}
+
+/// Maps `size` bytes of firmware memory (ACPI tables) at `phys` into the window, cached
+/// like any other RAM, and returns the virtual address of `phys`
+pub fn map_kernel_range(phys: u64, size: u64) -> u64 {
+    map_window_range(phys, size, false)
+}
+
+/// Maps `size` bytes of device registers at `phys` uncached into the window and returns
+/// the virtual address of `phys`
+pub fn map_mmio_range(phys: u64, size: u64) -> u64 {
+    map_window_range(phys, size, true)
+}
+
+/// A range already covered by an earlier call with the same caching reuses that mapping
+fn map_window_range(phys: u64, size: u64, uncached: bool) -> u64 {
+    let start = phys & !0xFFF;
+    let end = (phys + size.max(1) + 0xFFF) & !0xFFF;
+
+    // 1. Already mapped?
+    {
+        let regions = MMIO_REGIONS.lock();
+        let found = regions.iter().find(|&&(v, e, p, uc)| uc == uncached && start >= p && end <= p + (e - v));
+        if let Some(&(virt, _, p, _)) = found {
+            return virt + (phys - p);
+        }
+    }
+
+    // 2. Carve out fresh virtual space and map it page by page
+    let virt = {
+        let mut next = MMIO_NEXT.lock();
+        let virt = *next;
+        *next += end - start;
+        virt
+    };
+    for offset in (0..end - start).step_by(4096) {
+        with_page_tables(|| unsafe {
+            map_kernel_page_locked(virt + offset, start + offset);
+            if !uncached { return; }
+            if let Some(entry) = find_pte(virt + offset) {
+                entry.set_flags(entry.flags() | PageTableFlags::NO_CACHE | PageTableFlags::WRITE_THROUGH);
+                x86_64::instructions::tlb::flush(VirtAddr::new(virt + offset));
+            }
+        });
+    }
+    MMIO_REGIONS.lock().push((virt, virt + (end - start), start, uncached));
+    virt + (phys - start)
+}
+
+/// Maps a device register page into the HHDM with caching disabled
+pub unsafe fn map_mmio_page(phys: u64) -> u64 {
+    let page = phys & !0xFFF;
+    let virt = page + HHDM;
+    with_page_tables(|| {
+        map_kernel_page_locked(virt, page);
+        if let Some(entry) = find_pte(virt) {
+            let flags = entry.flags() | PageTableFlags::NO_CACHE | PageTableFlags::WRITE_THROUGH;
+            entry.set_flags(flags);
+            x86_64::instructions::tlb::flush(VirtAddr::new(virt));
+        }
+    });
+    phys + HHDM
+}
+
 unsafe fn zero_frame(phys: u64) {
     let ptr = (phys + HHDM) as *mut u64;
     for i in 0..(4096/8) { core::ptr::write_volatile(ptr.add(i), 0); }
@@ -123,12 +655,47 @@ unsafe fn zero_frame(phys: u64) {
 pub struct BootFrameAllocator {
     memmap: &'static MemoryMapResponse,
     next_free_frame: usize,
+    // Freed frames, linked through their first 8 bytes (via the HHDM); 0 = empty.
+    // Kept off the heap so freeing never allocates.
+    free_head: u64,
 }
 
+// The memory map is only ever read, and only under FRAME_ALLOCATOR's lock
+unsafe impl Send for BootFrameAllocator {}
+
 
 impl BootFrameAllocator {
     pub fn new(memmap: &'static MemoryMapResponse) -> Self {
-        BootFrameAllocator { memmap, next_free_frame: 0 }
+        BootFrameAllocator { memmap, next_free_frame: 0, free_head: 0 }
+    }
+
+    fn free(&mut self, frame: PhysAddr) {
+        unsafe { *((frame.as_u64() + HHDM) as *mut u64) = self.free_head; }
+        self.free_head = frame.as_u64();
+    }
+
+    /// Fresh frames until `count` of them are adjacent. A run broken by a hole in the
+    /// memory map goes to the free list, so nothing is lost.
+    fn allocate_contiguous(&mut self, count: usize) -> Option<PhysAddr> {
+        let mut start = 0u64;
+        let mut len = 0usize;
+        while len < count {
+            let frame = match self.usable_frames().nth(self.next_free_frame) {
+                Some(f) => f.start_address().as_u64(),
+                None => {
+                    for i in 0..len { self.free(PhysAddr::new(start + i as u64 * 4096)); }
+                    return None;
+                }
+            };
+            self.next_free_frame += 1;
+            if len > 0 && frame != start + len as u64 * 4096 {
+                for i in 0..len { self.free(PhysAddr::new(start + i as u64 * 4096)); }
+                len = 0;
+            }
+            if len == 0 { start = frame; }
+            len += 1;
+        }
+        Some(PhysAddr::new(start))
     }
 
     fn usable_frames(&self) -> impl Iterator<Item = PhysFrame> {
@@ -144,8 +711,13 @@ impl BootFrameAllocator {
 
 unsafe impl FrameAllocator<Size4KiB> for BootFrameAllocator {
     fn allocate_frame(&mut self) -> Option<PhysFrame<Size4KiB>> {
+        if self.free_head != 0 {
+            let frame = PhysAddr::new(self.free_head);
+            self.free_head = unsafe { *((frame.as_u64() + HHDM) as *const u64) };
+            return Some(PhysFrame::containing_address(frame));
+        }
         let frame = self.usable_frames().nth(self.next_free_frame);
-        self.next_free_frame += 1;
+        if frame.is_some() { self.next_free_frame += 1; }
         frame
     }
 }
\ No newline at end of file
@@ -8,10 +8,13 @@ const COMMAND_PORT: u16 = 0x64;
 
 pub struct Mouse {
     byte_cycle: u8,
-    packet: [u8; 3],
+    packet: [u8; 4],
+    pub four_byte_mode: bool, // IntelliMouse: 4th byte carries the wheel
+    pub scroll_delta: i8,     // Accumulated wheel movement, cleared by get_scroll_delta
     pub x: usize,
     pub y: usize,
     pub left_button: bool, // <--- NEW
+    pub right_button: bool,
     screen_width: usize,
     screen_height: usize,
     saved_background: [u32; 100], 
@@ -21,10 +24,13 @@ pub struct Mouse {
 lazy_static! {
     pub static ref MOUSE: Mutex<Mouse> = Mutex::new(Mouse {
         byte_cycle: 0,
-        packet: [0; 3],
+        packet: [0; 4],
+        four_byte_mode: false,
+        scroll_delta: 0,
         x: 512,
         y: 384,
         left_button: false, // <--- Init false
+        right_button: false,
         screen_width: 1024,
         screen_height: 768,
         saved_background: [0; 100],
@@ -32,10 +38,11 @@ lazy_static! {
     });
 }
 
-pub fn get_state() -> (usize, usize, bool) {
+/// (x, y, left button, right button)
+pub fn get_state() -> (usize, usize, bool, bool) {
     x86_64::instructions::interrupts::without_interrupts(|| {
         let m = MOUSE.lock();
-        (m.x, m.y, m.left_button)
+        (m.x, m.y, m.left_button, m.right_button)
     })
 }
 
@@ -50,6 +57,14 @@ pub fn get_position() -> (usize, usize) {
     }
 }
 
+/// Returns the wheel movement since the last call (positive = towards the user) and clears it
+pub fn get_scroll_delta() -> i8 {
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        let mut m = MOUSE.lock();
+        core::mem::take(&mut m.scroll_delta)
+    })
+}
+
 pub fn init(width: usize, height: usize) {
     x86_64::instructions::interrupts::without_interrupts(|| {
         let mut mouse = MOUSE.lock();
@@ -70,6 +85,17 @@ pub fn init(width: usize, height: usize) {
 
             write_mouse(&mut status, &mut cmd, &mut data, 0xF6);
             read_mouse(&mut status, &mut data);
+
+            // IntelliMouse knock: sample rates 200, 100, 80 switch on the wheel
+            for rate in [0xC8, 0x64, 0x50] {
+                write_mouse(&mut status, &mut cmd, &mut data, 0xF3);
+                read_mouse(&mut status, &mut data);
+                write_mouse(&mut status, &mut cmd, &mut data, rate);
+                read_mouse(&mut status, &mut data);
+            }
+            write_mouse(&mut status, &mut cmd, &mut data, 0xF2); // Get Device ID
+            read_mouse(&mut status, &mut data); // ACK
+            mouse.four_byte_mode = read_mouse(&mut status, &mut data) == 0x03;
             
             write_mouse(&mut status, &mut cmd, &mut data, 0xF4);
             read_mouse(&mut status, &mut data);
@@ -113,23 +139,41 @@ pub fn handle_interrupt() {
         }
         2 => {
             mouse.packet[2] = byte;
+            if mouse.four_byte_mode {
+                mouse.byte_cycle = 3;
+            } else {
+                mouse.byte_cycle = 0;
+                apply_packet(&mut mouse);
+            }
+        }
+        3 => {
+            mouse.packet[3] = byte;
             mouse.byte_cycle = 0;
 
-            let state = mouse.packet[0];
-            let mut dx = mouse.packet[1] as i32;
-            let mut dy = mouse.packet[2] as i32;
-            if (state & 0x10) != 0 { dx -= 256; }
-            if (state & 0x20) != 0 { dy -= 256; }
-
-            // Update Position
-            let x = (mouse.x as i32 + dx).clamp(0, (mouse.screen_width - 5) as i32);
-            let y = (mouse.y as i32 - dy).clamp(0, (mouse.screen_height - 5) as i32);
-            
-            mouse.x = x as usize;
-            mouse.y = y as usize;
-            
-            mouse.left_button = (state & 0x01) != 0;
+            // Bits 0-3: signed 4-bit Z movement
+            let z = (byte & 0x0F) as i8;
+            let z = if z & 0x08 != 0 { z - 16 } else { z };
+            mouse.scroll_delta = mouse.scroll_delta.saturating_add(z);
+            apply_packet(&mut mouse);
         }
         _ => mouse.byte_cycle = 0,
     }
+}
+
+fn apply_packet(mouse: &mut Mouse) {
+    let state = mouse.packet[0];
+    let mut dx = mouse.packet[1] as i32;
+    let mut dy = mouse.packet[2] as i32;
+    if (state & 0x10) != 0 { dx -= 256; }
+    if (state & 0x20) != 0 { dy -= 256; }
+
+    // Update Position
+    let x = (mouse.x as i32 + dx).clamp(0, (mouse.screen_width - 5) as i32);
+    let y = (mouse.y as i32 - dy).clamp(0, (mouse.screen_height - 5) as i32);
+    
+    mouse.x = x as usize;
+    mouse.y = y as usize;
+    
+    mouse.left_button = (state & 0x01) != 0;
+    mouse.right_button = (state & 0x02) != 0;
 }
\ No newline at end of file
@@ -1,6 +1,176 @@
 use alloc::vec::Vec;
 use alloc::format;
 use alloc::string::String;
+use alloc::collections::vec_deque::VecDeque;
+use core::sync::atomic::{AtomicBool, AtomicU8, AtomicU64, AtomicUsize, Ordering};
+use spin::Mutex;
+use lazy_static::lazy_static;
+use crate::logger::LogLevel;
+
+// --- TRAFFIC COUNTERS ---
+// Updated by the NIC driver on every frame so the shell can report totals.
+pub static RX_PACKETS: AtomicU64 = AtomicU64::new(0);
+pub static TX_PACKETS: AtomicU64 = AtomicU64::new(0);
+pub static TOTAL_RX_BYTES: AtomicU64 = AtomicU64::new(0);
+pub static TOTAL_TX_BYTES: AtomicU64 = AtomicU64::new(0);
+// Bytes handed to a TX descriptor that the card has not finished with yet
+pub static BYTES_IN_FLIGHT: AtomicU64 = AtomicU64::new(0);
+
+pub fn record_rx(len: usize) {
+    RX_PACKETS.fetch_add(1, Ordering::Relaxed);
+    TOTAL_RX_BYTES.fetch_add(len as u64, Ordering::Relaxed);
+}
+
+pub fn record_tx(len: usize) {
+    TX_PACKETS.fetch_add(1, Ordering::Relaxed);
+    TOTAL_TX_BYTES.fetch_add(len as u64, Ordering::Relaxed);
+    BYTES_IN_FLIGHT.fetch_add(len as u64, Ordering::Relaxed);
+}
+
+pub fn record_tx_done(len: usize) {
+    // Saturating: a descriptor reaped after a driver re-init must not underflow
+    let _ = BYTES_IN_FLIGHT.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |v| Some(v.saturating_sub(len as u64)));
+}
+
+// --- ARP CACHE ---
+pub const GATEWAY_IP: [u8; 4] = [10, 0, 2, 2]; // QEMU user-net gateway
+pub const BROADCAST_IP: [u8; 4] = [255, 255, 255, 255];
+const ARP_CACHE_SIZE: usize = 32;
+
+#[derive(Debug, Clone, Copy)]
+pub struct ArpEntry {
+    pub ip: [u8; 4],
+    pub mac: [u8; 6],
+    pub timestamp: u64, // TICK_COUNT when last seen
+}
+
+lazy_static! {
+    pub static ref ARP_CACHE: Mutex<Vec<ArpEntry>> = Mutex::new(Vec::new());
+}
+
+pub fn arp_cache_insert(ip: [u8; 4], mac: [u8; 6]) {
+    if ip == [0, 0, 0, 0] || mac == [0xFF; 6] { return; }
+    let now = crate::state::TICK_COUNT.load(Ordering::Relaxed);
+    let mut cache = ARP_CACHE.lock();
+    if let Some(entry) = cache.iter_mut().find(|e| e.ip == ip) {
+        entry.mac = mac;
+        entry.timestamp = now;
+        return;
+    }
+    if cache.len() >= ARP_CACHE_SIZE {
+        // Evict the entry we heard from longest ago
+        if let Some(oldest) = (0..cache.len()).min_by_key(|&i| cache[i].timestamp) {
+            cache.remove(oldest);
+        }
+    }
+    cache.push(ArpEntry { ip, mac, timestamp: now });
+}
+
+pub fn arp_cache_lookup(ip: [u8; 4]) -> Option<[u8; 6]> {
+    ARP_CACHE.lock().iter().find(|e| e.ip == ip).map(|e| e.mac)
+}
+
+// --- ROUTING ---
+#[derive(Debug, Clone, Copy)]
+pub struct Route {
+    pub network: [u8; 4],
+    pub mask: [u8; 4],
+    pub gateway: [u8; 4], // 0.0.0.0 = directly connected
+    pub iface: u8,
+}
+
+lazy_static! {
+    pub static ref ROUTING_TABLE: Mutex<Vec<Route>> = Mutex::new(Vec::new());
+}
+
+fn prefix_len(mask: [u8; 4]) -> u32 {
+    u32::from_be_bytes(mask).count_ones()
+}
+
+/// Adds a route, replacing any existing one for the same network and mask
+pub fn route_add(network: [u8; 4], mask: [u8; 4], gateway: [u8; 4], iface: u8) {
+    let m = u32::from_be_bytes(mask);
+    let network = (u32::from_be_bytes(network) & m).to_be_bytes();
+    let mut table = ROUTING_TABLE.lock();
+    table.retain(|r| !(r.network == network && r.mask == mask));
+    table.push(Route { network, mask, gateway, iface });
+}
+
+/// Next hop for `dst_ip`: the gateway of the longest matching prefix, or the destination
+/// itself when it's on a directly connected network. Before DHCP has filled the table,
+/// everything goes to the QEMU gateway.
+pub fn route_lookup(dst_ip: [u8; 4]) -> [u8; 4] {
+    let dst = u32::from_be_bytes(dst_ip);
+    let table = ROUTING_TABLE.lock();
+    let best = table.iter()
+        .filter(|r| dst & u32::from_be_bytes(r.mask) == u32::from_be_bytes(r.network))
+        .max_by_key(|r| prefix_len(r.mask));
+    match best {
+        Some(r) if r.gateway == [0, 0, 0, 0] => dst_ip,
+        Some(r) => r.gateway,
+        None => GATEWAY_IP,
+    }
+}
+
+// Send time of the last echo request, for RTT
+pub static PING_SENT_NS: AtomicU64 = AtomicU64::new(0);
+
+// --- TCPDUMP ---
+static TCPDUMP_ENABLED: AtomicBool = AtomicBool::new(false);
+static FILTER_BEFORE_TCPDUMP: AtomicU8 = AtomicU8::new(LogLevel::Info as u8);
+
+pub fn enable_tcpdump(enable: bool) {
+    let was_enabled = TCPDUMP_ENABLED.swap(enable, Ordering::Relaxed);
+    // Packet lines are Debug-level; let them through while capturing, then put back
+    // whatever filter was set before
+    if enable && !was_enabled {
+        FILTER_BEFORE_TCPDUMP.store(crate::state::LOG_LEVEL_FILTER.load(Ordering::Relaxed), Ordering::Relaxed);
+        crate::logger::set_filter(LogLevel::Debug);
+    } else if !enable && was_enabled {
+        crate::state::LOG_LEVEL_FILTER.store(FILTER_BEFORE_TCPDUMP.load(Ordering::Relaxed), Ordering::Relaxed);
+    }
+}
+
+pub fn tcpdump_enabled() -> bool {
+    TCPDUMP_ENABLED.load(Ordering::Relaxed)
+}
+
+pub fn fmt_mac(m: [u8; 6]) -> String {
+    format!("{:02x}:{:02x}:{:02x}:{:02x}:{:02x}:{:02x}", m[0], m[1], m[2], m[3], m[4], m[5])
+}
+
+pub fn fmt_ip(ip: [u8; 4]) -> String {
+    format!("{}.{}.{}.{}", ip[0], ip[1], ip[2], ip[3])
+}
+
+/// One-line summary of a frame, e.g. `[123456789ns] 52:54:00:12:34:56 > ff:ff:ff:ff:ff:ff ethertype ARP`
+fn tcpdump_line(data: &[u8]) -> String {
+    let eth = unsafe { &*(data.as_ptr() as *const EthernetHeader) };
+    let mut line = format!("[{}ns] {} > {} ethertype ",
+        crate::time::monotonic_ns(), fmt_mac(eth.src_mac), fmt_mac(eth.dest_mac));
+
+    match ntohs(eth.ethertype) {
+        0x0806 => line.push_str("ARP"),
+        0x0800 if data.len() >= 14 + 20 => {
+            let ip = unsafe { &*(data.as_ptr().add(14) as *const Ipv4Header) };
+            let ihl = ((ip.version_ihl & 0x0F) as usize) * 4;
+            let proto = match ip.protocol { 1 => "ICMP", 6 => "TCP", 17 => "UDP", _ => "IP" };
+            // TCP and UDP both start with src/dst port
+            if (ip.protocol == 6 || ip.protocol == 17) && data.len() >= 14 + ihl + 4 {
+                let p = &data[14 + ihl..];
+                let src_port = ((p[0] as u16) << 8) | p[1] as u16;
+                let dst_port = ((p[2] as u16) << 8) | p[3] as u16;
+                line.push_str(&format!("IPv4 {}:{} > {}:{} ({})",
+                    fmt_ip(ip.src_ip), src_port, fmt_ip(ip.dest_ip), dst_port, proto));
+            } else {
+                line.push_str(&format!("IPv4 {} > {} ({})", fmt_ip(ip.src_ip), fmt_ip(ip.dest_ip), proto));
+            }
+        }
+        other => line.push_str(&format!("0x{:04x}", other)),
+    }
+    line.push('\n');
+    line
+}
 
 // --- HEADER DEFINITIONS ---
 #[repr(C, packed)]
@@ -68,7 +238,349 @@ pub struct IcmpHeader {
     pub seq: u16,
 }
 
+#[repr(C, packed)]
+#[derive(Debug, Clone, Copy)]
+pub struct TcpHeader {
+    pub src_port: u16,
+    pub dest_port: u16,
+    pub seq: u32,
+    pub ack: u32,
+    pub data_offset: u8, // Upper 4 bits: header length in 32-bit words
+    pub flags: u8,
+    pub window: u16,
+    pub checksum: u16,
+    pub urgent: u16,
+}
+
 fn ntohs(n: u16) -> u16 { ((n & 0xFF) << 8) | ((n & 0xFF00) >> 8) }
+fn ntohl(n: u32) -> u32 { n.swap_bytes() }
+
+// --- TCP ---
+pub const TCP_FIN: u8 = 0x01;
+pub const TCP_SYN: u8 = 0x02;
+pub const TCP_RST: u8 = 0x04;
+pub const TCP_PSH: u8 = 0x08;
+pub const TCP_ACK: u8 = 0x10;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TcpState {
+    Closed,
+    Listen,
+    SynReceived,
+    SynSent,
+    Established,
+    FinWait1,  // We sent FIN, waiting for the peer's
+    LastAck,   // Peer closed first; waiting for the ACK of our FIN
+    TimeWait,
+}
+
+pub struct TcpConnection {
+    pub id: usize,
+    pub state: TcpState,
+    pub local_port: u16,
+    pub remote_ip: [u8; 4],
+    pub remote_port: u16,
+    pub remote_mac: [u8; 6],
+    pub seq: u32, // Next sequence number we send
+    pub ack: u32, // Next sequence number we expect
+    pub rx_buf: Vec<u8>,
+    pub parent: Option<usize>, // Listener id until the connection is accepted
+}
+
+/// A segment waiting for the NIC driver to wrap it in Ethernet/IPv4 and send it
+pub struct TcpSegment {
+    pub dst_mac: [u8; 6],
+    pub dst_ip: [u8; 4],
+    pub src_port: u16,
+    pub dst_port: u16,
+    pub seq: u32,
+    pub ack: u32,
+    pub flags: u8,
+    pub payload: Vec<u8>,
+}
+
+/// An echo reply waiting for the NIC driver, same idea as TcpSegment
+pub struct IcmpEcho {
+    pub dst_mac: [u8; 6],
+    pub dst_ip: [u8; 4],
+    pub id: u16,
+    pub seq: u16,
+    pub payload: Vec<u8>,
+}
+
+// Set once DHCP hands us an address; the driver then announces it with a gratuitous ARP
+pub static GRATUITOUS_ARP_PENDING: AtomicBool = AtomicBool::new(false);
+
+static NEXT_TCP_ID: AtomicUsize = AtomicUsize::new(1);
+static NEXT_EPHEMERAL_PORT: AtomicUsize = AtomicUsize::new(49152);
+
+lazy_static! {
+    pub static ref TCP_CONNECTIONS: Mutex<Vec<TcpConnection>> = Mutex::new(Vec::new());
+    // The packet handlers have no access to the driver, so replies are queued and
+    // flushed by Nic::flush_tcp after each received frame.
+    pub static ref TCP_TX_QUEUE: Mutex<VecDeque<TcpSegment>> = Mutex::new(VecDeque::new());
+    pub static ref ICMP_TX_QUEUE: Mutex<VecDeque<IcmpEcho>> = Mutex::new(VecDeque::new());
+    pub static ref UDP_SOCKETS: Mutex<Vec<BoundSocket>> = Mutex::new(Vec::new());
+}
+
+/// Next port from the dynamic range (49152-65535), wrapping around
+fn ephemeral_port() -> u16 {
+    (49152 + (NEXT_EPHEMERAL_PORT.fetch_add(1, Ordering::Relaxed) - 49152) % 16384) as u16
+}
+
+fn queue_segment(conn: &TcpConnection, flags: u8, payload: &[u8]) {
+    TCP_TX_QUEUE.lock().push_back(TcpSegment {
+        dst_mac: conn.remote_mac,
+        dst_ip: conn.remote_ip,
+        src_port: conn.local_port,
+        dst_port: conn.remote_port,
+        seq: conn.seq,
+        ack: conn.ack,
+        flags,
+        payload: payload.to_vec(),
+    });
+}
+
+/// Sends everything the stack has queued through the shared NIC
+fn flush() {
+    crate::nic::with_nic(|nic| nic.flush_tcp());
+}
+
+/// Receives one frame (if any) through the shared NIC
+fn poll() {
+    crate::nic::with_nic(|nic| nic.sniff_packet());
+}
+
+pub struct TcpListener {
+    id: usize,
+    pub port: u16,
+}
+
+impl TcpListener {
+    /// Registers a listen socket for `port` in the connection table
+    pub fn bind(port: u16) -> TcpListener {
+        reap_connections();
+        let id = NEXT_TCP_ID.fetch_add(1, Ordering::Relaxed);
+        TCP_CONNECTIONS.lock().push(TcpConnection {
+            id,
+            state: TcpState::Listen,
+            local_port: port,
+            remote_ip: [0; 4],
+            remote_port: 0,
+            remote_mac: [0; 6],
+            seq: 0,
+            ack: 0,
+            rx_buf: Vec::new(),
+            parent: None,
+        });
+        TcpListener { id, port }
+    }
+
+    /// Polls the NIC for a bit and returns the first connection that finished its handshake.
+    /// SYNs are answered from `handle_tcp`; this only waits for the final ACK.
+    pub fn accept(&self) -> Option<TcpSocket> {
+        for _ in 0..200 {
+            poll();
+            flush();
+
+            let mut conns = TCP_CONNECTIONS.lock();
+            if let Some(conn) = conns.iter_mut()
+                .find(|c| c.parent == Some(self.id) && c.state == TcpState::Established)
+            {
+                conn.parent = None;
+                return Some(TcpSocket { id: conn.id });
+            }
+            drop(conns);
+
+            for _ in 0..50_000 { core::hint::spin_loop(); }
+        }
+        None
+    }
+}
+
+impl Drop for TcpListener {
+    fn drop(&mut self) {
+        let mut conns = TCP_CONNECTIONS.lock();
+        conns.retain(|c| c.id != self.id);
+        // Half-open children die with their listener
+        conns.retain(|c| c.parent != Some(self.id));
+    }
+}
+
+pub struct TcpSocket {
+    pub id: usize,
+}
+
+/// Drops connections that are fully closed so the table doesn't grow forever
+fn reap_connections() {
+    TCP_CONNECTIONS.lock().retain(|c| c.state != TcpState::Closed && c.state != TcpState::TimeWait);
+}
+
+/// Active open: sends SYN and polls until the handshake completes. Returns the connection id.
+pub fn tcp_connect(dst_ip: [u8; 4], dst_port: u16) -> Option<usize> {
+    reap_connections();
+
+    let hop = route_lookup(dst_ip);
+    let remote_mac = crate::nic::with_nic(|nic| nic.resolve_mac(hop))??;
+
+    let id = NEXT_TCP_ID.fetch_add(1, Ordering::Relaxed);
+    let mut conn = TcpConnection {
+        id,
+        state: TcpState::SynSent,
+        local_port: ephemeral_port(),
+        remote_ip: dst_ip,
+        remote_port: dst_port,
+        remote_mac,
+        seq: unsafe { core::arch::x86_64::_rdtsc() } as u32, // Initial sequence number
+        ack: 0,
+        rx_buf: Vec::new(),
+        parent: None,
+    };
+    queue_segment(&conn, TCP_SYN, &[]);
+    conn.seq = conn.seq.wrapping_add(1);
+    TCP_CONNECTIONS.lock().push(conn);
+    flush();
+
+    for _ in 0..200 {
+        poll();
+        match (TcpSocket { id }).state() {
+            TcpState::Established => return Some(id),
+            TcpState::SynSent => {}
+            _ => break, // RST
+        }
+        for _ in 0..50_000 { core::hint::spin_loop(); }
+    }
+    TCP_CONNECTIONS.lock().retain(|c| c.id != id);
+    None
+}
+
+pub fn tcp_send(conn_id: usize, data: &[u8]) {
+    {
+        let mut conns = TCP_CONNECTIONS.lock();
+        let conn = match conns.iter_mut().find(|c| c.id == conn_id) {
+            Some(c) if c.state == TcpState::Established => c,
+            _ => return,
+        };
+        for chunk in data.chunks(1460) {
+            queue_segment(conn, TCP_PSH | TCP_ACK, chunk);
+            conn.seq = conn.seq.wrapping_add(chunk.len() as u32);
+        }
+    }
+    flush();
+}
+
+/// Polls the NIC once and drains whatever payload has arrived
+pub fn tcp_recv(conn_id: usize) -> Vec<u8> {
+    poll();
+    flush();
+    let mut conns = TCP_CONNECTIONS.lock();
+    match conns.iter_mut().find(|c| c.id == conn_id) {
+        Some(conn) => core::mem::take(&mut conn.rx_buf),
+        None => Vec::new(),
+    }
+}
+
+/// Starts an active close (FIN). A connection the peer already closed is simply dropped.
+pub fn tcp_close(conn_id: usize) {
+    {
+        let mut conns = TCP_CONNECTIONS.lock();
+        if let Some(pos) = conns.iter().position(|c| c.id == conn_id) {
+            if conns[pos].state == TcpState::Established {
+                let conn = &mut conns[pos];
+                queue_segment(conn, TCP_FIN | TCP_ACK, &[]);
+                conn.seq = conn.seq.wrapping_add(1);
+                conn.state = TcpState::FinWait1;
+            } else {
+                conns.remove(pos);
+            }
+        }
+    }
+    flush();
+}
+
+impl TcpSocket {
+    pub fn state(&self) -> TcpState {
+        TCP_CONNECTIONS.lock().iter().find(|c| c.id == self.id).map(|c| c.state).unwrap_or(TcpState::Closed)
+    }
+
+    pub fn peer(&self) -> Option<([u8; 4], u16)> {
+        TCP_CONNECTIONS.lock().iter().find(|c| c.id == self.id).map(|c| (c.remote_ip, c.remote_port))
+    }
+
+    pub fn send(&self, data: &[u8]) {
+        tcp_send(self.id, data);
+    }
+
+    pub fn recv(&self) -> Vec<u8> {
+        tcp_recv(self.id)
+    }
+
+    pub fn close(self) {
+        tcp_close(self.id);
+    }
+}
+
+// --- UDP ---
+// Datagrams kept per socket before the oldest is dropped
+const UDP_RX_QUEUE_LEN: usize = 32;
+
+/// A bound port and the datagrams (source IP, source port, payload) waiting on it
+pub struct BoundSocket {
+    pub local_port: u16,
+    pub rx_queue: VecDeque<([u8; 4], u16, Vec<u8>)>,
+}
+
+pub struct UdpSocket {
+    pub local_port: u16,
+}
+
+impl UdpSocket {
+    /// Registers `local_port` so `handle_udp` queues datagrams addressed to it.
+    /// Returns None if another socket already holds the port.
+    pub fn bind(local_port: u16) -> Option<Self> {
+        let mut sockets = UDP_SOCKETS.lock();
+        if sockets.iter().any(|s| s.local_port == local_port) {
+            return None;
+        }
+        sockets.push(BoundSocket { local_port, rx_queue: VecDeque::new() });
+        Some(UdpSocket { local_port })
+    }
+
+    /// Routes the datagram, resolving the next hop's MAC first. Broadcasts skip ARP.
+    /// Returns false if there is no NIC or the next hop doesn't answer.
+    pub fn send_to(&mut self, dst_ip: [u8; 4], dst_port: u16, data: &[u8]) -> bool {
+        let dst_mac = if dst_ip == BROADCAST_IP {
+            [0xFF; 6]
+        } else {
+            let hop = route_lookup(dst_ip);
+            match crate::nic::with_nic(|nic| nic.resolve_mac(hop)).flatten() {
+                Some(mac) => mac,
+                None => return false,
+            }
+        };
+        send_udp_frame(dst_mac, dst_ip, self.local_port, dst_port, data)
+    }
+
+    /// Polls the NIC once and pops the oldest queued datagram
+    pub fn recv_from(&mut self) -> Option<([u8; 4], u16, Vec<u8>)> {
+        poll();
+        UDP_SOCKETS.lock().iter_mut().find(|s| s.local_port == self.local_port)?.rx_queue.pop_front()
+    }
+}
+
+impl Drop for UdpSocket {
+    fn drop(&mut self) {
+        let mut sockets = UDP_SOCKETS.lock();
+        if let Some(pos) = sockets.iter().position(|s| s.local_port == self.local_port) {
+            sockets.remove(pos);
+        }
+    }
+}
+
+/// Builds Ethernet/IPv4/UDP headers around `payload` and hands the frame to the NIC
+pub fn send_udp_frame(dst_mac: [u8; 6], dst_ip: [u8; 4], src_port: u16, dst_port: u16, payload: &[u8]) -> bool {
+    crate::nic::with_nic(|nic| nic.send_udp(dst_mac, dst_ip, src_port, dst_port, payload)).is_some()
+}
 
 // --- HANDLERS ---
 
@@ -76,6 +588,10 @@ fn ntohs(n: u16) -> u16 { ((n & 0xFF) << 8) | ((n & 0xFF00) >> 8) }
 pub fn handle_packet(data: &[u8]) -> Option<([u8; 6], [u8; 4])> {
     if data.len() < 14 { return None; }
 
+    if tcpdump_enabled() {
+        crate::klog_debug!("{}", tcpdump_line(data));
+    }
+
     let eth_header = unsafe { &*(data.as_ptr() as *const EthernetHeader) };
     let ethertype = ntohs(eth_header.ethertype);
 
@@ -87,7 +603,7 @@ pub fn handle_packet(data: &[u8]) -> Option<([u8; 6], [u8; 4])> {
         },
         _ => {
             // UNCOMMENTED DEBUG PRINT:
-            crate::writer::print(&format!("[NET] Unknown Packet Type: {:04x}\n", ethertype));
+            crate::klog_debug!("[NET] Unknown Packet Type: {:04x}\n", ethertype);
             None
         }
     }
@@ -100,16 +616,19 @@ fn handle_arp(data: &[u8]) -> Option<([u8; 6], [u8; 4])> {
     let arp = unsafe { &*arp_ptr };
 
     let opcode = ntohs(arp.opcode);
+
+    // Requests and replies both tell us the sender's mapping
+    arp_cache_insert(arp.src_ip, arp.src_mac);
     
     if opcode == 1 {
         // ARP Request for US (10.0.2.15)
         if arp.dest_ip == [10, 0, 2, 15] {
-            crate::writer::print("[NET] ARP Request for ME! Sending Reply...\n");
+            crate::klog_info!("[NET] ARP Request for ME! Sending Reply...\n");
             // Return Sender's MAC AND Sender's IP so we reply to the right place
             return Some((arp.src_mac, arp.src_ip));
         }
     } else if opcode == 2 {
-        crate::writer::print("[NET] ARP Reply received.\n");
+        crate::klog_info!("[NET] ARP Reply received.\n");
     }
     None
 }
@@ -121,38 +640,355 @@ fn handle_ipv4(data: &[u8]) {
     if ip_header.protocol == 17 {
         handle_udp(data, ip_header_ptr);
     } else if ip_header.protocol == 1 {
-        handle_icmp(ip_header_ptr);
+        handle_icmp(data, ip_header);
+    } else if ip_header.protocol == 6 {
+        handle_tcp(data, ip_header);
     }
 }
 
+fn handle_tcp(data: &[u8], ip_hdr: &Ipv4Header) {
+    let ihl = ((ip_hdr.version_ihl & 0x0F) as usize) * 4;
+    let tcp_start = 14 + ihl;
+    if data.len() < tcp_start + 20 { return; }
+
+    let tcp = unsafe { &*(data.as_ptr().add(tcp_start) as *const TcpHeader) };
+    let header_len = ((tcp.data_offset >> 4) as usize) * 4;
+    let total_len = (ntohs(ip_hdr.total_length) as usize).min(data.len() - 14);
+    if header_len < 20 || ihl + header_len > total_len { return; }
+    let payload = &data[tcp_start + header_len..14 + total_len];
+
+    let src_ip = ip_hdr.src_ip;
+    let src_port = ntohs(tcp.src_port);
+    let dst_port = ntohs(tcp.dest_port);
+    let seq = ntohl(tcp.seq);
+    let ack = ntohl(tcp.ack);
+    let flags = tcp.flags;
+    let eth = unsafe { &*(data.as_ptr() as *const EthernetHeader) };
+
+    let mut conns = TCP_CONNECTIONS.lock();
+
+    // 1. Segment for an existing connection
+    if let Some(conn) = conns.iter_mut().find(|c| {
+        c.state != TcpState::Listen && c.local_port == dst_port && c.remote_ip == src_ip && c.remote_port == src_port
+    }) {
+        if flags & TCP_RST != 0 {
+            conn.state = TcpState::Closed;
+            return;
+        }
+        match conn.state {
+            TcpState::SynReceived => {
+                if flags & TCP_ACK != 0 && ack == conn.seq {
+                    conn.state = TcpState::Established;
+                }
+            }
+            TcpState::SynSent => {
+                // Our SYN answered: ACK their SYN and we're open
+                if flags & (TCP_SYN | TCP_ACK) == (TCP_SYN | TCP_ACK) && ack == conn.seq {
+                    conn.ack = seq.wrapping_add(1);
+                    conn.state = TcpState::Established;
+                    queue_segment(conn, TCP_ACK, &[]);
+                }
+                return;
+            }
+            TcpState::FinWait1 => {
+                // Still deliver data sent before the peer's FIN
+                if !payload.is_empty() && seq == conn.ack {
+                    conn.rx_buf.extend_from_slice(payload);
+                    conn.ack = conn.ack.wrapping_add(payload.len() as u32);
+                }
+                if flags & TCP_FIN != 0 {
+                    conn.ack = seq.wrapping_add(payload.len() as u32).wrapping_add(1);
+                    queue_segment(conn, TCP_ACK, &[]);
+                    conn.state = TcpState::TimeWait;
+                } else if !payload.is_empty() {
+                    queue_segment(conn, TCP_ACK, &[]);
+                }
+                return;
+            }
+            TcpState::LastAck => {
+                if flags & TCP_ACK != 0 { conn.state = TcpState::Closed; }
+                return;
+            }
+            _ => {}
+        }
+        if conn.state != TcpState::Established { return; }
+
+        // 2. In-order data goes to the receive buffer; anything else gets a duplicate ACK
+        if !payload.is_empty() {
+            if seq == conn.ack {
+                conn.rx_buf.extend_from_slice(payload);
+                conn.ack = conn.ack.wrapping_add(payload.len() as u32);
+            }
+            queue_segment(conn, TCP_ACK, &[]);
+        }
+
+        // 3. Peer is done sending: ACK its FIN together with ours
+        if flags & TCP_FIN != 0 && seq.wrapping_add(payload.len() as u32) == conn.ack {
+            conn.ack = conn.ack.wrapping_add(1);
+            queue_segment(conn, TCP_FIN | TCP_ACK, &[]);
+            conn.seq = conn.seq.wrapping_add(1);
+            conn.state = TcpState::LastAck;
+        }
+        return;
+    }
+
+    // 4. New SYN: hand it to the listener on that port
+    if flags & TCP_SYN != 0 && flags & TCP_ACK == 0 {
+        let listener_id = match conns.iter().find(|c| c.state == TcpState::Listen && c.local_port == dst_port) {
+            Some(l) => l.id,
+            None => return,
+        };
+        let mut conn = TcpConnection {
+            id: NEXT_TCP_ID.fetch_add(1, Ordering::Relaxed),
+            state: TcpState::SynReceived,
+            local_port: dst_port,
+            remote_ip: src_ip,
+            remote_port: src_port,
+            remote_mac: eth.src_mac,
+            seq: unsafe { core::arch::x86_64::_rdtsc() } as u32, // Initial sequence number
+            ack: seq.wrapping_add(1),
+            rx_buf: Vec::new(),
+            parent: Some(listener_id),
+        };
+        queue_segment(&conn, TCP_SYN | TCP_ACK, &[]);
+        conn.seq = conn.seq.wrapping_add(1);
+        conns.push(conn);
+    }
+}
+
+/// Queues the payload on the socket bound to the destination port; unbound ports drop it
 fn handle_udp(data: &[u8], ip_header_ptr: *const u8) {
-    let udp_header_ptr = unsafe { ip_header_ptr.add(20) };
-    let udp_header = unsafe { &*(udp_header_ptr as *const UdpHeader) };
+    if data.len() < 14 + 20 + 8 { return; }
+    let udp_header = unsafe { &*(ip_header_ptr.add(20) as *const UdpHeader) };
+    let ip = unsafe { &*(ip_header_ptr as *const Ipv4Header) };
     let dest_port = ntohs(udp_header.dest_port);
-    if dest_port == 68 {
-        handle_dhcp(udp_header_ptr);
+    if dest_port == DHCP_CLIENT_PORT {
+        // The offer comes straight from the DHCP server, so its MAC is worth keeping
+        let eth = unsafe { &*(data.as_ptr() as *const EthernetHeader) };
+        arp_cache_insert(ip.src_ip, eth.src_mac);
+    }
+
+    let start = 14 + 20 + 8;
+    let end = core::cmp::min(data.len(), 14 + 20 + ntohs(udp_header.length) as usize);
+    if end < start { return; }
+
+    let mut sockets = UDP_SOCKETS.lock();
+    if let Some(sock) = sockets.iter_mut().find(|s| s.local_port == dest_port) {
+        if sock.rx_queue.len() >= UDP_RX_QUEUE_LEN { sock.rx_queue.pop_front(); }
+        sock.rx_queue.push_back((ip.src_ip, ntohs(udp_header.src_port), data[start..end].to_vec()));
+    }
+}
+
+// --- DHCP ---
+const DHCP_SERVER_PORT: u16 = 67;
+const DHCP_CLIENT_PORT: u16 = 68;
+const DHCP_XID: u32 = 0x3903F326;
+
+/// Broadcasts a DISCOVER and takes the address from the first OFFER. Retries a few times.
+pub fn dhcp_configure() -> bool {
+    let mac = match crate::nic::with_nic(|nic| nic.mac_addr) {
+        Some(m) => m,
+        None => return false,
+    };
+
+    // 1. BOOTP header: request, Ethernet, 6-byte hardware address, then the magic cookie
+    let mut discover = alloc::vec![0u8; 244];
+    discover[0] = 1; discover[1] = 1; discover[2] = 6;
+    discover[4..8].copy_from_slice(&DHCP_XID.to_be_bytes());
+    discover[28..34].copy_from_slice(&mac); // CHADDR
+    discover[236..240].copy_from_slice(&[0x63, 0x82, 0x53, 0x63]);
+    discover[240..244].copy_from_slice(&[53, 1, 1, 255]); // Option 53: Discover, End
+
+    let mut sock = match UdpSocket::bind(DHCP_CLIENT_PORT) {
+        Some(s) => s,
+        None => {
+            crate::klog_error!("[NET] DHCP client port already in use.\n");
+            return false;
+        }
+    };
+    for _ in 0..3 {
+        // 2. Send it and wait for an offer carrying our transaction id
+        sock.send_to(BROADCAST_IP, DHCP_SERVER_PORT, &discover);
+        crate::klog_info!("[NET] DHCP DISCOVER sent.\n");
+        for _ in 0..100 {
+            while let Some((_, _, reply)) = sock.recv_from() {
+                if reply.len() >= 240 && reply[0] == 2 && reply[4..8] == DHCP_XID.to_be_bytes() {
+                    apply_dhcp_offer(&reply);
+                    return true;
+                }
+            }
+            for _ in 0..50_000 { core::hint::spin_loop(); }
+        }
     }
+    false
 }
 
-fn handle_dhcp(udp_header_ptr: *const u8) {
-    let dhcp_ptr = unsafe { udp_header_ptr.add(8) };
-    let dhcp = unsafe { &*(dhcp_ptr as *const DhcpPacket) };
+fn apply_dhcp_offer(reply: &[u8]) {
+    let dhcp = unsafe { &*(reply.as_ptr() as *const DhcpPacket) };
     let ip = dhcp.yiaddr;
+    let options = &reply[240..];
     
     // SAVE THE IP TO GLOBAL STATE
     crate::state::set_my_ip(ip);
+    GRATUITOUS_ARP_PENDING.store(true, Ordering::Relaxed);
+
+    // Option 1 (subnet mask) and 3 (router) give us the connected network and default route
+    let mut mask = [255, 255, 255, 0];
+    let mut gateway = GATEWAY_IP;
+    let mut i = 0;
+    while i < options.len() {
+        let code = options[i];
+        if code == 0 { i += 1; continue; }
+        if code == 255 || i + 1 >= options.len() { break; }
+        let len = options[i + 1] as usize;
+        let value = match options.get(i + 2..i + 2 + len) {
+            Some(v) => v,
+            None => break,
+        };
+        if len >= 4 && code == 1 { mask.copy_from_slice(&value[..4]); }
+        if len >= 4 && code == 3 { gateway.copy_from_slice(&value[..4]); }
+        i += 2 + len;
+    }
+    route_add(ip, mask, [0, 0, 0, 0], 0);
+    route_add([0, 0, 0, 0], [0, 0, 0, 0], gateway, 0);
     
-    crate::writer::print(&format!(
+    crate::klog_info!(
         "   >>> IP ASSIGNED AND SAVED: {}.{}.{}.{} <<<\n",
         ip[0], ip[1], ip[2], ip[3]
-    ));
+    );
 }
 
-fn handle_icmp(ip_header_ptr: *const u8) {
-    let icmp_ptr = unsafe { ip_header_ptr.add(20) };
+fn handle_icmp(data: &[u8], ip_header: &Ipv4Header) {
+    if data.len() < 14 + 20 + 8 { return; }
+    let icmp_ptr = unsafe { (ip_header as *const Ipv4Header as *const u8).add(20) };
     let icmp = unsafe { &*(icmp_ptr as *const IcmpHeader) };
-    if icmp.packet_type == 0 { 
+    if icmp.packet_type == 8 {
+        // Echo request: answer with the same id, seq and payload
+        let my_ip = crate::state::get_my_ip();
+        if my_ip == [0, 0, 0, 0] || ip_header.dest_ip != my_ip { return; }
+        let eth = unsafe { &*(data.as_ptr() as *const EthernetHeader) };
+        let src_ip = ip_header.src_ip;
+        arp_cache_insert(src_ip, eth.src_mac);
+        let dst_mac = arp_cache_lookup(src_ip).unwrap_or(eth.src_mac);
+
+        let ip_len = (ntohs(ip_header.total_length) as usize).min(data.len() - 14);
+        let payload = data.get(14 + 28..14 + ip_len).unwrap_or(&[]).to_vec();
+        ICMP_TX_QUEUE.lock().push_back(IcmpEcho {
+            dst_mac,
+            dst_ip: src_ip,
+            id: ntohs(icmp.id),
+            seq: ntohs(icmp.seq),
+            payload,
+        });
+        crate::klog_debug!("[NET] Echo request from {}, replying.\n", fmt_ip(src_ip));
+    } else if icmp.packet_type == 0 { 
         let seq = ntohs(icmp.seq);
-        crate::writer::print(&format!("[NET] PING REPLY! Seq={}\n", seq));
+        let rtt_ms = crate::time::monotonic_ns().saturating_sub(PING_SENT_NS.load(Ordering::Relaxed)) / 1_000_000;
+        let mac = arp_cache_lookup(ip_header.src_ip).map(fmt_mac).unwrap_or_else(|| String::from("?"));
+        crate::klog_info!("[NET] PING REPLY! Seq={} RTT={}ms MAC={}\n", seq, rtt_ms, mac);
     }
-}
\ No newline at end of file
+}
+// --- DNS ---
+pub const DNS_SERVER: [u8; 4] = [1, 1, 1, 1];
+
+/// Resolves `hostname` to an IPv4 address with a single A-record query to DNS_SERVER
+pub fn dns_query(hostname: &str) -> Option<[u8; 4]> {
+    let id = (unsafe { core::arch::x86_64::_rdtsc() } & 0xFFFF) as u16;
+
+    // 1. Header: ID, RD flag, one question
+    let mut query: Vec<u8> = Vec::with_capacity(12 + hostname.len() + 6);
+    query.extend_from_slice(&id.to_be_bytes());
+    query.extend_from_slice(&[0x01, 0x00, 0, 1, 0, 0, 0, 0, 0, 0]);
+
+    // 2. Question: length-prefixed labels, QTYPE=A, QCLASS=IN
+    for label in hostname.split('.').filter(|l| !l.is_empty()) {
+        if label.len() > 63 { return None; }
+        query.push(label.len() as u8);
+        query.extend_from_slice(label.as_bytes());
+    }
+    query.extend_from_slice(&[0, 0, 1, 0, 1]);
+
+    // 3. Send it from a fresh port (routed, normally through the gateway)
+    let mut sock = UdpSocket::bind(ephemeral_port())?;
+    if !sock.send_to(DNS_SERVER, 53, &query) {
+        return None;
+    }
+
+    // 4. Wait for the matching response
+    for _ in 0..100 {
+        while let Some((src_ip, src_port, reply)) = sock.recv_from() {
+            if src_ip == DNS_SERVER && src_port == 53 && reply.len() >= 12 && reply[0..2] == id.to_be_bytes() {
+                return parse_dns_a(&reply);
+            }
+        }
+        for _ in 0..50_000 { core::hint::spin_loop(); }
+    }
+    None
+}
+
+/// Skips an encoded name (labels or a compression pointer), returning the offset after it
+fn skip_dns_name(msg: &[u8], mut i: usize) -> Option<usize> {
+    loop {
+        let len = *msg.get(i)? as usize;
+        if len == 0 { return Some(i + 1); }
+        if len & 0xC0 == 0xC0 { return Some(i + 2); }
+        i += 1 + len;
+    }
+}
+
+/// First A record in the answer section of a DNS response
+fn parse_dns_a(msg: &[u8]) -> Option<[u8; 4]> {
+    let rcode = msg[3] & 0x0F;
+    if rcode != 0 { return None; }
+    let qdcount = u16::from_be_bytes([msg[4], msg[5]]);
+    let ancount = u16::from_be_bytes([msg[6], msg[7]]);
+
+    let mut i = 12;
+    for _ in 0..qdcount {
+        i = skip_dns_name(msg, i)? + 4; // QTYPE + QCLASS
+    }
+    for _ in 0..ancount {
+        i = skip_dns_name(msg, i)?;
+        let rr = msg.get(i..i + 10)?;
+        let rtype = u16::from_be_bytes([rr[0], rr[1]]);
+        let rdlen = u16::from_be_bytes([rr[8], rr[9]]) as usize;
+        i += 10;
+        let rdata = msg.get(i..i + rdlen)?;
+        if rtype == 1 && rdlen == 4 {
+            return Some([rdata[0], rdata[1], rdata[2], rdata[3]]);
+        }
+        i += rdlen; // CNAMEs etc. come before the address
+    }
+    None
+}
+
+// --- HTTP ---
+pub const HTTP_MAX_RESPONSE: usize = 64 * 1024;
+
+/// HTTP/1.0 GET: reads until the server closes the connection (or HTTP_MAX_RESPONSE) and
+/// returns the body, headers stripped. None if the connection fails.
+pub fn http_get(ip: [u8; 4], port: u16, path: &str) -> Option<Vec<u8>> {
+    let conn = tcp_connect(ip, port)?;
+    let request = format!("GET {} HTTP/1.0\r\nHost: {}\r\nConnection: close\r\n\r\n", path, fmt_ip(ip));
+    tcp_send(conn, request.as_bytes());
+
+    // Give up after ~300 polls without a byte
+    let mut response = Vec::new();
+    let mut idle = 0;
+    while idle < 300 && response.len() < HTTP_MAX_RESPONSE {
+        let chunk = tcp_recv(conn);
+        if chunk.is_empty() {
+            if (TcpSocket { id: conn }).state() != TcpState::Established { break; } // FIN received
+            idle += 1;
+            for _ in 0..50_000 { core::hint::spin_loop(); }
+        } else {
+            idle = 0;
+            response.extend_from_slice(&chunk);
+        }
+    }
+    tcp_close(conn);
+    response.truncate(HTTP_MAX_RESPONSE);
+
+    let body_start = response.windows(4).position(|w| w == b"\r\n\r\n").map(|p| p + 4).unwrap_or(0);
+    Some(response.split_off(body_start))
+}
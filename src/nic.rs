@@ -0,0 +1,367 @@
+use crate::{state, net};
+use crate::rtl8139::{Rtl8139, NetStats};
+use crate::virtio_net::VirtioNet;
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use core::sync::atomic::Ordering;
+use spin::Mutex;
+use lazy_static::lazy_static;
+
+// --- NETWORK INTERFACE ---
+// One frame-level interface over every NIC driver, so ARP, ICMP and the TCP/UDP framing
+// are written once. `probe` picks the card: the RTL8139 first, then a VirtIO one.
+
+pub trait NetDevice: Send {
+    /// Sends one Ethernet frame (no CRC); short frames are padded by the driver
+    fn transmit(&mut self, frame: &[u8]);
+    /// Next received Ethernet frame (no CRC), if any
+    fn receive(&mut self) -> Option<Vec<u8>>;
+    fn mac_addr(&self) -> [u8; 6];
+    /// Interface counters, if the driver keeps them
+    fn stats(&mut self) -> Option<NetStats> { None }
+    fn name(&self) -> &'static str;
+}
+
+impl NetDevice for Rtl8139 {
+    fn transmit(&mut self, frame: &[u8]) { Rtl8139::transmit(self, frame) }
+    fn receive(&mut self) -> Option<Vec<u8>> { Rtl8139::receive(self) }
+    fn mac_addr(&self) -> [u8; 6] { self.mac_addr }
+    fn stats(&mut self) -> Option<NetStats> { Some(self.read_stats()) }
+    fn name(&self) -> &'static str { "RTL8139" }
+}
+
+impl NetDevice for VirtioNet {
+    fn transmit(&mut self, frame: &[u8]) { self.send(frame) }
+    fn receive(&mut self) -> Option<Vec<u8>> { self.poll_rx() }
+    fn mac_addr(&self) -> [u8; 6] { self.mac_addr }
+    fn name(&self) -> &'static str { "VirtIO" }
+}
+
+pub struct Nic {
+    dev: Box<dyn NetDevice>,
+    pub mac_addr: [u8; 6],
+}
+
+impl Nic {
+    pub fn new(dev: Box<dyn NetDevice>) -> Self {
+        let mac_addr = dev.mac_addr();
+        Nic { dev, mac_addr }
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.dev.name()
+    }
+
+    pub fn read_stats(&mut self) -> Option<NetStats> {
+        self.dev.stats()
+    }
+
+    pub fn log_mac(&self) {
+        let m = self.mac_addr;
+        crate::klog_info!("[NET] {} MAC: {:02x}:{:02x}:{:02x}:{:02x}:{:02x}:{:02x}\n",
+            self.dev.name(), m[0], m[1], m[2], m[3], m[4], m[5]);
+    }
+
+    // --- ARP ---
+    pub fn send_arp_request(&mut self, target_ip: [u8; 4]) {
+        let mut pkt = [0u8; 60];
+        // Eth (broadcast)
+        for i in 0..6 { pkt[i] = 0xFF; pkt[i+6] = self.mac_addr[i]; }
+        pkt[12] = 0x08; pkt[13] = 0x06;
+        // ARP
+        pkt[14] = 0; pkt[15] = 1; pkt[16] = 8; pkt[17] = 0; pkt[18] = 6; pkt[19] = 4; pkt[21] = 1; // Request
+        let my_ip = state::get_my_ip();
+        let src = if my_ip == [0,0,0,0] { [10,0,2,15] } else { my_ip };
+        pkt[22..28].copy_from_slice(&self.mac_addr);
+        pkt[28..32].copy_from_slice(&src);
+        pkt[38..42].copy_from_slice(&target_ip);
+
+        self.dev.transmit(&pkt);
+        crate::klog_debug!("[NET] ARP Who-has {}.{}.{}.{} sent.\n", target_ip[0], target_ip[1], target_ip[2], target_ip[3]);
+    }
+
+    /// Announces our (new) IP so peers learn our MAC without asking
+    pub fn send_gratuitous_arp(&mut self) {
+        let my_ip = state::get_my_ip();
+        if my_ip == [0,0,0,0] { return; }
+        let mut pkt = [0u8; 60];
+        for i in 0..6 { pkt[i] = 0xFF; pkt[i+6] = self.mac_addr[i]; }
+        pkt[12] = 0x08; pkt[13] = 0x06;
+        // ARP request with sender IP == target IP
+        pkt[14] = 0; pkt[15] = 1; pkt[16] = 8; pkt[17] = 0; pkt[18] = 6; pkt[19] = 4; pkt[21] = 1;
+        pkt[22..28].copy_from_slice(&self.mac_addr);
+        pkt[28..32].copy_from_slice(&my_ip);
+        pkt[38..42].copy_from_slice(&my_ip);
+
+        self.dev.transmit(&pkt);
+        crate::klog_debug!("[NET] Gratuitous ARP for {}.{}.{}.{} sent.\n", my_ip[0], my_ip[1], my_ip[2], my_ip[3]);
+    }
+
+    /// MAC for `ip` from the ARP cache, broadcasting a request (and polling briefly) on a miss
+    pub fn resolve_mac(&mut self, ip: [u8; 4]) -> Option<[u8; 6]> {
+        if let Some(mac) = net::arp_cache_lookup(ip) { return Some(mac); }
+        self.send_arp_request(ip);
+        for _ in 0..100 {
+            self.sniff_packet();
+            if let Some(mac) = net::arp_cache_lookup(ip) { return Some(mac); }
+            for _ in 0..50_000 { core::hint::spin_loop(); }
+        }
+        None
+    }
+
+    // --- ICMP PING ---
+    pub fn send_ping(&mut self, seq: u16) {
+        let mut pkt = [0u8; 74];
+        let mut i = 0;
+        // Fall back to the standard QEMU gateway MAC if nobody answers ARP
+        let dest_mac = self.resolve_mac(net::GATEWAY_IP).unwrap_or([0x52, 0x54, 0x00, 0x12, 0x34, 0x56]);
+        for j in 0..6 { pkt[i] = dest_mac[j]; i += 1; }
+        for j in 0..6 { pkt[i] = self.mac_addr[j]; i += 1; }
+        pkt[i] = 0x08; pkt[i+1] = 0x00; i += 2;
+
+        let ip_start = i;
+        pkt[i] = 0x45; pkt[i+3] = 60; pkt[i+8] = 0x80; pkt[i+9] = 1; // ICMP
+        let my_ip = state::get_my_ip();
+        let src = if my_ip == [0,0,0,0] { [10,0,2,15] } else { my_ip };
+        for j in 0..4 { pkt[i+12+j] = src[j]; pkt[i+16+j] = net::GATEWAY_IP[j]; }
+        let csum = self.calc_ip_checksum(&pkt[ip_start..ip_start+20]);
+        pkt[ip_start+10] = (csum >> 8) as u8; pkt[ip_start+11] = (csum & 0xFF) as u8;
+        i += 20;
+
+        let icmp_start = i;
+        pkt[i] = 8; // Type 8: Echo Request
+        pkt[i+4] = 0x12; pkt[i+5] = 0x34; // ID
+        pkt[i+6] = (seq >> 8) as u8; pkt[i+7] = (seq & 0xFF) as u8;
+        let ic_csum = self.calc_ip_checksum(&pkt[icmp_start..icmp_start+40]);
+        pkt[icmp_start+2] = (ic_csum >> 8) as u8; pkt[icmp_start+3] = (ic_csum & 0xFF) as u8;
+        
+        net::PING_SENT_NS.store(crate::time::monotonic_ns(), Ordering::Relaxed);
+        self.dev.transmit(&pkt);
+        crate::klog_info!("[NET] ICMP Echo (Seq {}) sent.\n", seq);
+    }
+
+    pub fn send_icmp_reply(&mut self, dst_mac: [u8; 6], dst_ip: [u8; 4], id: u16, seq: u16, payload: &[u8]) {
+        let payload = &payload[..payload.len().min(1472)];
+        let icmp_len = 8 + payload.len();
+        let mut pkt = alloc::vec![0u8; 14 + 20 + icmp_len];
+
+        // Ethernet Header
+        pkt[0..6].copy_from_slice(&dst_mac);
+        pkt[6..12].copy_from_slice(&self.mac_addr);
+        pkt[12] = 0x08; pkt[13] = 0x00;
+
+        // IP Header
+        let ip_len = 20 + icmp_len;
+        let my_ip = state::get_my_ip();
+        let src = if my_ip == [0,0,0,0] { [10,0,2,15] } else { my_ip };
+        pkt[14] = 0x45;
+        pkt[16] = (ip_len >> 8) as u8; pkt[17] = (ip_len & 0xFF) as u8;
+        pkt[22] = 64; pkt[23] = 1; // TTL, Protocol ICMP
+        pkt[26..30].copy_from_slice(&src);
+        pkt[30..34].copy_from_slice(&dst_ip);
+        let csum = self.calc_ip_checksum(&pkt[14..34]);
+        pkt[24] = (csum >> 8) as u8; pkt[25] = (csum & 0xFF) as u8;
+
+        // ICMP Header: Type 0 (Echo Reply), checksum over header + payload
+        let c = 34;
+        pkt[c+4..c+6].copy_from_slice(&id.to_be_bytes());
+        pkt[c+6..c+8].copy_from_slice(&seq.to_be_bytes());
+        pkt[c+8..].copy_from_slice(payload);
+        let ic_csum = self.calc_ip_checksum(&pkt[c..]);
+        pkt[c+2] = (ic_csum >> 8) as u8; pkt[c+3] = (ic_csum & 0xFF) as u8;
+
+        self.dev.transmit(&pkt);
+    }
+
+    // --- ARP REPLY ---
+    pub fn send_arp_reply(&mut self, t_mac: [u8; 6], t_ip: [u8; 4]) {
+        let mut pkt = [0u8; 60];
+        // Eth
+        for i in 0..6 { pkt[i] = t_mac[i]; pkt[i+6] = self.mac_addr[i]; }
+        pkt[12] = 0x08; pkt[13] = 0x06;
+        // ARP
+        pkt[14] = 0; pkt[15] = 1; pkt[16] = 8; pkt[17] = 0; pkt[18] = 6; pkt[19] = 4; pkt[21] = 2; // Reply
+        for i in 0..6 { pkt[22+i] = self.mac_addr[i]; pkt[32+i] = t_mac[i]; }
+        let my_ip = state::get_my_ip();
+        let src = if my_ip == [0,0,0,0] { [10,0,2,15] } else { my_ip };
+        for i in 0..4 { pkt[28+i] = src[i]; pkt[38+i] = t_ip[i]; }
+        
+        self.dev.transmit(&pkt);
+        crate::klog_debug!("[NET] ARP Reply sent to Gateway.\n");
+    }
+
+    // --- TCP ---
+    pub fn send_tcp(&mut self, seg: &net::TcpSegment) {
+        let payload = &seg.payload[..seg.payload.len().min(1460)];
+        let tcp_len = 20 + payload.len();
+        let mut pkt = alloc::vec![0u8; 14 + 20 + tcp_len];
+
+        // Ethernet Header
+        pkt[0..6].copy_from_slice(&seg.dst_mac);
+        pkt[6..12].copy_from_slice(&self.mac_addr);
+        pkt[12] = 0x08; pkt[13] = 0x00;
+
+        // IP Header
+        let ip_len = 20 + tcp_len;
+        let my_ip = state::get_my_ip();
+        let src = if my_ip == [0,0,0,0] { [10,0,2,15] } else { my_ip };
+        pkt[14] = 0x45;
+        pkt[16] = (ip_len >> 8) as u8; pkt[17] = (ip_len & 0xFF) as u8;
+        pkt[20] = 0x40; // Don't Fragment
+        pkt[22] = 64; pkt[23] = 6; // TTL, Protocol TCP
+        pkt[26..30].copy_from_slice(&src);
+        pkt[30..34].copy_from_slice(&seg.dst_ip);
+        let csum = self.calc_ip_checksum(&pkt[14..34]);
+        pkt[24] = (csum >> 8) as u8; pkt[25] = (csum & 0xFF) as u8;
+
+        // TCP Header
+        let t = 34;
+        pkt[t..t+2].copy_from_slice(&seg.src_port.to_be_bytes());
+        pkt[t+2..t+4].copy_from_slice(&seg.dst_port.to_be_bytes());
+        pkt[t+4..t+8].copy_from_slice(&seg.seq.to_be_bytes());
+        pkt[t+8..t+12].copy_from_slice(&seg.ack.to_be_bytes());
+        pkt[t+12] = 5 << 4; // 20-byte header, no options
+        pkt[t+13] = seg.flags;
+        pkt[t+14] = 0x20; pkt[t+15] = 0x00; // Window 8192 (one RX ring)
+        pkt[t+20..].copy_from_slice(payload);
+
+        // Checksum covers the pseudo header (src, dst, proto, length) + segment
+        let mut pseudo: Vec<u8> = Vec::with_capacity(12 + tcp_len);
+        pseudo.extend_from_slice(&src);
+        pseudo.extend_from_slice(&seg.dst_ip);
+        pseudo.extend_from_slice(&[0, 6, (tcp_len >> 8) as u8, (tcp_len & 0xFF) as u8]);
+        pseudo.extend_from_slice(&pkt[t..]);
+        let tcp_csum = self.calc_ip_checksum(&pseudo);
+        pkt[t+16] = (tcp_csum >> 8) as u8; pkt[t+17] = (tcp_csum & 0xFF) as u8;
+
+        self.dev.transmit(&pkt);
+    }
+
+    // --- IPv4 ---
+    /// Wraps `payload` in an IP header and sends it to `dst_mac` (the next hop, already resolved)
+    pub fn send_ipv4(&mut self, dst_mac: [u8; 6], dst_ip: [u8; 4], proto: u8, payload: &[u8]) {
+        let payload = &payload[..payload.len().min(1480)];
+        let mut pkt = alloc::vec![0u8; 14 + 20 + payload.len()];
+
+        // Ethernet Header
+        pkt[0..6].copy_from_slice(&dst_mac);
+        pkt[6..12].copy_from_slice(&self.mac_addr);
+        pkt[12] = 0x08; pkt[13] = 0x00;
+
+        // IP Header
+        let ip_len = 20 + payload.len();
+        let my_ip = state::get_my_ip();
+        // Broadcasts before DHCP (the DISCOVER itself) come from 0.0.0.0
+        let src = if my_ip == [0,0,0,0] && dst_ip != net::BROADCAST_IP { [10,0,2,15] } else { my_ip };
+        pkt[14] = 0x45;
+        pkt[16] = (ip_len >> 8) as u8; pkt[17] = (ip_len & 0xFF) as u8;
+        pkt[20] = 0x40; // Don't Fragment
+        pkt[22] = 64; pkt[23] = proto;
+        pkt[26..30].copy_from_slice(&src);
+        pkt[30..34].copy_from_slice(&dst_ip);
+        let csum = self.calc_ip_checksum(&pkt[14..34]);
+        pkt[24] = (csum >> 8) as u8; pkt[25] = (csum & 0xFF) as u8;
+        pkt[34..].copy_from_slice(payload);
+
+        self.dev.transmit(&pkt);
+    }
+
+    // --- UDP ---
+    pub fn send_udp(&mut self, dst_mac: [u8; 6], dst_ip: [u8; 4], src_port: u16, dst_port: u16, payload: &[u8]) {
+        let payload = &payload[..payload.len().min(1472)];
+        let udp_len = 8 + payload.len();
+        let mut udp = alloc::vec![0u8; udp_len];
+
+        // UDP Header (checksum 0 = not computed, allowed over IPv4)
+        udp[0..2].copy_from_slice(&src_port.to_be_bytes());
+        udp[2..4].copy_from_slice(&dst_port.to_be_bytes());
+        udp[4..6].copy_from_slice(&(udp_len as u16).to_be_bytes());
+        udp[8..].copy_from_slice(payload);
+
+        self.send_ipv4(dst_mac, dst_ip, 17, &udp);
+    }
+
+    /// Sends the echo replies and announcements the stack queued while handling packets
+    fn flush_control(&mut self) {
+        if net::GRATUITOUS_ARP_PENDING.swap(false, Ordering::Relaxed) {
+            self.send_gratuitous_arp();
+        }
+        loop {
+            let echo = net::ICMP_TX_QUEUE.lock().pop_front();
+            match echo {
+                Some(e) => self.send_icmp_reply(e.dst_mac, e.dst_ip, e.id, e.seq, &e.payload),
+                None => break,
+            }
+        }
+    }
+
+    /// Sends every segment the TCP layer queued while handling packets
+    pub fn flush_tcp(&mut self) {
+        loop {
+            let seg = net::TCP_TX_QUEUE.lock().pop_front();
+            match seg {
+                Some(seg) => self.send_tcp(&seg),
+                None => break,
+            }
+        }
+    }
+
+    // --- RECEIVE ENGINE ---
+    /// Hands the frames the card has queued to the stack and sends whatever it answers
+    pub fn sniff_packet(&mut self) {
+        // Bounded so a flood can't starve the rest of the frame
+        for _ in 0..16 {
+            let packet = match self.dev.receive() {
+                Some(p) => p,
+                None => break,
+            };
+            // Some means it's an ARP request that needs a reply
+            if let Some((m, i)) = net::handle_packet(&packet) {
+                self.send_arp_reply(m, i);
+            }
+            self.flush_control();
+            self.flush_tcp();
+        }
+    }
+
+    fn calc_ip_checksum(&self, data: &[u8]) -> u16 {
+        let mut sum: u32 = 0;
+        for i in (0..data.len()).step_by(2) {
+            let word = if i + 1 < data.len() {
+                ((data[i] as u32) << 8) | (data[i+1] as u32)
+            } else {
+                (data[i] as u32) << 8
+            };
+            sum = sum.wrapping_add(word);
+        }
+        while (sum >> 16) != 0 { sum = (sum & 0xFFFF) + (sum >> 16); }
+        !sum as u16
+    }
+}
+
+
+// --- GLOBAL INSTANCE ---
+lazy_static! {
+    pub static ref NIC: Mutex<Option<Nic>> = Mutex::new(None);
+}
+
+/// Brings up the first supported card on the PCI bus
+pub fn probe() -> Option<Nic> {
+    let devices = crate::pci::scan_bus();
+    if let Some(dev) = devices.iter().find(|d| d.vendor_id == 0x10EC && d.device_id == 0x8139) {
+        crate::pci::enable_bus_mastering(dev.clone());
+        return Some(Nic::new(Box::new(Rtl8139::new(dev.clone()))));
+    }
+    let dev = devices.into_iter().find(crate::virtio_net::is_virtio_net)?;
+    crate::pci::enable_bus_mastering(dev.clone());
+    Some(Nic::new(Box::new(VirtioNet::new(dev)?)))
+}
+
+/// Runs `f` against the shared NIC, probing the PCI bus on first use
+pub fn with_nic<R>(f: impl FnOnce(&mut Nic) -> R) -> Option<R> {
+    let mut nic = NIC.lock();
+    if nic.is_none() {
+        *nic = Some(probe()?);
+    }
+    nic.as_mut().map(f)
+}
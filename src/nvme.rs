@@ -0,0 +1,358 @@
+use crate::pci::{PciDevice, pci_read_u32, pci_write_u32};
+use crate::{memory, state};
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::sync::atomic::{fence, Ordering};
+use spin::Mutex;
+use lazy_static::lazy_static;
+
+// --- CONTROLLER REGISTERS (BAR0) ---
+const REG_CAP: usize = 0x00;  // Capabilities (u64)
+const REG_VS: usize = 0x08;   // Version
+const REG_CC: usize = 0x14;   // Controller Configuration
+const REG_CSTS: usize = 0x1C; // Controller Status
+const REG_AQA: usize = 0x24;  // Admin Queue Attributes
+const REG_ASQ: usize = 0x28;  // Admin Submission Queue base (u64)
+const REG_ACQ: usize = 0x30;  // Admin Completion Queue base (u64)
+const DOORBELL_BASE: usize = 0x1000;
+const BAR0_MAP_SIZE: u64 = 0x4000; // Registers plus the first doorbells at any stride
+
+const CC_EN: u32 = 1;
+const CC_IOSQES: u32 = 6 << 16; // 64-byte submission entries
+const CC_IOCQES: u32 = 4 << 20; // 16-byte completion entries
+const CSTS_RDY: u32 = 1;
+const CSTS_CFS: u32 = 2; // Controller Fatal Status
+
+// Admin opcodes
+const ADMIN_CREATE_IO_SQ: u8 = 0x01;
+const ADMIN_CREATE_IO_CQ: u8 = 0x05;
+const ADMIN_IDENTIFY: u8 = 0x06;
+// NVM opcodes
+const NVM_WRITE: u8 = 0x01;
+const NVM_READ: u8 = 0x02;
+
+const CNS_NAMESPACE: u32 = 0;
+const CNS_CONTROLLER: u32 = 1;
+
+const QUEUE_ENTRIES: u16 = 64; // One 4 KiB page of submission entries
+const SQ_ENTRY_SIZE: usize = 64;
+const CQ_ENTRY_SIZE: usize = 16;
+const PAGE_SIZE: usize = 4096;
+// PRP1 + PRP2 address two pages without a PRP list
+const MAX_TRANSFER: usize = 2 * PAGE_SIZE;
+const NAMESPACE_ID: u32 = 1;
+const POLL_SPINS: usize = 10_000_000;
+
+/// NVMe controllers: mass storage, NVM subclass, NVMe programming interface
+pub fn is_nvme(dev: &PciDevice) -> bool {
+    dev.class == 0x01 && dev.subclass == 0x08 && dev.prog_if == 0x02
+}
+
+/// A submission/completion queue pair in one frame each, polled rather than interrupt-driven
+struct QueuePair {
+    id: u16,
+    sq_phys: u64,
+    cq_phys: u64,
+    sq_tail: u16,
+    cq_head: u16,
+    phase: bool, // Phase tag that marks a fresh completion entry; flips on each wrap
+    size: u16,
+    next_cid: u16,
+}
+
+impl QueuePair {
+    fn new(id: u16, size: u16) -> Self {
+        let sq_phys = alloc_zeroed_frame();
+        let cq_phys = alloc_zeroed_frame();
+        QueuePair { id, sq_phys, cq_phys, sq_tail: 0, cq_head: 0, phase: true, size, next_cid: 0 }
+    }
+}
+
+fn alloc_zeroed_frame() -> u64 {
+    let phys = memory::alloc_frame().as_u64();
+    let hhdm = state::HHDM_OFFSET.load(Ordering::Relaxed);
+    unsafe { core::ptr::write_bytes((phys + hhdm) as *mut u8, 0, PAGE_SIZE); }
+    phys
+}
+
+pub struct NvmeController {
+    regs: u64,           // Virtual address of BAR0
+    doorbell_stride: usize,
+    admin: QueuePair,
+    io: QueuePair,
+    data_phys: [u64; 2], // Two (not necessarily adjacent) pages for PRP1/PRP2
+    pub model: String,
+    pub serial: String,
+    pub block_size: usize,
+    pub capacity: u64,   // In blocks
+}
+
+impl NvmeController {
+    /// Resets and enables the controller at `bar0_phys`, then identifies it and creates
+    /// I/O queue pair 1. None if it doesn't come ready or a setup command fails.
+    pub fn new(bar0_phys: u64) -> Option<Self> {
+        let regs = memory::map_mmio_range(bar0_phys, BAR0_MAP_SIZE);
+        let cap = unsafe { core::ptr::read_volatile((regs as usize + REG_CAP) as *const u64) };
+        let max_entries = (cap & 0xFFFF) as u16 + 1; // MQES is 0-based
+        let doorbell_stride = 4 << ((cap >> 32) & 0xF);
+        let mps_min = (cap >> 48) & 0xF;
+        if mps_min > 0 {
+            crate::klog_error!("[NVME] Controller needs pages of at least {} KiB.\n", 4 << mps_min);
+            return None;
+        }
+        let size = QUEUE_ENTRIES.min(max_entries);
+
+        let mut ctrl = NvmeController {
+            regs,
+            doorbell_stride,
+            admin: QueuePair::new(0, size),
+            io: QueuePair::new(1, size),
+            data_phys: [alloc_zeroed_frame(), alloc_zeroed_frame()],
+            model: String::new(),
+            serial: String::new(),
+            block_size: 512,
+            capacity: 0,
+        };
+
+        // 1. Disable, and wait for the controller to notice
+        ctrl.write32(REG_CC, ctrl.read32(REG_CC) & !CC_EN);
+        if !ctrl.wait_ready(false) { return None; }
+
+        // 2. Admin queues (sizes are 0-based)
+        let q = (size - 1) as u32;
+        ctrl.write32(REG_AQA, (q << 16) | q);
+        ctrl.write64(REG_ASQ, ctrl.admin.sq_phys);
+        ctrl.write64(REG_ACQ, ctrl.admin.cq_phys);
+
+        // 3. NVM command set, 4 KiB pages (MPS=0), standard entry sizes, enable
+        ctrl.write32(REG_CC, CC_IOSQES | CC_IOCQES | CC_EN);
+        if !ctrl.wait_ready(true) { return None; }
+
+        // 4. Identify Controller: serial at 4..24, model at 24..64
+        let id = ctrl.identify(CNS_CONTROLLER, 0)?;
+        ctrl.serial = ascii_field(&id[4..24]);
+        ctrl.model = ascii_field(&id[24..64]);
+
+        // 5. Identify Namespace 1: size (NSZE) and the in-use LBA format
+        let ns = ctrl.identify(CNS_NAMESPACE, NAMESPACE_ID)?;
+        ctrl.capacity = u64::from_le_bytes(ns[0..8].try_into().unwrap());
+        let format = (ns[26] & 0xF) as usize;
+        let lbaf = u32::from_le_bytes(ns[128 + format * 4..132 + format * 4].try_into().unwrap());
+        ctrl.block_size = 1 << ((lbaf >> 16) & 0xFF);
+
+        // 6. I/O queue pair 1: completion queue first, the submission queue points at it
+        let qsize = ((size - 1) as u32) << 16;
+        let cq = ctrl.io.cq_phys;
+        ctrl.admin_command(ADMIN_CREATE_IO_CQ, 0, cq, 0, [qsize | 1, 1, 0])?;  // Physically contiguous, no IRQ
+        let sq = ctrl.io.sq_phys;
+        ctrl.admin_command(ADMIN_CREATE_IO_SQ, 0, sq, 0, [qsize | 1, (1 << 16) | 1, 0])?; // CQ 1, contiguous
+
+        let vs = ctrl.read32(REG_VS);
+        crate::klog_info!("[NVME] {} (NVMe {}.{}), {} blocks of {} bytes.\n",
+            ctrl.model, vs >> 16, (vs >> 8) & 0xFF, ctrl.capacity, ctrl.block_size);
+        Some(ctrl)
+    }
+
+    fn read32(&self, reg: usize) -> u32 {
+        unsafe { core::ptr::read_volatile((self.regs as usize + reg) as *const u32) }
+    }
+
+    fn write32(&self, reg: usize, value: u32) {
+        unsafe { core::ptr::write_volatile((self.regs as usize + reg) as *mut u32, value) }
+    }
+
+    fn write64(&self, reg: usize, value: u64) {
+        unsafe { core::ptr::write_volatile((self.regs as usize + reg) as *mut u64, value) }
+    }
+
+    /// Polls CSTS.RDY until it matches `ready`. False on timeout or a fatal status.
+    fn wait_ready(&self, ready: bool) -> bool {
+        for _ in 0..POLL_SPINS {
+            let csts = self.read32(REG_CSTS);
+            if csts & CSTS_CFS != 0 { break; }
+            if (csts & CSTS_RDY != 0) == ready { return true; }
+            core::hint::spin_loop();
+        }
+        crate::klog_error!("[NVME] Controller did not become {}.\n", if ready { "ready" } else { "idle" });
+        false
+    }
+
+    /// Queues one command and polls for its completion. Returns the status field (0 = success),
+    /// or None on timeout.
+    fn submit(&mut self, io: bool, opcode: u8, nsid: u32, prp1: u64, prp2: u64, cdw: [u32; 6]) -> Option<u16> {
+        let hhdm = state::HHDM_OFFSET.load(Ordering::Relaxed);
+        let stride = self.doorbell_stride;
+        let regs = self.regs as usize;
+        let q = if io { &mut self.io } else { &mut self.admin };
+
+        // 1. Build the 64-byte entry in place
+        let cid = q.next_cid;
+        q.next_cid = q.next_cid.wrapping_add(1);
+        let mut entry = [0u32; 16];
+        entry[0] = opcode as u32 | ((cid as u32) << 16);
+        entry[1] = nsid;
+        entry[6] = prp1 as u32;
+        entry[7] = (prp1 >> 32) as u32;
+        entry[8] = prp2 as u32;
+        entry[9] = (prp2 >> 32) as u32;
+        entry[10..16].copy_from_slice(&cdw);
+        let slot = (q.sq_phys + hhdm) as usize + q.sq_tail as usize * SQ_ENTRY_SIZE;
+        unsafe { core::ptr::write_volatile(slot as *mut [u32; 16], entry); }
+
+        // 2. Ring the submission doorbell
+        q.sq_tail = (q.sq_tail + 1) % q.size;
+        fence(Ordering::SeqCst);
+        unsafe { core::ptr::write_volatile((regs + DOORBELL_BASE + 2 * q.id as usize * stride) as *mut u32, q.sq_tail as u32); }
+
+        // 3. Wait for a completion whose phase tag matches ours
+        let cqe = (q.cq_phys + hhdm) as usize + q.cq_head as usize * CQ_ENTRY_SIZE;
+        let mut status = None;
+        for _ in 0..POLL_SPINS {
+            let dw3 = unsafe { core::ptr::read_volatile((cqe + 12) as *const u32) };
+            if ((dw3 >> 16) & 1 != 0) == q.phase {
+                status = Some(((dw3 >> 17) & 0x7FFF) as u16);
+                break;
+            }
+            core::hint::spin_loop();
+        }
+        status?;
+
+        // 4. Consume it and tell the controller
+        q.cq_head += 1;
+        if q.cq_head == q.size {
+            q.cq_head = 0;
+            q.phase = !q.phase;
+        }
+        unsafe { core::ptr::write_volatile((regs + DOORBELL_BASE + (2 * q.id as usize + 1) * stride) as *mut u32, q.cq_head as u32); }
+        status
+    }
+
+    /// An admin command that must succeed. None (and a log line) otherwise.
+    fn admin_command(&mut self, opcode: u8, nsid: u32, prp1: u64, prp2: u64, cdw10_12: [u32; 3]) -> Option<()> {
+        let cdw = [cdw10_12[0], cdw10_12[1], cdw10_12[2], 0, 0, 0];
+        match self.submit(false, opcode, nsid, prp1, prp2, cdw) {
+            Some(0) => Some(()),
+            Some(status) => {
+                crate::klog_error!("[NVME] Admin opcode {:#x} failed, status {:#x}.\n", opcode, status);
+                None
+            }
+            None => {
+                crate::klog_error!("[NVME] Admin opcode {:#x} timed out.\n", opcode);
+                None
+            }
+        }
+    }
+
+    /// Identify data structure (4 KiB) for `cns`
+    fn identify(&mut self, cns: u32, nsid: u32) -> Option<Vec<u8>> {
+        let buf = self.data_phys[0];
+        self.admin_command(ADMIN_IDENTIFY, nsid, buf, 0, [cns, 0, 0])?;
+        let hhdm = state::HHDM_OFFSET.load(Ordering::Relaxed);
+        Some(unsafe { core::slice::from_raw_parts((buf + hhdm) as *const u8, PAGE_SIZE) }.to_vec())
+    }
+
+    /// Reads `count` blocks from namespace 1 starting at `lba`, 8 KiB per command.
+    /// Empty on error or if the range runs past the end of the namespace.
+    pub fn read_lba(&mut self, lba: u64, count: u32) -> Vec<u8> {
+        if count == 0 || lba + count as u64 > self.capacity { return Vec::new(); }
+        let hhdm = state::HHDM_OFFSET.load(Ordering::Relaxed);
+        let per_cmd = (MAX_TRANSFER / self.block_size).max(1) as u32;
+        let mut out = Vec::with_capacity(count as usize * self.block_size);
+
+        let mut done = 0;
+        while done < count {
+            let blocks = per_cmd.min(count - done);
+            let bytes = blocks as usize * self.block_size;
+            let start = lba + done as u64;
+            // PRP2 is only consulted when the transfer crosses into a second page
+            let prp2 = if bytes > PAGE_SIZE { self.data_phys[1] } else { 0 };
+            let cdw = [start as u32, (start >> 32) as u32, blocks - 1, 0, 0, 0]; // NLB is 0-based
+            match self.submit(true, NVM_READ, NAMESPACE_ID, self.data_phys[0], prp2, cdw) {
+                Some(0) => {}
+                other => {
+                    crate::klog_error!("[NVME] Read of LBA {} failed ({:?}).\n", start, other);
+                    return Vec::new();
+                }
+            }
+            for (i, &page) in self.data_phys.iter().enumerate() {
+                let chunk = bytes.saturating_sub(i * PAGE_SIZE).min(PAGE_SIZE);
+                if chunk == 0 { break; }
+                out.extend_from_slice(unsafe { core::slice::from_raw_parts((page + hhdm) as *const u8, chunk) });
+            }
+            done += blocks;
+        }
+        out
+    }
+
+    /// Writes `data` to namespace 1 from `lba`, 8 KiB per command; a partial last block
+    /// is zero-padded. False if the range is out of bounds or a command fails.
+    pub fn write_lba(&mut self, lba: u64, data: &[u8]) -> bool {
+        let blocks_total = data.len().div_ceil(self.block_size) as u64;
+        if blocks_total == 0 || lba + blocks_total > self.capacity { return false; }
+        let hhdm = state::HHDM_OFFSET.load(Ordering::Relaxed);
+        let per_cmd = (MAX_TRANSFER / self.block_size).max(1);
+
+        for (i, chunk) in data.chunks(per_cmd * self.block_size).enumerate() {
+            let blocks = chunk.len().div_ceil(self.block_size);
+            let bytes = blocks * self.block_size;
+            // 1. Stage the chunk in the data pages, zero-filling the tail
+            for (p, &page) in self.data_phys.iter().enumerate() {
+                let page_len = bytes.saturating_sub(p * PAGE_SIZE).min(PAGE_SIZE);
+                if page_len == 0 { break; }
+                let src = chunk.get(p * PAGE_SIZE..).unwrap_or(&[]);
+                let copy = src.len().min(page_len);
+                unsafe {
+                    let dst = (page + hhdm) as *mut u8;
+                    core::ptr::copy_nonoverlapping(src.as_ptr(), dst, copy);
+                    core::ptr::write_bytes(dst.add(copy), 0, page_len - copy);
+                }
+            }
+            // 2. NVM Write
+            let start = lba + (i * per_cmd) as u64;
+            let prp2 = if bytes > PAGE_SIZE { self.data_phys[1] } else { 0 };
+            let cdw = [start as u32, (start >> 32) as u32, blocks as u32 - 1, 0, 0, 0];
+            match self.submit(true, NVM_WRITE, NAMESPACE_ID, self.data_phys[0], prp2, cdw) {
+                Some(0) => {}
+                other => {
+                    crate::klog_error!("[NVME] Write of LBA {} failed ({:?}).\n", start, other);
+                    return false;
+                }
+            }
+        }
+        true
+    }
+}
+
+/// Identify strings are space-padded ASCII
+fn ascii_field(bytes: &[u8]) -> String {
+    String::from_utf8_lossy(bytes).trim_end_matches([' ', '\0']).into()
+}
+
+// --- GLOBAL INSTANCE ---
+lazy_static! {
+    // None until the first probe; Some(None) if there is no controller or it failed to come up
+    pub static ref NVME: Mutex<Option<Option<NvmeController>>> = Mutex::new(None);
+}
+
+/// Finds and brings up the first NVMe controller
+fn probe() -> Option<NvmeController> {
+    let dev = crate::pci::scan_bus().into_iter().find(is_nvme)?;
+    let bar0 = crate::pci::read_bars(&dev).into_iter().find_map(|(i, bar)| match (i, bar) {
+        (0, crate::pci::Bar::Mmio64(addr)) => Some(addr),
+        (0, crate::pci::Bar::Mmio32(addr)) => Some(addr as u64),
+        _ => None,
+    })?;
+    // Memory space (bit 1) and bus mastering (bit 2) for MMIO registers and DMA
+    unsafe {
+        let command = pci_read_u32(dev.bus, dev.device, dev.function, 0x04);
+        pci_write_u32(dev.bus, dev.device, dev.function, 0x04, command | (1 << 1) | (1 << 2));
+    }
+    NvmeController::new(bar0)
+}
+
+/// Runs `f` against the first NVMe controller, probing the PCI bus on first use. A failed
+/// probe is remembered: the controller isn't reset and re-initialised on every call.
+pub fn with_nvme<R>(f: impl FnOnce(&mut NvmeController) -> R) -> Option<R> {
+    let mut nvme = NVME.lock();
+    nvme.get_or_insert_with(probe).as_mut().map(f)
+}
@@ -14,6 +14,18 @@ pub struct PciDevice {
     pub function: u8,
     pub vendor_id: u16,
     pub device_id: u16,
+    pub class: u8,
+    pub subclass: u8,
+    pub prog_if: u8,
+    pub revision: u8,
+}
+
+/// A decoded Base Address Register
+#[derive(Debug, Clone, Copy)]
+pub enum Bar {
+    Io(u32),
+    Mmio32(u32),
+    Mmio64(u64),
 }
 
 // 1. READ CONFIGURATION WORD
@@ -56,6 +68,8 @@ pub fn scan_bus() -> Vec<PciDevice> {
                 if vendor_id != 0xFFFF {
                     // Register 2 contains Device ID
                     let device_id = pci_read_word(bus, slot, 0, 2);
+                    // Register 0x08: revision, prog IF, subclass, class (low to high)
+                    let class_reg = pci_read_u32(bus, slot, 0, 0x08);
                     
                     devices.push(PciDevice {
                         bus,
@@ -63,6 +77,10 @@ pub fn scan_bus() -> Vec<PciDevice> {
                         function: 0, // Assuming function 0 for simplicity
                         vendor_id,
                         device_id,
+                        class: (class_reg >> 24) as u8,
+                        subclass: (class_reg >> 16) as u8,
+                        prog_if: (class_reg >> 8) as u8,
+                        revision: class_reg as u8,
                     });
                 }
             }
@@ -83,7 +101,62 @@ pub fn lookup_vendor(id: u16) -> &'static str {
     }
 }
 
-// ... existing code ...
+// Class/subclass to a "Class / Subclass" name
+pub fn lookup_class(class: u8, subclass: u8) -> &'static str {
+    match (class, subclass) {
+        (0x01, 0x01) => "Mass Storage / IDE",
+        (0x01, 0x05) => "Mass Storage / ATA",
+        (0x01, 0x06) => "Mass Storage / SATA",
+        (0x01, 0x08) => "Mass Storage / NVMe",
+        (0x01, 0x00) => "Mass Storage / SCSI",
+        (0x01, _) => "Mass Storage",
+        (0x02, 0x00) => "Network / Ethernet",
+        (0x02, _) => "Network",
+        (0x03, 0x00) => "Display / VGA",
+        (0x03, _) => "Display",
+        (0x04, 0x01) => "Multimedia / Audio",
+        (0x04, 0x03) => "Multimedia / HD Audio",
+        (0x04, _) => "Multimedia",
+        (0x05, _) => "Memory Controller",
+        (0x06, 0x00) => "Bridge / Host",
+        (0x06, 0x01) => "Bridge / ISA",
+        (0x06, 0x04) => "Bridge / PCI-to-PCI",
+        (0x06, 0x80) => "Bridge / Other",
+        (0x06, _) => "Bridge",
+        (0x07, _) => "Communication",
+        (0x08, _) => "System Peripheral",
+        (0x0C, 0x03) => "Serial Bus / USB",
+        (0x0C, 0x05) => "Serial Bus / SMBus",
+        (0x0C, _) => "Serial Bus",
+        (0x00, _) => "Unclassified",
+        _ => "Unknown",
+    }
+}
+
+/// The six BARs of a type-0 header (0x10..0x24). Empty BARs are skipped; a 64-bit
+/// BAR takes its upper half from the next register, which is then skipped too.
+pub fn read_bars(device: &PciDevice) -> Vec<(u8, Bar)> {
+    let mut bars = Vec::new();
+    let mut i = 0;
+    while i < 6 {
+        let offset = 0x10 + i * 4;
+        let low = unsafe { pci_read_u32(device.bus, device.device, device.function, offset) };
+        if low & 1 != 0 {
+            // I/O space: bits 1:0 are flags
+            if low & !0x3 != 0 { bars.push((i, Bar::Io(low & !0x3))); }
+        } else if (low >> 1) & 0x3 == 0x2 && i < 5 {
+            // 64-bit memory: type field (bits 2:1) = 0b10
+            let high = unsafe { pci_read_u32(device.bus, device.device, device.function, offset + 4) };
+            let addr = ((high as u64) << 32) | (low & !0xF) as u64;
+            if addr != 0 { bars.push((i, Bar::Mmio64(addr))); }
+            i += 1;
+        } else if low & !0xF != 0 {
+            bars.push((i, Bar::Mmio32(low & !0xF)));
+        }
+        i += 1;
+    }
+    bars
+}
 
 // NEW: Read a 32-bit double word (needed for BARs)
 pub unsafe fn pci_read_u32(bus: u8, slot: u8, func: u8, offset: u8) -> u32 {
@@ -0,0 +1,94 @@
+use alloc::collections::VecDeque;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicBool, Ordering};
+use spin::Mutex;
+use lazy_static::lazy_static;
+
+// --- PIPES ---
+// A bounded byte FIFO between a writer and a reader. Tasks reach them by ID through
+// syscalls 7-9 and close their ends with syscall 12; a pipe goes once both ends are closed.
+
+/// Bytes a pipe holds before `write` stops accepting more
+pub const PIPE_CAPACITY: usize = 64 * 1024;
+
+pub struct Pipe {
+    buf: Mutex<VecDeque<u8>>,
+    read_closed: AtomicBool,
+    write_closed: AtomicBool,
+}
+
+impl Pipe {
+    pub fn new() -> Self {
+        Pipe {
+            buf: Mutex::new(VecDeque::new()),
+            read_closed: AtomicBool::new(false),
+            write_closed: AtomicBool::new(false),
+        }
+    }
+
+    /// Appends as much of `data` as fits and returns how many bytes that was.
+    /// Nothing is taken once either end is closed.
+    pub fn write(&self, data: &[u8]) -> usize {
+        if self.read_closed.load(Ordering::Acquire) || self.write_closed.load(Ordering::Acquire) {
+            return 0;
+        }
+        let mut buf = self.buf.lock();
+        let n = data.len().min(PIPE_CAPACITY - buf.len());
+        buf.extend(&data[..n]);
+        n
+    }
+
+    /// Takes up to `max` bytes, oldest first. Empty if nothing is waiting.
+    pub fn read(&self, max: usize) -> Vec<u8> {
+        let mut buf = self.buf.lock();
+        let n = max.min(buf.len());
+        buf.drain(..n).collect()
+    }
+
+    pub fn close_read(&self) {
+        self.read_closed.store(true, Ordering::Release);
+    }
+
+    pub fn close_write(&self) {
+        self.write_closed.store(true, Ordering::Release);
+    }
+
+    fn fully_closed(&self) -> bool {
+        self.read_closed.load(Ordering::Acquire) && self.write_closed.load(Ordering::Acquire)
+    }
+}
+
+lazy_static! {
+    // Pipes created through syscall 7; a pipe's ID is its slot. Removed pipes leave an
+    // empty slot for the next `create`.
+    pub static ref PIPE_TABLE: Mutex<Vec<Option<Arc<Pipe>>>> = Mutex::new(Vec::new());
+}
+
+/// Creates a pipe in the table and returns its ID
+pub fn create() -> usize {
+    let mut table = PIPE_TABLE.lock();
+    let pipe = Some(Arc::new(Pipe::new()));
+    match table.iter().position(|slot| slot.is_none()) {
+        Some(id) => { table[id] = pipe; id }
+        None => { table.push(pipe); table.len() - 1 }
+    }
+}
+
+/// The pipe with ID `id`
+pub fn get(id: usize) -> Option<Arc<Pipe>> {
+    PIPE_TABLE.lock().get(id).cloned().flatten()
+}
+
+/// Closes the read (`write_end` false) or write end of pipe `id`, and drops the pipe from
+/// the table once both are closed. False if there is no such pipe.
+pub fn close(id: usize, write_end: bool) -> bool {
+    let mut table = PIPE_TABLE.lock();
+    let pipe = match table.get(id).and_then(|slot| slot.as_ref()) {
+        Some(p) => p,
+        None => return false,
+    };
+    if write_end { pipe.close_write(); } else { pipe.close_read(); }
+    if pipe.fully_closed() { table[id] = None; }
+    true
+}
@@ -1,8 +1,10 @@
 use crate::pci::{PciDevice, pci_read_u32};
-use crate::{writer, state, net};
+use crate::{state, net};
 use x86_64::instructions::port::Port;
-use alloc::format;
+use alloc::vec::Vec;
 use core::sync::atomic::Ordering;
+use spin::Mutex;
+use lazy_static::lazy_static;
 
 // --- REGISTERS ---
 const REG_MAC: u16 = 0x00;      // MAC Address
@@ -15,6 +17,7 @@ const REG_IMR: u16 = 0x3C;      // Interrupt Mask Register
 const REG_ISR: u16 = 0x3E;      // Interrupt Status Register
 const REG_TCR: u16 = 0x40;      // Transmit Configuration Register
 const REG_RCR: u16 = 0x44;      // Receive Configuration Register
+const REG_MPC: u16 = 0x4C;      // Missed Packet Counter (24 bits, cleared by any write)
 
 // --- MEMORY MAP ---
 // We use fixed Physical Addresses in the 32MB range to avoid Kernel/Heap collisions.
@@ -22,13 +25,38 @@ const RX_BUFFER_PHYS: u32 = 0x0200_0000;
 const TX_BUFFER_PHYS: u32 = 0x0201_0000; 
 const RX_BUF_SIZE: usize = 8192;
 
+// TSD bit 13: set by the card once it has DMA'd the frame out of our buffer
+const TSD_OWN: u32 = 1 << 13;
+// TSD error bits: FIFO underrun, aborted (excess collisions), out-of-window collision
+const TSD_TUN: u32 = 1 << 14;
+const TSD_TABT: u32 = 1 << 30;
+const TSD_OWC: u32 = 1 << 29;
+// CMD bit 0: the RX ring holds no unread packets
+const CMD_BUFE: u8 = 0x01;
+// RX header status bit 0: packet received OK
+const RX_ROK: u32 = 0x01;
+const RX_MAX_FRAME: usize = 1792; // Including the 4-byte CRC
+
+/// Interface counters: frames and bytes we handed to / took from the card, plus errors
+/// (failed transmits, corrupt RX headers and frames the card dropped for lack of ring space)
+#[derive(Clone, Copy, Default)]
+pub struct NetStats {
+    pub tx_packets: u32,
+    pub rx_packets: u32,
+    pub tx_bytes: u64,
+    pub rx_bytes: u64,
+    pub tx_errors: u32,
+    pub rx_errors: u32,
+}
+
 pub struct Rtl8139 {
     io_base: u16,
     pub mac_addr: [u8; 6],
     rx_buffer_ptr: *mut u8,
     tx_buffer_ptr: *mut u8,
     tx_cur: u8,
-    rx_offset: usize,
+    rx_offset: u16, // Where the next packet header starts in the RX ring
+    tx_pending: [usize; 4], // Bytes still owned by the card, per descriptor
 }
 
 impl Rtl8139 {
@@ -60,6 +88,7 @@ impl Rtl8139 {
                 tx_buffer_ptr: tx_ptr,
                 tx_cur: 0,
                 rx_offset: 0,
+                tx_pending: [0; 4],
             };
 
             driver.init();
@@ -89,139 +118,97 @@ impl Rtl8139 {
 
         // Enable Receiver (RE) and Transmitter (TE)
         cmd_port.write(0x0C); 
-        
-        writer::print("[NET] RTL8139 Driver Initialized (Ring Buffer Active).\n");
-    }
 
-    pub fn log_mac(&self) {
-        let m = self.mac_addr;
-        writer::print(&format!("[NET] Hardware MAC: {:02x}:{:02x}:{:02x}:{:02x}:{:02x}:{:02x}\n",
-            m[0], m[1], m[2], m[3], m[4], m[5]));
+        // Fresh counters for the fresh card
+        Port::<u32>::new(self.io_base + REG_MPC).write(0);
+        *RTL8139_STATS.lock() = NetStats::default();
+        
+        crate::klog_info!("[NET] RTL8139 Driver Initialized (Ring Buffer Active).\n");
     }
 
     pub fn get_hardware_status(&self) -> u16 {
         unsafe { Port::<u16>::new(self.io_base + REG_ISR).read() }
     }
 
-    // --- DHCP PROTOCOL ---
-    pub fn send_dhcp_discover(&mut self) {
-        let mut pkt = [0u8; 300];
-        let mut i = 0;
-
-        // Ethernet Header
-        for _ in 0..6 { pkt[i] = 0xFF; i += 1; }
-        for j in 0..6 { pkt[i] = self.mac_addr[j]; i += 1; }
-        pkt[i] = 0x08; pkt[i+1] = 0x00; i += 2; // Type IPv4
-
-        // IP Header
-        let ip_start = i;
-        pkt[i] = 0x45; pkt[i+2] = 0x01; pkt[i+3] = 0x10; // Len 272
-        pkt[i+8] = 0x40; pkt[i+9] = 17; // Protocol UDP
-        for j in 0..4 { pkt[i+16+j] = 0xFF; } // Dest 255.255.255.255
-        let csum = self.calc_ip_checksum(&pkt[ip_start..ip_start+20]);
-        pkt[ip_start+10] = (csum >> 8) as u8; pkt[ip_start+11] = (csum & 0xFF) as u8;
-        i += 20;
-
-        // UDP Header
-        pkt[i+1] = 68; pkt[i+3] = 67; // Ports 68 -> 67
-        pkt[i+5] = 0xFC; // Len 252
-        i += 8;
-
-        // DHCP Data
-        let dhcp_start = i;
-        pkt[i] = 0x01; pkt[i+1] = 0x01; pkt[i+2] = 0x06; i += 4;
-        pkt[i] = 0x39; pkt[i+1] = 0x03; pkt[i+2] = 0xF3; pkt[i+3] = 0x26; i += 4; // XID
-        i = dhcp_start + 28;
-        for j in 0..6 { pkt[i+j] = self.mac_addr[j]; i += 6; } // CHADDR
-        i = dhcp_start + 236;
-        pkt[i] = 0x63; pkt[i+1] = 0x82; pkt[i+2] = 0x53; pkt[i+3] = 0x63; i += 4; // Cookie
-        pkt[i] = 53; pkt[i+1] = 1; pkt[i+2] = 1; i += 3; // Option 53: Discover
-        pkt[i] = 255; // Option: End
-
-        self.transmit(&pkt);
-        writer::print("[NET] DHCP DISCOVER sent.\n");
+    /// Current counters. The card's missed-packet register is folded into `rx_errors`
+    /// and cleared, so it can't overflow between calls.
+    pub fn read_stats(&self) -> NetStats {
+        let missed = unsafe {
+            let mut mpc = Port::<u32>::new(self.io_base + REG_MPC);
+            let missed = mpc.read() & 0xFF_FFFF;
+            mpc.write(0);
+            missed
+        };
+        let mut stats = RTL8139_STATS.lock();
+        stats.rx_errors = stats.rx_errors.wrapping_add(missed);
+        *stats
     }
 
-    // --- ICMP PING ---
-    pub fn send_ping(&mut self, seq: u16) {
-        let mut pkt = [0u8; 74];
-        let mut i = 0;
-        let dest_mac = [0x52, 0x54, 0x00, 0x12, 0x34, 0x56]; // Standard QEMU Gateway MAC
-        for j in 0..6 { pkt[i] = dest_mac[j]; i += 1; }
-        for j in 0..6 { pkt[i] = self.mac_addr[j]; i += 1; }
-        pkt[i] = 0x08; pkt[i+1] = 0x00; i += 2;
-
-        let ip_start = i;
-        pkt[i] = 0x45; pkt[i+3] = 60; pkt[i+8] = 0x80; pkt[i+9] = 1; // ICMP
-        let my_ip = state::get_my_ip();
-        let src = if my_ip == [0,0,0,0] { [10,0,2,15] } else { my_ip };
-        for j in 0..4 { pkt[i+12+j] = src[j]; pkt[i+16+j] = [10, 0, 2, 2][j]; }
-        let csum = self.calc_ip_checksum(&pkt[ip_start..ip_start+20]);
-        pkt[ip_start+10] = (csum >> 8) as u8; pkt[ip_start+11] = (csum & 0xFF) as u8;
-        i += 20;
-
-        let icmp_start = i;
-        pkt[i] = 8; // Type 8: Echo Request
-        pkt[i+4] = 0x12; pkt[i+5] = 0x34; // ID
-        pkt[i+6] = (seq >> 8) as u8; pkt[i+7] = (seq & 0xFF) as u8;
-        let ic_csum = self.calc_ip_checksum(&pkt[icmp_start..icmp_start+40]);
-        pkt[icmp_start+2] = (ic_csum >> 8) as u8; pkt[icmp_start+3] = (ic_csum & 0xFF) as u8;
-        
-        self.transmit(&pkt);
-        writer::print(&format!("[NET] ICMP Echo (Seq {}) sent.\n", seq));
-    }
+    // --- RECEIVE ENGINE ---
+    /// Takes the next packet out of the RX ring. Each one is laid out as
+    /// [status: u16][length: u16 incl. CRC][frame...], starting on a 4-byte boundary.
+    pub fn receive(&mut self) -> Option<Vec<u8>> {
+        // 1. The card tells us when we've caught up with it
+        if unsafe { Port::<u8>::new(self.io_base + REG_CMD).read() } & CMD_BUFE != 0 { return None; }
+
+        let offset = self.rx_offset as usize;
+        let header = unsafe { core::ptr::read_volatile(self.rx_buffer_ptr.add(offset) as *const u32) };
+        let len = (header >> 16) as usize;
+        if header & RX_ROK == 0 || len <= 4 || len > RX_MAX_FRAME {
+            crate::klog_warn!("[NET] Bad RX header {:#010x}, resetting receiver.\n", header);
+            RTL8139_STATS.lock().rx_errors += 1;
+            unsafe { self.reset_rx(); }
+            return None;
+        }
 
-    // --- ARP REPLY ---
-    pub fn send_arp_reply(&mut self, t_mac: [u8; 6], t_ip: [u8; 4]) {
-        let mut pkt = [0u8; 60];
-        // Eth
-        for i in 0..6 { pkt[i] = t_mac[i]; pkt[i+6] = self.mac_addr[i]; }
-        pkt[12] = 0x08; pkt[13] = 0x06;
-        // ARP
-        pkt[14] = 0; pkt[15] = 1; pkt[16] = 8; pkt[17] = 0; pkt[18] = 6; pkt[19] = 4; pkt[21] = 2; // Reply
-        for i in 0..6 { pkt[22+i] = self.mac_addr[i]; pkt[32+i] = t_mac[i]; }
-        let my_ip = state::get_my_ip();
-        let src = if my_ip == [0,0,0,0] { [10,0,2,15] } else { my_ip };
-        for i in 0..4 { pkt[28+i] = src[i]; pkt[38+i] = t_ip[i]; }
-        
-        self.transmit(&pkt);
-        writer::print("[NET] ARP Reply sent to Gateway.\n");
+        // 2. Copy the frame out (minus the CRC), wrapping at the end of the ring
+        let mut packet = Vec::with_capacity(len - 4);
+        for i in 0..len - 4 {
+            let pos = (offset + 4 + i) % RX_BUF_SIZE;
+            packet.push(unsafe { core::ptr::read_volatile(self.rx_buffer_ptr.add(pos)) });
+        }
+
+        // 3. Hand the space back: CAPR trails the read pointer by 16 bytes
+        self.rx_offset = (((offset + len + 4 + 3) & !3) % RX_BUF_SIZE) as u16;
+        unsafe { Port::<u16>::new(self.io_base + REG_CAPR).write(self.rx_offset.wrapping_sub(16)); }
+
+        net::record_rx(packet.len());
+        let mut stats = RTL8139_STATS.lock();
+        stats.rx_packets = stats.rx_packets.wrapping_add(1);
+        stats.rx_bytes += packet.len() as u64;
+        Some(packet)
     }
 
-    // --- RECEIVE ENGINE ---
-    pub fn sniff_packet(&mut self) {
-        unsafe {
-            // Check the current offset for a valid header
-            let header_addr = self.rx_buffer_ptr.add(self.rx_offset);
-            let header = core::ptr::read_volatile(header_addr as *const u32);
-
-            // Bit 0 = ROK (Receive OK). 0xFFFFFFFF = Hardware not ready/Reset.
-            if (header & 0x01) != 0 && header != 0xFFFFFFFF {
-                let len = (header >> 16) as usize;
-                
-                if len > 4 && len < 2000 {
-                    // Create a slice skipping the 4-byte RTL header
-                    let data = core::slice::from_raw_parts(header_addr.add(4), len - 4);
-                    
-                    // Send to Network Stack for parsing. 
-                    // If it returns Some, it means it's an ARP request that needs a reply.
-                    if let Some((m, i)) = net::handle_packet(data) { 
-                        self.send_arp_reply(m, i); 
-                    }
-                }
+    /// Restarts reception from the top of the ring after a corrupt header
+    unsafe fn reset_rx(&mut self) {
+        let mut cmd_port = Port::<u8>::new(self.io_base + REG_CMD);
+        cmd_port.write(0x04); // TE only: disabling RE resets the card's ring pointer
+        Port::<u32>::new(self.io_base + REG_RBSTART).write(RX_BUFFER_PHYS);
+        Port::<u32>::new(self.io_base + REG_RCR).write(0xCF);
+        cmd_port.write(0x0C);
+        self.rx_offset = 0;
+        Port::<u16>::new(self.io_base + REG_CAPR).write(0u16.wrapping_sub(16));
+    }
 
-                // Advance Ring Pointer (Aligned to 4 bytes)
-                let step = (len + 4 + 3) & !3;
-                self.rx_offset = (self.rx_offset + step) % RX_BUF_SIZE;
+    // --- LOW LEVEL HELPERS ---
 
-                // Update CAPR register to let hardware know we've read the data
-                Port::<u16>::new(self.io_base + REG_CAPR).write((self.rx_offset as u16).wrapping_sub(0x10));
+    /// Checks every TX descriptor and releases the in-flight bytes of frames the card is done with
+    pub fn reap_tx(&mut self) {
+        for desc in 0..4 {
+            if self.tx_pending[desc] == 0 { continue; }
+            let status = unsafe { Port::<u32>::new(self.io_base + REG_TSD0 + (desc as u16 * 4)).read() };
+            if (status & TSD_OWN) != 0 {
+                net::record_tx_done(self.tx_pending[desc]);
+                self.tx_pending[desc] = 0;
+                if status & (TSD_TUN | TSD_TABT | TSD_OWC) != 0 {
+                    RTL8139_STATS.lock().tx_errors += 1;
+                }
             }
         }
     }
 
-    // --- LOW LEVEL HELPERS ---
-    fn transmit(&mut self, data: &[u8]) {
+    pub fn transmit(&mut self, data: &[u8]) {
+        self.reap_tx();
         unsafe {
             // 1. Copy data to the TX Buffer
             for (i, &b) in data.iter().enumerate() {
@@ -247,6 +234,20 @@ impl Rtl8139 {
             let tsd_port = self.io_base + REG_TSD0 + (self.tx_cur as u16 * 4);
             Port::<u32>::new(tsd_port).write(send_len as u32);
 
+            // Accounting: the card now owns these bytes until TSD_OWN comes back
+            let desc = self.tx_cur as usize;
+            if self.tx_pending[desc] != 0 {
+                // Descriptor reused before the card released it; don't double count
+                net::record_tx_done(self.tx_pending[desc]);
+            }
+            self.tx_pending[desc] = send_len;
+            net::record_tx(send_len);
+            {
+                let mut stats = RTL8139_STATS.lock();
+                stats.tx_packets = stats.tx_packets.wrapping_add(1);
+                stats.tx_bytes += send_len as u64;
+            }
+
             // 5. Rotate descriptor
             self.tx_cur = (self.tx_cur + 1) % 4;
             
@@ -254,17 +255,12 @@ impl Rtl8139 {
             for _ in 0..1000 { core::hint::spin_loop(); }
         }
     }
-    fn calc_ip_checksum(&self, data: &[u8]) -> u16 {
-        let mut sum: u32 = 0;
-        for i in (0..data.len()).step_by(2) {
-            let word = if i + 1 < data.len() {
-                ((data[i] as u32) << 8) | (data[i+1] as u32)
-            } else {
-                (data[i] as u32) << 8
-            };
-            sum = sum.wrapping_add(word);
-        }
-        while (sum >> 16) != 0 { sum = (sum & 0xFFFF) + (sum >> 16); }
-        !sum as u16
-    }
-}
\ No newline at end of file
+}
+
+// --- GLOBAL INSTANCE ---
+// The raw DMA pointers make the driver !Send; it is only ever reached through `nic::NIC`'s lock.
+unsafe impl Send for Rtl8139 {}
+
+lazy_static! {
+    pub static ref RTL8139_STATS: Mutex<NetStats> = Mutex::new(NetStats::default());
+}
@@ -1,13 +1,16 @@
+use alloc::boxed::Box;
 use alloc::string::String;
 use alloc::vec::Vec;
+use alloc::collections::vec_deque::VecDeque;
 use alloc::format;
 use core::arch::x86_64::_rdtsc;
 use spin::Mutex;
 use lazy_static::lazy_static;
+use crate::smp::MAX_CPUS;
 
 pub type Job = extern "C" fn(u64);
 
-fn task_exit() {
+pub fn task_exit() {
     unsafe {
         core::arch::asm!(
             "int 0x80",
@@ -17,12 +20,20 @@ fn task_exit() {
     loop { core::hint::spin_loop(); }
 }
 
-pub static mut SCHEDULER_CONTEXT: TaskContext = TaskContext {
+const EMPTY_CONTEXT: TaskContext = TaskContext {
     r15: 0, r14: 0, r13: 0, r12: 0, r11: 0, r10: 0, r9: 0, r8: 0,
     rbp: 0, rdi: 0, rsi: 0, rdx: 0, rcx: 0, rbx: 0, rax: 0,
     rip: 0, cs: 0, rflags: 0, rsp: 0, ss: 0,
 };
 
+// Where each CPU's `step` is parked while one of its tasks runs
+static mut SCHEDULER_CONTEXTS: [TaskContext; MAX_CPUS] = [EMPTY_CONTEXT; MAX_CPUS];
+
+/// The scheduler context of the CPU this runs on
+pub fn scheduler_context() -> *mut TaskContext {
+    unsafe { core::ptr::addr_of_mut!(SCHEDULER_CONTEXTS[crate::smp::cpu_id()]) }
+}
+
 #[repr(C, packed)]
 #[derive(Debug, Clone, Copy, Default)]
 pub struct TaskContext {
@@ -50,16 +61,156 @@ pub struct TaskContext {
     pub ss: u64,
 }
 
+/// FXSAVE/FXRSTOR image: x87, MMX and SSE registers plus MXCSR
+#[repr(C, align(16))]
+pub struct FpuState(pub [u8; 512]);
+
+impl FpuState {
+    /// The state FNINIT leaves behind: all exceptions masked, round-to-nearest
+    pub fn new() -> Self {
+        let mut area = [0u8; 512];
+        area[0..2].copy_from_slice(&0x037Fu16.to_le_bytes());  // FCW
+        area[24..28].copy_from_slice(&0x1F80u32.to_le_bytes()); // MXCSR
+        area[28..32].copy_from_slice(&0xFFFFu32.to_le_bytes()); // MXCSR_MASK
+        FpuState(area)
+    }
+}
+
+/// A task's FXSAVE area, taken from `slab::TASK_SLAB` (or the heap once that's full).
+/// Never moves, so the pointer handed to `context_switch` survives `tasks` reallocating.
+pub struct FpuArea(*mut FpuState);
+
+// Owned exclusively by its Task
+unsafe impl Send for FpuArea {}
+
+impl FpuArea {
+    pub fn new() -> Self {
+        let ptr = crate::slab::TASK_SLAB.lock().alloc() as *mut FpuState;
+        if ptr.is_null() {
+            return FpuArea(Box::into_raw(Box::new(FpuState::new())));
+        }
+        unsafe { ptr.write(FpuState::new()); }
+        FpuArea(ptr)
+    }
+}
+
+impl core::ops::Deref for FpuArea {
+    type Target = FpuState;
+    fn deref(&self) -> &FpuState {
+        unsafe { &*self.0 }
+    }
+}
+
+impl core::ops::DerefMut for FpuArea {
+    fn deref_mut(&mut self) -> &mut FpuState {
+        unsafe { &mut *self.0 }
+    }
+}
+
+impl Drop for FpuArea {
+    fn drop(&mut self) {
+        let mut slab = crate::slab::TASK_SLAB.lock();
+        if slab.owns(self.0 as *mut u8) {
+            slab.free(self.0 as *mut u8);
+        } else {
+            drop(unsafe { Box::from_raw(self.0) });
+        }
+    }
+}
+
+// --- LAZY FPU SWITCHING ---
+// FPU registers are only swapped when a task actually uses them. `step` sets CR0.TS before
+// running a task that doesn't own this CPU's FPU; its first x87/SSE instruction then raises
+// #NM, and `interrupts::nm_handler` saves the owner's registers and loads the task's. The
+// kernel is soft-float, so the registers stay untouched between bursts.
+pub const NO_FPU_OWNER: usize = usize::MAX;
+// Index of the task whose registers are loaded in each CPU's FPU
+pub static LAST_FPU_OWNER: [core::sync::atomic::AtomicUsize; MAX_CPUS] =
+    [const { core::sync::atomic::AtomicUsize::new(NO_FPU_OWNER) }; MAX_CPUS];
+
+/// Lets tasks use SSE: FXSAVE/FXRSTOR enabled, SSE exceptions reported, no x87 emulation
+pub fn init_fpu() {
+    use x86_64::registers::control::{Cr0, Cr0Flags, Cr4, Cr4Flags};
+    unsafe {
+        Cr0::update(|f| {
+            f.remove(Cr0Flags::EMULATE_COPROCESSOR);
+            f.insert(Cr0Flags::MONITOR_COPROCESSOR);
+        });
+        Cr4::update(|f| f.insert(Cr4Flags::OSFXSR | Cr4Flags::OSXMMEXCPT_ENABLE));
+        core::arch::asm!("fninit");
+    }
+}
+
+// Task ids are never reused, unlike indices into `tasks`, which shift on every removal
+static NEXT_TASK_ID: core::sync::atomic::AtomicU64 = core::sync::atomic::AtomicU64::new(1);
+
+fn next_task_id() -> u64 {
+    NEXT_TASK_ID.fetch_add(1, core::sync::atomic::Ordering::Relaxed)
+}
+
 pub struct Task {
+    pub id: u64,
     pub name: String,
     pub budget: u64,
     pub job: Job,
     pub last_cost: u64,
+    pub total_cycles: u64, // Sum of every burst's cost
+    pub run_count: u64,
     pub status: TaskStatus,
     pub violation_count: u32,
     pub penalty_cooldown: u32,
     pub context: TaskContext,
     pub stack: Vec<u8>,
+    pub input_buf: VecDeque<char>, // Keystrokes routed here while the task is in the foreground
+    pub guard_page: Option<u64>,   // Lowest stack page, left not-present to catch overflows
+    pub fpu_state: FpuArea,        // Out of line so the FXSAVE target survives `tasks` reallocating
+    pub fpu_used: bool,            // Has taken an #NM; until then `fpu_state` is still the FNINIT image
+    pub page_table_phys: u64,      // PML4 the task runs under
+    pub is_process: bool,          // Ring 3: owns a user stack and demand regions in its own PML4
+    pub image_frames: Vec<u64>,    // Process only: frames of its loaded segments and environment page
+    pub priority: u8,              // 0 (lowest) ..= MAX_PRIORITY
+    pub weight_counter: u8,        // Consecutive bursts left before `step` moves on
+}
+
+impl Task {
+    /// Stack top as handed to the task (where `task_exit` was pushed)
+    pub fn stack_top(&self) -> u64 {
+        self.stack.as_ptr() as u64 + self.stack.len() as u64 - 8
+    }
+}
+
+impl Drop for Task {
+    fn drop(&mut self) {
+        // The allocator writes into freed blocks, so the guard must be lifted first
+        if let Some(page) = self.guard_page {
+            unsafe { crate::memory::unmap_guard_page(page); }
+        }
+        // Everything a process owns goes back to the frame allocator with it: user stack,
+        // demand-paged memory, mmap regions, its image and finally its page tables
+        if self.is_process {
+            let pml4 = x86_64::PhysAddr::new(self.page_table_phys);
+            // The exit syscall drops the task while its tables are still loaded
+            if x86_64::registers::control::Cr3::read().0.start_address() == pml4 {
+                unsafe { crate::memory::switch_page_table(crate::memory::kernel_pml4()); }
+            }
+            crate::memory::release_demand_regions(pml4);
+            crate::shmem::release_mappings(pml4);
+            for i in 0..USER_STACK_PAGES {
+                unsafe {
+                    if let Some(frame) = crate::memory::unmap_user_page_in(pml4, USER_STACK_REGION + i * 4096) {
+                        crate::memory::free_frame(frame);
+                    }
+                }
+            }
+            unsafe {
+                // Still mapped, but the whole address space goes next
+                for &frame in &self.image_frames {
+                    crate::memory::free_frame(x86_64::PhysAddr::new(frame));
+                }
+                crate::memory::free_user_page_table(pml4);
+            }
+        }
+    }
 }
 
 #[derive(PartialEq, Clone, Copy)]
@@ -68,22 +219,61 @@ pub enum TaskStatus {
     Success,
     Failure,
     Penalty,
+    Sleeping { wake_tick: u64 }, // Skipped by `step` until TICK_COUNT reaches wake_tick
+    Blocked,                     // Waiting on a sync::Semaphore; skipped until signalled
+}
+
+impl TaskStatus {
+    pub fn label(&self) -> &'static str {
+        match self {
+            TaskStatus::Waiting => "WAIT",
+            TaskStatus::Success => "OK",
+            TaskStatus::Failure => "FAIL",
+            TaskStatus::Penalty => "PENT",
+            TaskStatus::Sleeping { .. } => "SLEEP",
+            TaskStatus::Blocked => "BLOCK",
+        }
+    }
 }
 
 pub struct Scheduler {
     pub tasks: Vec<Task>,
-    pub current_task_idx: Option<usize>,
+    pub current_task_idx: [Option<usize>; MAX_CPUS], // Task each CPU is running, by CPU index
 }
 
 impl Scheduler {
     pub fn new() -> Self {
         Scheduler {
             tasks: Vec::new(),
-            current_task_idx: None,
+            current_task_idx: [None; MAX_CPUS],
+        }
+    }
+
+    /// The task running on this CPU
+    pub fn current(&self) -> Option<usize> {
+        self.current_task_idx[crate::smp::cpu_id()]
+    }
+
+    /// Whether any CPU is running task `idx`
+    pub fn is_running(&self, idx: usize) -> bool {
+        self.current_task_idx.contains(&Some(idx))
+    }
+
+    /// Removes the task running on this CPU (it exited), shifting the other CPUs'
+    /// running indices to match
+    pub fn remove_current(&mut self) -> Option<Task> {
+        let idx = self.current_task_idx[crate::smp::cpu_id()].take()?;
+        let task = self.tasks.remove(idx);
+        for cur in self.current_task_idx.iter_mut().flatten() {
+            if *cur > idx { *cur -= 1; }
         }
+        shift_fpu_owners(idx);
+        Some(task)
     }
 
-    pub fn add_task(&mut self, name: &str, budget: u64, job: Job, arg: u64) {
+    /// Adds a task and returns its index in `tasks`
+    pub fn add_task(&mut self, name: &str, budget: u64, job: Job, arg: u64, priority: u8) -> usize {
+        let priority = priority.min(MAX_PRIORITY);
         let mut stack = alloc::vec![0u8; 65536];
         let stack_ptr = stack.as_ptr() as u64 + 65536;
         
@@ -93,6 +283,11 @@ impl Scheduler {
             *stack_top = task_exit as *const () as u64;
         }
 
+        // Guard the first whole page of the stack so an overflow faults instead of
+        // silently running into the neighbouring heap block
+        let guard = (stack.as_ptr() as u64 + 4095) & !4095;
+        let guard_page = if unsafe { crate::memory::map_guard_page(guard) } { Some(guard) } else { None };
+
         let mut context = TaskContext::default();
         context.rip = job as u64;
         context.rdi = arg; // Pass argument in RDI (System V ABI)
@@ -102,16 +297,128 @@ impl Scheduler {
         context.rflags = 0x202; // Interrupts enabled
 
         self.tasks.push(Task {
+            id: next_task_id(),
             name: String::from(name),
             budget,
             job,
             last_cost: 0,
+            total_cycles: 0,
+            run_count: 0,
             status: TaskStatus::Waiting,
             violation_count: 0,
             penalty_cooldown: 0,
             context,
             stack,
+            input_buf: VecDeque::new(),
+            guard_page,
+            fpu_state: FpuArea::new(),
+            fpu_used: false,
+            page_table_phys: crate::memory::kernel_pml4().as_u64(),
+            is_process: false,
+            image_frames: Vec::new(),
+            priority,
+            weight_counter: priority + 1,
         });
+        self.tasks.len() - 1
+    }
+
+    /// Adds a ring-3 task that starts at `entry` under the page table at `page_table_phys`.
+    /// Gets its own kernel stack plus a fresh user stack, and takes ownership of the frames
+    /// already loaded into that address space (`image_frames`). Returns the task's index.
+    pub fn spawn_process(&mut self, name: &str, entry: u64, page_table_phys: u64, image_frames: Vec<u64>, budget: u64, priority: u8) -> usize {
+        let priority = priority.min(MAX_PRIORITY);
+        let stack = alloc::vec![0u8; 65536];
+
+        // 1. User stack: USER_STACK_PAGES fresh frames in the process's own address space
+        let stack_base = USER_STACK_REGION;
+        for i in 0..USER_STACK_PAGES {
+            let frame = crate::memory::alloc_frame().as_u64();
+            unsafe { crate::memory::map_user_page_in(x86_64::PhysAddr::new(page_table_phys), stack_base + i * 4096, frame, false); }
+        }
+
+        // 2. First context: iretq straight into ring 3
+        let (code, data) = crate::gdt::get_user_selectors();
+        let context = TaskContext {
+            rip: entry,
+            cs: code as u64,
+            ss: data as u64,
+            rflags: 0x202, // Interrupts enabled
+            rsp: stack_base + USER_STACK_PAGES * 4096,
+            ..TaskContext::default()
+        };
+
+        self.tasks.push(Task {
+            id: next_task_id(),
+            name: String::from(name),
+            budget,
+            job: user_process_marker,
+            last_cost: 0,
+            total_cycles: 0,
+            run_count: 0,
+            status: TaskStatus::Waiting,
+            violation_count: 0,
+            penalty_cooldown: 0,
+            context,
+            stack,
+            input_buf: VecDeque::new(),
+            guard_page: None,
+            fpu_state: FpuArea::new(),
+            fpu_used: false,
+            page_table_phys,
+            is_process: true,
+            image_frames,
+            priority,
+            weight_counter: priority + 1,
+        });
+        self.tasks.len() - 1
+    }
+
+    /// Marks the running task as sleeping for `ticks` PIT ticks (~10 ms each).
+    /// The caller still has to yield; see `sleep`.
+    pub fn sleep_current(&mut self, ticks: u64) {
+        if let Some(idx) = self.current() {
+            let wake_tick = crate::state::TICK_COUNT.load(core::sync::atomic::Ordering::Relaxed) + ticks;
+            self.tasks[idx].status = TaskStatus::Sleeping { wake_tick };
+        }
+    }
+
+    /// Removes a task by index, keeping the running task's index valid.
+    /// A running task (on any CPU) cannot be removed this way (use the exit syscall).
+    pub fn remove_task(&mut self, idx: usize) -> Option<Task> {
+        if idx >= self.tasks.len() || self.is_running(idx) {
+            return None;
+        }
+        let task = self.tasks.remove(idx);
+        for cur in self.current_task_idx.iter_mut().flatten() {
+            if *cur > idx { *cur -= 1; }
+        }
+        shift_fpu_owners(idx);
+        unsafe {
+            if NEXT_TASK_IDX > idx { NEXT_TASK_IDX -= 1; }
+        }
+        Some(task)
+    }
+
+    /// Current index of the task with id `id`
+    pub fn find_by_id(&self, id: u64) -> Option<usize> {
+        self.tasks.iter().position(|t| t.id == id)
+    }
+
+    /// Index of the first task called `name`
+    pub fn find_task(&self, name: &str) -> Option<usize> {
+        self.tasks.iter().position(|t| t.name == name)
+    }
+
+    /// Index of the task with the biggest stack, our only per-task memory figure so far.
+    /// Skips tasks that are running (here or on another CPU) and the shell task while it
+    /// holds `SHELL`: removing it then would leave the lock held for good.
+    fn heaviest_task(&self) -> Option<usize> {
+        let shell_busy = crate::shell::SHELL.is_locked();
+        self.tasks.iter().enumerate()
+            .filter(|(i, _)| !self.is_running(*i))
+            .filter(|(_, t)| !(shell_busy && t.job as *const () == crate::shell::shell_task as *const ()))
+            .max_by_key(|(_, t)| t.stack.len())
+            .map(|(i, _)| i)
     }
 
     pub fn execute_frame(&mut self) {
@@ -121,18 +428,120 @@ impl Scheduler {
 
 static mut NEXT_TASK_IDX: usize = 0;
 
-pub fn step() {
+/// Task `idx` was removed: drop it as an FPU owner (its registers are discarded) and move
+/// the owners above it down a slot
+fn shift_fpu_owners(idx: usize) {
+    use core::sync::atomic::Ordering;
+    for owner in LAST_FPU_OWNER.iter() {
+        let cur = owner.load(Ordering::Relaxed);
+        if cur == idx {
+            owner.store(NO_FPU_OWNER, Ordering::Relaxed);
+        } else if cur != NO_FPU_OWNER && cur > idx {
+            owner.store(cur - 1, Ordering::Relaxed);
+        }
+    }
+}
+
+// --- PRIORITIES ---
+// Weighted round-robin: a task of priority P runs P+1 bursts in a row (its "tickets")
+// before `step` moves on, so its CPU share is proportional to P+1.
+pub const MAX_PRIORITY: u8 = 15;
+
+// --- USER PROCESSES ---
+// Every process has its own PML4 (`memory::create_user_page_table`), so they all put their
+// stack at the same address. The frames are freed when the task is dropped.
+const USER_STACK_REGION: u64 = 0x0100_0000;
+const USER_STACK_PAGES: u64 = 4;
+
+// `Task::job` for processes, which start at an ELF entry point instead of a kernel function
+extern "C" fn user_process_marker(_arg: u64) {}
+
+/// Puts the calling task to sleep for `ticks` PIT ticks and yields the CPU
+pub fn sleep(ticks: u64) {
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        SCHEDULER.lock().sleep_current(ticks);
+    });
+    unsafe { core::arch::asm!("int 0x80", in("rax") 3); } // yield
+}
+
+/// OOM policy: kills the task using the most memory and returns its name. Never picks the
+/// running task (see `heaviest_task`). None if the scheduler is busy (the allocation failed
+/// while it was locked) or there is no task that can be killed.
+pub fn kill_heaviest_task() -> Option<String> {
+    // No allocations in here: the heap is exhausted. The name is moved out, never cloned.
+    let mut task = x86_64::instructions::interrupts::without_interrupts(|| {
+        let mut sched = SCHEDULER.try_lock()?;
+        let idx = sched.heaviest_task()?;
+        sched.remove_task(idx)
+    })?;
+    Some(core::mem::take(&mut task.name))
+}
+
+/// Removes the task called `name`. Refuses (false) if the scheduler is busy, the task is
+/// the one running, or it's the shell task in the middle of a command: removing it then
+/// would leave `SHELL` locked for good.
+pub fn kill_by_name(name: &str) -> bool {
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        let mut sched = match SCHEDULER.try_lock() {
+            Some(s) => s,
+            None => {
+                crate::serial_println!("[KILL] Scheduler busy, not killing '{}'", name);
+                return false;
+            }
+        };
+        let idx = match sched.find_task(name) {
+            Some(i) => i,
+            None => return false,
+        };
+        if sched.tasks[idx].job as *const () == crate::shell::shell_task as *const () {
+            match crate::shell::SHELL.try_lock() {
+                Some(_guard) => {}
+                None => {
+                    crate::serial_println!("[KILL] '{}' holds SHELL, not killing it", name);
+                    return false;
+                }
+            }
+        }
+        sched.remove_task(idx).is_some()
+    })
+}
+
+/// Runs one burst of the next eligible task on this CPU. False if there was nothing to run.
+pub fn step() -> bool {
     let mut task_idx = None;
+    let cpu = crate::smp::cpu_id();
     
     x86_64::instructions::interrupts::without_interrupts(|| {
         let mut sched = SCHEDULER.lock();
         if sched.tasks.is_empty() { return; }
         
         let mut i = unsafe { NEXT_TASK_IDX } % sched.tasks.len();
+        let kernel_pml4 = crate::memory::kernel_pml4().as_u64();
         
-        // Find next non-penalized task
+        // Find next non-penalized, awake task
+        let now = crate::state::TICK_COUNT.load(core::sync::atomic::Ordering::Relaxed);
         let start_i = i;
         loop {
+            // Skip tasks another CPU is running, or whose FPU registers are still live in
+            // another CPU's FPU. Ring-3 processes stay on the BSP: the SYSCALL entry path
+            // has a single stack.
+            let fpu_elsewhere = LAST_FPU_OWNER.iter().enumerate()
+                .any(|(c, owner)| c != cpu && owner.load(core::sync::atomic::Ordering::Relaxed) == i);
+            let elsewhere = sched.is_running(i) || fpu_elsewhere
+                || (cpu != 0 && sched.tasks[i].page_table_phys != kernel_pml4);
+            if elsewhere || sched.tasks[i].status == TaskStatus::Blocked {
+                i = (i + 1) % sched.tasks.len();
+                if i == start_i { break; }
+                continue;
+            }
+            if let TaskStatus::Sleeping { wake_tick } = sched.tasks[i].status {
+                if now < wake_tick {
+                    i = (i + 1) % sched.tasks.len();
+                    if i == start_i { break; }
+                    continue;
+                }
+                sched.tasks[i].status = TaskStatus::Waiting;
+            }
             if sched.tasks[i].penalty_cooldown == 0 {
                 task_idx = Some(i);
                 break;
@@ -144,8 +553,17 @@ pub fn step() {
         }
         
         if let Some(idx) = task_idx {
-            sched.current_task_idx = Some(idx);
-            unsafe { NEXT_TASK_IDX = (idx + 1) % sched.tasks.len(); }
+            sched.current_task_idx[cpu] = Some(idx);
+            // Stay on this task until its tickets for the round are used up
+            let task = &mut sched.tasks[idx];
+            task.weight_counter = task.weight_counter.saturating_sub(1);
+            let next = if task.weight_counter == 0 {
+                task.weight_counter = task.priority + 1;
+                idx + 1
+            } else {
+                idx
+            };
+            unsafe { NEXT_TASK_IDX = next % sched.tasks.len(); }
         }
     });
 
@@ -153,15 +571,24 @@ pub fn step() {
         let start = unsafe { _rdtsc() };
 
         // 1. Copy context to load to a local variable to avoid pointer-into-Vec issues
-        let context_to_load = x86_64::instructions::interrupts::without_interrupts(|| {
+        let (context_to_load, cr3) = x86_64::instructions::interrupts::without_interrupts(|| {
             let sched = SCHEDULER.lock();
-            sched.tasks[idx].context
+            let task = &sched.tasks[idx];
+            (task.context, task.page_table_phys)
         });
         
-        // 2. Switch must be atomic w.r.t the saving into SCHEDULER_CONTEXT
+        // 2. Switch must be atomic w.r.t the saving into this CPU's scheduler context.
+        // Unless this task's registers are the ones in the FPU, its first FP instruction traps.
         unsafe {
             x86_64::instructions::interrupts::disable();
-            context_switch(&mut SCHEDULER_CONTEXT, &context_to_load as *const TaskContext);
+            let owns_fpu = LAST_FPU_OWNER[cpu].load(core::sync::atomic::Ordering::Relaxed) == idx;
+            x86_64::registers::control::Cr0::update(|f| f.set(x86_64::registers::control::Cr0Flags::TASK_SWITCHED, !owns_fpu));
+            context_switch(scheduler_context(), &context_to_load as *const TaskContext, cr3);
+            // Back from a process: return to the kernel's own address space
+            let kernel_pml4 = crate::memory::kernel_pml4();
+            if x86_64::registers::control::Cr3::read().0.start_address() != kernel_pml4 {
+                crate::memory::switch_page_table(kernel_pml4);
+            }
             x86_64::instructions::interrupts::enable();
         }
         
@@ -169,11 +596,26 @@ pub fn step() {
         
         x86_64::instructions::interrupts::without_interrupts(|| {
             let mut sched = SCHEDULER.lock();
-            sched.current_task_idx = None;
+            // Other CPUs may have removed tasks meanwhile; the slot was shifted to match
+            // (and emptied if the task exited)
+            let idx = match sched.current_task_idx[cpu].take() {
+                Some(i) => i,
+                None => return,
+            };
+
             if idx < sched.tasks.len() {
-                sched.tasks[idx].last_cost = end - start;
+                let cost = end - start;
+                sched.tasks[idx].last_cost = cost;
+                sched.tasks[idx].total_cycles += cost;
+                sched.tasks[idx].run_count += 1;
                 // Enforce Contract
-                if sched.tasks[idx].last_cost <= sched.tasks[idx].budget {
+                if matches!(sched.tasks[idx].status, TaskStatus::Sleeping { .. } | TaskStatus::Blocked) {
+                    // Went to sleep or blocked during this burst; keep it that way and give up the
+                    // rest of the round
+                    let task = &mut sched.tasks[idx];
+                    task.weight_counter = task.priority + 1;
+                    unsafe { NEXT_TASK_IDX = (idx + 1) % sched.tasks.len(); }
+                } else if sched.tasks[idx].last_cost <= sched.tasks[idx].budget {
                     sched.tasks[idx].status = TaskStatus::Success;
                     if sched.tasks[idx].violation_count > 0 { sched.tasks[idx].violation_count -= 1; }
                 } else {
@@ -187,12 +629,13 @@ pub fn step() {
             }
         });
     }
+    task_idx.is_some()
 }
 
 
 
 #[unsafe(naked)]
-pub unsafe extern "C" fn context_switch(save: *mut TaskContext, load: *const TaskContext) {
+pub unsafe extern "C" fn context_switch(save: *mut TaskContext, load: *const TaskContext, cr3: u64) {
     core::arch::naked_asm!(
         // 1. Save all registers and RFLAGS to stack
         "pushfq",
@@ -245,8 +688,15 @@ pub unsafe extern "C" fn context_switch(save: *mut TaskContext, load: *const Tas
         "mov [rax + 152], rbx",
         
         "cli",
+
+        // 3. Address space (rdx = cr3): skip the reload, and its TLB flush, if it's already live
+        "mov rax, cr3",
+        "cmp rax, rdx",
+        "je 2f",
+        "mov cr3, rdx",
+        "2:",
         
-        // 3. Load from 'load' (rsi)
+        // 4. Load from 'load' (rsi)
         "mov r15, [rsi + 0]",
         "mov r14, [rsi + 8]",
         "mov r13, [rsi + 16]",
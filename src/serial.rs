@@ -1,8 +1,13 @@
 use x86_64::instructions::port::Port;
+use alloc::collections::vec_deque::VecDeque;
+use alloc::string::String;
 use core::fmt;
 use spin::Mutex;
 use lazy_static::lazy_static;
 
+const COM1: u16 = 0x3F8;
+const RX_BUF_MAX: usize = 4096; // Oldest bytes are dropped beyond this
+
 pub struct SerialPort {
     data: Port<u8>,
     int_en: Port<u8>,
@@ -33,6 +38,7 @@ impl SerialPort {
             self.line_ctrl.write(0x03); // 8 bits, no parity, one stop bit
             self.fifo_ctrl.write(0xC7); // Enable FIFO, clear them, with 14-byte threshold
             self.modem_ctrl.write(0x0B); // IRQs enabled, RTS/DSR set
+            self.int_en.write(0x01);    // Interrupt on received data available
         }
     }
 
@@ -44,6 +50,13 @@ impl SerialPort {
         while !self.is_transmit_empty() {}
         unsafe { self.data.write(data); }
     }
+
+    /// A received byte, if one is waiting (LSR bit 0: data ready)
+    pub fn try_receive(&mut self) -> Option<u8> {
+        unsafe {
+            if self.line_sts.read() & 0x01 != 0 { Some(self.data.read()) } else { None }
+        }
+    }
 }
 
 impl fmt::Write for SerialPort {
@@ -57,10 +70,46 @@ impl fmt::Write for SerialPort {
 
 lazy_static! {
     pub static ref SERIAL1: Mutex<SerialPort> = {
-        let mut serial_port = SerialPort::new(0x3F8);
+        let mut serial_port = SerialPort::new(COM1);
         serial_port.init();
         Mutex::new(serial_port)
     };
+    pub static ref SERIAL_RX_BUF: Mutex<VecDeque<u8>> = Mutex::new(VecDeque::new());
+}
+
+/// COM1 IRQ: moves every byte the UART holds into SERIAL_RX_BUF. Reads the ports directly
+/// rather than through SERIAL1, which the interrupted code may be holding.
+pub fn handle_interrupt() {
+    let mut line_sts = Port::<u8>::new(COM1 + 5);
+    let mut data = Port::<u8>::new(COM1);
+    let mut buf = SERIAL_RX_BUF.lock();
+    unsafe {
+        while line_sts.read() & 0x01 != 0 {
+            if buf.len() >= RX_BUF_MAX { buf.pop_front(); }
+            buf.push_back(data.read());
+        }
+    }
+}
+
+/// Next received byte, if any
+pub fn read_byte() -> Option<u8> {
+    x86_64::instructions::interrupts::without_interrupts(|| SERIAL_RX_BUF.lock().pop_front())
+}
+
+/// A complete line (without its CR/LF) once one has arrived; partial lines stay buffered
+pub fn read_line() -> Option<String> {
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        let mut buf = SERIAL_RX_BUF.lock();
+        let end = buf.iter().position(|&b| b == b'\n' || b == b'\r')?;
+        let line: alloc::vec::Vec<u8> = buf.drain(..end).collect();
+        buf.pop_front(); // The terminator
+        if buf.front() == Some(&b'\n') { buf.pop_front(); } // CRLF
+        Some(String::from_utf8_lossy(&line).into_owned())
+    })
+}
+
+pub fn write_byte(byte: u8) {
+    x86_64::instructions::interrupts::without_interrupts(|| SERIAL1.lock().send(byte));
 }
 
 #[doc(hidden)]
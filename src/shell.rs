@@ -1,13 +1,12 @@
-use crate::{input, writer, fs, userspace, gdt, memory, state, pci, rtl8139, elf, compositor, logger, scheduler, ata}; 
+use crate::{input, writer, fs, pipe, memory, state, pci, nic, net, shmem, slab, calc, cpuid, diff, awk, base64, tar, elf, compositor, logger, scheduler, ata, lz}; 
 use alloc::string::{String, ToString};
 use alloc::vec::Vec;
 use alloc::vec; // Import vec! macro
 use alloc::format;
-use core::sync::atomic::{AtomicU64, Ordering};
+use core::sync::atomic::Ordering;
 use spin::Mutex;
 use lazy_static::lazy_static;
 
-pub static KERNEL_RSP: AtomicU64 = AtomicU64::new(0);
 
 pub struct Shell {
     command_buffer: String,
@@ -17,14 +16,64 @@ pub struct Shell {
     pub current_dir: String,
     pub history: Vec<String>,
     pub history_idx: usize,
+    pub history_search_mode: bool,         // Ctrl+R reverse-i-search in progress
+    pub history_search_query: String,
+    pub history_search_match: Option<usize>, // History index shown in the command line
+    pub history_search_saved: String,      // Command line to restore on Escape
     pub clipboard: String,
     pub nano_status: String,
     pub insertion_point: usize,
     pub prompt_start_idx: usize,
     pub prompt_start_y: usize,
+    pub fs_root: String, // chroot jail; every VFS path the shell touches lives below this
+    pub last_exit: i32,  // Exit code of the previous command ($?)
+    pub env: Vec<(String, String)>, // Shell variables; $PWD and $? are derived, not stored
+    pub background_tasks: Vec<u64>, // Task ids of jobs launched from this shell
+    pub fg_task: Option<u64>,       // Job currently receiving keyboard input
+    pub active_workspace: usize,      // 0..WORKSPACES; only its windows are drawn
+    pub tail_watching: Option<(String, usize)>, // `tail -f`: file and bytes already shown
+    pub console: Option<ConsoleMode>, // `console`: keyboard and COM1 bridged until Ctrl+Z
+    pub pending_block: String, // Lines of an unfinished `if`/`for`/`while`, joined with "; "
+    pub nesting: usize,        // `source` scripts and `$(...)` substitutions currently running
+    pub ctx: ShellContext,
+}
+
+/// Redirection state for the command being executed. While `output` is Some, everything the
+/// command prints is captured there instead of reaching the terminal; `input` is the previous
+/// pipeline stage's output.
+#[derive(Default)]
+pub struct ShellContext {
+    pub output: Option<String>,
+    pub input: Option<String>,
+}
+
+/// How `console` shows what arrives on COM1
+#[derive(Clone, Copy, PartialEq)]
+pub enum ConsoleMode {
+    Raw,   // Byte by byte, echoed back to the sender
+    Lines, // Whole lines once the terminator arrives
 }
 
 const MAX_WINDOWS: usize = 15;
+pub const WORKSPACES: usize = 4;
+const XARGS_MAX_RUNS: usize = 100; // Commands one `xargs` may run
+const SEQ_MAX_LINES: usize = 10_000; // Numbers one `seq` may print, so a huge range can't eat the heap
+const LOOP_MAX_ITERATIONS: usize = 1000; // Per `for`/`while`, so a runaway loop can't hog the CPU
+const MAX_NESTING: usize = 8; // `source`/`$(...)` levels, so a script sourcing itself can't overflow the stack
+
+// Candidates for Tab completion of the first word
+const BUILTIN_COMMANDS: &[&str] = &[
+    "awk", "base64", "beep", "bg", "browser", "calc", "cat", "catdisk", "cd", "chmod", "chroot",
+    "clear", "compress", "console", "cp", "cpuinfo", "date", "decompress", "diff", "disk", "dmesg",
+    "du", "echo", "env", "explorer", "export", "false", "fbinfo", "fetch", "fg", "find", "fm",
+    "goto", "grep", "head", "help", "hexdump", "install", "ip", "jobs", "kill", "kinfo", "ln", "ls",
+    "lsdisk", "lspci", "mkdir", "mmap", "move_to_workspace", "mv", "nano", "net", "netio", "ping",
+    "poweroff", "printf", "ps", "pwd", "reboot", "rename_window", "rm", "rmdisk", "route", "run",
+    "rundisk", "screenshot", "seq", "shmem", "shutdown", "slabinfo", "sleep", "smartctl", "sort",
+    "source", "stat", "sync", "tail", "tar", "tcp_listen", "tcpdump", "term", "test", "top",
+    "touch", "true", "unchroot", "uniq", "unset", "uptime", "wc", "wifi", "write", "writedisk",
+    "xargs", "xxd",
+];
 
 impl Shell {
     pub fn new() -> Self {
@@ -41,11 +90,26 @@ impl Shell {
             current_dir: "/".to_string(),
             history: Vec::new(),
             history_idx: 0,
+            history_search_mode: false,
+            history_search_query: String::new(),
+            history_search_match: None,
+            history_search_saved: String::new(),
             clipboard: String::new(),
             nano_status: String::new(),
             insertion_point: 0,
             prompt_start_idx: 0,
             prompt_start_y: compositor::TITLE_HEIGHT + 4,
+            fs_root: "/".to_string(),
+            last_exit: 0,
+            env: alloc::vec![("HOME".to_string(), "/".to_string())],
+            background_tasks: Vec::new(),
+            fg_task: None,
+            active_workspace: 0,
+            tail_watching: None,
+            console: None,
+            pending_block: String::new(),
+            nesting: 0,
+            ctx: ShellContext::default(),
         };
         
         // Correct initialization for the first window
@@ -79,7 +143,77 @@ impl Shell {
         fs::save_to_disk();
     }
 
+    /// Maps a path as seen inside the shell (possibly chrooted) to a real VFS path.
+    /// The shell path is normalized first, so `..` can never climb above `fs_root`.
+    pub fn resolve(&self, path: &str) -> String {
+        let view = fs::normalize_path(path);
+        if self.fs_root == "/" {
+            view
+        } else if view == "/" {
+            self.fs_root.clone()
+        } else {
+            format!("{}{}", self.fs_root, view)
+        }
+    }
+
+    /// Real VFS path of the current directory
+    pub fn cwd(&self) -> String {
+        self.resolve(&self.current_dir)
+    }
+
+    /// Real VFS path for a (relative or absolute) shell path
+    fn real_path(&self, path: &str) -> String {
+        if path.starts_with('/') {
+            self.resolve(path)
+        } else {
+            self.resolve(&format!("{}/{}", self.current_dir, path))
+        }
+    }
+
+    /// Splits a (relative or absolute) shell path into its real parent directory and final name
+    fn locate(&self, path: &str) -> (String, String) {
+        let full = if path.starts_with('/') {
+            fs::normalize_path(path)
+        } else {
+            fs::normalize_path(&format!("{}/{}", self.current_dir, path))
+        };
+        match full.rfind('/') {
+            Some(0) => (self.resolve("/"), full[1..].to_string()),
+            Some(idx) => (self.resolve(&full[..idx]), full[idx+1..].to_string()),
+            None => (self.cwd(), full),
+        }
+    }
+
+    /// Drops jobs whose task no longer exists (it exited or was killed)
+    fn prune_jobs(&mut self) {
+        let (jobs, fg) = (&mut self.background_tasks, &mut self.fg_task);
+        x86_64::instructions::interrupts::without_interrupts(|| {
+            let sched = scheduler::SCHEDULER.lock();
+            jobs.retain(|&id| sched.find_by_id(id).is_some());
+            if fg.is_some_and(|id| sched.find_by_id(id).is_none()) { *fg = None; }
+        });
+    }
+
+    /// Records the task at scheduler index `idx` as a job; returns its job (task id)
+    fn add_job(&mut self, idx: usize) -> Option<u64> {
+        let id = x86_64::instructions::interrupts::without_interrupts(|| {
+            scheduler::SCHEDULER.lock().tasks.get(idx).map(|t| t.id)
+        })?;
+        self.background_tasks.push(id);
+        Some(id)
+    }
+
+    /// Parses a job argument (`%3` or `3`) and checks it belongs to this shell
+    fn parse_job(&self, arg: &str) -> Option<u64> {
+        let id = arg.trim_start_matches('%').parse::<u64>().ok()?;
+        if self.background_tasks.contains(&id) { Some(id) } else { None }
+    }
+
     fn print(&mut self, text: &str) {
+        if let Some(out) = self.ctx.output.as_mut() {
+            out.push_str(text);
+            return;
+        }
         if let Some(win) = self.windows.get_mut(self.active_idx) {
             win.print(text);
         }
@@ -90,13 +224,69 @@ impl Shell {
         // LIMIT THROUGHPUT: Only process up to 10 keys per tick to avoid blowing the budget
         // and entering the "Penalty Box". This keeps the UI responsive even if user types fast.
         let mut processed_count = 0;
+
+        // A `beep` (or BEL) that has run its time
+        crate::speaker::poll();
+
+        // Any key ends `tail -f` (and is swallowed)
+        if self.tail_watching.is_some() && input::peek_key().is_some() {
+            input::pop_key();
+            self.tail_watching = None;
+            self.print("\n");
+            self.show_prompt();
+        }
         
         while let Some(c) = input::pop_key() {
             if processed_count >= 10 {
                 break;
             }
             processed_count += 1;
+
+            // Alt+1..4
+            if ('\u{E010}'..='\u{E013}').contains(&c) {
+                self.switch_workspace(c as usize - 0xE010);
+                continue;
+            }
+
+            // Ctrl+Tab / Ctrl+Shift+Tab
+            if c == '\u{E007}' || c == '\u{E008}' {
+                self.cycle_focus(c == '\u{E007}');
+                continue;
+            }
+
+            // Serial console: keystrokes go out on COM1 (and to the screen) until Ctrl+Z
+            if self.console.is_some() {
+                if c == '\x1A' {
+                    self.console = None;
+                    self.print("\n[console closed]\n");
+                    self.show_prompt();
+                } else if c.is_ascii() {
+                    crate::serial::write_byte(if c == '\n' { b'\r' } else { c as u8 });
+                    if c == '\n' || !c.is_ascii_control() { self.print(c.encode_utf8(&mut [0; 4])); }
+                }
+                continue;
+            }
+
+            // Foreground job owns the keyboard; Ctrl+Z hands it back to the shell
+            if let Some(fg) = self.fg_task {
+                if c == '\x1A' {
+                    self.fg_task = None;
+                    self.print(&format!("\n[{}] Detached\n> ", fg));
+                    continue;
+                }
+                let routed = x86_64::instructions::interrupts::without_interrupts(|| {
+                    let mut sched = scheduler::SCHEDULER.lock();
+                    match sched.find_by_id(fg) {
+                        Some(idx) => { sched.tasks[idx].input_buf.push_back(c); true }
+                        None => false,
+                    }
+                });
+                if routed { continue; }
+                self.fg_task = None;
+            }
+
             let active_idx = self.active_idx;
+            let cwd = self.cwd();
             if let Some(win) = self.windows.get_mut(active_idx) {
                 if win.title.starts_with("Nano - ") {
                     // NANO INPUT HANDLING
@@ -113,7 +303,7 @@ impl Shell {
                             let filename = win.title.trim_start_matches("Nano - ").to_string();
                             let content = win.text_buffer.clone();
                             let len = content.len();
-                            fs::touch(&self.current_dir, &filename, content.into_bytes());
+                            fs::touch(&cwd, &filename, content.into_bytes());
                             fs::save_to_disk();
                             self.nano_status = format!("[ Saved {} bytes ]", len);
                         }
@@ -143,7 +333,7 @@ impl Shell {
                         }
                         '\x12' => { // Ctrl+R (Read File)
                             // For now, let's just simulate reading a file named 'import.txt'
-                            if let Some(data) = fs::read(&self.current_dir, "import.txt") {
+                            if let Some(data) = fs::read(&cwd, "import.txt") {
                                 if let Ok(s) = String::from_utf8(data) {
                                     win.print(&s);
                                     self.nano_status = "[ Read import.txt ]".to_string();
@@ -162,17 +352,61 @@ impl Shell {
                 }
             }
 
+            // Ctrl+R: reverse-i-search through history
+            if c == '\x12' {
+                if self.history_search_mode {
+                    // Again: the next older match
+                    let from = self.history_search_match.unwrap_or(self.history.len());
+                    self.history_search(from);
+                } else {
+                    self.history_search_mode = true;
+                    self.history_search_query.clear();
+                    self.history_search_match = None;
+                    self.history_search_saved = self.command_buffer.clone();
+                }
+                self.redraw_command_line();
+                continue;
+            }
+            if self.history_search_mode {
+                match c {
+                    '\x1B' => { // Escape: back to what was typed before
+                        self.history_search_mode = false;
+                        self.command_buffer = core::mem::take(&mut self.history_search_saved);
+                        self.insertion_point = self.command_buffer.len();
+                        self.redraw_command_line();
+                        continue;
+                    }
+                    '\n' | '\r' => { // Enter: keep the match for editing
+                        self.history_search_mode = false;
+                        self.redraw_command_line();
+                        continue;
+                    }
+                    '\x08' => {
+                        self.history_search_query.pop();
+                        self.history_search(self.history.len());
+                        self.redraw_command_line();
+                        continue;
+                    }
+                    c if !c.is_control() && !('\u{E000}'..='\u{F8FF}').contains(&c) => {
+                        self.history_search_query.push(c);
+                        self.history_search(self.history.len());
+                        self.redraw_command_line();
+                        continue;
+                    }
+                    // Anything else leaves search mode and is handled as usual
+                    _ => self.history_search_mode = false,
+                }
+            }
+
             match c {
                 '\n' | '\r' => {
                     self.print("\n");
                     self.execute_command();
                     self.command_buffer.clear();
                     self.insertion_point = 0;
-                    if let Some(win) = self.windows.get_mut(self.active_idx) {
-                        self.prompt_start_idx = win.text_buffer.chars().count();
-                        self.prompt_start_y = win.cursor_y;
+                    if self.tail_watching.is_none() && self.console.is_none() {
+                        self.show_prompt();
                     }
-                    self.print("> "); 
                 }
                 '\x08' => {
                     if self.insertion_point > 0 {
@@ -234,6 +468,7 @@ impl Shell {
                     }
                     self.redraw_command_line();
                 }
+                '\t' => self.handle_tab_completion(),
                 '~' => {
                      let now = unsafe { core::arch::x86_64::_rdtsc() };
                      if now - self.last_spawn_time > 1_000_000_000 { 
@@ -256,9 +491,59 @@ impl Shell {
         for msg in logs {
             self.print(&msg);
         }
+
+        // 4. Serial console: show what arrived on COM1
+        match self.console {
+            Some(ConsoleMode::Raw) => {
+                while let Some(b) = crate::serial::read_byte() {
+                    crate::serial::write_byte(b); // Echo so the remote side sees its typing
+                    match b {
+                        b'\r' => { crate::serial::write_byte(b'\n'); self.print("\n"); }
+                        b if b == b'\n' || (b.is_ascii() && !b.is_ascii_control()) => {
+                            self.print((b as char).encode_utf8(&mut [0; 4]));
+                        }
+                        _ => {}
+                    }
+                }
+            }
+            Some(ConsoleMode::Lines) => {
+                while let Some(line) = crate::serial::read_line() {
+                    self.print(&format!("serial> {}\n", line));
+                }
+            }
+            None => {}
+        }
+
+        // 5. Follow mode: print whatever was appended since the last look
+        if let Some((name, offset)) = self.tail_watching.take() {
+            match fs::read(&self.cwd(), &name) {
+                Some(data) => {
+                    if data.len() > offset {
+                        self.print(&String::from_utf8_lossy(&data[offset..]));
+                    } else if data.len() < offset {
+                        self.print("tail: file truncated\n");
+                    }
+                    self.tail_watching = Some((name, data.len()));
+                }
+                None => {
+                    self.print(&format!("tail: {} is gone\n", name));
+                    self.show_prompt();
+                }
+            }
+        }
+    }
+
+    /// Records where the command line starts and prints "> "
+    fn show_prompt(&mut self) {
+        if let Some(win) = self.windows.get_mut(self.active_idx) {
+            self.prompt_start_idx = win.text_buffer.chars().count();
+            self.prompt_start_y = win.cursor_y;
+        }
+        let prompt = self.prompt_text();
+        self.print(&prompt);
     }
 
-    fn spawn_terminal(&mut self) {
+    pub fn spawn_terminal(&mut self) {
         if self.windows.len() >= MAX_WINDOWS {
             self.print("\nError: Maximum window limit reached (Resource Protection).\n");
             return;
@@ -267,8 +552,7 @@ impl Shell {
         let title = format!("Terminal {}", count);
         let mut win = compositor::Window::new(50 + (count*30), 50 + (count*30), 700, 400, &title);
         win.print("Chronos Terminal\n> ");
-        self.windows.push(win);
-        self.active_idx = self.windows.len() - 1; 
+        self.open_window(win);
     }
 
     fn execute_command(&mut self) {
@@ -281,8 +565,496 @@ impl Shell {
             self.history_idx = self.history.len();
         }
 
+        // A block opened on this line (or earlier) runs once its last `fi`/`done` is typed
+        if !self.pending_block.is_empty() || block_depth(&cmd) > 0 {
+            if cmd.is_empty() { return; }
+            if !self.pending_block.is_empty() { self.pending_block.push_str("; "); }
+            self.pending_block.push_str(&cmd);
+            if block_depth(&self.pending_block) <= 0 {
+                let block = core::mem::take(&mut self.pending_block);
+                self.execute_line(&block);
+            }
+            return;
+        }
+
+        self.execute_line(&cmd);
+    }
+
+    /// Value of variable `name`: `?` and `PWD` are live, everything else comes from `env`
+    fn get_var(&self, name: &str) -> Option<String> {
+        match name {
+            "?" => Some(format!("{}", self.last_exit)),
+            "PWD" => Some(self.current_dir.clone()),
+            _ => self.env.iter().find(|(k, _)| k == name).map(|(_, v)| v.clone()),
+        }
+    }
+
+    fn set_var(&mut self, name: &str, value: &str) {
+        match self.env.iter_mut().find(|(k, _)| k == name) {
+            Some(entry) => entry.1 = value.to_string(),
+            None => self.env.push((name.to_string(), value.to_string())),
+        }
+    }
+
+    /// Replaces `$NAME` and `$?` with their values (unset variables become empty).
+    /// Text inside single quotes is left alone.
+    pub fn expand_vars(&self, cmd: &str) -> String {
+        let mut out = String::new();
+        let mut in_single = false;
+        let mut in_double = false;
+        let mut chars = cmd.char_indices().peekable();
+        while let Some((i, c)) = chars.next() {
+            if c == '\'' && !in_double { in_single = !in_single; }
+            if c == '"' && !in_single { in_double = !in_double; }
+            if c != '$' || in_single {
+                out.push(c);
+                continue;
+            }
+            // $(( expr )): arithmetic expansion
+            if cmd[i..].starts_with("$((") {
+                if let Some(len) = arith_len(&cmd[i + 3..]) {
+                    let inner = &cmd[i + 3..i + 3 + len];
+                    let end = i + 3 + len + 2;
+                    match calc::eval(&self.expand_arith_vars(inner)) {
+                        Ok(v) => out.push_str(&format!("{}", v)),
+                        Err(_) => out.push_str(&cmd[i..end]), // Left as typed
+                    }
+                    while chars.peek().is_some_and(|&(j, _)| j < end) { chars.next(); }
+                    continue;
+                }
+            }
+            let mut name = String::new();
+            if chars.peek().map(|&(_, n)| n) == Some('?') {
+                name.push('?');
+                chars.next();
+            } else {
+                while let Some(&(_, n)) = chars.peek() {
+                    if !(n.is_ascii_alphanumeric() || n == '_') { break; }
+                    name.push(n);
+                    chars.next();
+                }
+            }
+            if name.is_empty() {
+                out.push('$');
+            } else if let Some(value) = self.get_var(&name) {
+                out.push_str(&value);
+            }
+        }
+        out
+    }
+
+    /// Inside `$(( ))` variables may be written with or without `$`; unset ones are 0
+    fn expand_arith_vars(&self, expr: &str) -> String {
+        let expr = self.expand_vars(expr);
+        let mut out = String::new();
+        let mut word = String::new();
+        for c in expr.chars().chain(core::iter::once(' ')) {
+            if c.is_ascii_alphanumeric() || c == '_' {
+                word.push(c);
+                continue;
+            }
+            if word.starts_with(|w: char| w.is_ascii_alphabetic() || w == '_') {
+                out.push_str(&self.get_var(&word).unwrap_or_else(|| String::from("0")));
+            } else {
+                out.push_str(&word); // Number (0x/0b prefixes included)
+            }
+            word.clear();
+            out.push(c);
+        }
+        out.pop();
+        out
+    }
+
+    /// Evaluates a `test` / `[` expression. Returns true when the condition holds.
+    pub fn eval_test(&self, expr: &str) -> bool {
+        let last_exit = format!("{}", self.last_exit);
+        let mut tokens: Vec<&str> = expr.split_whitespace()
+            .map(|t| if t == "$?" { last_exit.as_str() } else { t })
+            .collect();
+        // `[ ... ]` form: drop the closing bracket
+        if tokens.last() == Some(&"]") { tokens.pop(); }
+
+        if tokens.first() == Some(&"!") {
+            return !self.eval_test(&tokens[1..].join(" "));
+        }
+
+        match tokens.len() {
+            0 => false,
+            1 => !tokens[0].is_empty(),
+            2 => {
+                let arg = tokens[1];
+                match tokens[0] {
+                    "-z" => arg.is_empty(),
+                    "-n" => !arg.is_empty(),
+                    "-e" | "-f" | "-d" => {
+                        let (dir, name) = self.locate(arg);
+                        match fs::get_node_info(&dir, &name) {
+                            Some(info) => match tokens[0] {
+                                "-f" => !info.is_dir,
+                                "-d" => info.is_dir,
+                                _ => true,
+                            },
+                            // The chroot root itself has no parent entry to look up
+                            None => tokens[0] != "-f" && name.is_empty() && fs::ls(&dir).is_some(),
+                        }
+                    }
+                    _ => false,
+                }
+            }
+            3 => {
+                let (a, op, b) = (tokens[0], tokens[1], tokens[2]);
+                match op {
+                    "=" | "==" => a == b,
+                    "!=" => a != b,
+                    "-eq" | "-ne" | "-lt" | "-gt" | "-le" | "-ge" => {
+                        let (x, y) = match (a.parse::<i64>(), b.parse::<i64>()) {
+                            (Ok(x), Ok(y)) => (x, y),
+                            _ => return false,
+                        };
+                        match op {
+                            "-eq" => x == y,
+                            "-ne" => x != y,
+                            "-lt" => x < y,
+                            "-gt" => x > y,
+                            "-le" => x <= y,
+                            _ => x >= y,
+                        }
+                    }
+                    _ => false,
+                }
+            }
+            _ => false,
+        }
+    }
+
+    /// Handles `if <cond>; then <cmds>; [else <cmds>;] fi`.
+    /// The condition is either a `test`/`[` expression or any command (checked via `$?`).
+    fn execute_if(&mut self, line: &str) {
+        let segments: Vec<&str> = line.split(';').map(|s| s.trim()).filter(|s| !s.is_empty()).collect();
+        let cond = segments[0].trim_start_matches("if").trim();
+
+        let mut then_cmds: Vec<&str> = Vec::new();
+        let mut else_cmds: Vec<&str> = Vec::new();
+        let mut in_else = false;
+        let mut closed = false;
+        for seg in &segments[1..] {
+            let (keyword, rest) = seg.split_once(' ').unwrap_or((seg, ""));
+            match keyword {
+                "then" => { in_else = false; if !rest.is_empty() { then_cmds.push(rest.trim()); } }
+                "else" => { in_else = true; if !rest.is_empty() { else_cmds.push(rest.trim()); } }
+                "fi" => { closed = true; break; }
+                _ => if in_else { else_cmds.push(seg) } else { then_cmds.push(seg) },
+            }
+        }
+        if !closed {
+            self.print("Syntax error: expected 'fi'\n");
+            self.last_exit = 2;
+            return;
+        }
+
+        let holds = self.eval_condition(cond);
+        for cmd in if holds { then_cmds } else { else_cmds } {
+            self.execute_line(cmd);
+        }
+    }
+
+    /// An `if`/`while` condition: a `test`/`[` expression or any command (checked via `$?`)
+    fn eval_condition(&mut self, cond: &str) -> bool {
+        let cond = self.expand_vars(cond);
+        if let Some(expr) = cond.strip_prefix("test ") {
+            self.eval_test(expr)
+        } else if let Some(expr) = cond.strip_prefix("[ ") {
+            self.eval_test(expr)
+        } else {
+            self.execute_line(&cond);
+            self.last_exit == 0
+        }
+    }
+
+    /// Splits `for ...; do <body>; done` or `while ...; do <body>; done` into the header
+    /// and the body (re-joined with "; "). None if there is no `do` or closing `done`.
+    fn split_loop(line: &str) -> Option<(&str, String)> {
+        let segments: Vec<&str> = split_unquoted(line, ';').into_iter().filter(|s| !s.is_empty()).collect();
+        if segments.len() < 3 || *segments.last()? != "done" { return None; }
+        let first = segments[1].strip_prefix("do")?;
+        if !first.is_empty() && !first.starts_with(' ') { return None; }
+
+        let mut body: Vec<&str> = Vec::new();
+        if !first.trim().is_empty() { body.push(first.trim()); }
+        body.extend_from_slice(&segments[2..segments.len() - 1]);
+        Some((segments[0], body.join("; ")))
+    }
+
+    /// Handles `for <var> in <words>; do <cmds>; done`. The word list may use `$()`.
+    fn execute_for(&mut self, line: &str) {
+        let (header, body) = match Self::split_loop(line) {
+            Some(parts) => parts,
+            None => {
+                self.print("Syntax error: expected 'for <var> in <list>; do <cmds>; done'\n");
+                self.last_exit = 2;
+                return;
+            }
+        };
+        let header = header.strip_prefix("for").unwrap_or(header).trim();
+        let (var, list) = match header.split_once(' ') {
+            Some((var, rest)) if is_var_name(var) && (rest == "in" || rest.starts_with("in ")) => (var, &rest[2..]),
+            _ => {
+                self.print("Syntax error: expected 'for <var> in <list>'\n");
+                self.last_exit = 2;
+                return;
+            }
+        };
+
+        // 1. Build the word list once, before the body can change anything
+        let list = self.substitute_commands(list);
+        let list = self.expand_vars(&list);
+        let words: Vec<String> = list.split_whitespace().map(|w| w.to_string()).collect();
+        if words.len() > LOOP_MAX_ITERATIONS {
+            self.print(&format!("Warning: only the first {} items are used.\n", LOOP_MAX_ITERATIONS));
+        }
+
+        // 2. Run the body once per word
+        self.last_exit = 0;
+        for word in words.iter().take(LOOP_MAX_ITERATIONS) {
+            self.set_var(var, word);
+            self.execute_line(&body);
+        }
+    }
+
+    /// Handles `while <cond>; do <cmds>; done`, re-checking the condition each time round
+    fn execute_while(&mut self, line: &str) {
+        let (header, body) = match Self::split_loop(line) {
+            Some(parts) => parts,
+            None => {
+                self.print("Syntax error: expected 'while <cond>; do <cmds>; done'\n");
+                self.last_exit = 2;
+                return;
+            }
+        };
+        let cond = header.strip_prefix("while").unwrap_or(header).trim();
+
+        for _ in 0..LOOP_MAX_ITERATIONS {
+            if !self.eval_condition(cond) {
+                self.last_exit = 0;
+                return;
+            }
+            self.execute_line(&body);
+        }
+        self.print(&format!("Error: loop stopped after {} iterations.\n", LOOP_MAX_ITERATIONS));
+        self.last_exit = 1;
+    }
+
+    /// Counts one more `source`/`$(...)` level, or refuses (with an error) past MAX_NESTING.
+    /// The caller takes the level off `nesting` again when it's done.
+    fn enter_nested(&mut self) -> bool {
+        if self.nesting >= MAX_NESTING {
+            self.print(&format!("Error: more than {} nested scripts or substitutions.\n", MAX_NESTING));
+            self.last_exit = 1;
+            return false;
+        }
+        self.nesting += 1;
+        true
+    }
+
+    /// Runs `cmd` with its output captured instead of printed
+    fn capture(&mut self, cmd: &str) -> String {
+        if !self.enter_nested() { return String::new(); }
+        let saved_output = self.ctx.output.replace(String::new());
+        let saved_input = self.ctx.input.take();
+        self.execute_line(cmd);
+        let out = self.ctx.output.take().unwrap_or_default();
+        self.ctx.output = saved_output;
+        self.ctx.input = saved_input;
+        self.nesting -= 1;
+        out
+    }
+
+    /// Replaces each `$(cmd)` with what `cmd` prints, minus trailing newlines. Text inside
+    /// single quotes is left alone, and `$((` arithmetic is left for `expand_vars`.
+    fn substitute_commands(&mut self, text: &str) -> String {
+        let mut out = String::new();
+        let mut in_single = false;
+        let mut in_double = false;
+        let mut i = 0;
+        while let Some(c) = text[i..].chars().next() {
+            if c == '\'' && !in_double { in_single = !in_single; }
+            if c == '"' && !in_single { in_double = !in_double; }
+            let after = &text[i + c.len_utf8()..];
+            if c == '$' && !in_single && after.starts_with('(') && !after.starts_with("((") {
+                if let Some(len) = subst_len(&after[1..]) {
+                    let captured = self.capture(&after[1..1 + len]);
+                    out.push_str(captured.trim_end_matches('\n'));
+                    i += 2 + len + 1;
+                    continue;
+                }
+            }
+            out.push(c);
+            i += c.len_utf8();
+        }
+        out
+    }
+
+    /// Runs every line of a script file. Multi-line `if`/`for`/`while` blocks are joined
+    /// before execution.
+    fn source(&mut self, path: &str) {
+        if !self.enter_nested() { return; }
+        self.source_script(path);
+        self.nesting -= 1;
+    }
+
+    fn source_script(&mut self, path: &str) {
+        let (dir, name) = self.locate(path);
+        let script = match fs::read(&dir, &name).and_then(|d| String::from_utf8(d).ok()) {
+            Some(s) => s,
+            None => {
+                self.print("Error: Script not found.\n");
+                self.last_exit = 1;
+                return;
+            }
+        };
+
+        let mut block = String::new();
+        for line in script.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') { continue; }
+
+            if !block.is_empty() || block_depth(line) > 0 {
+                if !block.is_empty() { block.push_str("; "); }
+                block.push_str(line);
+                if block_depth(&block) <= 0 {
+                    let stmt = core::mem::take(&mut block);
+                    self.execute_line(&stmt);
+                }
+                continue;
+            }
+            self.execute_line(line);
+        }
+        if !block.is_empty() {
+            self.print("Syntax error: unterminated block\n");
+            self.last_exit = 2;
+        }
+    }
+
+    /// Copies new or resized entries from `src` into `dst` (both real VFS paths), recursing
+    /// into subdirectories. With `delete`, entries in `dst` missing from `src` are removed.
+    fn sync_dir(&mut self, src: &str, dst: &str, delete: bool) {
+        let src_entries = fs::ls(src).unwrap_or_default();
+        let dst_entries = fs::ls(dst).unwrap_or_default();
+
+        for (name, is_dir) in &src_entries {
+            let src_child = if src == "/" { format!("/{}", name) } else { format!("{}/{}", src, name) };
+            let dst_child = if dst == "/" { format!("/{}", name) } else { format!("{}/{}", dst, name) };
+            let existing = fs::get_node_info(dst, name);
+
+            if *is_dir {
+                match existing {
+                    Some(info) if info.is_dir => {}
+                    Some(_) => { fs::rm(dst, name); fs::mkdir(dst, name); }
+                    None => { fs::mkdir(dst, name); }
+                }
+                self.sync_dir(&src_child, &dst_child, delete);
+                continue;
+            }
+
+            let src_size = fs::get_node_info(src, name).map(|i| i.size).unwrap_or(0);
+            match existing {
+                Some(info) if !info.is_dir && info.size == src_size => {
+                    self.print(&format!("skip {}\n", name));
+                }
+                _ => {
+                    self.print(&format!("syncing {}...\n", name));
+                    if !fs::copy_node(src, name, dst, name) {
+                        self.print(&format!("Error: Could not copy '{}'.\n", name));
+                        self.last_exit = 1;
+                    }
+                }
+            }
+        }
+
+        if delete {
+            for (name, _) in dst_entries {
+                if !src_entries.iter().any(|(n, _)| *n == name) {
+                    self.print(&format!("deleting {}\n", name));
+                    fs::rm_recursive(dst, &name);
+                }
+            }
+        }
+    }
+
+    /// Piped stdin if there is any, otherwise the named file
+    fn read_input(&mut self, file: Option<&str>) -> Option<Vec<u8>> {
+        if let Some(input) = self.ctx.input.take() {
+            return Some(input.into_bytes());
+        }
+        fs::read(&self.cwd(), file?)
+    }
+
+    /// Runs `a | b | c`: each stage's captured output goes through a `Pipe` and becomes the
+    /// next stage's input. The last stage writes wherever the caller's output goes (terminal
+    /// or an outer capture).
+    fn execute_pipeline(&mut self, stages: Vec<&str>) {
+        let mut outer_output = self.ctx.output.take();
+        let mut data = self.ctx.input.take();
+        let count = stages.len();
+
+        for (i, stage) in stages.into_iter().enumerate() {
+            let last = i + 1 == count;
+            self.ctx.input = data.take();
+            self.ctx.output = if last { outer_output.take() } else { Some(String::new()) };
+            self.execute_line(stage);
+            if !last {
+                let out = self.ctx.output.take().unwrap_or_default();
+                data = Some(pass_through_pipe(out.as_bytes()));
+            }
+        }
+        self.ctx.input = None;
+    }
+
+    /// Runs one command line. Used by the prompt and by `source`d scripts.
+    fn execute_line(&mut self, cmd: &str) {
+        let mut statements = split_statements(cmd);
+        if statements.len() != 1 {
+            for statement in statements {
+                self.execute_line(&statement);
+            }
+            return;
+        }
+        let statement = statements.remove(0);
+        let cmd = statement.as_str();
+
+        if cmd.starts_with("if ") {
+            self.execute_if(cmd);
+            return;
+        }
+        if cmd.starts_with("for ") {
+            self.execute_for(cmd);
+            return;
+        }
+        if cmd.starts_with("while ") {
+            self.execute_while(cmd);
+            return;
+        }
+
+        let stages = split_unquoted(cmd, '|');
+        if stages.len() > 1 {
+            self.execute_pipeline(stages);
+            return;
+        }
+
+        let cmd = self.substitute_commands(cmd);
+        let cmd = self.expand_vars(&cmd);
+        let cmd = cmd.as_str();
+
+        // `KEY=VALUE`: the whole command is one assignment
+        if let Some((key, value)) = parse_assignment(cmd) {
+            self.set_var(key, value);
+            self.last_exit = 0;
+            return;
+        }
+
         let parts: Vec<&str> = cmd.split_whitespace().collect();
         if parts.is_empty() { return; }
+        self.last_exit = 0;
 
         match parts[0] {
             "help" => self.print("Commands: ls, net, ping, run, term, top, wifi\n"),
@@ -321,8 +1093,7 @@ impl Shell {
                 win.print("Welcome to Chronos Browser\n");
                 win.print("--------------------------\n");
                 win.print("Type 'goto <url>' to browse.\n");
-                self.windows.push(win);
-                self.active_idx = self.windows.len() - 1;
+                self.open_window(win);
                 self.print("Launched Web Browser.\n");
             },
             "install" => {
@@ -338,16 +1109,18 @@ impl Shell {
                 self.print("  [####################] 100% - Done!\n");
                 self.print("System installed successfully. Please reboot.\n");
             },
-            "shutdown" => {
-                crate::acpi::shutdown();
+            "shutdown" | "poweroff" => {
+                ata::flush_all_caches();
+                crate::acpi::shutdown_via_fadt();
             },
             "reboot" => {
-                self.print("Rebooting...\n");
-                unsafe {
-                    use x86_64::instructions::port::Port;
-                    // standard PS/2 keyboard controller reset
-                    Port::<u8>::new(0x64).write(0xFE);
+                // -f skips writing anything back to disk
+                if parts.get(1) != Some(&"-f") {
+                    fs::save_to_disk();
+                    ata::flush_all_caches();
                 }
+                self.print("Rebooting...\n");
+                crate::acpi::reboot();
             },
             "goto" => {
                 if parts.len() < 2 { self.print("Usage: goto <url>\n"); }
@@ -361,7 +1134,15 @@ impl Shell {
                             win.print(&format!("ADDRESS: {}\n", url));
                             win.print("--------------------------\n\n");
                             win.print("Status: Resolving host...\n");
-                            for _ in 0..200000 { core::hint::spin_loop(); }
+                            let host = url.trim_start_matches("http://").trim_start_matches("https://");
+                            let host = host.split('/').next().unwrap_or(host);
+                            match parse_ip(host).or_else(|| crate::net::dns_query(host)) {
+                                Some(ip) => win.print(&format!("Status: {} is {}.{}.{}.{}\n", host, ip[0], ip[1], ip[2], ip[3])),
+                                None => {
+                                    win.print(&format!("Error: Could not resolve {}\n", host));
+                                    continue;
+                                }
+                            }
                             win.print("Status: Connecting...\n");
                             for _ in 0..200000 { core::hint::spin_loop(); }
                             win.print("Status: Fetching HTML...\n");
@@ -375,45 +1156,67 @@ impl Shell {
                 }
             },
             "ls" => {
-                if let Some(items) = fs::ls(&self.current_dir) {
+                let long = parts.get(1) == Some(&"-l");
+                let cwd = self.cwd();
+                if let Some(items) = fs::ls(&cwd) {
                     for (name, is_dir) in items {
-                        if is_dir {
-                            self.print(&format!("[DIR]  {}\n", name));
+                        let info = fs::get_node_info(&cwd, &name);
+                        let link = info.as_ref().and_then(|i| i.link_target.clone());
+                        if long {
+                            let (size, modified, perms) = info.map(|i| (i.size, i.modified_ticks, i.permissions)).unwrap_or((0, 0, 0));
+                            let mut mode = fs::permission_string(is_dir, perms);
+                            let mut shown = name;
+                            if let Some(target) = link {
+                                mode.replace_range(0..1, "l");
+                                shown = format!("{} -> {}", shown, target);
+                            }
+                            self.print(&format!("{} {:8}  {}  {}\n", mode, size, ticks_to_clock(modified), shown));
+                        } else if let Some(target) = link {
+                            self.print(&format!("[LINK] {} -> {}\n", name, target));
                         } else {
-                            self.print(&format!("[FILE] {}\n", name));
+                            let kind = if is_dir { "[DIR] " } else { "[FILE]" };
+                            self.print(&format!("{} {}\n", kind, name));
                         }
                     }
                 } else {
                     self.print("Error: Could not list directory.\n");
                 }
             },
+            "chmod" => {
+                if parts.len() < 3 {
+                    self.print("Usage: chmod <mode> <name>\n");
+                } else {
+                    match u16::from_str_radix(parts[1], 8) {
+                        Ok(mode) if mode <= 0o777 => {
+                            if fs::set_permissions(&self.cwd(), parts[2], mode) {
+                                fs::save_to_disk();
+                            } else {
+                                self.print("Error: File not found.\n");
+                                self.last_exit = 1;
+                            }
+                        }
+                        _ => {
+                            self.print("Error: Mode must be octal, e.g. 755.\n");
+                            self.last_exit = 1;
+                        }
+                    }
+                }
+            },
             "cd" => {
                 if parts.len() < 2 {
                     self.print("Usage: cd <path>\n");
                 } else {
                     let path = parts[1];
-                    if path == ".." {
-                        if self.current_dir != "/" {
-                            if let Some(idx) = self.current_dir.trim_end_matches('/').rfind('/') {
-                                self.current_dir = self.current_dir[..idx+1].to_string();
-                                if self.current_dir.len() > 1 {
-                                    self.current_dir.pop();
-                                }
-                            }
-                        }
-                    } else if path == "/" {
-                        self.current_dir = "/".to_string();
+                    let new_path = if path.starts_with('/') {
+                        fs::normalize_path(path)
                     } else {
-                        let new_path = if self.current_dir == "/" {
-                            format!("/{}", path)
-                        } else {
-                            format!("{}/{}", self.current_dir, path)
-                        };
-                        if fs::ls(&new_path).is_some() {
-                            self.current_dir = new_path;
-                        } else {
-                            self.print("Error: Directory not found.\n");
-                        }
+                        fs::normalize_path(&format!("{}/{}", self.current_dir, path))
+                    };
+                    if fs::ls(&self.resolve(&new_path)).is_some() {
+                        self.current_dir = new_path;
+                    } else {
+                        self.print("Error: Directory not found.\n");
+                        self.last_exit = 1;
                     }
                 }
             },
@@ -421,7 +1224,7 @@ impl Shell {
                 if parts.len() < 2 {
                     self.print("Usage: mkdir <name>\n");
                 } else {
-                    if fs::mkdir(&self.current_dir, parts[1]) {
+                    if fs::mkdir(&self.cwd(), parts[1]) {
                         self.print(&format!("Directory '{}' created.\n", parts[1]));
                         fs::save_to_disk();
                     } else {
@@ -430,25 +1233,34 @@ impl Shell {
                 }
             },
             "rm" => {
-                if parts.len() < 2 {
-                    self.print("Usage: rm <name>\n");
-                } else {
-                    if fs::rm(&self.current_dir, parts[1]) {
-                        self.print(&format!("Removed '{}'.\n", parts[1]));
-                        fs::save_to_disk();
-                    } else {
-                        self.print("Error: Could not remove item.\n");
+                let recursive = parts.get(1) == Some(&"-r");
+                let target = if recursive { parts.get(2) } else { parts.get(1) };
+                match target {
+                    None => self.print("Usage: rm [-r] <name>\n"),
+                    Some(name) => {
+                        let ok = if recursive { fs::rm_recursive(&self.cwd(), name) } else { fs::rm(&self.cwd(), name) };
+                        if ok {
+                            self.print(&format!("Removed '{}'.\n", name));
+                            fs::save_to_disk();
+                        } else if !recursive && fs::get_node_info(&self.cwd(), name).is_some_and(|i| i.is_dir) {
+                            self.print(&format!("Error: '{}' is a non-empty directory (use rm -r).\n", name));
+                            self.last_exit = 1;
+                        } else {
+                            self.print("Error: Could not remove item.\n");
+                            self.last_exit = 1;
+                        }
                     }
                 }
             },
             "cat" => {
-                if parts.len() < 2 {
+                if parts.len() < 2 && self.ctx.input.is_none() {
                     self.print("Usage: cat <file>\n");
                 } else {
-                    if let Some(data) = fs::read(&self.current_dir, parts[1]) {
+                    let piped = self.ctx.input.is_some();
+                    if let Some(data) = self.read_input(parts.get(1).copied()) {
                         if let Ok(s) = String::from_utf8(data) {
                             self.print(&s);
-                            self.print("\n");
+                            if !piped { self.print("\n"); }
                         } else {
                             self.print("[Binary Data]\n");
                         }
@@ -462,7 +1274,7 @@ impl Shell {
                     self.print("Usage: write <file> <text>\n");
                 } else {
                     let text = parts[2..].join(" ");
-                    if fs::touch(&self.current_dir, parts[1], text.into_bytes()) {
+                    if fs::touch(&self.cwd(), parts[1], text.into_bytes()) {
                         self.print(&format!("File '{}' written.\n", parts[1]));
                         fs::save_to_disk();
                     } else {
@@ -471,11 +1283,11 @@ impl Shell {
                 }
             },
             "grep" => {
-                if parts.len() < 3 {
+                if parts.len() < 2 || (parts.len() < 3 && self.ctx.input.is_none()) {
                     self.print("Usage: grep <pattern> <file>\n");
                 } else {
                     let pattern = parts[1];
-                    if let Some(data) = fs::read(&self.current_dir, parts[2]) {
+                    if let Some(data) = self.read_input(parts.get(2).copied()) {
                         if let Ok(s) = String::from_utf8(data) {
                             for line in s.lines() {
                                 if line.contains(pattern) {
@@ -495,7 +1307,7 @@ impl Shell {
                 if parts.len() < 2 {
                     self.print("Usage: touch <file>\n");
                 } else {
-                    if fs::touch(&self.current_dir, parts[1], Vec::new()) {
+                    if fs::touch(&self.cwd(), parts[1], Vec::new()) {
                         self.print(&format!("File '{}' created.\n", parts[1]));
                         fs::save_to_disk();
                     } else {
@@ -506,23 +1318,104 @@ impl Shell {
             "pwd" => {
                 self.print(&format!("{}\n", self.current_dir));
             },
-            "cp" => {
+            "ln" => {
                 if parts.len() < 3 {
-                    self.print("Usage: cp <src> <dest>\n");
+                    self.print("Usage: ln <target> <link>\n");
                 } else {
-                    if fs::copy_node(&self.current_dir, parts[1], &self.current_dir, parts[2]) {
-                        self.print(&format!("Copied '{}' to '{}'.\n", parts[1], parts[2]));
+                    let target = self.real_path(parts[1]);
+                    let (dir, name) = self.locate(parts[2]);
+                    if fs::symlink(&dir, &name, &target) {
+                        self.print(&format!("Linked '{}' -> {}\n", parts[2], target));
                         fs::save_to_disk();
                     } else {
-                        self.print("Error: Could not copy.\n");
+                        self.print(&format!("Error: Could not create link '{}'.\n", parts[2]));
+                        self.last_exit = 1;
                     }
                 }
             },
+            "cp" => {
+                let recursive = parts.get(1) == Some(&"-r");
+                let args = if recursive { &parts[2..] } else { &parts[1..] };
+                if args.len() < 2 {
+                    self.print("Usage: cp [-r] <src> <dest>\n");
+                } else {
+                    let ok = if recursive {
+                        fs::cp_recursive(&self.cwd(), args[0], &self.cwd(), args[1])
+                    } else {
+                        fs::copy_node(&self.cwd(), args[0], &self.cwd(), args[1])
+                    };
+                    if ok {
+                        self.print(&format!("Copied '{}' to '{}'.\n", args[0], args[1]));
+                        fs::save_to_disk();
+                    } else if !recursive && fs::get_node_info(&self.cwd(), args[0]).is_some_and(|i| i.is_dir) {
+                        self.print(&format!("Error: '{}' is a directory (use cp -r).\n", args[0]));
+                        self.last_exit = 1;
+                    } else {
+                        self.print("Error: Could not copy.\n");
+                        self.last_exit = 1;
+                    }
+                }
+            },
+            "sync" => {
+                let delete = parts.contains(&"-delete");
+                let dirs: Vec<&str> = parts[1..].iter().copied().filter(|p| *p != "-delete").collect();
+                if dirs.len() < 2 {
+                    self.print("Usage: sync [-delete] <src_dir> <dst_dir>\n");
+                } else {
+                    let src = self.real_path(dirs[0]);
+                    let dst = self.real_path(dirs[1]);
+                    if fs::ls(&src).is_none() || fs::ls(&dst).is_none() {
+                        self.print("Error: Directory not found.\n");
+                        self.last_exit = 1;
+                    } else if dst == src || dst.starts_with(&format!("{}/", src)) || src == "/" {
+                        self.print("Error: Destination is inside source.\n");
+                        self.last_exit = 1;
+                    } else {
+                        self.sync_dir(&src, &dst, delete);
+                        fs::save_to_disk();
+                    }
+                }
+            },
+            "compress" => {
+                if parts.len() < 2 {
+                    self.print("Usage: compress <file>\n");
+                } else if let Some(data) = fs::read(&self.cwd(), parts[1]) {
+                    let packed = lz::compress(&data);
+                    let out_name = format!("{}.lz", parts[1]);
+                    self.print(&format!("{}: {} -> {} bytes\n", out_name, data.len(), packed.len()));
+                    fs::touch(&self.cwd(), &out_name, packed);
+                    fs::save_to_disk();
+                } else {
+                    self.print("Error: File not found.\n");
+                    self.last_exit = 1;
+                }
+            },
+            "decompress" => {
+                if parts.len() < 2 || !parts[1].ends_with(".lz") {
+                    self.print("Usage: decompress <file.lz>\n");
+                } else if let Some(data) = fs::read(&self.cwd(), parts[1]) {
+                    match lz::decompress(&data) {
+                        Some(plain) => {
+                            let out_name = parts[1].trim_end_matches(".lz");
+                            self.print(&format!("{}: {} -> {} bytes\n", out_name, data.len(), plain.len()));
+                            fs::touch(&self.cwd(), out_name, plain);
+                            fs::save_to_disk();
+                        }
+                        None => {
+                            self.print("Error: Corrupt archive.\n");
+                            self.last_exit = 1;
+                        }
+                    }
+                } else {
+                    self.print("Error: File not found.\n");
+                    self.last_exit = 1;
+                }
+            },
             "mv" => {
                 if parts.len() < 3 {
                     self.print("Usage: mv <src> <dest>\n");
                 } else {
-                    if fs::move_node(&self.current_dir, parts[1], &self.current_dir, parts[2]) {
+                    if fs::move_node(&self.cwd(), parts[1], &self.cwd(), parts[2]) {
                         self.print(&format!("Moved '{}' to '{}'.\n", parts[1], parts[2]));
                         fs::save_to_disk();
                     } else {
@@ -531,20 +1424,34 @@ impl Shell {
                 }
             },
             "find" => {
-                if parts.len() < 2 {
-                    self.print("Usage: find <pattern>\n");
+                let max_depth = match parts.iter().position(|p| *p == "-maxdepth") {
+                    Some(i) => match parts.get(i + 1).and_then(|n| n.parse::<usize>().ok()) {
+                        Some(n) => Some(n),
+                        None => { self.print("Usage: find <pattern> [-maxdepth <n>]\n"); return; }
+                    },
+                    None => None,
+                };
+                if parts.len() < 2 || parts[1] == "-maxdepth" {
+                    self.print("Usage: find <pattern> [-maxdepth <n>]\n");
                 } else {
                     let pattern = parts[1];
-                    fs::walk_tree("/", |path, node| {
+                    let root = self.fs_root.clone();
+                    let mut hits = Vec::new();
+                    fs::walk_tree(&root, max_depth, |path, node| {
                         if node.name().contains(pattern) {
-                            self.print(&format!("{}\n", path));
+                            // Report paths as seen from inside the chroot
+                            let view = if root == "/" { path } else { &path[root.len()..] };
+                            hits.push(if view.is_empty() { "/".to_string() } else { view.to_string() });
                         }
                     });
+                    for hit in hits {
+                        self.print(&format!("{}\n", hit));
+                    }
                 }
             },
             "du" => {
                 let mut total_size = 0;
-                fs::walk_tree(&self.current_dir, |_, node| {
+                fs::walk_tree(&self.cwd(), None, |_, node| {
                     if let fs::Node::File { data, .. } = node {
                         total_size += data.len();
                     }
@@ -555,28 +1462,56 @@ impl Shell {
                 if parts.len() < 2 {
                     self.print("Usage: stat <file>\n");
                 } else {
-                    if let Some(info) = fs::get_node_info(&self.current_dir, parts[1]) {
+                    if let Some(info) = fs::get_node_info(&self.cwd(), parts[1]) {
                         self.print(&format!("Name: {}\n", info.name));
-                        self.print(&format!("Type: {}\n", if info.is_dir { "Directory" } else { "File" }));
+                        if let Some(target) = &info.link_target {
+                            self.print(&format!("Type: Symlink -> {}\n", target));
+                        } else {
+                            self.print(&format!("Type: {}\n", if info.is_dir { "Directory" } else { "File" }));
+                        }
                         if !info.is_dir {
                             self.print(&format!("Size: {} bytes\n", info.size));
                         } else {
                             self.print(&format!("Children: {}\n", info.child_count));
                         }
+                        self.print(&format!("Created:  {} (tick {})\n", ticks_to_clock(info.created_ticks), info.created_ticks));
+                        self.print(&format!("Modified: {} (tick {})\n", ticks_to_clock(info.modified_ticks), info.modified_ticks));
                     } else {
                         self.print("Error: Not found.\n");
                     }
                 }
             },
+            "tail" | "head" if parts.contains(&"-f") => {
+                // Last lines now, then new bytes every tick until a key is pressed
+                let (file, n) = head_tail_args(&parts);
+                let data = match file {
+                    Some(f) => fs::read(&self.cwd(), f),
+                    None => { self.print("Usage: tail -f <file> [-n lines]\n"); return; }
+                };
+                let data = match data {
+                    Some(d) => d,
+                    None => {
+                        self.print("Error: File not found.\n");
+                        self.last_exit = 1;
+                        return;
+                    }
+                };
+                let text = String::from_utf8_lossy(&data);
+                let lines: Vec<&str> = text.lines().collect();
+                for line in &lines[lines.len().saturating_sub(n)..] {
+                    self.print(line);
+                    self.print("\n");
+                }
+                if self.ctx.output.is_none() {
+                    self.tail_watching = file.map(|f| (f.to_string(), data.len()));
+                }
+            },
             "head" => {
-                if parts.len() < 2 {
+                let (file, n) = head_tail_args(&parts);
+                if file.is_none() && self.ctx.input.is_none() {
                     self.print("Usage: head <file> [-n lines]\n");
                 } else {
-                    let mut n = 10;
-                    if parts.len() > 3 && parts[2] == "-n" {
-                        n = parts[3].parse().unwrap_or(10);
-                    }
-                    if let Some(data) = fs::read(&self.current_dir, parts[1]) {
+                    if let Some(data) = self.read_input(file) {
                         if let Ok(s) = String::from_utf8(data) {
                             for line in s.lines().take(n) {
                                 self.print(line);
@@ -588,15 +1523,51 @@ impl Shell {
                     }
                 }
             },
+            "hexdump" | "xxd" => {
+                let mut file = None;
+                let mut limit = usize::MAX;
+                let mut i = 1;
+                while i < parts.len() {
+                    if parts[i] == "-n" && i + 1 < parts.len() {
+                        limit = parts[i + 1].parse().unwrap_or(usize::MAX);
+                        i += 2;
+                    } else {
+                        file = Some(parts[i]);
+                        i += 1;
+                    }
+                }
+                if file.is_none() && self.ctx.input.is_none() {
+                    self.print(&format!("Usage: {} <file> [-n bytes]\n", parts[0]));
+                } else if let Some(data) = self.read_input(file) {
+                    let data = &data[..data.len().min(limit)];
+                    let mut out = String::new();
+                    for (row, chunk) in data.chunks(16).enumerate() {
+                        // Offset | 16 hex bytes (padded on a short last row) | ASCII
+                        out.push_str(&format!("{:08x}  ", row * 16));
+                        for col in 0..16 {
+                            match chunk.get(col) {
+                                Some(b) => out.push_str(&format!("{:02x} ", b)),
+                                None => out.push_str("   "),
+                            }
+                        }
+                        out.push(' ');
+                        for &b in chunk {
+                            out.push(if (0x20..0x7F).contains(&b) { b as char } else { '.' });
+                        }
+                        out.push('\n');
+                    }
+                    self.print(&out);
+                } else {
+                    self.print("Error: File not found.\n");
+                    self.last_exit = 1;
+                }
+            },
             "tail" => {
-                if parts.len() < 2 {
+                let (file, n) = head_tail_args(&parts);
+                if file.is_none() && self.ctx.input.is_none() {
                     self.print("Usage: tail <file> [-n lines]\n");
                 } else {
-                    let mut n = 10;
-                    if parts.len() > 3 && parts[2] == "-n" {
-                        n = parts[3].parse().unwrap_or(10);
-                    }
-                    if let Some(data) = fs::read(&self.current_dir, parts[1]) {
+                    if let Some(data) = self.read_input(file) {
                         if let Ok(s) = String::from_utf8(data) {
                             let lines: Vec<&str> = s.lines().collect();
                             let start = if lines.len() > n { lines.len() - n } else { 0 };
@@ -610,21 +1581,316 @@ impl Shell {
                     }
                 }
             },
-            "wc" => {
-                if parts.len() < 2 {
-                    self.print("Usage: wc <file>\n");
+            "base64" => {
+                let decode = parts.contains(&"-d");
+                let redirect = parts.iter().position(|p| *p == ">");
+                let args = &parts[1..redirect.unwrap_or(parts.len())];
+                let file = args.iter().copied().find(|a| *a != "-d");
+                let target = redirect.and_then(|i| parts.get(i + 1).copied());
+
+                if (file.is_none() && self.ctx.input.is_none()) || (redirect.is_some() && target.is_none()) {
+                    self.print("Usage: base64 [-d] <file> [> out]\n");
+                } else if let Some(data) = self.read_input(file) {
+                    let result = if decode {
+                        base64::decode(&String::from_utf8_lossy(&data))
+                    } else {
+                        let mut text = base64::encode(&data);
+                        text.push('\n');
+                        Ok(text.into_bytes())
+                    };
+                    match (result, target) {
+                        (Ok(bytes), Some(out)) => {
+                            if fs::touch(&self.cwd(), out, bytes) {
+                                fs::save_to_disk();
+                            } else {
+                                self.print("Error: Could not write to file.\n");
+                                self.last_exit = 1;
+                            }
+                        }
+                        (Ok(bytes), None) => match String::from_utf8(bytes) {
+                            Ok(text) => self.print(&text),
+                            Err(e) => self.print(&format!("[Binary data, {} bytes]\n", e.as_bytes().len())),
+                        },
+                        (Err(e), _) => {
+                            self.print(&format!("Error: Invalid base64: {}.\n", e));
+                            self.last_exit = 1;
+                        }
+                    }
                 } else {
-                    if let Some(data) = fs::read(&self.current_dir, parts[1]) {
-                        let bytes = data.len();
-                        if let Ok(s) = String::from_utf8(data) {
-                            let lines = s.lines().count();
-                            let words = s.split_whitespace().count();
-                            self.print(&format!("{} {} {} {}\n", lines, words, bytes, parts[1]));
+                    self.print("Error: File not found.\n");
+                    self.last_exit = 1;
+                }
+            },
+            "tar" => {
+                let cwd = self.cwd();
+                match (parts.get(1).copied(), parts.get(2).copied()) {
+                    (Some("c"), Some(archive)) if parts.len() > 3 => {
+                        let mut files = Vec::new();
+                        for name in &parts[3..] {
+                            match fs::read(&cwd, name) {
+                                Some(data) => files.push((name.to_string(), data)),
+                                None => {
+                                    self.print(&format!("Error: '{}' not found.\n", name));
+                                    self.last_exit = 1;
+                                    return;
+                                }
+                            }
+                        }
+                        let count = files.len();
+                        if fs::touch(&cwd, archive, tar::create(files)) {
+                            fs::save_to_disk();
+                            self.print(&format!("Archived {} file(s) into {}\n", count, archive));
                         } else {
-                            self.print(&format!("- - {} {}\n", bytes, parts[1]));
+                            self.print("Error: Could not write to file.\n");
+                            self.last_exit = 1;
+                        }
+                    }
+                    (Some("x"), Some(archive)) => match fs::read(&cwd, archive) {
+                        Some(data) => {
+                            let files = tar::extract(&data);
+                            for (name, contents) in files.iter() {
+                                // Entries land flat in the current directory
+                                let base = name.rsplit('/').next().unwrap_or(name);
+                                if base.is_empty() || !fs::touch(&cwd, base, contents.clone()) {
+                                    self.print(&format!("Error: Could not write '{}'.\n", name));
+                                    self.last_exit = 1;
+                                } else {
+                                    self.print(&format!("{}\n", base));
+                                }
+                            }
+                            fs::save_to_disk();
                         }
+                        None => {
+                            self.print("Error: File not found.\n");
+                            self.last_exit = 1;
+                        }
+                    },
+                    _ => self.print("Usage: tar c <archive.tar> <file...> | tar x <archive.tar>\n"),
+                }
+            },
+            "diff" => {
+                let unified = parts.contains(&"-u");
+                let files: Vec<&str> = parts[1..].iter().copied().filter(|a| *a != "-u").collect();
+                if files.len() != 2 {
+                    self.print("Usage: diff [-u] <file1> <file2>\n");
+                } else {
+                    let cwd = self.cwd();
+                    match (fs::read(&cwd, files[0]), fs::read(&cwd, files[1])) {
+                        (Some(a), Some(b)) => {
+                            let (a, b) = (String::from_utf8_lossy(&a), String::from_utf8_lossy(&b));
+                            let a_lines: Vec<&str> = a.lines().collect();
+                            let b_lines: Vec<&str> = b.lines().collect();
+                            if a_lines.len() > diff::MAX_LINES || b_lines.len() > diff::MAX_LINES {
+                                self.print(&format!("Error: diff is limited to {} lines per file.\n", diff::MAX_LINES));
+                                self.last_exit = 1;
+                            } else {
+                                let result = diff::diff(&a_lines, &b_lines);
+                                let same = result.iter().all(|l| matches!(l, diff::DiffLine::Equal(_)));
+                                if unified {
+                                    self.print(&diff::format_unified(&result, files[0], files[1]));
+                                } else if !same {
+                                    self.print(&diff::format_normal(&result));
+                                }
+                                // Like diff(1): exit status 1 when the files differ
+                                if !same { self.last_exit = 1; }
+                            }
+                        }
+                        _ => {
+                            self.print("Error: File not found.\n");
+                            self.last_exit = 1;
+                        }
+                    }
+                }
+            },
+            "smartctl" => {
+                let drive = ata::AtaDrive::new(true);
+                if let Some(data) = drive.smart_read_data() {
+                    let mut out = String::from("ID  ATTRIBUTE                VALUE WORST RAW\n");
+                    for attr in ata::AtaDrive::parse_smart(&data) {
+                        out.push_str(&format!("{:<3} {:<24} {:>5} {:>5} {}\n", attr.id, attr.name, attr.value, attr.worst, attr.raw));
+                    }
+                    self.print(&out);
+                    if data[ata::SMART_STATUS_OFFSET] & 0x08 != 0 {
+                        self.print("WARNING: Drive pre-failure condition\n");
+                    }
+                } else {
+                    self.print("Error: SMART data unavailable.\n");
+                    self.last_exit = 1;
+                }
+            },
+            "wc" => {
+                let mut file = None;
+                let (mut lines_f, mut words_f, mut bytes_f) = (false, false, false);
+                for arg in &parts[1..] {
+                    if arg.starts_with('-') && arg.len() > 1 {
+                        lines_f |= arg.contains('l');
+                        words_f |= arg.contains('w');
+                        bytes_f |= arg.contains('c');
                     } else {
-                        self.print("Error: File not found.\n");
+                        file = Some(*arg);
+                    }
+                }
+                // No flags: all three counts
+                if !(lines_f || words_f || bytes_f) {
+                    (lines_f, words_f, bytes_f) = (true, true, true);
+                }
+
+                if file.is_none() && self.ctx.input.is_none() {
+                    self.print("Usage: wc [-l] [-w] [-c] <file>\n");
+                } else if let Some(data) = self.read_input(file) {
+                    let bytes = data.len();
+                    let text = String::from_utf8(data).ok();
+                    let mut fields = Vec::new();
+                    // Counts that need text print "-" for binary data
+                    let count = |f: fn(&str) -> usize| text.as_deref().map_or(String::from("-"), |s| f(s).to_string());
+                    if lines_f { fields.push(count(|s| s.lines().count())); }
+                    if words_f { fields.push(count(|s| s.split_whitespace().count())); }
+                    if bytes_f { fields.push(bytes.to_string()); }
+                    if let Some(name) = file { fields.push(name.to_string()); }
+                    self.print(&format!("{}\n", fields.join(" ")));
+                } else {
+                    self.print("Error: File not found.\n");
+                    self.last_exit = 1;
+                }
+            },
+            "sort" => {
+                let mut file = None;
+                let (mut reverse, mut fold) = (false, false);
+                for arg in &parts[1..] {
+                    if arg.starts_with('-') && arg.len() > 1 {
+                        reverse |= arg.contains('r');
+                        fold |= arg.contains('i');
+                    } else {
+                        file = Some(*arg);
+                    }
+                }
+                if file.is_none() && self.ctx.input.is_none() {
+                    self.print("Usage: sort [-r] [-i] <file>\n");
+                } else if let Some(data) = self.read_input(file) {
+                    let s = String::from_utf8_lossy(&data);
+                    let mut lines: Vec<&str> = s.lines().collect();
+                    if fold {
+                        lines.sort_by_key(|l| l.to_lowercase());
+                    } else {
+                        lines.sort();
+                    }
+                    if reverse { lines.reverse(); }
+                    let mut out = String::new();
+                    for line in lines {
+                        out.push_str(line);
+                        out.push('\n');
+                    }
+                    self.print(&out);
+                } else {
+                    self.print("Error: File not found.\n");
+                    self.last_exit = 1;
+                }
+            },
+            "uniq" => {
+                if parts.len() < 2 && self.ctx.input.is_none() {
+                    self.print("Usage: uniq <file>\n");
+                } else if let Some(data) = self.read_input(parts.get(1).copied()) {
+                    // Only consecutive repeats collapse, so `sort | uniq` removes every duplicate
+                    let s = String::from_utf8_lossy(&data);
+                    let mut out = String::new();
+                    let mut prev: Option<&str> = None;
+                    for line in s.lines() {
+                        if prev != Some(line) {
+                            out.push_str(line);
+                            out.push('\n');
+                        }
+                        prev = Some(line);
+                    }
+                    self.print(&out);
+                } else {
+                    self.print("Error: File not found.\n");
+                    self.last_exit = 1;
+                }
+            },
+            "export" => {
+                // `export KEY=VALUE ...` sets; a bare `export` lists like `env`, plus $PWD
+                if parts.len() > 1 {
+                    for arg in split_quoted(cmd).iter().skip(1) {
+                        match arg.split_once('=') {
+                            Some((key, value)) if is_var_name(key) => self.set_var(key, value),
+                            None if is_var_name(arg) => {
+                                if self.get_var(arg).is_none() { self.set_var(arg, ""); }
+                            }
+                            _ => {
+                                self.print(&format!("export: '{}': not a valid identifier\n", arg));
+                                self.last_exit = 1;
+                            }
+                        }
+                    }
+                    return;
+                }
+                let mut vars = alloc::vec![("PWD".to_string(), self.current_dir.clone())];
+                vars.extend(self.env.iter().cloned());
+                vars.sort();
+                for (k, v) in vars {
+                    self.print(&format!("{}={}\n", k, v));
+                }
+            },
+            "env" => {
+                for (k, v) in self.env.clone() {
+                    self.print(&format!("{}={}\n", k, v));
+                }
+            },
+            "unset" => {
+                match parts.get(1) {
+                    Some(name) => self.env.retain(|(k, _)| k != name),
+                    None => self.print("Usage: unset <VAR>\n"),
+                }
+            },
+            "calc" => {
+                let expr = cmd.trim_start()["calc".len()..].trim();
+                if expr.is_empty() {
+                    self.print("Usage: calc <expr>\n");
+                    return;
+                }
+                match calc::eval(expr) {
+                    Ok(value) => self.print(&format!("{}\n", value)),
+                    Err(e) => {
+                        self.print(&format!("Error: {}\n", e));
+                        self.last_exit = 1;
+                    }
+                }
+            },
+            "awk" => {
+                let args = split_quoted(cmd);
+                if args.len() < 2 || (args.len() < 3 && self.ctx.input.is_none()) {
+                    self.print("Usage: awk <program> <file>\n");
+                } else {
+                    match awk::AwkProgram::parse(&args[1]) {
+                        Ok(program) => match self.read_input(args.get(2).map(|s| s.as_str())) {
+                            Some(data) => {
+                                let text = String::from_utf8_lossy(&data);
+                                self.print(&program.run(&text));
+                            }
+                            None => {
+                                self.print("Error: File not found.\n");
+                                self.last_exit = 1;
+                            }
+                        },
+                        Err(e) => {
+                            self.print(&format!("awk: {}\n", e));
+                            self.last_exit = 2;
+                        }
+                    }
+                }
+            },
+            "printf" => {
+                let args = split_quoted(cmd);
+                if args.len() < 2 {
+                    self.print("Usage: printf <format> [args...]\n");
+                    self.last_exit = 1;
+                } else {
+                    match format_printf(&args[1], &args[2..]) {
+                        Ok(text) => self.print(&text),
+                        Err(arg) => {
+                            self.print(&format!("Error: printf: '{}' is not a number.\n", arg));
+                            self.last_exit = 1;
+                        }
                     }
                 }
             },
@@ -647,14 +1913,14 @@ impl Shell {
                         let text = parts[1..idx].join(" ");
                         let filename = parts[idx+1];
                         let mut final_data = if append {
-                            fs::read(&self.current_dir, filename).unwrap_or_default()
+                            fs::read(&self.cwd(), filename).unwrap_or_default()
                         } else {
                             Vec::new()
                         };
                         final_data.extend_from_slice(text.as_bytes());
                         final_data.push(b'\n');
                         
-                        if fs::touch(&self.current_dir, filename, final_data) {
+                        if fs::touch(&self.cwd(), filename, final_data) {
                             fs::save_to_disk();
                         } else {
                             self.print("Error: Could not write to file.\n");
@@ -675,51 +1941,253 @@ impl Shell {
                     self.print("Error: Maximum window limit reached.\n");
                     return;
                 }
-                let mut win = compositor::Window::new(300, 100, 400, 500, "System Monitor");
-                self.windows.push(win);
-                self.active_idx = self.windows.len() - 1;
+                let win = compositor::Window::new(300, 100, 400, 500, "System Monitor");
+                self.open_window(win);
+            },
+            "ps" => {
+                // -a also lists tasks sitting out a penalty cooldown
+                let all = parts.get(1) == Some(&"-a");
+                let tasks: Vec<(usize, String, &'static str, u64, u64, u32)> = x86_64::instructions::interrupts::without_interrupts(|| {
+                    let sched = scheduler::SCHEDULER.lock();
+                    sched.tasks.iter().enumerate()
+                        .filter(|(_, t)| all || t.status != scheduler::TaskStatus::Penalty)
+                        .map(|(i, t)| (i, t.name.clone(), t.status.label(), t.budget, t.last_cost, t.violation_count))
+                        .collect()
+                });
+                self.print("PID  NAME            STATUS  BUDGET(us)  LAST_MS  VIOLATIONS\n");
+                for (pid, name, status, budget, last, violations) in tasks {
+                    self.print(&format!("{:3}  {:14}  {:6}  {:10}  {:7}  {:10}\n",
+                        pid, name, status, crate::time::cycles_to_us(budget), crate::time::cycles_to_ms(last), violations));
+                }
+            },
+            "screenshot" => {
+                if parts.len() < 2 {
+                    self.print("Usage: screenshot <file.ppm>\n");
+                    return;
+                }
+                let width = state::SCREEN_WIDTH.load(Ordering::Relaxed);
+                let height = state::SCREEN_HEIGHT.load(Ordering::Relaxed);
+                let ppm = {
+                    let frame = state::LAST_FRAME.lock();
+                    if frame.len() < width * height { None } else { Some(compositor::encode_ppm(&frame, width, height)) }
+                };
+                match ppm {
+                    Some(bytes) => {
+                        let size = bytes.len();
+                        if fs::touch(&self.cwd(), parts[1], bytes) {
+                            fs::save_to_disk();
+                            self.print(&format!("Saved {}x{} screenshot to {} ({} bytes)\n", width, height, parts[1], size));
+                        } else {
+                            self.print("Error: Could not write to file.\n");
+                            self.last_exit = 1;
+                        }
+                    }
+                    None => {
+                        self.print("Error: No frame has been drawn yet.\n");
+                        self.last_exit = 1;
+                    }
+                }
+            },
+            "lspci" => {
+                let mut out = String::new();
+                for dev in pci::scan_bus() {
+                    out.push_str(&format!("{:02x}:{:02x}.{} {} {:04x} [{}]",
+                        dev.bus, dev.device, dev.function, pci::lookup_vendor(dev.vendor_id),
+                        dev.device_id, pci::lookup_class(dev.class, dev.subclass)));
+                    if dev.prog_if != 0 { out.push_str(&format!(" (prog-if {:02x})", dev.prog_if)); }
+                    out.push_str(&format!(" (rev {:02x})\n", dev.revision));
+                    for (i, bar) in pci::read_bars(&dev) {
+                        let desc = match bar {
+                            pci::Bar::Io(port) => format!("I/O ports at {:#x}", port),
+                            pci::Bar::Mmio32(addr) => format!("Memory at {:#x} (32-bit)", addr),
+                            pci::Bar::Mmio64(addr) => format!("Memory at {:#x} (64-bit)", addr),
+                        };
+                        out.push_str(&format!("    BAR{}: {}\n", i, desc));
+                    }
+                }
+                self.print(&out);
             },
             "net" => {
+                match parts.get(1).copied() {
+                    Some("stat") => {
+                        let stats = nic::NIC.lock().as_mut().map(|nic| (nic.name(), nic.read_stats()));
+                        match stats {
+                            Some((_, Some(s))) => {
+                                self.print("            RX          TX\n");
+                                self.print(&format!("Packets  {:10}  {:10}\n", s.rx_packets, s.tx_packets));
+                                self.print(&format!("Bytes    {:10}  {:10}\n", s.rx_bytes, s.tx_bytes));
+                                self.print(&format!("Errors   {:10}  {:10}\n", s.rx_errors, s.tx_errors));
+                            }
+                            Some((name, None)) => {
+                                self.print(&format!("Error: The {} driver keeps no counters.\n", name));
+                                self.last_exit = 1;
+                            }
+                            None => {
+                                self.print("Error: Network not initialized (run 'net').\n");
+                                self.last_exit = 1;
+                            }
+                        }
+                        return;
+                    }
+                    Some("mac") => {
+                        if nic::NIC.lock().as_ref().map(|nic| nic.log_mac()).is_none() {
+                            self.print("Error: Network not initialized (run 'net').\n");
+                            self.last_exit = 1;
+                        }
+                        return;
+                    }
+                    Some("ip") => return self.execute_line("ip"),
+                    _ => {}
+                }
                 self.print("Initializing Network...\n");
-                let devices = pci::scan_bus();
-                for dev in devices {
-                    if dev.vendor_id == 0x10EC && dev.device_id == 0x8139 {
-                        pci::enable_bus_mastering(dev.clone());
-                        let mut driver = rtl8139::Rtl8139::new(dev);
-                        driver.send_dhcp_discover();
-                        loop {
+                // The card is shared from here on: DHCP and the TCP stack both go through it
+                match nic::with_nic(|nic| (nic.name(), nic.mac_addr)) {
+                    Some((name, mac)) => {
+                        self.print(&format!("{} NIC, MAC {}\n", name, net::fmt_mac(mac)));
+                        if net::dhcp_configure() {
+                            self.print("Success!\n");
+                        } else {
+                            self.print("Error: No DHCP offer received.\n");
+                            self.last_exit = 1;
+                        }
+                    }
+                    None => {
+                        self.print("Error: No network card found.\n");
+                        self.last_exit = 1;
+                    }
+                }
+            },
+            "ping" => {
+                let found = nic::with_nic(|driver| {
+                    for i in 1..=4 {
+                        driver.send_ping(i as u16);
+                        for _ in 0..200 {
                             driver.sniff_packet();
-                            if state::get_my_ip() != [0,0,0,0] { self.print("Success!\n"); break; }
                             for _ in 0..50_000 { core::hint::spin_loop(); }
                         }
-                        break;
                     }
+                });
+                match (found, net::arp_cache_lookup(net::GATEWAY_IP)) {
+                    (None, _) => self.print("Error: No network card found.\n"),
+                    (Some(_), Some(mac)) => self.print(&format!("Gateway MAC (cached): {}\n", net::fmt_mac(mac))),
+                    (Some(_), None) => self.print("Gateway MAC: unresolved\n"),
                 }
             },
-            "ping" => {
-                let devices = pci::scan_bus();
-                for dev in devices {
-                    if dev.vendor_id == 0x10EC && dev.device_id == 0x8139 {
-                        pci::enable_bus_mastering(dev.clone());
-                        let mut driver = rtl8139::Rtl8139::new(dev);
-                        for i in 1..=4 {
-                            driver.send_ping(i as u16);
-                            for _ in 0..200 {
-                                driver.sniff_packet();
-                                for _ in 0..50_000 { core::hint::spin_loop(); }
+            "route" => {
+                match parts.get(1) {
+                    Some(&"add") => {
+                        let addrs: Vec<Option<[u8; 4]>> = parts.iter().skip(2).map(|p| parse_ip(p)).collect();
+                        match addrs.as_slice() {
+                            [Some(network), Some(mask), Some(gw)] => {
+                                net::route_add(*network, *mask, *gw, 0);
+                                self.print("Route added.\n");
                             }
+                            _ => self.print("Usage: route add <net> <mask> <gw>\n"),
                         }
                     }
+                    Some(&"show") | None => {
+                        let mut table = net::ROUTING_TABLE.lock().clone();
+                        table.sort_by_key(|r| core::cmp::Reverse(u32::from_be_bytes(r.mask)));
+                        self.print("NETWORK          MASK             GATEWAY          IFACE\n");
+                        for r in table {
+                            let gw = if r.gateway == [0, 0, 0, 0] { String::from("on-link") } else { net::fmt_ip(r.gateway) };
+                            self.print(&format!("{:16} {:16} {:16} {}\n", net::fmt_ip(r.network), net::fmt_ip(r.mask), gw, r.iface));
+                        }
+                    }
+                    _ => self.print("Usage: route show | route add <net> <mask> <gw>\n"),
+                }
+            },
+            "tcpdump" => {
+                match parts.get(1) {
+                    Some(&"on") => { net::enable_tcpdump(true); self.print("tcpdump: on\n"); }
+                    Some(&"off") => { net::enable_tcpdump(false); self.print("tcpdump: off\n"); }
+                    _ => self.print(&format!("Usage: tcpdump on|off (currently {})\n",
+                        if net::tcpdump_enabled() { "on" } else { "off" })),
+                }
+            },
+            "fetch" => {
+                let (host, port) = match (parts.get(1), parts.get(2).and_then(|p| p.parse::<u16>().ok())) {
+                    (Some(host), Some(port)) => (*host, port),
+                    _ => { self.print("Usage: fetch <ip|host> <port> [path]\n"); return; }
+                };
+                let path = parts.get(3).copied().unwrap_or("/");
+                let ip = match parse_ip(host) {
+                    Some(ip) => ip,
+                    None => match net::dns_query(host) {
+                        Some(ip) => ip,
+                        None => {
+                            self.print(&format!("Error: Could not resolve {}\n", host));
+                            self.last_exit = 1;
+                            return;
+                        }
+                    },
+                };
+                self.print(&format!("Connecting to {}:{}...\n", net::fmt_ip(ip), port));
+                let body = match net::http_get(ip, port, path) {
+                    Some(b) => b,
+                    None => {
+                        self.print("Error: Connection failed.\n");
+                        self.last_exit = 1;
+                        return;
+                    }
+                };
+                match core::str::from_utf8(&body) {
+                    Ok(text) => {
+                        self.print(text);
+                        self.print(&format!("\n[{} bytes]\n", body.len()));
+                    }
+                    // Cut mid-character by the 64 KiB cap: still text
+                    Err(e) if e.error_len().is_none() => {
+                        self.print(core::str::from_utf8(&body[..e.valid_up_to()]).unwrap_or(""));
+                        self.print(&format!("\n[{} bytes, truncated]\n", body.len()));
+                    }
+                    Err(_) => self.print(&format!("[Binary data, {} bytes]\n", body.len())),
+                }
+            },
+            "tcp_listen" => {
+                let port = match parts.get(1).and_then(|p| p.parse::<u16>().ok()) {
+                    Some(p) => p,
+                    None => { self.print("Usage: tcp_listen <port>\n"); return; }
+                };
+                if nic::with_nic(|_| ()).is_none() {
+                    self.print("Error: No network card found.\n");
+                    self.last_exit = 1;
+                    return;
+                }
+                let listener = net::TcpListener::bind(port);
+                self.print(&format!("Listening on port {} (press any key to stop)...\n", listener.port));
+                while input::pop_key().is_none() {
+                    let sock = match listener.accept() {
+                        Some(s) => s,
+                        None => continue,
+                    };
+                    if let Some((ip, p)) = sock.peer() {
+                        self.print(&format!("Connection from {}.{}.{}.{}:{}\n", ip[0], ip[1], ip[2], ip[3], p));
+                    }
+                    sock.send(b"Hello from Chronos!\r\n");
+
+                    // Echo back whatever the client sends until it hangs up
+                    for _ in 0..200 {
+                        let data = sock.recv();
+                        if !data.is_empty() {
+                            self.print(&String::from_utf8_lossy(&data));
+                            sock.send(&data);
+                        }
+                        if sock.state() != net::TcpState::Established { break; }
+                        for _ in 0..50_000 { core::hint::spin_loop(); }
+                    }
+                    sock.close();
+                    self.print("Connection closed.\n");
                 }
+                self.print("Listener stopped.\n");
             },
             "fm" | "explorer" => {
                 if self.windows.len() >= MAX_WINDOWS {
                     self.print("Error: Maximum window limit reached.\n");
                     return;
                 }
-                let mut win = compositor::Window::new(150, 150, 500, 400, "File Explorer");
-                self.windows.push(win);
-                self.active_idx = self.windows.len() - 1;
+                let win = compositor::Window::new(150, 150, 500, 400, "File Explorer");
+                self.open_window(win);
             },
             "nano" => {
                 if parts.len() < 2 {
@@ -730,25 +2198,45 @@ impl Shell {
                         return;
                     }
                     let filename = parts[1].to_string();
-                    let content = fs::read(&self.current_dir, &filename)
+                    let content = fs::read(&self.cwd(), &filename)
                         .and_then(|d| String::from_utf8(d).ok())
                         .unwrap_or_default();
                     
                     let mut win = compositor::Window::new(100, 100, 600, 450, &format!("Nano - {}", filename));
                     win.print(&content);
-                    self.windows.push(win);
-                    self.active_idx = self.windows.len() - 1;
+                    self.open_window(win);
                 }
             },
             "run" => {
                 if parts.len() < 2 { self.print("Usage: run <filename>\n"); } else {
                     if let Some(file) = fs::list_files().iter().find(|f| f.name.contains(parts[1])) {
                         self.print(&format!("Loading ELF: {}\n", file.name));
-                        elf::load_and_run(&file.data);
+                        if let Some(job) = elf::load_and_run(&file.data, &self.env).and_then(|idx| self.add_job(idx)) {
+                            self.print(&format!("[{}] UserApp\n", job));
+                        }
                     } else { self.print("File not found.\n"); }
                 }
             },
             "disk" => {
+                // Prefer NVMe, then a VirtIO disk, for reads when the machine has one
+                if parts.len() == 2 && parts[1] == "read" {
+                    if let Some((model, data)) = crate::nvme::with_nvme(|nvme| (nvme.model.clone(), nvme.read_lba(0, 1))) {
+                        self.print(&format!("[DISK] NVMe Controller Detected: {}\n[DISK] Reading LBA 0...\n", model));
+                        let text: String = data.iter().take_while(|&&c| c != 0)
+                            .map(|&c| if (32..=126).contains(&c) { c as char } else { '.' })
+                            .collect();
+                        self.print(&format!("Data: {}\n", text));
+                        return;
+                    }
+                    if let Some(data) = crate::virtio_blk::with_blk(|blk| blk.read_sectors(0, 1)) {
+                        self.print("[DISK] VirtIO Block Device Detected.\n[DISK] Reading Sector 0...\n");
+                        let text: String = data.iter().take_while(|&&c| c != 0)
+                            .map(|&c| if (32..=126).contains(&c) { c as char } else { '.' })
+                            .collect();
+                        self.print(&format!("Data: {}\n", text));
+                        return;
+                    }
+                }
                 let drive = ata::AtaDrive::new(true); // Master Drive
                 if drive.identify() {
                     self.print("[DISK] ATA Master Drive Detected.\n");
@@ -766,7 +2254,7 @@ impl Shell {
                         
                         self.print(&format!("[DISK] Writing '{}' to Sector 0...\n", content));
                         drive.write_sectors(0, &sector);
-                        self.print("[DISK] Write complete.\n");
+                        self.print("[DISK] Write queued (flushed within 5s).\n");
                     } 
                     else if parts.len() > 1 && parts[1] == "read" {
                         self.print("[DISK] Reading Sector 0...\n");
@@ -785,8 +2273,11 @@ impl Shell {
                             }
                         }
                         self.print("\n");
+                    } else if parts.len() > 1 && parts[1] == "flush" {
+                        self.print(&format!("[DISK] Flushing {} cached sectors...\n", ata::dirty_sectors()));
+                        ata::flush_all_caches();
                     } else {
-                        self.print("Usage: disk read | disk write <text>\n");
+                        self.print("Usage: disk read | disk write <text> | disk flush\n");
                     }
                 } else {
                     self.print("[DISK] No drive found.\n");
@@ -825,74 +2316,512 @@ impl Shell {
                     }
                 }
             },  
+            "writedisk" => {
+                if parts.len() < 3 {
+                    self.print("Usage: writedisk <file> <text>\n");
+                } else if let Some(fat_fs) = crate::fat::Fat32::new() {
+                    let content = parts[2..].join(" ");
+                    if fat_fs.write_file(parts[1], content.as_bytes()) {
+                        self.print(&format!("[DISK] Wrote {} bytes to '{}'.\n", content.len(), parts[1]));
+                    } else {
+                        self.print("Error: Write failed.\n");
+                        self.last_exit = 1;
+                    }
+                } else {
+                    self.print("[ERROR] Could not mount FAT32.\n");
+                }
+            },
+            "rmdisk" => {
+                if parts.len() < 2 {
+                    self.print("Usage: rmdisk <file>\n");
+                } else if let Some(fat_fs) = crate::fat::Fat32::new() {
+                    if fat_fs.delete_file(parts[1]) {
+                        self.print(&format!("[DISK] Deleted '{}'.\n", parts[1]));
+                    } else {
+                        self.print("Error: File not found on disk.\n");
+                        self.last_exit = 1;
+                    }
+                } else {
+                    self.print("[ERROR] Could not mount FAT32.\n");
+                }
+            },
             "rundisk" => {
                 if parts.len() < 2 { self.print("Usage: rundisk <file>\n"); } 
                 else {
                     if let Some(fat_fs) = crate::fat::Fat32::new() {
                         if let Some(file_data) = fat_fs.read_file(parts[1]) {
                             self.print(&format!("File size: {}\n", file_data.len()));
-                            
-                            let user_virt_base = 0x400_000;
-                            unsafe {
-                                // 1. Allocate and map 8 fresh pages (32KB)
-                                for i in 0..8 {
-                                    let v = user_virt_base + (i * 4096);
-                                    let p = memory::alloc_frame().as_u64();
-                                    memory::map_user_page(v, p);
-
-                                    // 2. Copy data from the file into the virtual address
-                                    let offset = i as usize * 4096;
-                                    if offset < file_data.len() {
-                                        let chunk = core::cmp::min(file_data.len() - offset, 4096);
-                                        core::ptr::copy_nonoverlapping(
-                                            file_data.as_ptr().add(offset),
-                                            v as *mut u8,
-                                            chunk
-                                        );
-                                    }
+                            // Same path as `run`: its own address space, scheduled as a process
+                            match elf::load_and_run(&file_data, &self.env).and_then(|idx| self.add_job(idx)) {
+                                Some(job) => {
+                                    self.print(&format!("[{}] UserApp\n", job));
+                                }
+                                None => {
+                                    self.print("Error: Not a loadable ELF file.\n");
+                                    self.last_exit = 1;
                                 }
-
-                                // 3. Setup Stack (Mapped at 0x800000)
-                                let stack_virt = 0x800_000;
-                                memory::map_user_page(stack_virt, memory::alloc_frame().as_u64());
-                                
-                                // 4. Get entry point
-                                let raw_entry = *(file_data.as_ptr().add(24) as *const u64);
-                                self.print(&format!("Raw entry: {:x}\n", raw_entry));
-                                let target = if raw_entry >= user_virt_base { raw_entry } else { user_virt_base + raw_entry };
-
-                                self.print(&format!("[LOADER] Jumping to Ring 3 at {:x}\n", target));
-                                
-                                KERNEL_RSP.store(unsafe { let r: u64; core::arch::asm!("mov {}, rsp", out(reg) r); r & !0xF }, Ordering::Relaxed);
-                                
-                                let (code, data) = gdt::get_user_selectors();
-                                userspace::jump_to_code_raw(target, code, data, stack_virt + 4096);
                             }
                         } else { self.print("File not found on HDD.\n"); }
                     } else { self.print("[ERROR] Could not mount FAT32.\n"); }
                 }
             },                                    
+            "beep" => {
+                match (parts.get(1).and_then(|s| s.parse::<u32>().ok()), parts.get(2).and_then(|s| s.parse::<u32>().ok())) {
+                    (Some(freq), Some(ms)) if (20..=20_000).contains(&freq) => crate::speaker::beep(freq, ms),
+                    _ => {
+                        self.print("Usage: beep <freq 20-20000> <ms>\n");
+                        self.last_exit = 1;
+                    }
+                }
+            },
+            "sleep" => {
+                match parts.get(1).and_then(|s| s.parse::<u64>().ok()) {
+                    // PIT runs at ~100 Hz
+                    Some(secs) => scheduler::sleep(secs * 100),
+                    None => {
+                        self.print("Usage: sleep <seconds>\n");
+                        self.last_exit = 1;
+                    }
+                }
+            },
+            "dmesg" => {
+                let mut min = None; // -l: only this level and above
+                let mut count = usize::MAX;
+                let mut i = 1;
+                while i < parts.len() {
+                    match (parts[i], parts.get(i + 1)) {
+                        ("-c", _) => {
+                            logger::clear_history();
+                            return;
+                        }
+                        ("-l", Some(level)) => match logger::LogLevel::parse(level) {
+                            Some(l) => min = Some(l),
+                            None => {
+                                self.print("Error: Level must be debug, info, warn or error.\n");
+                                self.last_exit = 1;
+                                return;
+                            }
+                        },
+                        ("-n", Some(n)) => match n.parse() {
+                            Ok(n) => count = n,
+                            Err(_) => {
+                                self.print("Error: -n needs a number.\n");
+                                self.last_exit = 1;
+                                return;
+                            }
+                        },
+                        _ => {
+                            self.print("Usage: dmesg [-c] [-l <level>] [-n <count>]\n");
+                            self.last_exit = 1;
+                            return;
+                        }
+                    }
+                    i += 2;
+                }
+                let history = match min {
+                    Some(level) => logger::history(level),
+                    None => logger::get_history(),
+                };
+                let skip = history.len().saturating_sub(count);
+                let mut out = String::new();
+                for (tick, msg) in &history[skip..] {
+                    out.push_str(&format!("[{:8}] {}", tick, msg));
+                    if !msg.ends_with('\n') { out.push('\n'); }
+                }
+                self.print(&out);
+            },
+            "jobs" => {
+                self.prune_jobs();
+                let jobs: Vec<(u64, String, &'static str)> = x86_64::instructions::interrupts::without_interrupts(|| {
+                    let sched = scheduler::SCHEDULER.lock();
+                    self.background_tasks.iter()
+                        .filter_map(|&id| sched.find_by_id(id).map(|idx| &sched.tasks[idx]))
+                        .map(|t| (t.id, t.name.clone(), t.status.label()))
+                        .collect()
+                });
+                if jobs.is_empty() {
+                    self.print("No jobs.\n");
+                }
+                for (idx, name, status) in jobs {
+                    let marker = if self.fg_task == Some(idx) { "+" } else { " " };
+                    self.print(&format!("[{}]{} {:12} {}\n", idx, marker, name, status));
+                }
+            },
+            "fg" => {
+                self.prune_jobs();
+                if parts.len() < 2 {
+                    self.print("Usage: fg <n>\n");
+                } else if let Some(idx) = self.parse_job(parts[1]) {
+                    self.fg_task = Some(idx);
+                    self.print(&format!("[{}] Foreground (Ctrl+Z to detach)\n", idx));
+                } else {
+                    self.print("Error: No such job.\n");
+                    self.last_exit = 1;
+                }
+            },
+            "bg" => {
+                self.prune_jobs();
+                if parts.len() < 2 {
+                    self.print("Usage: bg <n>\n");
+                } else if let Some(idx) = self.parse_job(parts[1]) {
+                    if self.fg_task == Some(idx) { self.fg_task = None; }
+                    self.print(&format!("[{}] Background\n", idx));
+                } else {
+                    self.print("Error: No such job.\n");
+                    self.last_exit = 1;
+                }
+            },
+            "kill" => {
+                self.prune_jobs();
+                if parts.len() < 2 {
+                    self.print("Usage: kill %<n> | kill <name>\n");
+                } else if !parts[1].starts_with('%') {
+                    let name = parts[1];
+                    if scheduler::kill_by_name(name) {
+                        self.prune_jobs();
+                        self.print(&format!("Killed: {}\n", name));
+                    } else {
+                        self.print("Error: Not found\n");
+                        self.last_exit = 1;
+                    }
+                } else if let Some(job) = self.parse_job(parts[1]) {
+                    let removed = x86_64::instructions::interrupts::without_interrupts(|| {
+                        let mut sched = scheduler::SCHEDULER.lock();
+                        let idx = sched.find_by_id(job)?;
+                        sched.remove_task(idx)
+                    });
+                    match removed {
+                        Some(task) => {
+                            self.prune_jobs();
+                            self.print(&format!("[{}] Killed {}\n", job, task.name));
+                        }
+                        None => {
+                            self.print("Error: Cannot kill running task.\n");
+                            self.last_exit = 1;
+                        }
+                    }
+                } else {
+                    self.print("Error: No such job.\n");
+                    self.last_exit = 1;
+                }
+            },
+            "uptime" => {
+                let hz = state::TSC_HZ.load(Ordering::Relaxed).max(1);
+                let now = unsafe { core::arch::x86_64::_rdtsc() };
+                let secs = now.saturating_sub(state::BOOT_TSC.load(Ordering::Relaxed)) / hz;
+                self.print(&format!("up {}d {}h {}m {}s\n", secs / 86400, secs / 3600 % 24, secs / 60 % 60, secs % 60));
+                let ns = crate::time::get_monotonic_ns();
+                let source = if crate::hpet::is_enabled() { "HPET" } else { "PIT" };
+                self.print(&format!("{} clock: {}.{:03} s\n", source, ns / 1_000_000_000, ns / 1_000_000 % 1000));
+            },
+            "console" => {
+                let mode = match parts.get(1) {
+                    None => ConsoleMode::Raw,
+                    Some(&"-l") => ConsoleMode::Lines,
+                    Some(_) => { self.print("Usage: console [-l]\n"); return; }
+                };
+                self.console = Some(mode);
+                self.print("Serial console on COM1 (Ctrl+Z to exit)\n");
+            },
+            "date" => {
+                self.print(&crate::time::read_rtc_full().format());
+                self.print("\n");
+            },
+            "slabinfo" => {
+                let caches = x86_64::instructions::interrupts::without_interrupts(|| {
+                    [("task", slab::TASK_SLAB.lock().stats()), ("node", slab::NODE_SLAB.lock().stats())]
+                });
+                self.print("CACHE  OBJ_SIZE  USED  TOTAL  FRAMES\n");
+                for (name, (size, used, total, frames)) in caches {
+                    self.print(&format!("{:6} {:8}  {:4}  {:5}  {:6}\n", name, size, used, total, frames));
+                }
+            },
+            "mmap" => {
+                let vaddr = parts.get(2).and_then(|v| u64::from_str_radix(v.trim_start_matches("0x"), 16).ok());
+                match (parts.get(1), vaddr) {
+                    (Some(file), Some(vaddr)) => {
+                        let path = self.real_path(file);
+                        match shmem::mmap(vaddr, 0, shmem::MAP_FILE, Some(&path)) {
+                            Some(virt) => {
+                                // Read back through the new mapping itself, not the VFS
+                                let len = x86_64::instructions::interrupts::without_interrupts(|| {
+                                    shmem::MMAP_TABLE.lock().iter().find(|r| r.virt == virt).map(|r| r.length).unwrap_or(0)
+                                }).min(64);
+                                let bytes = unsafe { core::slice::from_raw_parts(virt as *const u8, len) };
+                                self.print(&format!("{} mapped at {:#x}\n", file, virt));
+                                for (row, chunk) in bytes.chunks(16).enumerate() {
+                                    let hex: Vec<String> = chunk.iter().map(|b| format!("{:02x}", b)).collect();
+                                    let ascii: String = chunk.iter().map(|&b| if (0x20..0x7F).contains(&b) { b as char } else { '.' }).collect();
+                                    self.print(&format!("{:#x}  {:47}  {}\n", virt + row as u64 * 16, hex.join(" "), ascii));
+                                }
+                                // The read above should have set Accessed but not Dirty
+                                self.print(&format!("First page: accessed={} dirty={}\n",
+                                    memory::get_page_accessed(virt), memory::get_page_dirty(virt)));
+                                shmem::munmap(virt);
+                            }
+                            None => {
+                                self.print("Error: No such file or bad address.\n");
+                                self.last_exit = 1;
+                            }
+                        }
+                    }
+                    _ => self.print("Usage: mmap <file> <hex vaddr>\n"),
+                }
+            },
+            "fbinfo" => {
+                let response = match crate::FRAMEBUFFER_REQUEST.get_response() {
+                    Some(r) => r,
+                    None => {
+                        self.print("Error: No framebuffer from the bootloader.\n");
+                        self.last_exit = 1;
+                        return;
+                    }
+                };
+                for (i, fb) in response.framebuffers().enumerate() {
+                    let model = if fb.memory_model() == limine::framebuffer::MemoryModel::RGB { "RGB" } else { "unknown" };
+                    self.print(&format!("Framebuffer {}:\n", i));
+                    self.print(&format!("  Address:      {:p}\n", fb.addr()));
+                    self.print(&format!("  Resolution:   {}x{}\n", fb.width(), fb.height()));
+                    self.print(&format!("  Pitch:        {} bytes\n", fb.pitch()));
+                    self.print(&format!("  BPP:          {}\n", fb.bpp()));
+                    self.print(&format!("  Memory model: {}\n", model));
+                    self.print(&format!("  Red:          {} bits at {}\n", fb.red_mask_size(), fb.red_mask_shift()));
+                    self.print(&format!("  Green:        {} bits at {}\n", fb.green_mask_size(), fb.green_mask_shift()));
+                    self.print(&format!("  Blue:         {} bits at {}\n", fb.blue_mask_size(), fb.blue_mask_shift()));
+                    self.print(&format!("  EDID:         {}\n", fb.edid().map(|e| format!("{} bytes", e.len())).unwrap_or_else(|| "none".to_string())));
+                    self.print(&format!("  Video modes:  {}\n", fb.modes().map(|m| m.len()).unwrap_or(0)));
+                }
+            },
+            "rename_window" => {
+                let title = cmd.trim_start()["rename_window".len()..].trim();
+                if title.is_empty() {
+                    self.print("Usage: rename_window <new_title>\n");
+                } else if let Some(win) = self.windows.get_mut(self.active_idx) {
+                    win.set_title(title);
+                }
+            },
+            "shmem" => {
+                let key = parts.get(2).and_then(|k| k.parse::<u32>().ok());
+                match (parts.get(1).copied(), key) {
+                    (Some("create"), Some(key)) => {
+                        let size = parts.get(3).and_then(|s| s.parse::<usize>().ok()).unwrap_or(0);
+                        match shmem::shmem_create(key, size) {
+                            Some(virt) => self.print(&format!("shmem {}: {} bytes at {:#x}\n", key, size, virt)),
+                            None => {
+                                self.print("Error: Key in use or bad size.\n");
+                                self.last_exit = 1;
+                            }
+                        }
+                    }
+                    (Some("attach"), Some(key)) => {
+                        let vaddr = parts.get(3).and_then(|v| u64::from_str_radix(v.trim_start_matches("0x"), 16).ok());
+                        match vaddr {
+                            Some(vaddr) if shmem::shmem_attach(key, vaddr) => {
+                                self.print(&format!("shmem {} attached at {:#x}\n", key, vaddr));
+                            }
+                            Some(_) => {
+                                self.print("Error: No such key or bad address.\n");
+                                self.last_exit = 1;
+                            }
+                            None => self.print("Usage: shmem attach <key> <hex vaddr>\n"),
+                        }
+                    }
+                    (Some("list"), _) => {
+                        let regions: Vec<(u32, usize, u64)> = x86_64::instructions::interrupts::without_interrupts(|| {
+                            shmem::SHMEM_TABLE.lock().iter().map(|r| (r.key, r.size, r.kernel_virt)).collect()
+                        });
+                        self.print("KEY             SIZE  KERNEL VADDR\n");
+                        for (key, size, virt) in regions {
+                            self.print(&format!("{:10}  {:8}  {:#x}\n", key, size, virt));
+                        }
+                    }
+                    _ => self.print("Usage: shmem create <key> <size> | attach <key> <hex vaddr> | list\n"),
+                }
+            },
+            "kinfo" => {
+                let hhdm = state::HHDM_OFFSET.load(Ordering::Relaxed);
+                let delta = state::KERNEL_DELTA.load(Ordering::Relaxed);
+                let mut out = format!("HHDM offset:  {:#018x}\n", hhdm);
+                // The slide is a secret worth keeping; only show it when asked for debugging
+                if parts.get(1) == Some(&"-d") {
+                    let slide = state::KASLR_OFFSET.load(Ordering::Relaxed);
+                    out.push_str(&format!("Kernel base:  {:#018x}\n", memory::KERNEL_DEFAULT_BASE.wrapping_add(slide)));
+                    out.push_str(&format!("Virt - phys:  {:#018x}\n", delta));
+                    out.push_str(&format!("KASLR offset: {:#x} ({} MiB)\n", slide, slide >> 20));
+                } else {
+                    out.push_str("KASLR offset: hidden (kinfo -d to show)\n");
+                }
+                self.print(&out);
+            },
+            "cpuinfo" => {
+                let info = cpuid::read();
+                self.print(&format!("Vendor:   {}\n", info.vendor_str()));
+                self.print(&format!("Brand:    {}\n", info.brand_str()));
+                self.print(&format!("Family:   {:#x}  Model: {:#x}  Stepping: {}\n", info.family, info.model, info.stepping));
+                let flags: Vec<&str> = info.features.list().iter().filter(|(_, on)| *on).map(|(n, _)| *n).collect();
+                self.print(&format!("Features: {}\n", flags.join(" ")));
+                let missing: Vec<&str> = info.features.list().iter().filter(|(_, on)| !*on).map(|(n, _)| *n).collect();
+                if !missing.is_empty() {
+                    self.print(&format!("Missing:  {}\n", missing.join(" ")));
+                }
+            },
+            "move_to_workspace" => {
+                match parts.get(1).and_then(|n| n.parse::<usize>().ok()) {
+                    Some(n) if (1..=WORKSPACES).contains(&n) => {
+                        let idx = self.active_idx;
+                        if let Some(win) = self.windows.get_mut(idx) {
+                            win.workspace = n - 1;
+                        }
+                        // The window left the screen (unless it's already there): focus what's left
+                        if let Some(top) = self.windows.iter().rposition(|w| self.is_visible(w)) {
+                            self.active_idx = top;
+                        }
+                    }
+                    _ => self.print(&format!("Usage: move_to_workspace <1-{}>\n", WORKSPACES)),
+                }
+            },
+            "netio" => {
+                self.print(&format!("RX: {}pkts, {} bytes | TX: {}pkts, {} bytes | In-flight: {} bytes\n",
+                    net::RX_PACKETS.load(Ordering::Relaxed), net::TOTAL_RX_BYTES.load(Ordering::Relaxed),
+                    net::TX_PACKETS.load(Ordering::Relaxed), net::TOTAL_TX_BYTES.load(Ordering::Relaxed),
+                    net::BYTES_IN_FLIGHT.load(Ordering::Relaxed)));
+            },
+            "chroot" => {
+                if parts.len() < 2 {
+                    self.print("Usage: chroot <dir>\n");
+                } else {
+                    let view = if parts[1].starts_with('/') {
+                        parts[1].to_string()
+                    } else {
+                        format!("{}/{}", self.current_dir, parts[1])
+                    };
+                    // Normalize the combined path and make sure it didn't escape the current jail
+                    let new_root = fs::normalize_path(&format!("{}/{}", self.fs_root, view));
+                    let inside = self.fs_root == "/" || new_root == self.fs_root
+                        || new_root.starts_with(&format!("{}/", self.fs_root));
+                    if !inside {
+                        self.print("Error: Cannot escape chroot.\n");
+                    } else if fs::ls(&new_root).is_none() {
+                        self.print("Error: Directory not found.\n");
+                    } else {
+                        self.print(&format!("Root is now '{}'.\n", new_root));
+                        self.fs_root = new_root;
+                        self.current_dir = "/".to_string();
+                    }
+                }
+            },
+            "unchroot" => {
+                self.current_dir = self.cwd();
+                self.fs_root = "/".to_string();
+                self.print("Root restored to '/'.\n");
+            },
             "ip" => {
                 let ip = state::get_my_ip();
                 self.print(&format!("IP: {}.{}.{}.{}\n", ip[0], ip[1], ip[2], ip[3]));
             },
+            "test" | "[" => {
+                let ok = self.eval_test(&parts[1..].join(" "));
+                self.last_exit = if ok { 0 } else { 1 };
+            },
+            "true" => {}
+            "false" => self.last_exit = 1,
+            "seq" => {
+                let nums: Result<Vec<i64>, _> = parts[1..].iter().map(|p| p.parse::<i64>()).collect();
+                let (start, step, end) = match nums.as_deref() {
+                    Ok([end]) => (1, 1, *end),
+                    Ok([start, end]) => (*start, 1, *end),
+                    Ok([start, step, end]) => (*start, *step, *end),
+                    _ => {
+                        self.print("Usage: seq [start] [step] end\n");
+                        self.last_exit = 1;
+                        return;
+                    }
+                };
+                if step == 0 {
+                    self.print("Error: Step must not be zero.\n");
+                    self.last_exit = 1;
+                    return;
+                }
+                // A step pointing away from `end` prints nothing, like seq(1)
+                let mut out = String::new();
+                let mut n = start;
+                let mut count = 0;
+                while (step > 0 && n <= end) || (step < 0 && n >= end) {
+                    if count == SEQ_MAX_LINES {
+                        out.push_str(&format!("seq: stopped after {} numbers\n", SEQ_MAX_LINES));
+                        self.last_exit = 1;
+                        break;
+                    }
+                    count += 1;
+                    out.push_str(&format!("{}\n", n));
+                    n = match n.checked_add(step) {
+                        Some(next) => next,
+                        None => break,
+                    };
+                }
+                self.print(&out);
+            },
+            "xargs" => {
+                // -n <N>: tokens per invocation (default: one per run)
+                let (batch, cmd_start) = match (parts.get(1), parts.get(2)) {
+                    (Some(&"-n"), Some(n)) => match n.parse::<usize>() {
+                        Ok(n) if n > 0 => (n, 3),
+                        _ => {
+                            self.print("Error: -n needs a positive number.\n");
+                            self.last_exit = 1;
+                            return;
+                        }
+                    },
+                    _ => (1, 1),
+                };
+                if parts.len() <= cmd_start {
+                    self.print("Usage: xargs [-n <N>] <command> [args...]\n");
+                    return;
+                }
+                // Without a pipe, read the /dev/stdin device (keys typed ahead)
+                let input = match self.ctx.input.take() {
+                    Some(input) => input,
+                    None => {
+                        let (dir, name) = self.locate("/dev/stdin");
+                        match fs::read(&dir, &name) {
+                            Some(data) => String::from_utf8_lossy(&data).into_owned(),
+                            None => {
+                                self.print("Error: xargs needs piped input.\n");
+                                self.last_exit = 1;
+                                return;
+                            }
+                        }
+                    }
+                };
+
+                let base = parts[cmd_start..].join(" ");
+                let tokens: Vec<&str> = input.split_whitespace().collect();
+                for (runs, chunk) in tokens.chunks(batch).enumerate() {
+                    if runs == XARGS_MAX_RUNS {
+                        self.print(&format!("xargs: stopped after {} invocations\n", XARGS_MAX_RUNS));
+                        self.last_exit = 1;
+                        return;
+                    }
+                    let line = format!("{} {}", base, chunk.join(" "));
+                    self.execute_line(&line);
+                }
+                if tokens.is_empty() { self.execute_line(&base); }
+            },
+            "source" | "." => {
+                if parts.len() < 2 {
+                    self.print("Usage: source <script.sh>\n");
+                } else {
+                    self.source(parts[1]);
+                }
+            },
             "clear" => { self.windows.clear(); self.print("> "); },
-            _ => self.print("Unknown command.\n"),
+            _ => {
+                self.print("Unknown command.\n");
+                self.last_exit = 127;
+            },
         }
     }
 
-    pub extern "C" fn run_user_trampoline(entry_point: u64) {
-        let _hhdm = state::HHDM_OFFSET.load(Ordering::Relaxed);
-        let user_stack_virt = 0x800_000;
-
-        // Allocate a new physical frame for this task's user stack
-        let stack_frame = memory::alloc_frame();
-        
-        unsafe { memory::map_user_page(user_stack_virt, stack_frame.as_u64()); }
-        
-        let (code, data) = gdt::get_user_selectors();
-        userspace::jump_to_code_raw(entry_point, code, data, user_stack_virt + 4096);
-    }
     // FIXED: Made public so main.rs can call it safely
     pub fn update_monitor(win: &mut compositor::Window) {
         win.clear(); 
@@ -904,23 +2833,19 @@ impl Shell {
         win.print(&format!("Memory: {} / {} KB\n\n", used/1024, total/1024));
 
         // Copy task data while interrupts are disabled, then print after
-        let task_data: alloc::vec::Vec<(usize, alloc::string::String, &'static str, u64)> = 
+        let task_data: alloc::vec::Vec<(usize, alloc::string::String, u8, &'static str, u64, u64, u64)> = 
             x86_64::instructions::interrupts::without_interrupts(|| {
                 let sched = scheduler::SCHEDULER.lock();
                 sched.tasks.iter().enumerate().map(|(i, task)| {
-                    let status = match task.status {
-                        scheduler::TaskStatus::Waiting => "WAIT",
-                        scheduler::TaskStatus::Success => "OK",
-                        scheduler::TaskStatus::Failure => "FAIL",
-                        scheduler::TaskStatus::Penalty => "PENT",
-                    };
-                    (i, task.name.clone(), status, task.last_cost)
+                    let cpu = (task.last_cost * 100).checked_div(task.budget).unwrap_or(0);
+                    (i, task.name.clone(), task.priority, task.status.label(), task.last_cost, cpu, task.total_cycles)
                 }).collect()
             });
         
-        win.print("ID   NAME          STATUS    COST\n");
-        for (i, name, status, cost) in task_data {
-            win.print(&format!("{:2}   {:12}  {:4}      {:8}\n", i, name, status, cost));
+        win.print("ID   NAME          PRI  STATUS    COST(us)  CPU%   TOTAL_MS\n");
+        for (i, name, pri, status, cost, cpu, total) in task_data {
+            win.print(&format!("{:2}   {:12}  {:3}  {:4}      {:8}  {:4}   {:8}\n",
+                i, name, pri, status, crate::time::cycles_to_us(cost), cpu, crate::time::cycles_to_ms(total)));
         }
     }
 
@@ -966,7 +2891,234 @@ impl Shell {
         win.print_fixed(5, h - 15, "^X Exit  ^R ReadFile ^\u{005C} Replace ^U Uncut  ^T ToSpell ^_ GoToLine", 0xFFFFFFFF);
     }
 
+    /// Completes the word under the cursor: commands (and files) for the first word,
+    /// files/directories for arguments. Several matches are listed instead.
+    fn handle_tab_completion(&mut self) {
+        // 1. Find the partial word ending at the cursor
+        let point = self.insertion_point.min(self.command_buffer.len());
+        let before = &self.command_buffer[..point];
+        let word_start = before.rfind(' ').map(|i| i + 1).unwrap_or(0);
+        let word = before[word_start..].to_string();
+        let is_command = before[..word_start].trim().is_empty();
+
+        // 2. Split "dir/part" so paths complete inside their directory
+        let (dir_prefix, partial) = match word.rfind('/') {
+            Some(i) => (word[..=i].to_string(), word[i + 1..].to_string()),
+            None => (String::new(), word.clone()),
+        };
+        let dir = if dir_prefix.is_empty() { self.cwd() } else { self.real_path(&dir_prefix) };
+
+        // 3. Gather candidates
+        let mut matches: Vec<String> = Vec::new();
+        if is_command && dir_prefix.is_empty() {
+            for cmd in BUILTIN_COMMANDS {
+                if cmd.starts_with(partial.as_str()) { matches.push(cmd.to_string()); }
+            }
+        }
+        if let Some(entries) = fs::ls(&dir) {
+            for (name, is_dir) in entries {
+                if name.starts_with(partial.as_str()) {
+                    matches.push(if is_dir { format!("{}/", name) } else { name });
+                }
+            }
+        }
+        matches.sort();
+        matches.dedup();
+
+        // 4. Apply
+        match matches.len() {
+            0 => {}
+            1 => {
+                let mut completion = format!("{}{}", dir_prefix, matches[0]);
+                if !completion.ends_with('/') { completion.push(' '); }
+                self.command_buffer.replace_range(word_start..point, &completion);
+                self.insertion_point = word_start + completion.len();
+                self.redraw_command_line();
+            }
+            _ => {
+                self.print(&format!("\n{}\n", matches.join("  ")));
+                if let Some(win) = self.windows.get_mut(self.active_idx) {
+                    self.prompt_start_idx = win.text_buffer.chars().count();
+                    self.prompt_start_y = win.cursor_y;
+                }
+                self.redraw_command_line();
+            }
+        }
+    }
+
+    /// Resizes a window (clamped to the minimum size) and reflows its text into the new width.
+    /// For the active terminal the prompt position is recomputed so editing keeps working.
+    pub fn resize_window(&mut self, idx: usize, width: usize, height: usize) {
+        let tracks_prompt = idx == self.active_idx;
+        if let Some(win) = self.windows.get_mut(idx) {
+            let text = win.text_buffer.clone();
+            win.width = width.max(compositor::MIN_WIDTH);
+            win.height = height.max(compositor::MIN_HEIGHT);
+            win.realloc_buffer();
+            win.draw_decorations();
+            win.clear();
+
+            if tracks_prompt {
+                let split = text.char_indices().nth(self.prompt_start_idx).map(|(i, _)| i).unwrap_or(text.len());
+                win.print(&text[..split]);
+                self.prompt_start_y = win.cursor_y;
+                win.print(&text[split..]);
+            } else {
+                win.print(&text);
+            }
+        }
+    }
+
+    // --- TASKBAR ---
+    const WORKSPACE_BUTTON_X: usize = 10;
+    const WORKSPACE_BUTTON_W: usize = 36;
+    const TASKBAR_BUTTON_X: usize = Self::WORKSPACE_BUTTON_X + WORKSPACES * Self::WORKSPACE_BUTTON_W + 10;
+    const TASKBAR_BUTTON_W: usize = 100;
+
+    /// Adds a new window on the current workspace and focuses it
+    fn open_window(&mut self, mut win: compositor::Window) {
+        win.workspace = self.active_workspace;
+        self.windows.push(win);
+        self.active_idx = self.windows.len() - 1;
+    }
+
+    /// Drawn right now: on the active workspace and not minimized
+    pub fn is_visible(&self, win: &compositor::Window) -> bool {
+        win.workspace == self.active_workspace && !win.minimized
+    }
+
+    /// Shows workspace `n` and focuses its topmost window
+    pub fn switch_workspace(&mut self, n: usize) {
+        if n >= WORKSPACES { return; }
+        self.active_workspace = n;
+        if let Some(top) = self.windows.iter().rposition(|w| self.is_visible(w)) {
+            self.active_idx = top;
+        }
+    }
+
+    /// Moves focus to the next (or previous) visible window by index. Z-order is untouched.
+    pub fn cycle_focus(&mut self, forward: bool) {
+        let count = self.windows.len();
+        for step in 1..count {
+            let idx = if forward { (self.active_idx + step) % count } else { (self.active_idx + count - step) % count };
+            if self.is_visible(&self.windows[idx]) {
+                self.active_idx = idx;
+                return;
+            }
+        }
+    }
+
+    /// Closes a window, keeping at least one open
+    pub fn close_window(&mut self, idx: usize) {
+        if self.windows.len() <= 1 || idx >= self.windows.len() { return; }
+        self.windows.remove(idx);
+        if self.active_idx >= self.windows.len() {
+            self.active_idx = self.windows.len() - 1;
+        }
+    }
+
+    /// Maximizes a window to the screen above the taskbar, or restores its old rectangle
+    pub fn toggle_maximize(&mut self, idx: usize, screen_w: usize, screen_h: usize) {
+        let win = match self.windows.get_mut(idx) {
+            Some(w) => w,
+            None => return,
+        };
+        if win.maximized {
+            if let Some((x, y, w, h)) = win.saved_rect {
+                win.x = x; win.y = y; win.width = w; win.height = h;
+                win.maximized = false; win.saved_rect = None;
+                win.realloc_buffer(); win.draw_decorations();
+            }
+        } else {
+            win.saved_rect = Some((win.x, win.y, win.width, win.height));
+            win.x = 0; win.y = 0; win.width = screen_w; win.height = screen_h - 30;
+            win.maximized = true;
+            win.realloc_buffer(); win.draw_decorations();
+        }
+    }
+
+    /// Hides a window and hands focus to the topmost one still visible
+    pub fn minimize_window(&mut self, idx: usize) {
+        if let Some(win) = self.windows.get_mut(idx) {
+            win.minimized = true;
+        }
+        if let Some(top) = self.windows.iter().rposition(|w| self.is_visible(w)) {
+            self.active_idx = top;
+        }
+    }
+
+    /// Workspace buttons `[1]`..`[4]`, then one button per minimized window of this workspace
+    pub fn draw_taskbar_buttons(&self, taskbar: &mut compositor::Window) {
+        for n in 0..WORKSPACES {
+            let x = Self::WORKSPACE_BUTTON_X + n * Self::WORKSPACE_BUTTON_W;
+            let color = if n == self.active_workspace { 0xFF6060A0 } else { 0xFF303040 };
+            taskbar.draw_rect(x, 4, Self::WORKSPACE_BUTTON_W - 6, 22, color);
+            taskbar.print_fixed(x + 2, 7, &format!("[{}]", n + 1), 0xFFFFFFFF);
+        }
+        // Focused window's title, right-aligned before the clock and CAPS indicator
+        if let Some(win) = self.windows.get(self.active_idx).filter(|w| self.is_visible(w)) {
+            let title: String = win.title.chars().take(24).collect();
+            let label = format!("* {} *", title);
+            let x = taskbar.width.saturating_sub(180 + label.chars().count() * 9);
+            taskbar.print_fixed(x, 7, &label, 0xFFFFFFFF);
+        }
+        let minimized = self.windows.iter().filter(|w| w.minimized && w.workspace == self.active_workspace);
+        for (slot, win) in minimized.enumerate() {
+            let x = Self::TASKBAR_BUTTON_X + slot * Self::TASKBAR_BUTTON_W;
+            let label: String = win.title.chars().take(8).collect();
+            taskbar.draw_rect(x, 4, Self::TASKBAR_BUTTON_W - 6, 22, 0xFF404060);
+            taskbar.print_fixed(x + 6, 7, &label, 0xFFFFFFFF);
+        }
+    }
+
+    /// Switches workspace or restores the minimized window whose taskbar button is at `mx`
+    /// (bringing it to the front). Returns true if a button was hit.
+    pub fn taskbar_click(&mut self, mx: usize) -> bool {
+        let workspaces_end = Self::WORKSPACE_BUTTON_X + WORKSPACES * Self::WORKSPACE_BUTTON_W;
+        if (Self::WORKSPACE_BUTTON_X..workspaces_end).contains(&mx) {
+            self.switch_workspace((mx - Self::WORKSPACE_BUTTON_X) / Self::WORKSPACE_BUTTON_W);
+            return true;
+        }
+        if mx < Self::TASKBAR_BUTTON_X { return false; }
+        let slot = (mx - Self::TASKBAR_BUTTON_X) / Self::TASKBAR_BUTTON_W;
+        let workspace = self.active_workspace;
+        let idx = match self.windows.iter().enumerate().filter(|(_, w)| w.minimized && w.workspace == workspace).nth(slot) {
+            Some((i, _)) => i,
+            None => return false,
+        };
+        let mut win = self.windows.remove(idx);
+        win.minimized = false;
+        self.windows.push(win);
+        self.active_idx = self.windows.len() - 1;
+        true
+    }
+
+    /// Newest history entry below index `before` containing the query; updates the
+    /// command line when one is found
+    fn history_search(&mut self, before: usize) {
+        if self.history_search_query.is_empty() { return; }
+        let found = self.history[..before.min(self.history.len())].iter()
+            .rposition(|h| h.contains(self.history_search_query.as_str()));
+        if let Some(i) = found {
+            self.history_search_match = Some(i);
+            self.command_buffer = self.history[i].clone();
+            self.insertion_point = self.command_buffer.len();
+        }
+    }
+
+    /// `> `, or the reverse-i-search banner while Ctrl+R is active
+    fn prompt_text(&self) -> String {
+        if !self.history_search_mode {
+            // Continuation prompt while a multi-line block is being typed
+            return String::from(if self.pending_block.is_empty() { "> " } else { "... " });
+        }
+        let failed = !self.history_search_query.is_empty()
+            && !self.command_buffer.contains(self.history_search_query.as_str());
+        format!("({}reverse-i-search)'{}': ", if failed { "failed " } else { "" }, self.history_search_query)
+    }
+
     fn redraw_command_line(&mut self) {
+        let prompt = self.prompt_text();
         if let Some(win) = self.windows.get_mut(self.active_idx) {
             // 1. Clean up the text buffer and the screen
             win.truncate_text_buffer(self.prompt_start_idx);
@@ -975,7 +3127,7 @@ impl Shell {
             win.clear_from(win.cursor_y);
             
             // 2. Reprint the prompt and the full command
-            win.print("> ");
+            win.print(&prompt);
             let cmd = self.command_buffer.clone();
             win.print(&cmd);
             
@@ -983,8 +3135,9 @@ impl Shell {
             // We do this by "re-printing" up to the insertion point
             win.cursor_x = compositor::BORDER_WIDTH + 4;
             win.cursor_y = self.prompt_start_y;
-            win.draw_char_no_buf('>');
-            win.draw_char_no_buf(' ');
+            for p in prompt.chars() {
+                win.draw_char_no_buf(p);
+            }
             let chars: alloc::vec::Vec<char> = self.command_buffer.chars().collect();
             for i in 0..self.insertion_point {
                 if i < chars.len() {
@@ -995,6 +3148,261 @@ impl Shell {
     }
 }
 
+// --- SCRIPTING HELPERS ---
+
+/// Splits a command line on `sep` (`|` for pipelines, `;` for sequences), ignoring
+/// separators inside quotes and `$( )`
+fn split_unquoted(line: &str, sep: char) -> Vec<&str> {
+    let mut stages = Vec::new();
+    let mut quote: Option<char> = None;
+    let mut subst_depth = 0;
+    let mut start = 0;
+    for (i, c) in line.char_indices() {
+        match quote {
+            Some(q) if c == q => quote = None,
+            Some(_) => {}
+            None if c == '"' || c == '\'' => quote = Some(c),
+            None if c == '(' && line[..i].ends_with('$') => subst_depth += 1,
+            None if c == ')' && subst_depth > 0 => subst_depth -= 1,
+            None if c == sep && subst_depth == 0 => {
+                stages.push(line[start..i].trim());
+                start = i + 1;
+            }
+            None => {}
+        }
+    }
+    stages.push(line[start..].trim());
+    stages
+}
+
+/// +1 if `segment` opens an `if`/`for`/`while`, -1 if it closes one with `fi`/`done`
+fn block_delta(segment: &str) -> i32 {
+    let mut words = segment.split_whitespace();
+    let mut first = words.next().unwrap_or("");
+    if matches!(first, "then" | "else" | "do") { first = words.next().unwrap_or(""); }
+    match first {
+        "if" | "for" | "while" => 1,
+        "fi" | "done" => -1,
+        _ => 0,
+    }
+}
+
+/// How many blocks `line` leaves open (0 once every `if`/`for`/`while` is closed)
+fn block_depth(line: &str) -> i32 {
+    split_unquoted(line, ';').iter().map(|s| block_delta(s)).sum()
+}
+
+/// Splits a line on `;` into statements, keeping each `if ... fi` / `for ... done` /
+/// `while ... done` whole
+fn split_statements(line: &str) -> Vec<String> {
+    let mut statements = Vec::new();
+    let mut current: Vec<&str> = Vec::new();
+    let mut depth = 0;
+    for segment in split_unquoted(line, ';') {
+        depth += block_delta(segment);
+        current.push(segment);
+        if depth <= 0 {
+            let statement = current.join("; ");
+            if !statement.trim().is_empty() { statements.push(statement); }
+            current.clear();
+            depth = 0;
+        }
+    }
+    if !current.is_empty() { statements.push(current.join("; ")); } // Unterminated block
+    statements
+}
+
+/// Length of the command after `$(`, up to its closing `)`
+fn subst_len(s: &str) -> Option<usize> {
+    let mut depth = 0usize;
+    for (i, b) in s.bytes().enumerate() {
+        match b {
+            b'(' => depth += 1,
+            b')' if depth > 0 => depth -= 1,
+            b')' => return Some(i),
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Letters, digits and `_`, not starting with a digit
+fn is_var_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars.next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Moves one pipeline stage's output through a `Pipe`, a capacity's worth at a time.
+/// Stages run one after another, so the reader drains each chunk straight away.
+fn pass_through_pipe(data: &[u8]) -> String {
+    let pipe = pipe::Pipe::new();
+    let mut received = Vec::with_capacity(data.len());
+    let mut rest = data;
+    while !rest.is_empty() {
+        let n = pipe.write(rest);
+        received.extend(pipe.read(n));
+        rest = &rest[n..];
+    }
+    pipe.close_write();
+    pipe.close_read();
+    String::from_utf8_lossy(&received).into_owned()
+}
+
+/// `NAME=value` or `NAME="some value"` -> (NAME, value). None for anything else,
+/// including `a = b` and `x==y`.
+fn parse_assignment(cmd: &str) -> Option<(&str, &str)> {
+    let (key, value) = cmd.split_once('=')?;
+    if !is_var_name(key) { return None; }
+    for q in ['"', '\''] {
+        if value.len() >= 2 && value.starts_with(q) && value.ends_with(q) {
+            return Some((key, &value[1..value.len() - 1]));
+        }
+    }
+    if value.contains(char::is_whitespace) || value.starts_with('=') { return None; }
+    Some((key, value))
+}
+
+/// "10.0.2.2" -> [10, 0, 2, 2]
+fn parse_ip(s: &str) -> Option<[u8; 4]> {
+    let mut ip = [0u8; 4];
+    let mut count = 0;
+    for (i, part) in s.split('.').enumerate() {
+        if i >= 4 { return None; }
+        ip[i] = part.parse().ok()?;
+        count += 1;
+    }
+    if count == 4 { Some(ip) } else { None }
+}
+
+/// Date and time of a node timestamp (`time::wall_ticks`); 0 means unknown
+fn ticks_to_clock(tick: u64) -> String {
+    if tick == 0 { return String::from("---------- --:--:--"); }
+    crate::time::wall_ticks_to_time(tick).format()
+}
+
+/// Length of the expression after `$((`, up to the `))` that closes it
+fn arith_len(s: &str) -> Option<usize> {
+    let mut depth = 0usize;
+    let bytes = s.as_bytes();
+    for (i, &b) in bytes.iter().enumerate() {
+        match b {
+            b'(' => depth += 1,
+            b')' if depth > 0 => depth -= 1,
+            b')' => return if bytes.get(i + 1) == Some(&b')') { Some(i) } else { None },
+            _ => {}
+        }
+    }
+    None
+}
+
+/// `head`/`tail` arguments: optional file and `-n <lines>` in any order
+fn head_tail_args<'a>(parts: &[&'a str]) -> (Option<&'a str>, usize) {
+    let mut file = None;
+    let mut n = 10;
+    let mut i = 1;
+    while i < parts.len() {
+        if parts[i] == "-n" && i + 1 < parts.len() {
+            n = parts[i + 1].parse().unwrap_or(10);
+            i += 2;
+        } else if parts[i] == "-f" {
+            i += 1;
+        } else {
+            file = Some(parts[i]);
+            i += 1;
+        }
+    }
+    (file, n)
+}
+
+/// Splits a command line on whitespace, keeping "double" or 'single' quoted runs together
+fn split_quoted(line: &str) -> Vec<String> {
+    let mut args = Vec::new();
+    let mut current = String::new();
+    let mut quote: Option<char> = None;
+    let mut in_arg = false;
+
+    for c in line.chars() {
+        match quote {
+            Some(q) if c == q => quote = None,
+            Some(_) => current.push(c),
+            None if c == '"' || c == '\'' => { quote = Some(c); in_arg = true; }
+            None if c.is_whitespace() => {
+                if in_arg { args.push(core::mem::take(&mut current)); in_arg = false; }
+            }
+            None => { current.push(c); in_arg = true; }
+        }
+    }
+    if in_arg { args.push(current); }
+    args
+}
+
+/// Expands `%s %d %x %o %c %%` (with optional `-` and width) and `\n \t \\` escapes.
+/// Fails with the offending argument if `%d`, `%x` or `%o` gets a non-integer.
+fn format_printf(fmt: &str, args: &[String]) -> Result<String, String> {
+    let chars: Vec<char> = fmt.chars().collect();
+    let mut out = String::new();
+    let mut arg_idx = 0;
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+        i += 1;
+
+        // 1. Escape sequences
+        if c == '\\' && i < chars.len() {
+            match chars[i] {
+                'n' => out.push('\n'),
+                't' => out.push('\t'),
+                '\\' => out.push('\\'),
+                other => { out.push('\\'); out.push(other); }
+            }
+            i += 1;
+            continue;
+        }
+        if c != '%' || i >= chars.len() {
+            out.push(c);
+            continue;
+        }
+
+        // 2. Directive: [-][width]conversion
+        let left = chars[i] == '-';
+        if left { i += 1; }
+        let mut width = 0;
+        while i < chars.len() && chars[i].is_ascii_digit() {
+            width = width * 10 + chars[i].to_digit(10).unwrap_or(0) as usize;
+            i += 1;
+        }
+        if i >= chars.len() { break; }
+        let conv = chars[i];
+        i += 1;
+
+        if conv == '%' {
+            out.push('%');
+            continue;
+        }
+        let arg = args.get(arg_idx).map(|a| a.as_str()).unwrap_or("");
+        arg_idx += 1;
+        // A missing argument counts as 0, like an empty string does for %s
+        let num = || if arg.is_empty() { Ok(0) } else { arg.parse::<i64>().map_err(|_| String::from(arg)) };
+        let text = match conv {
+            's' => String::from(arg),
+            'd' => format!("{}", num()?),
+            'x' => format!("{:x}", num()?),
+            'o' => format!("{:o}", num()?),
+            'c' => arg.chars().next().map(String::from).unwrap_or_default(),
+            other => format!("%{}", other),
+        };
+
+        // 3. Pad to width
+        let pad = width.saturating_sub(text.chars().count());
+        if !left { for _ in 0..pad { out.push(' '); } }
+        out.push_str(&text);
+        if left { for _ in 0..pad { out.push(' '); } }
+    }
+    Ok(out)
+}
+
 lazy_static! {
     pub static ref SHELL: Mutex<Option<Shell>> = Mutex::new(None);
 }
@@ -1025,6 +3433,9 @@ pub fn resume_shell() -> ! {
     let mut is_dragging = false;
     let mut drag_offset_x = 0usize;
     let mut drag_offset_y = 0usize;
+    let mut is_resizing = false;
+    let mut resize_anchor = (0usize, 0usize); // Grab point's distance from the bottom-right corner
+    let mut prev_btn = false;
 
     loop {
         // 1. Run scheduler step (handles context switching)
@@ -1032,27 +3443,34 @@ pub fn resume_shell() -> ! {
 
 
         // 2. GUI Logic - Mouse handling
-        let (mx, my, btn) = crate::mouse::get_state();
+        let (mx, my, btn, _) = crate::mouse::get_state();
         
         let mut draw_list: Vec<&compositor::Window> = Vec::new();
         let mut active_idx = None;
 
         // 1. Taskbar (Always drawn)
         let mut taskbar = compositor::Window::new(0, height - 30, width, 30, "Taskbar");
+        taskbar.set_alpha(220); // Translucent glass
         let time = crate::time::read_rtc();
         let time_str = format!("{:02}:{:02}:{:02}", time.hours, time.minutes, time.seconds);
         taskbar.cursor_x = width - 100;
         taskbar.cursor_y = 5;
         taskbar.print(&time_str);
-        draw_list.push(&taskbar);
+        if crate::interrupts::is_caps_lock() {
+            taskbar.cursor_x = width - 160;
+            taskbar.print("CAPS");
+        }
 
         if let Some(mut shell_mutex_lock) = SHELL.try_lock() {
             if let Some(ref mut shell_mutex) = *shell_mutex_lock {
+                // Taskbar buttons restore minimized windows (on press only)
+                let taskbar_hit = btn && !prev_btn && my >= height - 30 && shell_mutex.taskbar_click(mx);
+
                 // A. Focus / Z-Order
-                if btn && !is_dragging {
+                if btn && !taskbar_hit && !is_dragging && !is_resizing {
                     let mut clicked_idx = None;
                     for (i, win) in shell_mutex.windows.iter().enumerate().rev() {
-                        if win.contains(mx, my) {
+                        if shell_mutex.is_visible(win) && win.contains(mx, my) {
                             clicked_idx = Some(i);
                             break;
                         }
@@ -1095,6 +3513,11 @@ pub fn resume_shell() -> ! {
                                 win.data = vec![0xFF000000; win.width * win.height];
                                 win.draw_decorations();
                             }
+                        } else if action == 3 {
+                            shell_mutex.minimize_window(new_idx);
+                        } else if win.resize_corner_contains(mx, my) && !win.maximized {
+                            is_resizing = true;
+                            resize_anchor = (win.x + win.width - mx, win.y + win.height - my);
                         } else if win.is_title_bar(mx, my) {
                             is_dragging = true;
                             drag_offset_x = mx - win.x;
@@ -1103,6 +3526,19 @@ pub fn resume_shell() -> ! {
                     }
                 } else if !btn {
                     is_dragging = false;
+                    is_resizing = false;
+                }
+
+                // Resizing
+                if is_resizing {
+                    let idx = shell_mutex.active_idx;
+                    if let Some(win) = shell_mutex.windows.get(idx) {
+                        let new_w = (mx + resize_anchor.0).saturating_sub(win.x).max(compositor::MIN_WIDTH);
+                        let new_h = (my + resize_anchor.1).saturating_sub(win.y).max(compositor::MIN_HEIGHT);
+                        if new_w != win.width || new_h != win.height {
+                            shell_mutex.resize_window(idx, new_w, new_h);
+                        }
+                    }
                 }
 
                 // B. Dragging
@@ -1114,16 +3550,29 @@ pub fn resume_shell() -> ! {
                     }
                 }
 
+                // Mouse wheel scrolls the active window's history (3 lines per notch)
+                let wheel = crate::mouse::get_scroll_delta();
+                if wheel != 0 {
+                    let idx = shell_mutex.active_idx;
+                    if let Some(win) = shell_mutex.windows.get_mut(idx) {
+                        let lines = wheel.unsigned_abs() as usize * 3;
+                        if wheel < 0 { win.scroll_up(lines); } else { win.scroll_down(lines); }
+                    }
+                }
+
                 // C. Update Task Manager windows
+                let cwd = shell_mutex.cwd();
                 for win in shell_mutex.windows.iter_mut() {
                     if win.title == "System Monitor" {
                         Shell::update_monitor(win);
                     } else if win.title == "File Explorer" {
-                        Shell::update_explorer(win, &shell_mutex.current_dir);
+                        Shell::update_explorer(win, &cwd);
                     }
                 }
 
-                for win in &shell_mutex.windows {
+                shell_mutex.draw_taskbar_buttons(&mut taskbar);
+                draw_list.push(&taskbar);
+                for win in shell_mutex.windows.iter().filter(|w| shell_mutex.is_visible(w)) {
                     draw_list.push(win);
                 }
                 active_idx = Some(shell_mutex.active_idx);
@@ -1131,8 +3580,10 @@ pub fn resume_shell() -> ! {
             }
         } else {
             // Fallback rendering
+            draw_list.push(&taskbar);
             desktop.render(&draw_list, None, mx, my);
         }
+        prev_btn = btn;
 
     }
 }
@@ -0,0 +1,275 @@
+use crate::{fs, memory, state};
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::sync::atomic::Ordering;
+use spin::Mutex;
+use lazy_static::lazy_static;
+use x86_64::PhysAddr;
+
+// --- SHARED MEMORY ---
+// A region is a set of physical frames identified by a key. The creator gets them mapped
+// contiguously in a kernel window; `shmem_attach` maps the same frames into user space,
+// so every mapping sees the same bytes.
+
+const SHMEM_BASE: u64 = 0xFFFF_D000_0000_0000; // Kernel window, clear of the HHDM and kernel image
+const MAX_SIZE: usize = 16 * 1024 * 1024;
+const USER_TOP: u64 = 0x0000_8000_0000_0000;
+
+pub struct ShmemRegion {
+    pub key: u32,
+    pub phys_frames: Vec<PhysAddr>,
+    pub size: usize,
+    pub kernel_virt: u64,
+}
+
+lazy_static! {
+    pub static ref SHMEM_TABLE: Mutex<Vec<ShmemRegion>> = Mutex::new(Vec::new());
+    pub static ref MMAP_TABLE: Mutex<Vec<MmapRegion>> = Mutex::new(Vec::new());
+}
+
+static mut NEXT_KERNEL_VIRT: u64 = SHMEM_BASE;
+
+/// Creates region `key` of `size` bytes (rounded up to pages, zeroed) and returns its
+/// kernel virtual address. None if the key is taken or the size is 0 or too big.
+pub fn shmem_create(key: u32, size: usize) -> Option<u64> {
+    if size == 0 || size > MAX_SIZE { return None; }
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        let mut table = SHMEM_TABLE.lock();
+        if table.iter().any(|r| r.key == key) { return None; }
+
+        // Take every frame up front: a user program asks for these, so running out of
+        // memory must fail the call, not panic, and leave nothing behind
+        let pages = size.div_ceil(4096);
+        let mut phys_frames = Vec::with_capacity(pages);
+        for _ in 0..pages {
+            match memory::try_alloc_frame() {
+                Some(frame) => phys_frames.push(frame),
+                None => {
+                    for frame in phys_frames { unsafe { memory::free_frame(frame); } }
+                    return None;
+                }
+            }
+        }
+
+        let kernel_virt = unsafe { NEXT_KERNEL_VIRT };
+        for (i, frame) in phys_frames.iter().enumerate() {
+            let virt = kernel_virt + i as u64 * 4096;
+            unsafe {
+                memory::map_kernel_page(virt, frame.as_u64());
+                core::ptr::write_bytes(virt as *mut u8, 0, 4096);
+            }
+        }
+        unsafe { NEXT_KERNEL_VIRT += pages as u64 * 4096; }
+
+        table.push(ShmemRegion { key, phys_frames, size, kernel_virt });
+        Some(kernel_virt)
+    })
+}
+
+/// Maps region `key` at page-aligned user address `user_virt` in the active page tables
+pub fn shmem_attach(key: u32, user_virt: u64) -> bool {
+    if user_virt & 0xFFF != 0 { return false; }
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        let table = SHMEM_TABLE.lock();
+        let region = match table.iter().find(|r| r.key == key) {
+            Some(r) => r,
+            None => return false,
+        };
+        let end = match user_virt.checked_add(region.phys_frames.len() as u64 * 4096) {
+            Some(e) if e <= USER_TOP => e,
+            _ => return false,
+        };
+        // Never map over existing pages: the frames behind them would leak, and the process
+        // would silently lose what it had there
+        if (user_virt..end).step_by(4096).any(|v| memory::is_mapped(v) || memory::is_demand_page(v)) {
+            return false;
+        }
+        for (i, frame) in region.phys_frames.iter().enumerate() {
+            unsafe { memory::map_user_data_page(user_virt + i as u64 * 4096, frame.as_u64()); }
+        }
+        true
+    })
+}
+
+// --- MEMORY MAPPINGS ---
+// `mmap` backs a user range with fresh frames, zeroed (MAP_ANON) or holding a copy of a
+// VFS file (MAP_FILE). `munmap` drops the pages again and copies the ones written through
+// the mapping back into the file.
+
+pub const MAP_ANON: u64 = 1;
+pub const MAP_FILE: u64 = 2;
+const MMAP_BASE: u64 = 0x0000_7000_0000_0000; // Kernel-chosen addresses start here
+
+pub struct MmapRegion {
+    pub virt: u64,
+    pub length: usize,
+    pub page_table_phys: u64,  // Address space the mapping lives in
+    pub phys_frames: Vec<PhysAddr>,
+    pub file: Option<String>,  // Backing VFS path of a MAP_FILE mapping
+    pub writable: bool,        // Backing file has owner write permission
+}
+
+static mut NEXT_MMAP_VIRT: u64 = MMAP_BASE;
+
+fn active_page_table() -> u64 {
+    x86_64::registers::control::Cr3::read().0.start_address().as_u64()
+}
+
+/// Absolute VFS path -> (parent directory, name)
+fn split_path(path: &str) -> (String, String) {
+    let full = fs::normalize_path(path);
+    match full.rsplit_once('/') {
+        Some(("", name)) => ("/".to_string(), name.to_string()),
+        Some((dir, name)) => (dir.to_string(), name.to_string()),
+        None => ("/".to_string(), full),
+    }
+}
+
+/// Maps `length` bytes (0 = the whole file) at page-aligned `virt_hint`, or at a kernel-chosen
+/// address if the hint is 0, in the active page tables. Returns the address.
+pub fn mmap(virt_hint: u64, length: usize, flags: u64, path: Option<&str>) -> Option<u64> {
+    if virt_hint & 0xFFF != 0 { return None; }
+
+    // 1. Contents: a copy of the file, or nothing for an anonymous mapping
+    let (data, file, writable) = if flags & MAP_FILE != 0 {
+        let path = fs::normalize_path(path?);
+        let (dir, name) = split_path(&path);
+        // Permissions are the target's: a link itself always reads as 0o777
+        let (dir, name) = fs::resolve(&dir, &name)?;
+        let data = fs::read(&dir, &name)?;
+        let writable = fs::get_node_info(&dir, &name).is_some_and(|info| info.permissions & 0o200 != 0);
+        (data, Some(path), writable)
+    } else if flags & MAP_ANON != 0 {
+        (Vec::new(), None, true)
+    } else {
+        return None;
+    };
+    let length = if length == 0 { data.len() } else { length };
+    if length == 0 || length > MAX_SIZE { return None; }
+    let pages = length.div_ceil(4096);
+    let size = pages as u64 * 4096;
+
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        let mut table = MMAP_TABLE.lock();
+        let page_table_phys = active_page_table();
+
+        // 2. Pick the address; it may not overlap another mapping in this address space
+        let virt = if virt_hint != 0 { virt_hint } else { unsafe { NEXT_MMAP_VIRT } };
+        let end = virt.checked_add(size)?;
+        if end > USER_TOP { return None; }
+        let overlaps = table.iter().any(|r| {
+            r.page_table_phys == page_table_phys && virt < r.virt + r.phys_frames.len() as u64 * 4096 && r.virt < end
+        });
+        if overlaps { return None; }
+        // Nor anything else living there: the ELF image (BSS included), stacks, shmem
+        if (virt..end).step_by(4096).any(|v| memory::is_mapped(v) || memory::is_demand_page(v)) {
+            return None;
+        }
+
+        // 3. Take every frame up front so running out of memory leaves nothing behind
+        let mut phys_frames = Vec::with_capacity(pages);
+        for _ in 0..pages {
+            match memory::try_alloc_frame() {
+                Some(frame) => phys_frames.push(frame),
+                None => {
+                    for frame in phys_frames { unsafe { memory::free_frame(frame); } }
+                    return None;
+                }
+            }
+        }
+        if virt_hint == 0 { unsafe { NEXT_MMAP_VIRT += size; } }
+
+        // 4. Fill each frame through the HHDM, then map it
+        let hhdm = state::HHDM_OFFSET.load(Ordering::Relaxed);
+        for (i, &frame) in phys_frames.iter().enumerate() {
+            let start = (i * 4096).min(data.len());
+            let chunk = &data[start..(start + 4096).min(data.len())];
+            unsafe {
+                let dst = (frame.as_u64() + hhdm) as *mut u8;
+                core::ptr::write_bytes(dst, 0, 4096);
+                core::ptr::copy_nonoverlapping(chunk.as_ptr(), dst, chunk.len());
+                memory::map_user_data_page(virt + i as u64 * 4096, frame.as_u64());
+            }
+        }
+
+        table.push(MmapRegion { virt, length, page_table_phys, phys_frames, file, writable });
+        Some(virt)
+    })
+}
+
+/// Removes the mapping starting at `virt` in the active page tables. Dirty pages of a writable
+/// file mapping are written back to the file, then the frames are freed.
+pub fn munmap(virt: u64) -> bool {
+    let region = x86_64::instructions::interrupts::without_interrupts(|| {
+        let mut table = MMAP_TABLE.lock();
+        let page_table_phys = active_page_table();
+        let pos = table.iter().position(|r| r.virt == virt && r.page_table_phys == page_table_phys)?;
+        Some(table.remove(pos))
+    });
+    let region = match region {
+        Some(r) => r,
+        None => return false,
+    };
+
+    // 1. Flush while the pages (and their Dirty bits) are still mapped, then unmap and free
+    write_back(&region);
+    for (i, &frame) in region.phys_frames.iter().enumerate() {
+        unsafe {
+            memory::unmap_user_page(region.virt + i as u64 * 4096);
+            memory::free_frame(frame);
+        }
+    }
+    true
+}
+
+/// Drops every mapping of the address space at `pml4_phys` (its process exited) and frees
+/// the frames. Nothing is written back: only `munmap` copies changes into the file.
+pub fn release_mappings(pml4_phys: PhysAddr) {
+    let released: Vec<MmapRegion> = x86_64::instructions::interrupts::without_interrupts(|| {
+        let mut table = MMAP_TABLE.lock();
+        let (released, kept) = core::mem::take(&mut *table).into_iter()
+            .partition(|r| r.page_table_phys == pml4_phys.as_u64());
+        *table = kept;
+        released
+    });
+    for region in released {
+        for (i, &frame) in region.phys_frames.iter().enumerate() {
+            unsafe {
+                memory::unmap_user_page_in(pml4_phys, region.virt + i as u64 * 4096);
+                memory::free_frame(frame);
+            }
+        }
+    }
+}
+
+/// Copies the pages written through a writable file mapping over the file (which keeps its
+/// length) and marks them clean. Untouched pages cost no VFS write at all.
+fn write_back(region: &MmapRegion) {
+    let path = match (&region.file, region.writable) {
+        (Some(path), true) => path,
+        _ => return,
+    };
+    let dirty: Vec<usize> = (0..region.phys_frames.len())
+        .filter(|&i| memory::get_page_dirty(region.virt + i as u64 * 4096))
+        .collect();
+    if dirty.is_empty() { return; }
+
+    let (dir, name) = split_path(path);
+    let (dir, name) = match fs::resolve(&dir, &name) {
+        Some(r) => r,
+        None => return,
+    };
+    if let Some(mut data) = fs::read(&dir, &name) {
+        let hhdm = state::HHDM_OFFSET.load(Ordering::Relaxed);
+        for i in dirty {
+            memory::clear_page_dirty(region.virt + i as u64 * 4096);
+            let start = i * 4096;
+            if start >= data.len() { break; }
+            let end = (start + 4096).min(data.len());
+            let src = (region.phys_frames[i].as_u64() + hhdm) as *const u8;
+            unsafe { core::ptr::copy_nonoverlapping(src, data[start..end].as_mut_ptr(), end - start); }
+        }
+        fs::touch(&dir, &name, data);
+        fs::save_to_disk();
+    }
+}
@@ -0,0 +1,105 @@
+use x86_64::PhysAddr;
+use core::sync::atomic::Ordering;
+use spin::Mutex;
+use lazy_static::lazy_static;
+use crate::state;
+
+// --- SLAB CACHES ---
+// Fixed-size objects carved out of whole 4 KiB frames, reached through the HHDM.
+// Freed objects go straight back on the cache's free list, so allocating and freeing
+// the same size over and over never fragments the buddy heap. A cache grows a frame
+// at a time, up to N frames; after that `alloc` returns null and callers fall back.
+
+const FRAME_SIZE: usize = 4096;
+
+// Lives inside each free object
+struct SlabBlock {
+    next: *mut SlabBlock,
+}
+
+pub struct SlabCache<const N: usize> {
+    free_list: *mut SlabBlock,
+    total: usize, // Objects across all frames
+    used: usize,
+    obj_size: usize,
+    frames: [u64; N], // HHDM addresses of the backing frames
+    frame_count: usize,
+}
+
+// The raw pointers only ever point into the cache's own frames, guarded by a Mutex
+unsafe impl<const N: usize> Send for SlabCache<N> {}
+
+impl<const N: usize> SlabCache<N> {
+    /// Builds a cache of `obj_size` objects (rounded up to 16 bytes) over `backing_frame`
+    pub fn new(backing_frame: PhysAddr, obj_size: usize) -> Self {
+        let obj_size = obj_size.max(core::mem::size_of::<SlabBlock>()).next_multiple_of(16);
+        assert!(obj_size <= FRAME_SIZE, "slab object larger than a frame");
+        let mut cache = SlabCache {
+            free_list: core::ptr::null_mut(),
+            total: 0,
+            used: 0,
+            obj_size,
+            frames: [0; N],
+            frame_count: 0,
+        };
+        cache.add_frame(backing_frame);
+        cache
+    }
+
+    /// Threads every object in the frame onto the free list
+    fn add_frame(&mut self, frame: PhysAddr) {
+        if self.frame_count >= N { return; }
+        let base = frame.as_u64() + state::HHDM_OFFSET.load(Ordering::Relaxed);
+        self.frames[self.frame_count] = base;
+        self.frame_count += 1;
+
+        let count = FRAME_SIZE / self.obj_size;
+        for i in (0..count).rev() {
+            let block = (base as usize + i * self.obj_size) as *mut SlabBlock;
+            unsafe { (*block).next = self.free_list; }
+            self.free_list = block;
+        }
+        self.total += count;
+    }
+
+    /// One object, or null once the cache is full and can't grow
+    pub fn alloc(&mut self) -> *mut u8 {
+        if self.free_list.is_null() && self.frame_count < N {
+            self.add_frame(crate::memory::alloc_frame());
+        }
+        if self.free_list.is_null() { return core::ptr::null_mut(); }
+        let block = self.free_list;
+        self.free_list = unsafe { (*block).next };
+        self.used += 1;
+        block as *mut u8
+    }
+
+    /// Returns an object from `alloc` to the free list
+    pub fn free(&mut self, ptr: *mut u8) {
+        debug_assert!(self.owns(ptr), "pointer not from this slab");
+        let block = ptr as *mut SlabBlock;
+        unsafe { (*block).next = self.free_list; }
+        self.free_list = block;
+        self.used -= 1;
+    }
+
+    /// Whether `ptr` lies in one of this cache's frames
+    pub fn owns(&self, ptr: *mut u8) -> bool {
+        let addr = ptr as u64;
+        self.frames[..self.frame_count].iter().any(|&base| addr >= base && addr < base + FRAME_SIZE as u64)
+    }
+
+    /// (object size, used, total, frames)
+    pub fn stats(&self) -> (usize, usize, usize, usize) {
+        (self.obj_size, self.used, self.total, self.frame_count)
+    }
+}
+
+lazy_static! {
+    // FXSAVE areas, one per task (8 per frame)
+    pub static ref TASK_SLAB: Mutex<SlabCache<32>> =
+        Mutex::new(SlabCache::new(crate::memory::alloc_frame(), core::mem::size_of::<crate::scheduler::FpuState>()));
+    // File-system nodes, one per directory entry (see `fs::NodeBox`)
+    pub static ref NODE_SLAB: Mutex<SlabCache<16>> =
+        Mutex::new(SlabCache::new(crate::memory::alloc_frame(), core::mem::size_of::<crate::fs::Node>()));
+}
@@ -0,0 +1,251 @@
+use core::sync::atomic::{AtomicU8, AtomicUsize, Ordering};
+use crate::{acpi, gdt, interrupts, lapic, memory, scheduler, state};
+
+// --- SMP BRING-UP ---
+// Limine leaves the application processors (APs) halted, waiting for an INIT-SIPI-SIPI.
+// A SIPI starts an AP in 16-bit real mode at a page below 1 MiB, so a small trampoline is
+// copied there. It loads a throwaway GDT, turns on PAE + long mode + paging using the
+// kernel's own CR3, and jumps to `ap_entry` on the stack the BSP picked for that CPU.
+
+pub const MAX_CPUS: usize = 8;
+const AP_STACK_SIZE: usize = 65536;
+
+#[repr(C, align(16))]
+struct ApStacks([[u8; AP_STACK_SIZE]; MAX_CPUS]);
+
+// Index 0 (the BSP) is unused: it keeps the stack Limine gave it
+static mut AP_STACKS: ApStacks = ApStacks([[0; AP_STACK_SIZE]; MAX_CPUS]);
+
+// CPU index (0 = BSP) by APIC ID, so `cpu_id` is one LAPIC read
+static APIC_TO_CPU: [AtomicU8; 256] = [const { AtomicU8::new(0) }; 256];
+static CPUS_ONLINE: AtomicUsize = AtomicUsize::new(1);
+
+core::arch::global_asm!(
+    ".pushsection .text.ap_trampoline, \"ax\"",
+    ".global ap_trampoline_start",
+    ".global ap_trampoline_end",
+    ".global ap_tr_long_mode",
+    ".global ap_tr_gdt",
+    ".global ap_tr_gdt_base",
+    ".global ap_tr_far_jump",
+    ".global ap_tr_cr3",
+    ".global ap_tr_efer",
+    ".global ap_tr_stack",
+    ".global ap_tr_cpu",
+    ".global ap_tr_entry",
+    ".code16",
+    "ap_trampoline_start:",
+    "cli",
+    "cld",
+    // CS = page >> 4, so DS = CS makes trampoline offsets directly addressable
+    "mov %cs, %ax",
+    "mov %ax, %ds",
+    "lgdtl ap_tr_gdtr - ap_trampoline_start",
+    // PAE, then EFER (LME, and NXE if the BSP has it), then PE + PG in one go
+    "mov %cr4, %eax",
+    "or $0x20, %eax",
+    "mov %eax, %cr4",
+    "movl ap_tr_cr3 - ap_trampoline_start, %eax",
+    "mov %eax, %cr3",
+    "mov $0xC0000080, %ecx",
+    "movl ap_tr_efer - ap_trampoline_start, %eax",
+    "xor %edx, %edx",
+    "wrmsr",
+    "mov %cr0, %eax",
+    "or $0x80000001, %eax",
+    "mov %eax, %cr0",
+    "ljmpl *(ap_tr_far_jump - ap_trampoline_start)",
+    ".code64",
+    "ap_tr_long_mode:",
+    "mov $0x10, %ax",
+    "mov %ax, %ds",
+    "mov %ax, %es",
+    "mov %ax, %ss",
+    "xor %eax, %eax",
+    "mov %ax, %fs",
+    "mov %ax, %gs",
+    "mov ap_tr_stack(%rip), %rsp",
+    "mov ap_tr_cpu(%rip), %rdi",
+    "jmp *ap_tr_entry(%rip)",
+    // Filled in by `start_ap` before every SIPI
+    ".balign 8",
+    "ap_tr_gdt:",
+    ".quad 0",
+    ".quad 0x00AF9A000000FFFF", // 0x08: 64-bit code
+    ".quad 0x00CF92000000FFFF", // 0x10: data
+    "ap_tr_gdtr:",
+    ".word 23",
+    "ap_tr_gdt_base:",
+    ".long 0",                  // Linear address of ap_tr_gdt
+    "ap_tr_far_jump:",
+    ".long 0",                  // Linear address of ap_tr_long_mode
+    ".word 0x08",
+    "ap_tr_cr3:",
+    ".long 0",
+    "ap_tr_efer:",
+    ".long 0",
+    ".balign 8",
+    "ap_tr_stack:",
+    ".quad 0",
+    "ap_tr_cpu:",
+    ".quad 0",
+    "ap_tr_entry:",
+    ".quad 0",
+    "ap_trampoline_end:",
+    ".popsection",
+    options(att_syntax)
+);
+
+extern "C" {
+    static ap_trampoline_start: u8;
+    static ap_trampoline_end: u8;
+    static ap_tr_long_mode: u8;
+    static ap_tr_gdt: u8;
+    static ap_tr_gdt_base: u8;
+    static ap_tr_far_jump: u8;
+    static ap_tr_cr3: u8;
+    static ap_tr_efer: u8;
+    static ap_tr_stack: u8;
+    static ap_tr_cpu: u8;
+    static ap_tr_entry: u8;
+}
+
+/// Offset of a trampoline label from its start
+fn tr_offset(label: *const u8) -> u64 {
+    label as u64 - core::ptr::addr_of!(ap_trampoline_start) as u64
+}
+
+/// Index of the CPU this runs on: 0 for the BSP, 1.. for APs in start-up order
+pub fn cpu_id() -> usize {
+    APIC_TO_CPU[lapic::id() as usize].load(Ordering::Relaxed) as usize
+}
+
+/// CPUs running, BSP included
+pub fn cpus_online() -> usize {
+    CPUS_ONLINE.load(Ordering::Acquire)
+}
+
+/// Busy-waits using the TSC (assumes 1 GHz before it is calibrated)
+fn delay_us(us: u64) {
+    let hz = match state::TSC_HZ.load(Ordering::Relaxed) {
+        0 => 1_000_000_000,
+        hz => hz,
+    };
+    let start = unsafe { core::arch::x86_64::_rdtsc() };
+    while unsafe { core::arch::x86_64::_rdtsc() } - start < hz / 1_000_000 * us {
+        core::hint::spin_loop();
+    }
+}
+
+/// Starts every AP listed in the MADT, up to `num_cpus` CPUs in all. Needs the LAPIC
+/// (`interrupts::switch_to_apic`) and interrupts enabled.
+pub fn init(num_cpus: usize) {
+    if !lapic::is_enabled() || num_cpus < 2 { return; }
+    let bsp_id = lapic::id();
+
+    // 1. The trampoline page must be below 1 MiB, and CR3 must fit in 32 bits
+    let page = match memory::low_memory_page() {
+        Some(p) => p,
+        None => {
+            crate::klog_warn!("[SMP] No free page below 1 MiB for the trampoline.\n");
+            return;
+        }
+    };
+    let cr3 = memory::kernel_pml4().as_u64();
+    if cr3 > u32::MAX as u64 {
+        crate::klog_warn!("[SMP] Kernel page table above 4 GiB, APs not started.\n");
+        return;
+    }
+
+    // 2. Copy it in and identity-map it: the instruction right after paging comes on
+    // is fetched from the same (physical = virtual) address
+    let hhdm = state::HHDM_OFFSET.load(Ordering::Relaxed);
+    let tramp = (page + hhdm) as *mut u8;
+    unsafe {
+        let start = core::ptr::addr_of!(ap_trampoline_start);
+        let len = tr_offset(core::ptr::addr_of!(ap_trampoline_end)) as usize;
+        core::ptr::copy_nonoverlapping(start, tramp, len);
+        memory::map_kernel_page(page, page);
+
+        let put32 = |label: *const u8, value: u32| {
+            core::ptr::write_unaligned(tramp.add(tr_offset(label) as usize) as *mut u32, value)
+        };
+        put32(core::ptr::addr_of!(ap_tr_gdt_base), (page + tr_offset(core::ptr::addr_of!(ap_tr_gdt))) as u32);
+        put32(core::ptr::addr_of!(ap_tr_far_jump), (page + tr_offset(core::ptr::addr_of!(ap_tr_long_mode))) as u32);
+        put32(core::ptr::addr_of!(ap_tr_cr3), cr3 as u32);
+        // LMA (bit 10) is the CPU's to set
+        let efer = x86_64::registers::model_specific::Msr::new(0xC000_0080).read();
+        put32(core::ptr::addr_of!(ap_tr_efer), (efer & !(1 << 10)) as u32);
+    }
+
+    // 3. APs run their LAPIC timers at the PIT's rate
+    lapic::calibrate_timer();
+
+    // 4. INIT-SIPI-SIPI each AP in turn, waiting for it before reusing the trampoline
+    let mut next_cpu = 1;
+    for apic_id in acpi::cpu_apic_ids() {
+        if apic_id == bsp_id { continue; }
+        if next_cpu >= num_cpus.min(MAX_CPUS) { break; }
+        if start_ap(tramp, page, apic_id, next_cpu) {
+            next_cpu += 1;
+        } else {
+            crate::klog_warn!("[SMP] CPU with APIC ID {} did not start.\n", apic_id);
+        }
+    }
+    crate::klog_info!("[SMP] {} CPU(s) online.\n", cpus_online());
+}
+
+/// Sends one AP through the trampoline as CPU `cpu`. False if it never checked in.
+fn start_ap(tramp: *mut u8, page: u64, apic_id: u8, cpu: usize) -> bool {
+    APIC_TO_CPU[apic_id as usize].store(cpu as u8, Ordering::Relaxed);
+    let online_before = cpus_online();
+
+    unsafe {
+        // Entered like a call: 16-byte aligned minus a return address
+        let stack_top = core::ptr::addr_of!(AP_STACKS.0[cpu]) as u64 + AP_STACK_SIZE as u64 - 8;
+        let put64 = |label: *const u8, value: u64| {
+            core::ptr::write_unaligned(tramp.add(tr_offset(label) as usize) as *mut u64, value)
+        };
+        put64(core::ptr::addr_of!(ap_tr_stack), stack_top);
+        put64(core::ptr::addr_of!(ap_tr_cpu), cpu as u64);
+        put64(core::ptr::addr_of!(ap_tr_entry), ap_entry as *const () as u64);
+    }
+
+    lapic::send_init(apic_id);
+    delay_us(10_000);
+    for _ in 0..2 {
+        lapic::send_sipi(apic_id, (page >> 12) as u8);
+        delay_us(200);
+        if cpus_online() > online_before { return true; }
+    }
+
+    // Give it up to ~100 ms to get through the trampoline
+    for _ in 0..1000 {
+        if cpus_online() > online_before { return true; }
+        delay_us(100);
+    }
+    false
+}
+
+/// First Rust code on an AP, still on its `AP_STACKS` stack with interrupts off
+extern "C" fn ap_entry(cpu_id: u64) -> ! {
+    gdt::init_ap();
+    interrupts::init_idt();
+    scheduler::init_fpu();
+    ap_main(cpu_id as u8)
+}
+
+/// Brings up the AP's LAPIC and timer, then runs tasks alongside the BSP for good
+pub fn ap_main(cpu_id: u8) -> ! {
+    lapic::init_ap();
+    lapic::start_timer(interrupts::InterruptIndex::Timer as u8);
+    CPUS_ONLINE.fetch_add(1, Ordering::AcqRel);
+    crate::serial_print!("[SMP] CPU {} online.\n", cpu_id);
+
+    x86_64::instructions::interrupts::enable();
+    loop {
+        if !scheduler::step() {
+            x86_64::instructions::hlt(); // Nothing runnable: wait for the next tick
+        }
+    }
+}
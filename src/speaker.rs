@@ -0,0 +1,49 @@
+use x86_64::instructions::port::Port;
+use core::sync::atomic::Ordering;
+use crate::state;
+
+// --- PC SPEAKER ---
+// PIT channel 2 drives the speaker through port 0x61: bit 0 gates the channel, bit 1
+// connects its output. `beep` only starts the tone; `Shell::run` calls `poll` each pass
+// and silences it once SPEAKER_OFF_TICK has passed, so nothing blocks.
+
+const PIT_HZ: u32 = 1_193_180;
+const PIT_COMMAND: u16 = 0x43;
+const PIT_CHANNEL2: u16 = 0x42;
+const SPEAKER_PORT: u16 = 0x61;
+const SPEAKER_BITS: u8 = 0x03;
+
+/// Plays `frequency_hz` for about `duration_ms` (rounded up to whole ~10 ms PIT ticks)
+pub fn beep(frequency_hz: u32, duration_ms: u32) {
+    if frequency_hz == 0 || duration_ms == 0 { return; }
+    let divisor = (PIT_HZ / frequency_hz).clamp(1, u16::MAX as u32) as u16;
+    unsafe {
+        // Channel 2, lobyte/hibyte, mode 3 (square wave)
+        Port::<u8>::new(PIT_COMMAND).write(0xB6);
+        Port::<u8>::new(PIT_CHANNEL2).write(divisor as u8);
+        Port::<u8>::new(PIT_CHANNEL2).write((divisor >> 8) as u8);
+
+        let mut control = Port::<u8>::new(SPEAKER_PORT);
+        let value = control.read();
+        control.write(value | SPEAKER_BITS);
+    }
+    let ticks = (duration_ms as u64).div_ceil(10);
+    state::SPEAKER_OFF_TICK.store(state::TICK_COUNT.load(Ordering::Relaxed) + ticks, Ordering::Relaxed);
+}
+
+pub fn stop() {
+    unsafe {
+        let mut control = Port::<u8>::new(SPEAKER_PORT);
+        let value = control.read();
+        control.write(value & !SPEAKER_BITS);
+    }
+    state::SPEAKER_OFF_TICK.store(0, Ordering::Relaxed);
+}
+
+/// Silences the speaker once the current beep has run its time
+pub fn poll() {
+    let off = state::SPEAKER_OFF_TICK.load(Ordering::Relaxed);
+    if off != 0 && state::TICK_COUNT.load(Ordering::Relaxed) >= off {
+        stop();
+    }
+}
@@ -1,18 +1,31 @@
 
 
-use core::sync::atomic::{AtomicU32, AtomicU64, AtomicUsize, Ordering}; // Added AtomicUsize
+use core::sync::atomic::{AtomicU8, AtomicU32, AtomicU64, AtomicUsize, Ordering}; // Added AtomicUsize
+use alloc::vec::Vec;
+use spin::Mutex;
 
 // ... existing vars ...
 pub static CYCLE_BUDGET: AtomicU64 = AtomicU64::new(2_500_000);
 pub static KEY_COUNT: AtomicU64 = AtomicU64::new(0);
+pub static TICK_COUNT: AtomicU64 = AtomicU64::new(0); // PIT ticks since boot (~100 Hz)
+pub static BOOT_EPOCH_TICKS: AtomicU64 = AtomicU64::new(0); // Ticks from the Unix epoch to boot, set by time::init_wall_clock
+pub static BOOT_TSC: AtomicU64 = AtomicU64::new(0);    // TSC at the top of _start
+pub static TSC_HZ: AtomicU64 = AtomicU64::new(0);      // Calibrated at boot by time::calibrate_tsc_with_pit
 pub static HHDM_OFFSET: AtomicU64 = AtomicU64::new(0);
 pub static KERNEL_DELTA: AtomicU64 = AtomicU64::new(0);
+pub static KASLR_OFFSET: AtomicU64 = AtomicU64::new(0); // Slide of the kernel image from its default base
 pub static MY_IP: AtomicU32 = AtomicU32::new(0);
+pub static CPU_FEATURES: AtomicU32 = AtomicU32::new(0); // cpuid::CpuFeatures bits, set at boot
+pub static LOG_LEVEL_FILTER: AtomicU8 = AtomicU8::new(1); // logger::LogLevel::Info
+pub static SPEAKER_OFF_TICK: AtomicU64 = AtomicU64::new(0); // Tick the current beep ends at (0 = silent)
 
 // Video State
 pub static VIDEO_PTR: AtomicU64 = AtomicU64::new(0);
 pub static SCREEN_WIDTH: AtomicUsize = AtomicUsize::new(1024); // Default
 pub static SCREEN_HEIGHT: AtomicUsize = AtomicUsize::new(768);
+pub static BYTES_PER_PIXEL: AtomicUsize = AtomicUsize::new(4); // 3 on a 24bpp mode
+// Copy of the last composited frame (SCREEN_WIDTH x SCREEN_HEIGHT), for screenshots
+pub static LAST_FRAME: Mutex<Vec<u32>> = Mutex::new(Vec::new());
 
 pub fn set_my_ip(ip: [u8; 4]) {
     let combined = ((ip[0] as u32) << 24) | ((ip[1] as u32) << 16) | ((ip[2] as u32) << 8) | (ip[3] as u32);
@@ -0,0 +1,71 @@
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicI32, Ordering};
+use spin::Mutex;
+use crate::scheduler::{TaskStatus, SCHEDULER};
+
+/// Counting semaphore for tasks. A task that has to wait is marked `Blocked` and gives up
+/// the CPU instead of spinning; `signal` hands it back to the scheduler.
+///
+/// Both sides take the scheduler lock, so neither may be called from an interrupt handler
+/// (or anything else that can run while the scheduler is locked).
+pub struct Semaphore {
+    count: AtomicI32,
+    waiters: Mutex<Vec<u64>>, // Task ids of blocked tasks, oldest first; ids survive `tasks` shifting
+}
+
+impl Semaphore {
+    pub const fn new(count: i32) -> Self {
+        Semaphore {
+            count: AtomicI32::new(count),
+            waiters: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Takes one unit, blocking the calling task until one is available
+    pub fn wait(&self) {
+        // The scheduler lock orders this against `signal` on another CPU: otherwise the
+        // wakeup could land between the decrement and joining the waiters list
+        let must_block = x86_64::instructions::interrupts::without_interrupts(|| {
+            let mut sched = SCHEDULER.lock();
+            if self.count.fetch_sub(1, Ordering::AcqRel) > 0 { return false; }
+
+            match sched.current() {
+                Some(idx) => {
+                    sched.tasks[idx].status = TaskStatus::Blocked;
+                    self.waiters.lock().push(sched.tasks[idx].id);
+                    true
+                }
+                // Not running as a task (e.g. the main loop): can't block, take it anyway
+                None => false,
+            }
+        });
+
+        if must_block {
+            unsafe { core::arch::asm!("int 0x80", in("rax") 3); } // yield
+        }
+    }
+
+    /// Returns one unit and wakes the longest-waiting task, if any
+    pub fn signal(&self) {
+        x86_64::instructions::interrupts::without_interrupts(|| {
+            let mut sched = SCHEDULER.lock();
+            self.count.fetch_add(1, Ordering::AcqRel);
+            let mut waiters = self.waiters.lock();
+            while !waiters.is_empty() {
+                let id = waiters.remove(0);
+                match sched.find_by_id(id) {
+                    Some(idx) => {
+                        let task = &mut sched.tasks[idx];
+                        if task.status == TaskStatus::Blocked {
+                            task.status = TaskStatus::Waiting;
+                        }
+                        return;
+                    }
+                    // Killed while waiting: it will never take its unit, so give that back
+                    // and wake the next one instead
+                    None => { self.count.fetch_add(1, Ordering::AcqRel); }
+                }
+            }
+        });
+    }
+}
@@ -0,0 +1,102 @@
+use alloc::string::String;
+use alloc::vec::Vec;
+use alloc::format;
+
+// --- USTAR ARCHIVES ---
+// Each regular file is a 512-byte header followed by its data, zero-padded to a
+// block boundary. Two zero blocks end the archive. Numbers are octal ASCII.
+//   [0..100] name, [100..108] mode, [124..136] size, [136..148] mtime,
+//   [148..156] checksum, [156] type, [257..263] "ustar\0", [263..265] "00"
+
+const BLOCK: usize = 512;
+const NAME_LEN: usize = 100;
+
+/// Writes `value` as zero-padded octal filling `field` minus a trailing NUL
+fn put_octal(field: &mut [u8], value: u64) {
+    let digits = field.len() - 1;
+    let text = format!("{:0width$o}", value, width = digits);
+    field[..digits].copy_from_slice(&text.as_bytes()[text.len() - digits..]);
+    field[digits] = 0;
+}
+
+/// Octal ASCII up to the first NUL or space
+fn get_octal(field: &[u8]) -> Option<u64> {
+    let mut value = 0u64;
+    for &c in field.iter().skip_while(|&&c| c == b' ') {
+        match c {
+            b'0'..=b'7' => value = value.checked_mul(8)? + (c - b'0') as u64,
+            0 | b' ' => break,
+            _ => return None,
+        }
+    }
+    Some(value)
+}
+
+/// Sum of the header bytes with the checksum field counted as spaces
+fn checksum(header: &[u8]) -> u64 {
+    header.iter().enumerate()
+        .map(|(i, &b)| if (148..156).contains(&i) { b' ' as u64 } else { b as u64 })
+        .sum()
+}
+
+/// Names longer than 100 bytes are truncated
+pub fn create(files: Vec<(String, Vec<u8>)>) -> Vec<u8> {
+    let mut out = Vec::new();
+    for (name, data) in files {
+        let mut header = [0u8; BLOCK];
+        let name_bytes = &name.as_bytes()[..name.len().min(NAME_LEN)];
+        header[..name_bytes.len()].copy_from_slice(name_bytes);
+        put_octal(&mut header[100..108], 0o644);
+        put_octal(&mut header[108..116], 0); // uid
+        put_octal(&mut header[116..124], 0); // gid
+        put_octal(&mut header[124..136], data.len() as u64);
+        put_octal(&mut header[136..148], 0); // mtime: no wall clock for VFS files
+        header[156] = b'0'; // Regular file
+        header[257..263].copy_from_slice(b"ustar\0");
+        header[263..265].copy_from_slice(b"00");
+
+        // Six octal digits, NUL, space
+        let sum = checksum(&header);
+        put_octal(&mut header[148..155], sum);
+        header[155] = b' ';
+
+        out.extend_from_slice(&header);
+        out.extend_from_slice(&data);
+        out.resize(out.len().next_multiple_of(BLOCK), 0);
+    }
+    out.resize(out.len() + 2 * BLOCK, 0);
+    out
+}
+
+/// Regular files only; directories, links and the like are skipped. Stops at the
+/// end-of-archive marker or the first damaged header.
+pub fn extract(archive: &[u8]) -> Vec<(String, Vec<u8>)> {
+    let mut files = Vec::new();
+    let mut pos = 0;
+    while pos + BLOCK <= archive.len() {
+        let header = &archive[pos..pos + BLOCK];
+        if header.iter().all(|&b| b == 0) { break; }
+        if get_octal(&header[148..156]) != Some(checksum(header)) { break; }
+        let size = match get_octal(&header[124..136]) {
+            Some(size) => size as usize,
+            None => break,
+        };
+        pos += BLOCK;
+        if pos + size > archive.len() { break; }
+
+        if header[156] == b'0' || header[156] == 0 {
+            let field = |range: core::ops::Range<usize>| {
+                let raw = &header[range];
+                let end = raw.iter().position(|&b| b == 0).unwrap_or(raw.len());
+                String::from_utf8_lossy(&raw[..end]).into_owned()
+            };
+            // ustar splits long paths into prefix + "/" + name
+            let prefix = if &header[257..262] == b"ustar" { field(345..500) } else { String::new() };
+            let name = field(0..NAME_LEN);
+            let name = if prefix.is_empty() { name } else { format!("{}/{}", prefix, name) };
+            files.push((name, archive[pos..pos + size].to_vec()));
+        }
+        pos += size.next_multiple_of(BLOCK);
+    }
+    files
+}
@@ -1,8 +1,82 @@
 use x86_64::instructions::port::Port;
+use core::sync::atomic::Ordering;
+use crate::state;
+use alloc::format;
+use alloc::string::String;
 
 const CMOS_ADDR: u16 = 0x70;
 const CMOS_DATA: u16 = 0x71;
 
+// Must match the divisor programmed in interrupts::init_pit
+const PIT_BASE_HZ: u128 = 1_193_182;
+const PIT_DIVISOR: u128 = 11931;
+const TICKS_PER_SEC: u64 = 100;
+
+/// Nanoseconds since boot, at PIT tick resolution (~10 ms)
+pub fn monotonic_ns() -> u64 {
+    let ticks = state::TICK_COUNT.load(Ordering::Relaxed) as u128;
+    (ticks * PIT_DIVISOR * 1_000_000_000 / PIT_BASE_HZ) as u64
+}
+
+/// Nanoseconds since boot from the HPET main counter; PIT resolution without an HPET
+pub fn get_monotonic_ns() -> u64 {
+    let period_fs = crate::hpet::period_fs();
+    if period_fs == 0 { return monotonic_ns(); }
+    let ns = (crate::hpet::read_counter() as u128 * period_fs as u128 / 1_000_000) as u64;
+    crate::hpet::start_ns() + ns
+}
+
+/// Measures the TSC against a 10 ms one-shot on PIT channel 2 (channel 0 keeps driving
+/// the scheduler tick). The first result is kept in `state::TSC_HZ`.
+pub fn calibrate_tsc_with_pit() -> u64 {
+    let known = state::TSC_HZ.load(Ordering::Relaxed);
+    if known != 0 { return known; }
+
+    const CALIBRATION_MS: u64 = 10;
+    let count = (PIT_BASE_HZ as u64 * CALIBRATION_MS / 1000) as u16;
+
+    let cycles = x86_64::instructions::interrupts::without_interrupts(|| unsafe {
+        let mut ctrl = Port::<u8>::new(0x61);
+        let mut cmd = Port::<u8>::new(0x43);
+        let mut ch2 = Port::<u8>::new(0x42);
+
+        // 1. Gate channel 2 on, speaker output off
+        let old = ctrl.read();
+        ctrl.write((old & !0x02) | 0x01);
+
+        // 2. Channel 2, lo/hi byte, mode 0 (interrupt on terminal count)
+        cmd.write(0xB0);
+        ch2.write((count & 0xFF) as u8);
+        ch2.write((count >> 8) as u8);
+
+        // 3. OUT2 (bit 5 of port 0x61) goes high when the count reaches zero
+        let start = core::arch::x86_64::_rdtsc();
+        while ctrl.read() & 0x20 == 0 { core::hint::spin_loop(); }
+        let end = core::arch::x86_64::_rdtsc();
+
+        ctrl.write(old);
+        end - start
+    });
+
+    let hz = cycles * (1000 / CALIBRATION_MS);
+    state::TSC_HZ.store(hz, Ordering::Relaxed);
+    hz
+}
+
+/// TSC cycles -> microseconds (0 until calibrated)
+pub fn cycles_to_us(cycles: u64) -> u64 {
+    let hz = state::TSC_HZ.load(Ordering::Relaxed);
+    if hz == 0 { return 0; }
+    (cycles as u128 * 1_000_000 / hz as u128) as u64
+}
+
+/// TSC cycles -> milliseconds (0 until calibrated)
+pub fn cycles_to_ms(cycles: u64) -> u64 {
+    let hz = state::TSC_HZ.load(Ordering::Relaxed);
+    if hz == 0 { return 0; }
+    (cycles as u128 * 1000 / hz as u128) as u64
+}
+
 pub struct Time {
     pub hours: u8,
     pub minutes: u8,
@@ -32,6 +106,97 @@ pub fn read_rtc() -> Time {
     }
 }
 
+pub struct FullTime {
+    pub year: u16,
+    pub month: u8,
+    pub day: u8,
+    pub hours: u8,
+    pub minutes: u8,
+    pub seconds: u8,
+}
+
+impl FullTime {
+    /// "YYYY-MM-DD HH:MM:SS"
+    pub fn format(&self) -> String {
+        format!("{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+            self.year, self.month, self.day, self.hours, self.minutes, self.seconds)
+    }
+
+    /// Seconds since 1970-01-01 00:00:00, taking the RTC as UTC. A 12-hour PM flag is dropped.
+    pub fn to_unix(&self) -> u64 {
+        // Days from the civil date, with March as the first month so leap days fall last
+        let (m, d) = (self.month as i64, self.day as i64);
+        let y = self.year as i64 - if m <= 2 { 1 } else { 0 };
+        let era = y.div_euclid(400);
+        let yoe = y - era * 400;
+        let doy = (153 * (m + if m > 2 { -3 } else { 9 }) + 2) / 5 + d - 1;
+        let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+        let days = (era * 146097 + doe - 719468).max(0) as u64;
+        days * 86400 + (self.hours & 0x7F) as u64 * 3600 + self.minutes as u64 * 60 + self.seconds as u64
+    }
+
+    /// Inverse of `to_unix`
+    pub fn from_unix(secs: u64) -> FullTime {
+        let z = (secs / 86400) as i64 + 719468;
+        let era = z.div_euclid(146097);
+        let doe = z - era * 146097;
+        let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+        let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+        let mp = (5 * doy + 2) / 153;
+        let day = (doy - (153 * mp + 2) / 5 + 1) as u8;
+        let month = if mp < 10 { mp + 3 } else { mp - 9 } as u8;
+        let year = (yoe + era * 400 + if month <= 2 { 1 } else { 0 }) as u16;
+        let rem = secs % 86400;
+        FullTime { year, month, day, hours: (rem / 3600) as u8, minutes: (rem / 60 % 60) as u8, seconds: (rem % 60) as u8 }
+    }
+}
+
+/// Pins the RTC's wall-clock time to the current tick so `wall_ticks` can count from it
+pub fn init_wall_clock() {
+    let epoch = read_rtc_full().to_unix() * TICKS_PER_SEC;
+    let now = state::TICK_COUNT.load(Ordering::Relaxed);
+    state::BOOT_EPOCH_TICKS.store(epoch.saturating_sub(now), Ordering::Relaxed);
+}
+
+/// PIT ticks since the Unix epoch. Unlike `TICK_COUNT` these still mean the same
+/// moment after a reboot, so they are what gets saved to disk.
+pub fn wall_ticks() -> u64 {
+    state::BOOT_EPOCH_TICKS.load(Ordering::Relaxed) + state::TICK_COUNT.load(Ordering::Relaxed)
+}
+
+/// Date and time of a `wall_ticks` value
+pub fn wall_ticks_to_time(ticks: u64) -> FullTime {
+    FullTime::from_unix(ticks / TICKS_PER_SEC)
+}
+
+/// Date and time from the CMOS clock. The century register (0x32) is what QEMU and most
+/// firmware provide; if it reads as zero, the 21st century is assumed.
+pub fn read_rtc_full() -> FullTime {
+    unsafe {
+        while is_updating() { core::hint::spin_loop(); }
+
+        let mut regs = [
+            read_register(0x00), read_register(0x02), read_register(0x04), // seconds, minutes, hours
+            read_register(0x07), read_register(0x08), read_register(0x09), // day, month, year
+            read_register(0x32),                                           // century
+        ];
+        let register_b = read_register(0x0B);
+
+        // Same BCD rule as read_rtc; the hour keeps its 12-hour PM flag (bit 7)
+        if (register_b & 0x04) == 0 {
+            for (i, v) in regs.iter_mut().enumerate() {
+                let pm = if i == 2 { *v & 0x80 } else { 0 };
+                let raw = *v & !pm;
+                *v = ((raw & 0x0F) + ((raw / 16) * 10)) | pm;
+            }
+        }
+        let [seconds, minutes, hours, day, month, year, century] = regs;
+        let century = if century == 0 { 20 } else { century as u16 };
+
+        FullTime { year: century * 100 + year as u16, month, day, hours, minutes, seconds }
+    }
+}
+
 unsafe fn is_updating() -> bool {
     let mut addr = Port::<u8>::new(CMOS_ADDR);
     let mut data = Port::<u8>::new(CMOS_DATA);
@@ -0,0 +1,235 @@
+use crate::pci::{PciDevice, pci_read_u32};
+use crate::state;
+use x86_64::instructions::port::Port;
+use alloc::vec::Vec;
+use core::sync::atomic::{fence, Ordering};
+use spin::Mutex;
+use lazy_static::lazy_static;
+
+// --- LEGACY (I/O PORT) REGISTERS ---
+const REG_DEVICE_FEATURES: u16 = 0x00;
+const REG_GUEST_FEATURES: u16 = 0x04;
+const REG_QUEUE_PFN: u16 = 0x08;     // Physical page number of the virtqueue
+const REG_QUEUE_SIZE: u16 = 0x0C;
+const REG_QUEUE_SELECT: u16 = 0x0E;
+const REG_QUEUE_NOTIFY: u16 = 0x10;
+const REG_STATUS: u16 = 0x12;
+const REG_CAPACITY: u16 = 0x14;      // Device config: capacity in 512-byte sectors (u64)
+
+// Device status bits, set one after another during initialization
+const STATUS_ACKNOWLEDGE: u8 = 1;
+const STATUS_DRIVER: u8 = 2;
+const STATUS_DRIVER_OK: u8 = 4;
+const STATUS_FEATURES_OK: u8 = 8;
+
+// Descriptor flags
+const DESC_NEXT: u16 = 1;
+const DESC_WRITE: u16 = 2; // Device writes into this buffer
+
+const REQ_IN: u32 = 0;  // Read request
+const REQ_OUT: u32 = 1; // Write request
+const REQ_STATUS_OK: u8 = 0;
+
+// --- MEMORY MAP ---
+// Fixed physical addresses after the RTL8139's buffers, same reasoning as there.
+const QUEUE_PHYS: u64 = 0x0202_0000;  // Up to 64 KiB of descriptors + rings
+const REQ_PHYS: u64 = 0x0203_0000;    // 16-byte request header
+const STATUS_PHYS: u64 = 0x0203_0010; // 1-byte status written by the device
+const DATA_PHYS: u64 = 0x0203_1000;   // Data buffer
+pub const MAX_SECTORS: u16 = 120;     // 60 KiB, what fits in the data buffer
+
+#[repr(C)]
+struct VirtqDesc {
+    addr: u64,
+    len: u32,
+    flags: u16,
+    next: u16,
+}
+
+#[repr(C)]
+struct BlkRequest {
+    kind: u32,
+    reserved: u32,
+    sector: u64,
+}
+
+pub struct VirtioBlk {
+    io_base: u16,
+    queue_addr: u64,   // Virtual address of the virtqueue (descriptor table first)
+    queue_size: u16,
+    avail_idx: u16,    // Next free slot in the available ring
+    last_used: u16,    // Last used-ring index we consumed
+    pub capacity: u64, // Sectors
+}
+
+/// VirtIO block devices in legacy/transitional mode. 0x1042 is the modern-only ID,
+/// which has no legacy I/O registers and isn't supported here.
+pub fn is_virtio_blk(dev: &PciDevice) -> bool {
+    dev.vendor_id == 0x1AF4 && dev.device_id == 0x1001
+}
+
+impl VirtioBlk {
+    pub fn new(dev: PciDevice) -> Self {
+        unsafe {
+            // 1. I/O base from BAR0
+            let bar0 = pci_read_u32(dev.bus, dev.device, dev.function, 0x10);
+            let io_base = (bar0 & !0x3) as u16;
+            let hhdm = state::HHDM_OFFSET.load(Ordering::Relaxed);
+
+            let mut driver = VirtioBlk {
+                io_base,
+                queue_addr: hhdm + QUEUE_PHYS,
+                queue_size: 0,
+                avail_idx: 0,
+                last_used: 0,
+                capacity: 0,
+            };
+            driver.init();
+            driver
+        }
+    }
+
+    unsafe fn init(&mut self) {
+        let mut status = Port::<u8>::new(self.io_base + REG_STATUS);
+
+        // 1. Reset, then announce ourselves
+        status.write(0);
+        status.write(STATUS_ACKNOWLEDGE);
+        status.write(STATUS_ACKNOWLEDGE | STATUS_DRIVER);
+
+        // 2. Features: we need none of the optional ones
+        let _offered = Port::<u32>::new(self.io_base + REG_DEVICE_FEATURES).read();
+        Port::<u32>::new(self.io_base + REG_GUEST_FEATURES).write(0);
+        status.write(STATUS_ACKNOWLEDGE | STATUS_DRIVER | STATUS_FEATURES_OK);
+
+        // 3. Queue 0: legacy devices dictate the size, we supply page-aligned memory
+        Port::<u16>::new(self.io_base + REG_QUEUE_SELECT).write(0);
+        self.queue_size = Port::<u16>::new(self.io_base + REG_QUEUE_SIZE).read();
+        let bytes = Self::queue_bytes(self.queue_size);
+        for i in 0..bytes {
+            core::ptr::write_volatile((self.queue_addr as *mut u8).add(i), 0);
+        }
+        Port::<u32>::new(self.io_base + REG_QUEUE_PFN).write((QUEUE_PHYS >> 12) as u32);
+
+        // 4. Ready
+        status.write(STATUS_ACKNOWLEDGE | STATUS_DRIVER | STATUS_FEATURES_OK | STATUS_DRIVER_OK);
+
+        let lo = Port::<u32>::new(self.io_base + REG_CAPACITY).read() as u64;
+        let hi = Port::<u32>::new(self.io_base + REG_CAPACITY + 4).read() as u64;
+        self.capacity = (hi << 32) | lo;
+
+        crate::klog_info!("[VIRTIO] Block device: {} sectors, queue size {}.\n", self.capacity, self.queue_size);
+    }
+
+    // Legacy layout: descriptors, then the available ring, then (page aligned) the used ring
+    fn avail_offset(size: u16) -> usize {
+        16 * size as usize
+    }
+
+    fn used_offset(size: u16) -> usize {
+        (Self::avail_offset(size) + 6 + 2 * size as usize + 0xFFF) & !0xFFF
+    }
+
+    fn queue_bytes(size: u16) -> usize {
+        Self::used_offset(size) + 6 + 8 * size as usize
+    }
+
+    /// Reads `count` sectors starting at `lba`. Empty on error or timeout.
+    pub fn read_sectors(&mut self, lba: u64, count: u16) -> Vec<u8> {
+        let count = count.min(MAX_SECTORS);
+        if !self.request(REQ_IN, lba, count) { return Vec::new(); }
+        let hhdm = state::HHDM_OFFSET.load(Ordering::Relaxed);
+        unsafe { core::slice::from_raw_parts((hhdm + DATA_PHYS) as *const u8, count as usize * 512) }.to_vec()
+    }
+
+    /// Writes whole sectors from `data` starting at `lba` (a partial last sector is
+    /// zero-padded), MAX_SECTORS at a time. False on the first failed request.
+    pub fn write_sectors(&mut self, lba: u64, data: &[u8]) -> bool {
+        let hhdm = state::HHDM_OFFSET.load(Ordering::Relaxed);
+        let chunk_bytes = MAX_SECTORS as usize * 512;
+        for (i, chunk) in data.chunks(chunk_bytes).enumerate() {
+            let count = chunk.len().div_ceil(512) as u16;
+            unsafe {
+                let buf = (hhdm + DATA_PHYS) as *mut u8;
+                core::ptr::copy_nonoverlapping(chunk.as_ptr(), buf, chunk.len());
+                core::ptr::write_bytes(buf.add(chunk.len()), 0, count as usize * 512 - chunk.len());
+            }
+            if !self.request(REQ_OUT, lba + (i * MAX_SECTORS as usize) as u64, count) { return false; }
+        }
+        true
+    }
+
+    /// Runs one request of `count` sectors through queue 0 using the shared data buffer
+    fn request(&mut self, kind: u32, lba: u64, count: u16) -> bool {
+        let len = count as usize * 512;
+        if count == 0 || self.queue_size == 0 || lba + count as u64 > self.capacity {
+            return false;
+        }
+        let hhdm = state::HHDM_OFFSET.load(Ordering::Relaxed);
+        let op = if kind == REQ_IN { "Read" } else { "Write" };
+
+        unsafe {
+            // 1. Request header and status byte
+            let req = (hhdm + REQ_PHYS) as *mut BlkRequest;
+            core::ptr::write_volatile(req, BlkRequest { kind, reserved: 0, sector: lba });
+            let status = (hhdm + STATUS_PHYS) as *mut u8;
+            core::ptr::write_volatile(status, 0xFF);
+
+            // 2. Descriptor chain: header -> data (device-writable for reads) -> status
+            let desc = self.queue_addr as *mut VirtqDesc;
+            let data_flags = if kind == REQ_IN { DESC_NEXT | DESC_WRITE } else { DESC_NEXT };
+            core::ptr::write_volatile(desc, VirtqDesc { addr: REQ_PHYS, len: 16, flags: DESC_NEXT, next: 1 });
+            core::ptr::write_volatile(desc.add(1), VirtqDesc { addr: DATA_PHYS, len: len as u32, flags: data_flags, next: 2 });
+            core::ptr::write_volatile(desc.add(2), VirtqDesc { addr: STATUS_PHYS, len: 1, flags: DESC_WRITE, next: 0 });
+
+            // 3. Publish the chain head in the available ring, then bump its index
+            let avail = (self.queue_addr as usize + Self::avail_offset(self.queue_size)) as *mut u16;
+            let slot = self.avail_idx % self.queue_size;
+            core::ptr::write_volatile(avail.add(2 + slot as usize), 0);
+            fence(Ordering::SeqCst);
+            self.avail_idx = self.avail_idx.wrapping_add(1);
+            core::ptr::write_volatile(avail.add(1), self.avail_idx);
+            fence(Ordering::SeqCst);
+            Port::<u16>::new(self.io_base + REG_QUEUE_NOTIFY).write(0);
+
+            // 4. Poll the used ring
+            let used_idx = (self.queue_addr as usize + Self::used_offset(self.queue_size) + 2) as *const u16;
+            let mut done = false;
+            for _ in 0..10_000_000 {
+                if core::ptr::read_volatile(used_idx) != self.last_used {
+                    done = true;
+                    break;
+                }
+                core::hint::spin_loop();
+            }
+            if !done {
+                crate::klog_error!("[VIRTIO] {} of LBA {} timed out.\n", op, lba);
+                return false;
+            }
+            self.last_used = self.last_used.wrapping_add(1);
+            fence(Ordering::SeqCst);
+
+            if core::ptr::read_volatile(status) != REQ_STATUS_OK {
+                crate::klog_error!("[VIRTIO] {} of LBA {} failed.\n", op, lba);
+                return false;
+            }
+            true
+        }
+    }
+}
+
+// --- GLOBAL INSTANCE ---
+lazy_static! {
+    pub static ref BLK: Mutex<Option<VirtioBlk>> = Mutex::new(None);
+}
+
+/// Runs `f` against the VirtIO disk, probing the PCI bus on first use
+pub fn with_blk<R>(f: impl FnOnce(&mut VirtioBlk) -> R) -> Option<R> {
+    let mut blk = BLK.lock();
+    if blk.is_none() {
+        let dev = crate::pci::scan_bus().into_iter().find(is_virtio_blk)?;
+        crate::pci::enable_bus_mastering(dev.clone());
+        *blk = Some(VirtioBlk::new(dev));
+    }
+    blk.as_mut().map(f)
+}
@@ -0,0 +1,269 @@
+use crate::pci::{PciDevice, pci_read_u32};
+use crate::{state, net, memory};
+use x86_64::instructions::port::Port;
+use alloc::vec::Vec;
+use core::sync::atomic::{fence, Ordering};
+
+// --- LEGACY (I/O PORT) REGISTERS ---
+const REG_DEVICE_FEATURES: u16 = 0x00;
+const REG_GUEST_FEATURES: u16 = 0x04;
+const REG_QUEUE_PFN: u16 = 0x08;
+const REG_QUEUE_SIZE: u16 = 0x0C;
+const REG_QUEUE_SELECT: u16 = 0x0E;
+const REG_QUEUE_NOTIFY: u16 = 0x10;
+const REG_STATUS: u16 = 0x12;
+const REG_MAC: u16 = 0x14; // Device config, valid with VIRTIO_NET_F_MAC
+
+const STATUS_ACKNOWLEDGE: u8 = 1;
+const STATUS_DRIVER: u8 = 2;
+const STATUS_DRIVER_OK: u8 = 4;
+const STATUS_FEATURES_OK: u8 = 8;
+
+const F_MAC: u32 = 1 << 5;
+
+const DESC_WRITE: u16 = 2;
+
+const RX_QUEUE: u16 = 0;
+const TX_QUEUE: u16 = 1;
+
+// virtio_net_hdr without MRG_RXBUF (legacy): 10 bytes, all zero for a plain frame.
+// The 12-byte form adds num_buffers and only applies with MRG_RXBUF or VERSION_1.
+const NET_HDR_LEN: usize = 10;
+const RX_BUFFERS: u16 = 16;
+const RX_BUF_LEN: usize = 1526; // Header + largest Ethernet frame, with slack
+const BUF_STRIDE: u64 = 2048;   // Two buffers per frame
+
+#[repr(C)]
+struct VirtqDesc {
+    addr: u64,
+    len: u32,
+    flags: u16,
+    next: u16,
+}
+
+/// One legacy split virtqueue: descriptors, available ring, then (page aligned) used ring
+struct Virtqueue {
+    virt: u64,
+    size: u16,
+    avail_idx: u16,
+    last_used: u16,
+}
+
+impl Virtqueue {
+    fn avail(&self) -> *mut u16 {
+        (self.virt as usize + 16 * self.size as usize) as *mut u16
+    }
+
+    fn used_offset(size: u16) -> usize {
+        (16 * size as usize + 6 + 2 * size as usize + 0xFFF) & !0xFFF
+    }
+
+    fn used(&self) -> *const u16 {
+        (self.virt as usize + Self::used_offset(self.size)) as *const u16
+    }
+
+    unsafe fn set_desc(&mut self, i: u16, addr: u64, len: u32, flags: u16) {
+        let desc = self.virt as *mut VirtqDesc;
+        core::ptr::write_volatile(desc.add(i as usize), VirtqDesc { addr, len, flags, next: 0 });
+    }
+
+    /// Offers descriptor `head` to the device
+    unsafe fn push_avail(&mut self, head: u16) {
+        let avail = self.avail();
+        core::ptr::write_volatile(avail.add(2 + (self.avail_idx % self.size) as usize), head);
+        fence(Ordering::SeqCst);
+        self.avail_idx = self.avail_idx.wrapping_add(1);
+        core::ptr::write_volatile(avail.add(1), self.avail_idx);
+        fence(Ordering::SeqCst);
+    }
+
+    /// Next (descriptor id, bytes written) the device has finished with
+    unsafe fn pop_used(&mut self) -> Option<(u16, usize)> {
+        let used = self.used();
+        if core::ptr::read_volatile(used.add(1)) == self.last_used { return None; }
+        fence(Ordering::SeqCst);
+        // Used elements are { id: u32, len: u32 } after the 4-byte flags/idx header
+        let elem = (used as usize + 4 + 8 * (self.last_used % self.size) as usize) as *const u32;
+        let id = core::ptr::read_volatile(elem) as u16;
+        let len = core::ptr::read_volatile(elem.add(1)) as usize;
+        self.last_used = self.last_used.wrapping_add(1);
+        Some((id, len))
+    }
+}
+
+pub struct VirtioNet {
+    io_base: u16,
+    pub mac_addr: [u8; 6],
+    rx: Virtqueue,
+    tx: Virtqueue,
+    rx_frames: [u64; RX_BUFFERS as usize / 2],
+    tx_buf: u64,
+}
+
+/// VirtIO network cards in legacy/transitional mode (I/O BAR0)
+pub fn is_virtio_net(dev: &PciDevice) -> bool {
+    dev.vendor_id == 0x1AF4 && dev.device_id == 0x1000
+}
+
+impl VirtioNet {
+    /// None if there is no memory left for the queues
+    pub fn new(dev: PciDevice) -> Option<Self> {
+        unsafe {
+            let bar0 = pci_read_u32(dev.bus, dev.device, dev.function, 0x10);
+            let io_base = (bar0 & !0x3) as u16;
+
+            // Buffers come from the frame allocator (one frame each, the RX ones shared by
+            // two); the queues once their size is known
+            let mut frames = [0u64; RX_BUFFERS as usize / 2 + 1];
+            for i in 0..frames.len() {
+                match memory::try_alloc_frame() {
+                    Some(f) => frames[i] = f.as_u64(),
+                    None => {
+                        for &f in &frames[..i] { memory::free_frame(x86_64::PhysAddr::new(f)); }
+                        return None;
+                    }
+                }
+            }
+            let mut rx_frames = [0u64; RX_BUFFERS as usize / 2];
+            rx_frames.copy_from_slice(&frames[1..]);
+            let mut driver = VirtioNet {
+                io_base,
+                mac_addr: [0; 6],
+                rx: Virtqueue { virt: 0, size: 0, avail_idx: 0, last_used: 0 },
+                tx: Virtqueue { virt: 0, size: 0, avail_idx: 0, last_used: 0 },
+                rx_frames,
+                tx_buf: frames[0],
+            };
+            if driver.init() { return Some(driver); }
+            // The device was reset, so it no longer owns any of this
+            for &f in &frames { memory::free_frame(x86_64::PhysAddr::new(f)); }
+            None
+        }
+    }
+
+    /// Physical address of receive buffer `i`
+    fn rx_buf_phys(&self, i: u16) -> u64 {
+        self.rx_frames[i as usize / 2] + (i as u64 % 2) * BUF_STRIDE
+    }
+
+    unsafe fn init(&mut self) -> bool {
+        let mut status = Port::<u8>::new(self.io_base + REG_STATUS);
+
+        // 1. Reset, then announce ourselves
+        status.write(0);
+        status.write(STATUS_ACKNOWLEDGE);
+        status.write(STATUS_ACKNOWLEDGE | STATUS_DRIVER);
+
+        // 2. Features: only the MAC address in config space
+        let offered = Port::<u32>::new(self.io_base + REG_DEVICE_FEATURES).read();
+        Port::<u32>::new(self.io_base + REG_GUEST_FEATURES).write(offered & F_MAC);
+        status.write(STATUS_ACKNOWLEDGE | STATUS_DRIVER | STATUS_FEATURES_OK);
+
+        // 3. Queues
+        match (self.setup_queue(RX_QUEUE), self.setup_queue(TX_QUEUE)) {
+            (Some(rx), Some(tx)) => { self.rx = rx; self.tx = tx; }
+            _ => {
+                crate::klog_error!("[VIRTIO] No memory for the net queues.\n");
+                status.write(0);
+                return false;
+            }
+        }
+
+        // 4. MAC: from the device if offered, otherwise a locally administered one
+        if offered & F_MAC != 0 {
+            for i in 0..6 {
+                self.mac_addr[i] = Port::<u8>::new(self.io_base + REG_MAC + i as u16).read();
+            }
+        } else {
+            self.mac_addr = [0x02, 0x00, 0x00, 0x12, 0x34, 0x56];
+        }
+
+        // 5. Give the device every receive buffer up front
+        let hhdm = state::HHDM_OFFSET.load(Ordering::Relaxed);
+        for i in 0..RX_BUFFERS.min(self.rx.size) {
+            let phys = self.rx_buf_phys(i);
+            for b in 0..RX_BUF_LEN {
+                core::ptr::write_volatile(((hhdm + phys) as *mut u8).add(b), 0);
+            }
+            self.rx.set_desc(i, phys, RX_BUF_LEN as u32, DESC_WRITE);
+            self.rx.push_avail(i);
+        }
+
+        status.write(STATUS_ACKNOWLEDGE | STATUS_DRIVER | STATUS_FEATURES_OK | STATUS_DRIVER_OK);
+        Port::<u16>::new(self.io_base + REG_QUEUE_NOTIFY).write(RX_QUEUE);
+
+        let m = self.mac_addr;
+        crate::klog_info!("[VIRTIO] Net device: MAC {:02x}:{:02x}:{:02x}:{:02x}:{:02x}:{:02x}\n",
+            m[0], m[1], m[2], m[3], m[4], m[5]);
+        true
+    }
+
+    /// Gives queue `index` zeroed, physically contiguous memory sized for the device's
+    /// queue length. None if the frames can't be found.
+    unsafe fn setup_queue(&mut self, index: u16) -> Option<Virtqueue> {
+        let hhdm = state::HHDM_OFFSET.load(Ordering::Relaxed);
+        Port::<u16>::new(self.io_base + REG_QUEUE_SELECT).write(index);
+        let size = Port::<u16>::new(self.io_base + REG_QUEUE_SIZE).read();
+        let bytes = Virtqueue::used_offset(size) + 6 + 8 * size as usize;
+        let phys = memory::alloc_contiguous_frames(bytes.div_ceil(4096))?.as_u64();
+        for i in 0..bytes {
+            core::ptr::write_volatile(((hhdm + phys) as *mut u8).add(i), 0);
+        }
+        Port::<u32>::new(self.io_base + REG_QUEUE_PFN).write((phys >> 12) as u32);
+        Some(Virtqueue { virt: hhdm + phys, size, avail_idx: 0, last_used: 0 })
+    }
+
+    /// Sends one Ethernet frame, waiting until the device has taken it
+    pub fn send(&mut self, frame: &[u8]) {
+        if self.tx.size == 0 { return; }
+        let frame = &frame[..frame.len().min(RX_BUF_LEN - NET_HDR_LEN)];
+        let len = NET_HDR_LEN + frame.len();
+        let hhdm = state::HHDM_OFFSET.load(Ordering::Relaxed);
+
+        unsafe {
+            // 1. Header (all zero: no checksum offload, no GSO) + frame in one buffer
+            let buf = (hhdm + self.tx_buf) as *mut u8;
+            for i in 0..NET_HDR_LEN { core::ptr::write_volatile(buf.add(i), 0); }
+            core::ptr::copy_nonoverlapping(frame.as_ptr(), buf.add(NET_HDR_LEN), frame.len());
+
+            // 2. Post it and kick the device
+            self.tx.set_desc(0, self.tx_buf, len as u32, 0);
+            self.tx.push_avail(0);
+            net::record_tx(len);
+            Port::<u16>::new(self.io_base + REG_QUEUE_NOTIFY).write(TX_QUEUE);
+
+            // 3. The single TX buffer is reused, so wait for it to come back
+            for _ in 0..1_000_000 {
+                if self.tx.pop_used().is_some() {
+                    net::record_tx_done(len);
+                    return;
+                }
+                core::hint::spin_loop();
+            }
+        }
+        crate::klog_warn!("[VIRTIO] TX timed out.\n");
+    }
+
+    /// Next received frame (without the virtio header), if any. The buffer goes straight
+    /// back to the device.
+    pub fn poll_rx(&mut self) -> Option<Vec<u8>> {
+        let hhdm = state::HHDM_OFFSET.load(Ordering::Relaxed);
+        unsafe {
+            let (id, len) = self.rx.pop_used()?;
+            let phys = self.rx_buf_phys(id);
+            let len = len.min(RX_BUF_LEN);
+            let frame = if len > NET_HDR_LEN {
+                core::slice::from_raw_parts((hhdm + phys + NET_HDR_LEN as u64) as *const u8, len - NET_HDR_LEN).to_vec()
+            } else {
+                Vec::new()
+            };
+
+            self.rx.set_desc(id, phys, RX_BUF_LEN as u32, DESC_WRITE);
+            self.rx.push_avail(id);
+            Port::<u16>::new(self.io_base + REG_QUEUE_NOTIFY).write(RX_QUEUE);
+
+            net::record_rx(frame.len());
+            Some(frame)
+        }
+    }
+}
@@ -8,15 +8,68 @@ const LINE_SPACING: usize = 2;
 const LETTER_SPACING: usize = 0;
 const BORDER_PADDING: usize = 10;
 const CHAR_WIDTH_GUESS: usize = 9; // Approximate width for backspacing
+pub const DEFAULT_FG: u32 = 0xFFFFFFFF; // White
+
+// --- ANSI COLORS ---
+// Only SGR foreground colours are understood: 0/39 reset, 30–37 dark, 90–97 bright.
+// Anything else in an escape sequence is swallowed so it never shows up as glyphs.
+
+/// Colour for one SGR parameter, None if it doesn't set the foreground
+pub fn ansi_color(code: u32) -> Option<u32> {
+    let color = match code {
+        0 | 39 => DEFAULT_FG,
+        30 => 0xFF000000, // Black
+        31 => 0xFFCD0000, // Red
+        32 => 0xFF00CD00, // Green
+        33 => 0xFFCDCD00, // Yellow
+        34 => 0xFF3B5BEE, // Blue
+        35 => 0xFFCD00CD, // Magenta
+        36 => 0xFF00CDCD, // Cyan
+        37 => 0xFFE5E5E5, // White
+        90 => 0xFF7F7F7F, // Bright Black (Grey)
+        91 => 0xFFFF4040,
+        92 => 0xFF40FF40,
+        93 => 0xFFFFFF40,
+        94 => 0xFF7F7FFF,
+        95 => 0xFFFF40FF,
+        96 => 0xFF40FFFF,
+        97 => 0xFFFFFFFF,
+        _ => return None,
+    };
+    Some(color)
+}
+
+/// Parses the escape sequence at the start of `s` (which begins with ESC).
+/// Returns its length in bytes and the foreground colour after applying it.
+pub fn parse_escape(s: &str, current: u32) -> (usize, u32) {
+    let bytes = s.as_bytes();
+    if bytes.get(1) != Some(&b'[') { return (1, current); } // Lone ESC: drop it
+
+    // 1. Parameters run up to the final byte (0x40..=0x7E)
+    let mut end = 2;
+    while end < bytes.len() && !(0x40..=0x7E).contains(&bytes[end]) { end += 1; }
+    if end == bytes.len() { return (end, current); } // Cut off: swallow the rest
+
+    // 2. Only `m` (SGR) changes anything; an empty parameter list means reset
+    let mut color = current;
+    if bytes[end] == b'm' {
+        for param in s[2..end].split(';') {
+            let code = if param.is_empty() { 0 } else { param.parse().unwrap_or(u32::MAX) };
+            if let Some(c) = ansi_color(code) { color = c; }
+        }
+    }
+    (end + 1, color)
+}
 
 // --- THE WRITER STRUCT ---
 pub struct Writer {
     pub video_ptr: *mut u32,
     pub width: usize,
     pub height: usize,
-    pub pitch: usize,
+    pub pitch_bytes: usize, // Bytes from one row to the next
     pub cursor_x: usize,
     pub cursor_y: usize,
+    pub fg_color: u32,
 }
 
 // SAFETY WAIVER:
@@ -30,25 +83,50 @@ lazy_static! {
 }
 
 impl Writer {
-    pub fn init(video_ptr: *mut u32, width: usize, height: usize, pitch: usize) {
+    pub fn init(video_ptr: *mut u32, width: usize, height: usize, pitch_bytes: usize) {
         let mut writer = WRITER.lock();
         *writer = Some(Writer {
             video_ptr,
             width,
             height,
-            pitch,
+            pitch_bytes,
             cursor_x: BORDER_PADDING,
             cursor_y: BORDER_PADDING,
+            fg_color: DEFAULT_FG,
         });
     }
 
+    /// Byte offset of pixel (x, y) in the framebuffer
+    fn pixel_offset(&self, x: usize, y: usize) -> usize {
+        y * self.pitch_bytes + x * crate::state::BYTES_PER_PIXEL.load(core::sync::atomic::Ordering::Relaxed)
+    }
+
+    /// Copies a `width` x `height` frame to the screen, row by row to honour the pitch
+    pub fn blit(&mut self, frame: &[u32], width: usize, height: usize) {
+        let rows = height.min(self.height);
+        let cols = width.min(self.width);
+        for y in 0..rows {
+            let row = &frame[y * width..y * width + cols];
+            unsafe {
+                if crate::state::BYTES_PER_PIXEL.load(core::sync::atomic::Ordering::Relaxed) == 4 {
+                    let dst = (self.video_ptr as *mut u8).add(y * self.pitch_bytes) as *mut u32;
+                    core::ptr::copy_nonoverlapping(row.as_ptr(), dst, cols);
+                } else {
+                    for (x, &color) in row.iter().enumerate() {
+                        put_pixel(self.video_ptr, self.pixel_offset(x, y), color);
+                    }
+                }
+            }
+        }
+    }
+
     // Erase the whole screen to Chronos Blue
     pub fn clear(&mut self) {
         for y in 0..self.height {
             for x in 0..self.width {
                 unsafe {
-                    let offset = y * self.pitch + x;
-                    *self.video_ptr.add(offset) = 0x00102040; // Deep Blue Theme
+                    let offset = self.pixel_offset(x, y);
+                    put_pixel(self.video_ptr, offset, 0x00102040); // Deep Blue Theme
                 }
             }
         }
@@ -71,12 +149,17 @@ impl Writer {
     }
 
     pub fn write_string(&mut self, s: &str) {
-        for c in s.chars() {
-            self.write_char(c);
-        }
+        self.direct_print(s);
     }
     pub fn direct_print(&mut self, text: &str) {
-        for c in text.chars() {
+        let mut rest = text;
+        while let Some(pos) = rest.find('\x1b') {
+            for c in rest[..pos].chars() { self.write_char(c); }
+            let (len, color) = parse_escape(&rest[pos..], self.fg_color);
+            self.fg_color = color;
+            rest = &rest[pos + len..];
+        }
+        for c in rest.chars() {
             self.write_char(c);
         }
     }    
@@ -101,9 +184,9 @@ impl Writer {
             for y in 0..16 {
                 for x in 0..CHAR_WIDTH_GUESS {
                     unsafe {
-                        let offset = (self.cursor_y + y) * self.pitch + (self.cursor_x + x);
+                        let offset = self.pixel_offset(self.cursor_x + x, self.cursor_y + y);
                         if (self.cursor_x + x) < self.width && (self.cursor_y + y) < self.height {
-                            *self.video_ptr.add(offset) = 0x00102040; 
+                            put_pixel(self.video_ptr, offset, 0x00102040);
                         }
                     }
                 }
@@ -127,12 +210,12 @@ impl Writer {
                     
                     if pixel_x < self.width && pixel_y < self.height {
                         unsafe {
-                            let offset = pixel_y * self.pitch + pixel_x;
-                            // Simple text color (White)
+                            let offset = self.pixel_offset(pixel_x, pixel_y);
+                            // Scale each channel of the text colour by the glyph intensity
                             let intensity = *byte as u32;
-                            // Mix intensity with white (0xFFFFFF)
-                            let color = (intensity << 16) | (intensity << 8) | intensity;
-                            *self.video_ptr.add(offset) = color;
+                            let scale = |shift: u32| ((self.fg_color >> shift) & 0xFF) * intensity / 255;
+                            let color = (scale(16) << 16) | (scale(8) << 8) | scale(0);
+                            put_pixel(self.video_ptr, offset, color);
                         }
                     }
                 }
@@ -143,6 +226,19 @@ impl Writer {
     }
 }
 
+/// Stores a 0x00RRGGBB pixel at byte `offset` (y * pitch_bytes + x * bytes_per_pixel) in
+/// the framebuffer, as a whole word or, on a 24bpp mode, as its low three bytes
+#[inline]
+pub unsafe fn put_pixel(video_ptr: *mut u32, offset: usize, color: u32) {
+    let dst = (video_ptr as *mut u8).add(offset);
+    if crate::state::BYTES_PER_PIXEL.load(core::sync::atomic::Ordering::Relaxed) == 4 {
+        *(dst as *mut u32) = color;
+    } else {
+        let bytes = color.to_le_bytes();
+        core::ptr::copy_nonoverlapping(bytes.as_ptr(), dst, 3);
+    }
+}
+
 // Helper to print from anywhere
 // Helper to print from anywhere
 pub fn print(s: &str) {